@@ -1,15 +1,70 @@
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::fs;
-use std::process::Command;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::completion::{
-    generate_completions, handle_first_run, setup_completion_for_current_shell,
+    Shell, generate_completions, handle_first_run, remove_completion, setup_completion,
+    static_completion_script,
 };
 use crate::config::{
-    create_aliases_backup, get_agent, get_aliases_file, load_aliases, save_aliases, set_agent,
+    AGENT_PRESETS, AgentSource, AgentSpec, AliasEntry, DEFAULT_PROFILE, EffectiveAliases,
+    LOCAL_ALIASES_FILE, MoveAliasError, Settings, clear_selection, count_tags,
+    create_aliases_backup, create_daily_backup, daily_backup_due, disable_local_aliases,
+    find_agent_preset, get_agent, get_agent_file, get_aliases_file, get_archive_file,
+    get_auto_backup_dir, get_backup_dir, get_backup_file, get_config_dir, get_local_aliases_file,
+    get_profile, get_profile_settings_file, get_settings_file, get_trash_file, list_profiles,
+    load_agents, load_aliases, load_aliases_from, load_archive, load_effective_aliases,
+    load_settings, load_trash, local_aliases_enabled, move_alias, profile_exists, resolve_agent,
+    restore_removed, save_agents, save_aliases, save_aliases_to, save_archive, save_trash,
+    select_profile, selection_env, set_agent, set_agent_env, set_agent_fallbacks, set_agent_spec,
+    set_profile_setting, trash_alias, unset_agent, use_agent, validate_agent_name,
+    validate_env_name, validate_profile_name, verbose_from_env,
+};
+use crate::crypto;
+use crate::error::{QwkError, did_you_mean};
+use crate::export::{
+    DocumentFormat, ExportDocument, ImportChange, classify_imported, merge_imported,
+    parse_document, render_document,
+};
+use crate::history::{
+    HistoryEntry, get_history_file, load_history, record_history, select_history,
+};
+use crate::llm::{ImportedTemplates, default_templates_dir, export_templates, import_templates};
+use crate::maintenance::{
+    Backup, PlannedAction, apply_plan, count_aliases_in, find_backups, plan_prune_backups,
+    plan_reset, print_dry_run, select_backup,
+};
+use crate::notify::{notification_text, send_notification};
+use crate::output::{set_quiet, status};
+use crate::process::{AgentStdout, TIMEOUT_EXIT_CODE, exec_agent, exit_code, run_agent};
+use crate::prompt_files::{PROMPTS_DIR, resolve_prompt_files};
+use crate::runs::{RunEntry, get_runs_file, load_runs, record_run, select_runs};
+use crate::safety::{
+    check_execution_args, dangerous_arg_patterns, find_dangerous_args, warn_dangerous_args,
+};
+use crate::sync::{self, GitError};
+use crate::table::{layout_columns, render_table};
+use crate::undo::{
+    clear_undo, get_undo_journal_file, get_undo_snapshot_file, load_undo_entry, record_undo,
+};
+use crate::usage::{UsageRecord, load_usage, record_usage, usage_stats};
+use crate::utils::{
+    ConfirmError, PROMPT_PLACEHOLDER, RenderError, TemplateContext, agent_call_args,
+    check_prompt_size, closest_matches, confirm, confirm_reset, expand_tilde, find_executable,
+    format_age, format_duration_ms, format_local_time, format_size, get_current_datetime,
+    join_agent_command, mask_secret, parse_agent_command, parse_batch_prompts,
+    read_prompt_from_clipboard, read_prompt_from_file, read_prompt_from_stdin,
+    read_prompt_interactive, read_text_from_stdin, remove_agent_args, render_alias,
+    shell_agent_call, stdin_is_input, suggest_executable, template_vars, truncate_prompt,
+    uses_positional_args,
 };
-use crate::utils::{confirm_reset, parse_agent_command, read_prompt_from_stdin, truncate_prompt};
 
 #[derive(Parser)]
 #[command(name = "qwk")]
@@ -20,6 +75,37 @@ pub struct Cli {
 
     #[arg(help = "Run a stored shortcut")]
     pub shortcut: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "Use the shortcuts and agent of this profile (default: $QWK_PROFILE)"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Ignore the .qwk.json of the current project (or set QWK_NO_LOCAL)"
+    )]
+    pub no_local: bool,
+
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Report on stderr which alias and agent a shortcut runs, how, and for how long (or set QWK_VERBOSE=1)"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Don't print status lines such as \"Alias 'x' set successfully\"; errors and warnings still show"
+    )]
+    pub quiet: bool,
 }
 
 #[derive(Subcommand)]
@@ -27,44 +113,462 @@ pub enum Commands {
     #[command(long_flag = "set")]
     #[command(about = "Set an alias for a prompt")]
     #[command(
-        long_about = "Set an alias for a prompt. If no prompt is provided, it will be read from stdin."
+        long_about = "Set an alias for a prompt. If no prompt is provided, it will be read from stdin.\n\nWith --batch, stdin holds several entries: a line containing only '@name' starts an entry and the following lines are its prompt. Start a prompt line with '@@' to make it begin with a literal '@'."
     )]
     Set {
-        #[arg(help = "The alias name to set")]
-        alias: String,
+        #[arg(help = "The alias name to set", required_unless_present = "batch")]
+        alias: Option<String>,
         #[arg(help = "The prompt text (optional, will read from stdin if not provided)")]
         prompt: Option<String>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            value_hint = ValueHint::FilePath,
+            help = "Read the prompt from a file",
+            conflicts_with_all = ["prompt", "batch"]
+        )]
+        from_file: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Read the prompt from the system clipboard",
+            conflicts_with_all = ["prompt", "from_file", "batch"]
+        )]
+        from_clipboard: bool,
+        #[arg(
+            long,
+            help = "Read several '@name' entries from stdin and set them all at once",
+            conflicts_with_all = ["alias", "prompt"]
+        )]
+        batch: bool,
+        #[arg(long, help = "A short human-written description shown by --list")]
+        description: Option<String>,
+        #[arg(
+            long = "tag",
+            help = "Tag the alias (repeatable); replaces existing tags"
+        )]
+        tags: Vec<String>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            value_hint = ValueHint::DirPath,
+            help = "Run the agent in this directory (an empty path removes it)",
+            conflicts_with = "batch"
+        )]
+        cwd: Option<String>,
+        #[arg(
+            long,
+            value_name = "BOOL",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "true",
+            help = "Ask before running the shortcut, unless it's run with --yes (--confirm=false stops asking)",
+            conflicts_with = "batch"
+        )]
+        confirm: Option<bool>,
+        #[arg(
+            long,
+            value_name = "COMMAND",
+            value_hint = ValueHint::CommandString,
+            help = "Run this command before the agent, and not the agent if it fails (an empty command removes it)",
+            conflicts_with = "batch"
+        )]
+        pre_hook: Option<String>,
+        #[arg(
+            long,
+            value_name = "COMMAND",
+            value_hint = ValueHint::CommandString,
+            help = "Run this command after the agent, with QWK_EXIT_CODE and QWK_DURATION_MS set (an empty command removes it)",
+            conflicts_with = "batch"
+        )]
+        post_hook: Option<String>,
+        #[arg(
+            long,
+            help = "Save to the project's .qwk.json, creating one here if there's none",
+            conflicts_with = "batch"
+        )]
+        local: bool,
     },
     #[command(long_flag = "agent")]
     #[command(about = "Set the agent command to use")]
     #[command(
-        long_about = "Set the agent command to use when executing shortcuts. Can include default arguments that will be passed on every call. Defaults to 'claude'."
+        long_about = "Set the agent command to use when executing shortcuts. Can include default arguments that will be passed on every call. Defaults to 'claude'. Without a command, shows the current agent, where it is set, and how it splits into the program and its arguments. --preset sets the command of a common AI CLI, listed by --presets. --unset goes back to the default. --add-arg and --remove-arg change the default arguments of the current agent without retyping it. --fallback adds an agent command to try, in order, when the agent's program isn't installed. Setting an agent whose program isn't on PATH only warns, unless --check makes it an error. With --stdin-prompt, the prompt is written to the agent's stdin instead of being passed as its last argument, for agents that read it there or prompts too long for the command line. With --shell, the command is run as a shell script (sh -c, or cmd /C on Windows), so that it can be a pipeline such as \"llm | glow -\". The prompt is passed to the script as a parameter rather than pasted into it, so the shell never interprets it, but the command itself is run as written: only set one you would type into a shell yourself."
     )]
     Agent {
+        #[arg(
+            group = "new_agent",
+            value_hint = ValueHint::CommandString,
+            help = "The command to use as the agent (can include default arguments in quotes); without it, show the current agent"
+        )]
+        command: Option<String>,
+        #[arg(
+            long,
+            group = "new_agent",
+            help = "Use the agent command of a common AI CLI; see --presets"
+        )]
+        preset: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "new_agent",
+            help = "Print only the current agent command, for scripts"
+        )]
+        porcelain: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["new_agent", "porcelain"],
+            help = "Go back to the default agent"
+        )]
+        unset: bool,
+        #[arg(
+            long,
+            requires = "new_agent",
+            conflicts_with = "check",
+            help = "Don't check that the agent's program is on PATH"
+        )]
+        force: bool,
+        #[arg(
+            long,
+            requires = "new_agent",
+            help = "Refuse to set an agent whose program isn't on PATH, instead of warning"
+        )]
+        check: bool,
+        #[arg(
+            long,
+            requires = "new_agent",
+            help = "Write the prompt to the agent's stdin instead of passing it as an argument"
+        )]
+        stdin_prompt: bool,
+        #[arg(
+            long,
+            requires = "new_agent",
+            help = "Run the command as a shell script, e.g. a pipeline; the shell interprets it, so only use commands you trust"
+        )]
+        shell: bool,
+        #[arg(
+            long,
+            value_name = "ARGS",
+            allow_hyphen_values = true,
+            conflicts_with_all = ["new_agent", "porcelain", "unset"],
+            help = "Append arguments to the current agent's (repeatable)"
+        )]
+        add_arg: Vec<String>,
+        #[arg(
+            long,
+            value_name = "ARGS",
+            allow_hyphen_values = true,
+            conflicts_with_all = ["new_agent", "porcelain", "unset"],
+            help = "Remove arguments from the current agent's, e.g. \"--model opus\" (repeatable)"
+        )]
+        remove_arg: Vec<String>,
+        #[arg(
+            long,
+            value_name = "COMMAND",
+            conflicts_with_all = ["new_agent", "porcelain", "unset", "add_arg", "remove_arg"],
+            help = "Add an agent command to try when the agent's program isn't found (repeatable)"
+        )]
+        fallback: Vec<String>,
+        #[arg(
+            long,
+            conflicts_with_all = ["new_agent", "porcelain", "unset", "add_arg", "remove_arg"],
+            help = "Remove the fallback agents, before adding any given with --fallback"
+        )]
+        clear_fallbacks: bool,
+    },
+    #[command(long_flag = "presets")]
+    #[command(about = "List the agent presets for --agent --preset")]
+    Presets,
+    #[command(long_flag = "agent-add")]
+    #[command(about = "Register a named agent command")]
+    #[command(
+        long_about = "Register an agent command under a name, replacing one of the same name. Switch to it with --agent-use, or run a single shortcut with it with 'qwk <shortcut> --with <name>'. Named agents are shared by all profiles. With --stdin-prompt, the prompt is written to the agent's stdin instead of being passed as its last argument. With --shell, the command is run as a shell script, as with --agent --shell."
+    )]
+    AgentAdd {
+        #[arg(help = "The name of the agent")]
+        name: String,
         #[arg(help = "The command to use as the agent (can include default arguments in quotes)")]
         command: String,
+        #[arg(
+            long,
+            help = "Write the prompt to the agent's stdin instead of passing it as an argument"
+        )]
+        stdin_prompt: bool,
+        #[arg(
+            long,
+            help = "Run the command as a shell script, e.g. a pipeline; the shell interprets it, so only use commands you trust"
+        )]
+        shell: bool,
+    },
+    #[command(long_flag = "agent-use")]
+    #[command(about = "Run shortcuts with a named agent")]
+    #[command(
+        long_about = "Make a named agent from --agent-add the one shortcuts run with in the active profile. Setting an agent command with --agent switches back to that command."
+    )]
+    AgentUse {
+        #[arg(help = "The name of the agent")]
+        name: String,
+    },
+    #[command(long_flag = "agents")]
+    #[command(about = "List the named agents, marking the active one")]
+    Agents,
+    #[command(long_flag = "agent-env")]
+    #[command(about = "Set, list or unset environment variables of the agent")]
+    #[command(
+        long_about = "Manage the environment variables shortcuts run the agent with, e.g. the address of its server or an API key. They belong to the agent in use: a named agent chosen with --agent-use keeps them in agents.json, an agent set with --agent in config.toml. --for changes a named agent instead. Values are only ever printed masked after their first few characters."
+    )]
+    AgentEnv {
+        #[arg(value_enum, help = "What to do")]
+        action: EnvAction,
+        #[arg(help = "KEY=VALUE to set, or the KEY to unset")]
+        variable: Option<String>,
+        #[arg(
+            long = "for",
+            value_name = "NAME",
+            help = "Change the named agent instead of the one in use"
+        )]
+        agent: Option<String>,
     },
     #[command(long_flag = "list")]
     #[command(about = "List all available shortcuts")]
     #[command(
         long_about = "List all available shortcuts with their alias names and a preview of their associated prompts."
     )]
-    List,
+    List {
+        #[arg(long, value_enum, default_value_t = ListSort::Name, help = "Order of the listed shortcuts")]
+        sort: ListSort,
+        #[arg(
+            long,
+            help = "Show only alias names, in columns when writing to a terminal"
+        )]
+        names: bool,
+        #[arg(long = "tag", help = "Only list aliases with this tag (repeatable)")]
+        tags: Vec<String>,
+        #[arg(
+            long,
+            help = "Also show when each alias was created and last modified, and where it runs"
+        )]
+        long: bool,
+        #[arg(long, help = "Don't end the list with the shortcut count and agent")]
+        no_summary: bool,
+        #[arg(long, help = "List archived shortcuts instead")]
+        archived: bool,
+        #[arg(
+            long,
+            help = "List a corrupted shortcuts file as empty instead of failing"
+        )]
+        force_empty: bool,
+        #[arg(
+            long,
+            value_name = "CHARS",
+            help = "Characters of each prompt to show (default: list_preview_width in config.toml)"
+        )]
+        width: Option<usize>,
+    },
+    #[command(long_flag = "show")]
+    #[command(about = "Show the prompt of a shortcut")]
+    #[command(
+        long_about = "Show the stored prompt of a shortcut. With --vars, list the template variables and positional arguments it expects instead, along with their defaults. With --resolved, expand the other aliases it includes; placeholders filled in at run time are left as they are."
+    )]
+    Show {
+        #[arg(help = "The alias name to show")]
+        alias: String,
+        #[arg(long, help = "List the template variables the prompt expects")]
+        vars: bool,
+        #[arg(
+            long,
+            help = "Expand {{alias:name}} references in the prompt",
+            conflicts_with = "vars"
+        )]
+        resolved: bool,
+    },
+    #[command(long_flag = "count")]
+    #[command(about = "Print the number of shortcuts")]
+    Count,
+    #[command(long_flag = "tags")]
+    #[command(about = "List all tags with their alias counts")]
+    Tags,
+    #[command(long_flag = "stats")]
+    #[command(about = "Show how often each shortcut has been run")]
+    #[command(
+        long_about = "Show each shortcut with its run count and last run time, most used first."
+    )]
+    Stats {
+        #[arg(long, help = "Print the statistics as JSON")]
+        json: bool,
+    },
+    #[command(long_flag = "pin")]
+    #[command(about = "Pin a shortcut to the top of the list and completions")]
+    Pin {
+        #[arg(help = "The alias name to pin")]
+        alias: String,
+    },
+    #[command(long_flag = "unpin")]
+    #[command(about = "Unpin a pinned shortcut")]
+    Unpin {
+        #[arg(help = "The alias name to unpin")]
+        alias: String,
+    },
+    #[command(long_flag = "archive")]
+    #[command(about = "Move a shortcut to the archive")]
+    #[command(
+        long_about = "Move a shortcut from the aliases file into archive.json. Archived shortcuts can't be run and are hidden from --list and completions until they are unarchived."
+    )]
+    Archive {
+        #[arg(help = "The alias name to archive")]
+        alias: String,
+        #[arg(long, help = "Replace an archived shortcut of the same name")]
+        force: bool,
+    },
+    #[command(long_flag = "unarchive")]
+    #[command(about = "Restore an archived shortcut")]
+    Unarchive {
+        #[arg(help = "The alias name to restore")]
+        alias: String,
+        #[arg(long, help = "Replace an existing shortcut of the same name")]
+        force: bool,
+    },
     #[command(long_flag = "remove")]
     #[command(about = "Remove a specific shortcut")]
     #[command(
-        long_about = "Remove a specific shortcut by alias name. The shortcut will be permanently deleted from the aliases file."
+        long_about = "Remove a specific shortcut by alias name. It goes to the trash, which keeps the last 50 removed shortcuts; --restore-removed puts it back. Shortcuts removed from a project's .qwk.json are deleted outright."
     )]
     Remove {
         #[arg(help = "The alias name to remove")]
         alias: String,
+        #[arg(long, help = "Remove it from the project's .qwk.json instead")]
+        local: bool,
+    },
+    #[command(long_flag = "trash")]
+    #[command(about = "List the removed shortcuts in the trash")]
+    Trash,
+    #[command(long_flag = "restore-removed")]
+    #[command(about = "Put a removed shortcut back from the trash")]
+    #[command(
+        long_about = "Put the most recently removed shortcut of this name back from the trash. A shortcut of the same name is only replaced with --force."
+    )]
+    RestoreRemoved {
+        #[arg(help = "The alias name to put back")]
+        alias: String,
+        #[arg(long, help = "Replace an existing shortcut of the same name")]
+        force: bool,
     },
     #[command(long_flag = "reset")]
     #[command(about = "Reset all shortcuts (creates backup)")]
     #[command(
-        long_about = "Reset all shortcuts by clearing the aliases file. A backup will be created automatically. The agent setting is preserved."
+        long_about = "Reset all shortcuts by clearing the aliases file. A backup will be created automatically. The agent setting is preserved. It asks first unless confirm_destructive is false, and the answer must come from a terminal: without one it refuses, so scripts pass --yes."
+    )]
+    Reset {
+        #[arg(
+            long,
+            help = "Show what would be backed up and removed without changing anything"
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            help = "Don't ask for confirmation, even with confirm_destructive in config.toml"
+        )]
+        yes: bool,
+    },
+    #[command(long_flag = "backups")]
+    #[command(about = "List the backups of the shortcuts")]
+    #[command(
+        long_about = "List the backups of the active profile's shortcuts, newest first, with their age, size and number of shortcuts. Backups that can't be parsed are marked as corrupted. The snapshots taken with backup_on_write are listed too, under backups/auto/."
+    )]
+    Backups {
+        #[arg(long, value_enum, default_value_t = ListFormat::Table, help = "Output format")]
+        format: ListFormat,
+    },
+    #[command(long_flag = "restore")]
+    #[command(about = "Restore the shortcuts from a backup")]
+    #[command(
+        long_about = "Replace the shortcuts with those of a backup: the most recent one, or the one with the given number or name in the --backups listing. The current shortcuts are backed up first, so a restore can be undone by restoring that backup."
+    )]
+    Restore {
+        #[arg(help = "Number or file name of the backup in the --backups listing")]
+        backup: Option<String>,
+        #[arg(long, help = "Don't ask for confirmation")]
+        yes: bool,
+    },
+    #[command(long_flag = "prune-backups")]
+    #[command(about = "Delete all but the most recent backups")]
+    #[command(
+        long_about = "Delete all but the most recent aliases_backup_YYYYMMDD_HHMMSS.json files of the active profile, along with the settings backed up with them. Keeps keep_backups from config.toml (10 by default) unless --keep is given. No other files are touched."
     )]
-    Reset,
+    PruneBackups {
+        #[arg(long, value_name = "N", help = "Number of backups to keep")]
+        keep: Option<usize>,
+        #[arg(
+            long,
+            help = "List the backups that would be deleted without deleting them"
+        )]
+        dry_run: bool,
+    },
+    #[command(long_flag = "import")]
+    #[command(about = "Import shortcuts from a file or another tool")]
+    #[command(
+        long_about = "Import shortcuts from a document written by --export or a plain aliases.json, merging them into the current ones. With --format markdown, each ## heading of a Markdown file names an alias, lowercased with dashes for spaces, and the section under it is the prompt; code fences are kept as they are. With --format llm, reads templates of the llm CLI from a template file or directory, defaulting to llm's templates directory. A backup of the current shortcuts is created before anything is written."
+    )]
+    Import {
+        #[arg(
+            value_hint = ValueHint::AnyPath,
+            help = "File or directory to import from"
+        )]
+        path: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            help = "Format of the imported files [default: from the file extension, else json]"
+        )]
+        format: Option<TransferFormat>,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = ImportStrategy::Overwrite,
+            help = "What to do with imported aliases that differ from existing ones"
+        )]
+        strategy: ImportStrategy,
+        #[arg(long, help = "Also use the agent of the imported file")]
+        include_agent: bool,
+        #[arg(
+            long,
+            help = "Show what would be added and changed without changing anything; exits with 3 if nothing would"
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            help = "Prefix added to imported alias names [default: llm. with --format llm]"
+        )]
+        prefix: Option<String>,
+    },
+    #[command(long_flag = "export")]
+    #[command(about = "Export shortcuts to a file or another tool")]
+    #[command(
+        long_about = "Export every shortcut and the agent as a single document, with sorted keys so it diffs cleanly, to stdout or the --output file. With --format markdown, writes only the prompts, as a ## section per alias that --import --format markdown reads back. With --format llm, writes one llm template per alias into the given directory instead."
+    )]
+    Export {
+        #[arg(
+            value_hint = ValueHint::DirPath,
+            help = "Directory to write llm templates to"
+        )]
+        path: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "json",
+            help = "Format of the exported files"
+        )]
+        format: TransferFormat,
+        #[arg(
+            long,
+            conflicts_with = "path",
+            value_hint = ValueHint::FilePath,
+            help = "File to write to instead of stdout"
+        )]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Prefix removed from exported alias names [default: llm. with --format llm]"
+        )]
+        prefix: Option<String>,
+    },
     #[command(long_flag = "complete")]
     #[command(about = "Generate completions (internal use)")]
     #[command(
@@ -72,207 +576,4055 @@ pub enum Commands {
     )]
     #[command(hide = true)]
     Complete {
-        #[arg(help = "Partial input to complete")]
+        #[arg(allow_hyphen_values = true, help = "Partial input to complete")]
         partial: Option<String>,
+        #[arg(long, help = "Print 'name:description' pairs for zsh's _describe")]
+        with_descriptions: bool,
+        #[arg(
+            long,
+            help = "Shell to format the candidates for, with descriptions for zsh and fish"
+        )]
+        shell: Option<Shell>,
+        #[arg(
+            long,
+            allow_hyphen_values = true,
+            help = "The word before the one being completed"
+        )]
+        previous: Option<String>,
+        #[arg(
+            last = true,
+            help = "The words of the command line up to the one being completed, which comes last"
+        )]
+        words: Vec<String>,
     },
     #[command(long_flag = "setup-completion")]
     #[command(about = "Set up shell autocompletion")]
     #[command(
-        long_about = "Set up autocompletion for your current shell. This will modify your shell's configuration file."
+        long_about = "Set up autocompletion for the shell qwk is run from, or for the login shell named by $SHELL when that can't be told, or for the one given with --shell. This will modify your shell's configuration file, unless --print is given to print the script instead."
+    )]
+    SetupCompletion {
+        #[arg(
+            long,
+            help = "Shell to set up: bash, zsh, fish or powershell [default: the current one]"
+        )]
+        shell: Option<Shell>,
+        #[arg(
+            long,
+            help = "Print the completion script instead of adding it to an rc file"
+        )]
+        print: bool,
+    },
+    #[command(long_flag = "remove-completion")]
+    #[command(about = "Remove shell autocompletion from your rc file")]
+    #[command(
+        long_about = "Remove the autocompletion script that qwk added to your shell's configuration file, including one added by an earlier version. The file is copied to <file>.qwk.bak first."
+    )]
+    RemoveCompletion {
+        #[arg(
+            long,
+            help = "Shell to remove it for: bash, zsh, fish or powershell [default: the current one]"
+        )]
+        shell: Option<Shell>,
+    },
+    #[command(long_flag = "completions")]
+    #[command(about = "Print a static completion script for packaging")]
+    #[command(
+        long_about = "Print a completion script for the given shell that completes qwk's commands and options without running qwk on every TAB, for distributions to ship under their completion directories. Being static, it can't complete the names of your shortcuts; the script that --setup-completion installs does, and is the one to use otherwise."
+    )]
+    Completions {
+        #[arg(value_enum, help = "Shell to print the script for")]
+        shell: clap_complete::Shell,
+    },
+    #[command(long_flag = "profiles")]
+    #[command(about = "List the profiles, marking the active one")]
+    #[command(
+        long_about = "List the profiles, marking the active one with '*'. The default profile uses the config directory itself; named profiles live in its profiles/ directory and are created by their first --set."
+    )]
+    Profiles,
+    #[command(long_flag = "history")]
+    #[command(about = "Show the log of changes to the shortcuts")]
+    #[command(
+        long_about = "Show the changes made to the active profile's shortcuts, newest first: when, by which command, to which aliases, and the start of each new prompt. The log is kept in history.log in the config directory, one JSON object per line, and holds the last 10,000 changes."
+    )]
+    History {
+        #[arg(help = "Only show changes to this alias")]
+        alias: Option<String>,
+        #[arg(long, help = "Show at most this many changes")]
+        limit: Option<usize>,
+    },
+    #[command(long_flag = "runs")]
+    #[command(about = "Show the last shortcut runs")]
+    #[command(
+        long_about = "Show the last shortcuts run in the active profile, newest first: when, which alias, the arguments given after --, the exit code and how long the agent took. The log is kept in runs.jsonl, one JSON object per line, and its older half is dropped once it passes 1 MB. Set track_runs = false in config.toml to stop logging runs."
+    )]
+    Runs {
+        #[arg(long, help = "Only show runs of this alias")]
+        alias: Option<String>,
+        #[arg(long, default_value_t = 20, help = "Show at most this many runs")]
+        limit: usize,
+    },
+    #[command(long_flag = "run")]
+    #[command(about = "Run a shortcut, whatever its name")]
+    #[command(
+        long_about = "Run a shortcut, as 'qwk <shortcut>' does. Spelling it out always runs the shortcut, even one named like a command, such as 'qwk run run'. Everything after the name is taken as it would be after 'qwk <shortcut>': options such as --var and --dry-run, arguments for the prompt, and after --, arguments for the agent."
+    )]
+    Run {
+        #[arg(help = "The shortcut to run")]
+        shortcut: String,
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "Options and arguments, as after 'qwk <shortcut>'"
+        )]
+        args: Vec<String>,
+    },
+    #[command(long_flag = "chain")]
+    #[command(about = "Run several shortcuts one after the other")]
+    #[command(
+        long_about = "Run the named shortcuts in order, each as 'qwk <shortcut>' would, stopping at the first that fails or can't run unless --keep-going is given. All the names are checked before anything runs. Arguments after -- are passed to the agent of every shortcut in the chain. Piped input goes to the first shortcut only. At the end, the exit code of each shortcut is printed on stderr, and qwk exits with that of the first that failed."
+    )]
+    Chain {
+        #[arg(required = true, help = "The shortcuts to run, in order")]
+        aliases: Vec<String>,
+        #[arg(long, help = "Run the rest of the shortcuts after one fails")]
+        keep_going: bool,
+        #[arg(
+            last = true,
+            help = "Arguments passed to the agent of every shortcut in the chain"
+        )]
+        agent_args: Vec<String>,
+    },
+    #[command(long_flag = "last")]
+    #[command(about = "Run the last shortcut run again")]
+    #[command(
+        long_about = "Run the shortcut last run in the active profile again, with the same arguments after --, taken from the log shown by --runs. The command is printed on stderr before it runs. Other options of that run, such as --var or --with, aren't logged and so aren't repeated."
+    )]
+    Last {
+        #[arg(
+            long,
+            help = "Print the agent command it would run, without running it"
+        )]
+        dry_run: bool,
+    },
+    #[command(long_flag = "undo")]
+    #[command(about = "Revert the last command that changed the shortcuts")]
+    #[command(
+        long_about = "Put the shortcuts back as they were before the last --set, --remove, --import, --reset, --restore, --archive, --unarchive, --pin or --unpin, after saying which command that was. Only the last command can be undone, once; changes to a project's .qwk.json aren't recorded."
+    )]
+    Undo,
+    #[command(long_flag = "encrypt")]
+    #[command(about = "Turn encryption of the stored shortcuts on or off")]
+    #[command(
+        long_about = "Turn encryption of the active profile's aliases.json, archive.json and trash.json on or off. Encrypted files are decrypted with a passphrase asked for on the terminal, or taken from QWK_PASSPHRASE. Turning encryption off writes plain JSON again. Backups taken before encryption was turned on stay in plain text."
+    )]
+    Encrypt {
+        #[arg(value_enum, help = "Whether to encrypt")]
+        state: Toggle,
+    },
+    #[command(long_flag = "migrate-to-files")]
+    #[command(about = "Store each prompt in its own file under prompts/")]
+    #[command(
+        long_about = "Move every prompt of the active profile out of aliases.json into its own Markdown file in the prompts/ directory next to it, and keep saving them that way. aliases.json then refers to each file with prompt_file. Alias groups like work/standup become subdirectories. qwk deletes the .md files in prompts/ that no alias uses. Set prompt_files = false in config.toml to go back to inline prompts. Can't be combined with --encrypt."
+    )]
+    MigrateToFiles,
+    #[command(long_flag = "sync")]
+    #[command(about = "Commit the shortcuts to the config directory's git repository")]
+    #[command(
+        long_about = "Commit the active profile's aliases.json and archive.json and the config.toml files to the git repository the config directory is in, with a message naming the changed aliases. Other changes in the repository are left alone. Set auto_commit = true in config.toml to commit after every command that changes the shortcuts."
     )]
-    SetupCompletion,
+    Sync {
+        #[arg(long, help = "Pull after committing")]
+        pull: bool,
+        #[arg(long, help = "Push after committing (and pulling)")]
+        push: bool,
+    },
+    #[command(long_flag = "config-path")]
+    #[command(about = "Print the path of the settings file")]
+    #[command(
+        long_about = "Print the path of config.toml, which holds tool-wide settings. The file doesn't need to exist."
+    )]
+    ConfigPath,
 }
 
-pub fn list_aliases() {
-    let aliases = load_aliases();
-
-    if aliases.is_empty() {
-        println!("No shortcuts available.");
-        return;
+impl Commands {
+    /// Whether the command may change the files `--sync` commits, for `auto_commit`.
+    fn modifies_config(&self) -> bool {
+        match self {
+            Commands::Set { .. }
+            | Commands::Agent { .. }
+            | Commands::AgentAdd { .. }
+            | Commands::AgentUse { .. }
+            | Commands::Restore { .. }
+            | Commands::Encrypt { .. }
+            | Commands::MigrateToFiles
+            | Commands::Undo
+            | Commands::RestoreRemoved { .. }
+            | Commands::Archive { .. }
+            | Commands::Unarchive { .. }
+            | Commands::Pin { .. }
+            | Commands::Unpin { .. }
+            | Commands::Remove { .. } => true,
+            Commands::Import { dry_run, .. } | Commands::Reset { dry_run, .. } => !dry_run,
+            Commands::AgentEnv { action, .. } => *action != EnvAction::List,
+            _ => false,
+        }
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListSort {
+    /// Alphabetical by alias name
+    Name,
+    /// Most recently run first
+    Recent,
+    /// Most frequently run first
+    Used,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// Aligned columns for reading
+    Table,
+    /// A JSON array for scripts
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TransferFormat {
+    /// A single JSON document
+    Json,
+    /// A single YAML document
+    Yaml,
+    /// A single TOML document
+    Toml,
+    /// A `## alias` section per prompt, without the agent or other fields
+    Markdown,
+    /// Templates of the llm CLI (one YAML file per template)
+    Llm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Toggle {
+    On,
+    Off,
+}
 
-    println!("Available shortcuts:");
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EnvAction {
+    /// Set KEY=VALUE
+    Set,
+    /// Print the variables, with their values masked
+    List,
+    /// Remove KEY
+    Unset,
+}
 
-    // Sort aliases by name for consistent output
-    let mut sorted_aliases: Vec<_> = aliases.iter().collect();
-    sorted_aliases.sort_by_key(|(name, _)| *name);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImportStrategy {
+    /// Keep the existing alias
+    Skip,
+    /// Replace the existing alias with the imported one
+    Overwrite,
+    /// Ask about each conflict
+    Prompt,
+}
 
-    for (alias, prompt) in sorted_aliases {
-        let truncated_prompt = truncate_prompt(prompt, 60);
-        println!("  {} - {}", alias, truncated_prompt);
+impl TransferFormat {
+    /// The format of a single exported document, or `None` for llm's template files.
+    fn document_format(self) -> Option<DocumentFormat> {
+        match self {
+            TransferFormat::Json => Some(DocumentFormat::Json),
+            TransferFormat::Yaml => Some(DocumentFormat::Yaml),
+            TransferFormat::Toml => Some(DocumentFormat::Toml),
+            TransferFormat::Markdown => Some(DocumentFormat::Markdown),
+            TransferFormat::Llm => None,
+        }
     }
 }
 
-pub fn execute_shortcut(shortcut: &str, args: &[String]) {
-    let aliases = load_aliases();
+/// Prefix for the names of llm templates, which keeps them apart from the user's own aliases.
+const LLM_PREFIX: &str = "llm.";
 
-    if let Some(prompt) = aliases.get(shortcut) {
-        let agent_str = get_agent();
-        let (agent_command, agent_default_args) = parse_agent_command(&agent_str);
+/// Orders alias names for display. Ties, including never-run aliases, fall back to
+/// alphabetical order.
+pub fn sort_alias_names(
+    names: &mut [&String],
+    usage: &HashMap<String, UsageRecord>,
+    sort: ListSort,
+) {
+    names.sort();
+    match sort {
+        ListSort::Name => {}
+        ListSort::Recent => names.sort_by_key(|name| {
+            std::cmp::Reverse(usage.get(*name).and_then(|record| record.last_used))
+        }),
+        ListSort::Used => names.sort_by_key(|name| {
+            std::cmp::Reverse(usage.get(*name).map_or(0, |record| record.count))
+        }),
+    }
+}
 
-        // Check for -- separator and collect per-call agent arguments
-        let mut per_call_args = Vec::new();
-        if args.len() > 2 {
-            if let Some(separator_pos) = args.iter().position(|arg| arg == "--") {
-                // Everything after -- are per-call agent arguments
-                per_call_args.extend_from_slice(&args[separator_pos + 1..]);
-            } else {
-                // Invalid format - too many args without --
-                eprintln!(
-                    "Invalid usage. Use 'qwk {} -- <agent-args>' to pass arguments to the agent",
-                    shortcut
-                );
-                std::process::exit(1);
+#[derive(Debug, PartialEq)]
+pub enum ListItem<'a> {
+    Alias(&'a str),
+    /// A `group/` prefix and the full names of its members, in display order
+    Group(&'a str, Vec<&'a str>),
+}
+
+/// Groups namespaced aliases (`group/name`) by the prefix before the first slash. Each
+/// group is placed where its first member appears in `names`.
+pub fn group_alias_names<'a>(names: &[&'a str]) -> Vec<ListItem<'a>> {
+    let mut items: Vec<ListItem> = Vec::new();
+    for name in names {
+        match name.split_once('/') {
+            Some((group, _)) => {
+                let existing = items.iter_mut().find_map(|item| match item {
+                    ListItem::Group(existing, members) if *existing == group => Some(members),
+                    _ => None,
+                });
+                match existing {
+                    Some(members) => members.push(name),
+                    None => items.push(ListItem::Group(group, vec![name])),
+                }
             }
+            None => items.push(ListItem::Alias(name)),
         }
+    }
+    items
+}
+
+/// Closing line of `--list`, e.g. "12 shortcuts, agent: claude --model sonnet".
+pub fn format_list_summary(count: usize, agent: &str) -> String {
+    let noun = if count == 1 { "shortcut" } else { "shortcuts" };
+    format!("{} {}, agent: {}", count, noun, agent)
+}
+
+/// What `--list` shows, from its flags and `config.toml`.
+pub struct ListOptions<'a> {
+    pub sort: ListSort,
+    pub names_only: bool,
+    pub tags: &'a [String],
+    pub long: bool,
+    pub summary: bool,
+    pub archived: bool,
+    pub force_empty: bool,
+    pub preview_width: usize,
+}
+
+pub fn list_aliases(options: ListOptions) -> Result<(), QwkError> {
+    let ListOptions {
+        sort,
+        names_only,
+        tags,
+        long,
+        summary,
+        archived,
+        force_empty,
+        preview_width,
+    } = options;
 
-        // Build command: agent [default_args] [per_call_args] prompt
-        let mut cmd = Command::new(&agent_command);
-        for arg in &agent_default_args {
-            cmd.arg(arg);
+    let loaded = if archived {
+        load_archive().map(|aliases| EffectiveAliases {
+            aliases,
+            ..Default::default()
+        })
+    } else {
+        load_effective_aliases()
+    };
+    let EffectiveAliases { mut aliases, local } = match loaded {
+        Ok(aliases) => aliases,
+        Err(e) if force_empty => {
+            eprintln!("Warning: {}", e);
+            EffectiveAliases::default()
+        }
+        Err(e) => {
+            return Err(QwkError::new(format!(
+                "Error: {}\nPass --force-empty to list it as empty anyway.",
+                e
+            )));
         }
-        for arg in &per_call_args {
-            cmd.arg(arg);
+    };
+    aliases.retain(|_, entry| entry.has_tags(tags));
+    let print_summary = || {
+        // The agent has nothing to do with archived aliases, which can't be run
+        if summary && !archived {
+            println!();
+            println!("{}", format_list_summary(aliases.len(), &get_agent()));
+        }
+    };
+
+    if aliases.is_empty() {
+        if names_only {
+            // Nothing to print for scripts consuming the names
+            return Ok(());
+        } else if archived {
+            println!("No archived shortcuts.");
+        } else if tags.is_empty() {
+            println!("No shortcuts available.");
+        } else {
+            println!("No shortcuts tagged {}.", tags.join(", "));
         }
-        cmd.arg(prompt);
+        print_summary();
+        return Ok(());
+    }
 
-        let status = cmd.status();
+    let usage = if sort == ListSort::Name {
+        HashMap::new()
+    } else {
+        load_usage()
+    };
+    let mut names: Vec<_> = aliases.keys().collect();
+    sort_alias_names(&mut names, &usage, sort);
+    // Stable, so pinned aliases keep the requested order among themselves
+    names.sort_by_key(|name| !aliases[*name].pinned);
 
-        match status {
-            Ok(exit_status) => {
-                std::process::exit(exit_status.code().unwrap_or(0));
+    if names_only {
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        if io::stdout().is_terminal() {
+            let width = terminal_size::terminal_size().map_or(80, |(width, _)| width.0 as usize);
+            for line in layout_columns(&names, width) {
+                println!("{}", line);
             }
-            Err(e) => {
-                eprintln!("Error executing agent '{}': {}", agent_command, e);
-                std::process::exit(1);
+        } else {
+            for name in names {
+                println!("{}", name);
             }
         }
-    } else {
-        eprintln!("Shortcut '{}' not found", shortcut);
-        std::process::exit(1);
+        return Ok(());
     }
-}
 
-pub fn run() {
-    let args: Vec<String> = env::args().collect();
+    let print_alias = |indent: &str, label: &str, alias: &str| {
+        let entry = &aliases[alias];
+        // Prefer the human-written description over a slice of the prompt
+        let preview = truncate_prompt(
+            entry.description.as_ref().unwrap_or(&entry.prompt),
+            preview_width,
+        );
+        let scope = if local.contains(alias) {
+            " (local)"
+        } else {
+            ""
+        };
+        if entry.tags.is_empty() {
+            println!("{}{}{} - {}", indent, label, scope, preview);
+        } else {
+            println!(
+                "{}{}{} - {} [{}]",
+                indent,
+                label,
+                scope,
+                preview,
+                entry.tags.join(", ")
+            );
+        }
+        if long {
+            let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+                time.map_or("unknown".to_string(), format_local_time)
+            };
+            println!(
+                "{}    created {}, updated {}",
+                indent,
+                format_time(entry.created_at),
+                format_time(entry.updated_at)
+            );
+            if let Some(cwd) = &entry.cwd {
+                println!("{}    runs in {}", indent, cwd);
+            }
+            if entry.confirm {
+                println!("{}    asks before running", indent);
+            }
+            if let Some(hook) = &entry.pre_hook {
+                println!("{}    runs '{}' first", indent, hook);
+            }
+            if let Some(hook) = &entry.post_hook {
+                println!("{}    runs '{}' after", indent, hook);
+            }
+        }
+    };
 
-    // Handle first run setup (but not for completion calls)
-    if args.len() < 2 || !args[1].contains("complete") {
-        handle_first_run();
+    println!(
+        "{} shortcuts:",
+        if archived { "Archived" } else { "Available" }
+    );
+    let (pinned, names): (Vec<&str>, Vec<&str>) = names
+        .iter()
+        .map(|name| name.as_str())
+        .partition(|name| aliases[*name].pinned);
+    for alias in pinned {
+        print_alias("  ", &format!("* {}", alias), alias);
+    }
+    for item in group_alias_names(&names) {
+        match item {
+            ListItem::Alias(alias) => print_alias("  ", alias, alias),
+            ListItem::Group(group, members) => {
+                println!("  {}/", group);
+                for alias in members {
+                    print_alias("    ", &alias[group.len() + 1..], alias);
+                }
+            }
+        }
     }
+    print_summary();
+    Ok(())
+}
 
-    // Handle direct shortcut execution (qwk foo) or (qwk foo -- agent-args)
-    if args.len() >= 2 && !args[1].starts_with("--") {
-        let shortcut = &args[1];
-        execute_shortcut(shortcut, &args);
+/// Rewrites the profile's shortcuts files encrypted or in plain text, after switching the
+/// `encrypt` setting that `save_aliases` follows.
+pub fn set_encryption(enable: bool) -> Result<(), QwkError> {
+    let state = if enable { "on" } else { "off" };
+    if load_settings_or_fail()?.encrypt == enable {
+        status!("Encryption is already {}", state);
+        return Ok(());
     }
 
-    // Parse with clap for other commands
-    let cli = Cli::parse();
+    if enable && load_settings_or_fail()?.prompt_files {
+        return Err(QwkError::new(format!(
+            "Error: the prompts are kept in their own files, which can't be encrypted; set prompt_files = false in {} first",
+            get_profile_settings_file().display()
+        )));
+    }
 
-    match cli.command {
-        Some(Commands::Set { alias, prompt }) => {
-            let prompt_text = if let Some(p) = prompt {
-                p
-            } else {
-                match read_prompt_from_stdin() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!("Error reading prompt: {}", e);
-                        std::process::exit(1);
-                    }
-                }
-            };
+    let aliases = load_aliases_or_fail()?;
+    let archive = load_archive_or_fail()?;
+    let trash = load_trash()?;
+    if enable {
+        // Ask for the new passphrase before anything changes
+        if let Err(e) = crypto::passphrase(true) {
+            return Err(QwkError::new(format!("Error: {}", e)));
+        }
+    }
 
-            let mut aliases = load_aliases();
-            aliases.insert(alias.clone(), prompt_text);
+    if let Err(e) = set_profile_setting("encrypt", toml::Value::Boolean(enable)) {
+        return Err(QwkError::io("Error updating settings", e));
+    }
+    if let Err(e) = save_aliases(&aliases) {
+        return Err(QwkError::io("Error saving aliases", e));
+    }
+    if get_archive_file().exists()
+        && let Err(e) = save_archive(&archive)
+    {
+        return Err(QwkError::io("Error saving archive", e));
+    }
+    if get_trash_file().exists()
+        && let Err(e) = save_trash(&trash)
+    {
+        return Err(QwkError::io("Error saving the trash", e));
+    }
+    status!("Encryption is {}", state);
 
-            if let Err(e) = save_aliases(&aliases) {
-                eprintln!("Error saving alias: {}", e);
-                std::process::exit(1);
-            }
+    if enable {
+        let plain_backups = find_backups(&get_backup_dir())
+            .unwrap_or_default()
+            .iter()
+            .filter(|backup| !crypto::is_encrypted_file(&backup.path))
+            .count();
+        if plain_backups > 0 {
+            status!(
+                "{} earlier backups are still in plain text; `qwk --prune-backups --keep 0` removes them",
+                plain_backups
+            );
+        }
+    }
+    Ok(())
+}
 
-            println!("Alias '{}' set successfully", alias);
+pub fn show_history(alias: Option<&str>, limit: Option<usize>) -> Result<(), QwkError> {
+    let entries = load_history().map_err(|e| {
+        QwkError::new(format!(
+            "Error reading {}: {}",
+            get_history_file().display(),
+            e
+        ))
+    })?;
+    let profile = get_profile();
+    let selected = select_history(&entries, profile.as_deref(), alias, limit);
+    if selected.is_empty() {
+        match alias {
+            Some(alias) => println!("No changes to '{}' recorded.", alias),
+            None => println!("No changes recorded."),
         }
+        return Ok(());
+    }
 
-        Some(Commands::Agent { command }) => {
-            if let Err(e) = set_agent(&command) {
-                eprintln!("Error setting agent: {}", e);
-                std::process::exit(1);
-            }
+    let rows: Vec<Vec<String>> = selected
+        .iter()
+        .map(|entry| {
+            let details = match (&entry.prompt, &entry.source) {
+                (Some(prompt), _) => prompt.clone(),
+                (None, Some(source)) => source.clone(),
+                (None, None) => String::new(),
+            };
+            vec![
+                format_local_time(entry.at),
+                entry.operation.clone(),
+                entry.aliases.join(", "),
+                details,
+            ]
+        })
+        .collect();
+    for line in render_table(&["DATE", "OPERATION", "ALIASES", "DETAILS"], &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
 
-            println!("Agent set to '{}'", command);
+pub fn show_runs(alias: Option<&str>, limit: usize) -> Result<(), QwkError> {
+    let runs = load_runs().map_err(|e| {
+        QwkError::new(format!(
+            "Error reading {}: {}",
+            get_runs_file().display(),
+            e
+        ))
+    })?;
+    let selected = select_runs(&runs, alias, limit);
+    if selected.is_empty() {
+        match alias {
+            Some(alias) => println!("No runs of '{}' recorded.", alias),
+            None => println!("No runs recorded."),
         }
-
-        Some(Commands::List) => {
-            list_aliases();
+        if !load_settings_or_fail()?.track_runs {
+            println!("Runs aren't logged while track_runs is false.");
         }
+        return Ok(());
+    }
 
-        Some(Commands::Complete { partial }) => {
-            generate_completions(partial);
-        }
+    let rows: Vec<Vec<String>> = selected
+        .iter()
+        .map(|run| {
+            let args = shlex::try_join(run.args.iter().map(String::as_str))
+                .unwrap_or_else(|_| format!("{:?}", run.args));
+            vec![
+                format_local_time(run.at),
+                run.alias.clone(),
+                run.exit_code.to_string(),
+                format_duration_ms(run.duration_ms),
+                args,
+            ]
+        })
+        .collect();
+    for line in render_table(&["DATE", "ALIAS", "EXIT", "DURATION", "ARGS"], &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Runs `names` in turn with the same agent arguments, stopping at the first failure
+/// unless `keep_going`, and returns the first failure's code after summing up.
+pub fn run_chain(
+    names: &[String],
+    keep_going: bool,
+    agent_args: &[String],
+    verbose: Verbose,
+) -> Result<i32, QwkError> {
+    let aliases = load_effective_aliases_or_fail()?;
+    let unknown: Vec<&str> = names
+        .iter()
+        .filter(|name| !aliases.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(QwkError::AliasesNotFound(
+            unknown.into_iter().map(String::from).collect(),
+        ));
+    }
 
-        Some(Commands::SetupCompletion) => {
-            if let Err(e) = setup_completion_for_current_shell() {
-                eprintln!("Error setting up autocompletion: {}", e);
-                std::process::exit(1);
+    let mut codes = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        eprintln!("[{}/{}] {}", i + 1, names.len(), name);
+        let mut args = vec!["qwk".to_string(), name.clone()];
+        if !agent_args.is_empty() {
+            args.push("--".to_string());
+            args.extend(agent_args.iter().cloned());
+        }
+        // A shortcut that can't run counts as failing with its error's code, so the rest of
+        // the chain and the summary still follow; Ctrl-C stops the chain all the same
+        let (code, cancelled) = match run_shortcut(name, &args, verbose, false) {
+            Ok(code) => (code, false),
+            Err(e) => {
+                e.report();
+                (e.code(), matches!(e, QwkError::Cancelled))
             }
+        };
+        codes.push(code);
+        if cancelled || (code != 0 && !keep_going) {
+            break;
         }
+    }
 
-        Some(Commands::Remove { alias }) => {
-            let mut aliases = load_aliases();
+    let rows: Vec<Vec<String>> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let exit = codes
+                .get(i)
+                .map_or("skipped".to_string(), |code| code.to_string());
+            vec![name.clone(), exit]
+        })
+        .collect();
+    // On stderr, so that the agents' output can be piped on its own
+    for line in render_table(&["SHORTCUT", "EXIT"], &rows) {
+        eprintln!("{}", line);
+    }
+    let failed = codes.into_iter().find(|&code| code != 0);
+    Ok(failed.unwrap_or(0))
+}
 
-            if aliases.remove(&alias).is_some() {
-                if let Err(e) = save_aliases(&aliases) {
-                    eprintln!("Error saving aliases after removal: {}", e);
-                    std::process::exit(1);
-                }
-                println!("Shortcut '{}' removed successfully", alias);
-            } else {
-                println!("Shortcut '{}' does not exist", alias);
-            }
-        }
+/// Runs the shortcut of the last logged run again, with the same arguments after `--`.
+pub fn run_last(dry_run: bool, verbose: Verbose) -> Result<i32, QwkError> {
+    let runs = load_runs().map_err(|e| {
+        QwkError::new(format!(
+            "Error reading {}: {}",
+            get_runs_file().display(),
+            e
+        ))
+    })?;
+    let Some(last) = runs.last() else {
+        return Err(QwkError::new(
+            "No runs recorded yet, so there's nothing to run again.",
+        ));
+    };
 
-        Some(Commands::Reset) => {
-            if !confirm_reset() {
-                println!("Reset cancelled.");
-                return;
-            }
+    let mut args = vec!["qwk".to_string(), last.alias.clone()];
+    if dry_run {
+        args.push("--dry-run".to_string());
+    }
+    if !last.args.is_empty() {
+        args.push("--".to_string());
+        args.extend(last.args.iter().cloned());
+    }
+    let command =
+        shlex::try_join(args.iter().map(String::as_str)).unwrap_or_else(|_| format!("{:?}", args));
+    eprintln!("Running: {}", command);
+    run_shortcut(&last.alias, &args, verbose, true)
+}
 
-            match create_aliases_backup() {
-                Ok(Some(backup_path)) => {
-                    println!("Backup created: {}", backup_path);
-                }
-                Ok(None) => {
-                    println!("No existing aliases file to backup.");
-                }
-                Err(e) => {
-                    eprintln!("Error creating backup: {}", e);
-                    std::process::exit(1);
-                }
-            }
+/// Records the undo step for `command`, which is about to change the aliases file.
+fn record_undo_or_fail(command: &str) -> Result<(), QwkError> {
+    if let Err(e) = record_undo(command) {
+        return Err(QwkError::io("Error recording the undo step", e));
+    }
+    Ok(())
+}
 
-            let aliases_file = get_aliases_file();
-            if aliases_file.exists() {
-                if let Err(e) = fs::remove_file(&aliases_file) {
-                    eprintln!("Error removing aliases file: {}", e);
-                    std::process::exit(1);
-                }
-            }
+pub fn undo_last_command() -> Result<(), QwkError> {
+    let entry = load_undo_entry().map_err(|e| {
+        QwkError::new(format!(
+            "Error reading {}: {}",
+            get_undo_journal_file().display(),
+            e
+        ))
+    })?;
+    let Some(entry) = entry else {
+        status!("Nothing to undo.");
+        return Ok(());
+    };
+    status!(
+        "Undoing: {} at {}",
+        entry.command,
+        format_local_time(entry.at)
+    );
 
-            println!("All shortcuts have been reset.");
-        }
+    let result = if entry.had_aliases {
+        let aliases = load_aliases_from(&get_undo_snapshot_file())?;
+        save_aliases(&aliases).map(|()| aliases.len())
+    } else {
+        // There were no shortcuts at all before
+        fs::remove_file(get_aliases_file())
+            .or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+            .map(|()| 0)
+    };
+    let count = result.map_err(|e| QwkError::io("Error restoring shortcuts", e))?;
+    if let Err(e) = clear_undo() {
+        eprintln!("Warning: could not remove the undo step: {}", e);
+    }
+    record_history(HistoryEntry::new("undo", vec![]).with_source(entry.command));
+    status!(
+        "Restored {} {}",
+        count,
+        if count == 1 { "shortcut" } else { "shortcuts" }
+    );
+    Ok(())
+}
 
-        None => {
-            if let Some(shortcut) = cli.shortcut {
-                // This case is handled above, but included for completeness
-                eprintln!("Shortcut '{}' not found", shortcut);
-                std::process::exit(1);
-            } else {
-                // Show help if no command provided
-                let mut cmd = Cli::command();
-                cmd.print_help().unwrap();
+pub fn migrate_to_prompt_files() -> Result<(), QwkError> {
+    let settings = load_settings_or_fail()?;
+    if settings.encrypt {
+        return Err(QwkError::new(
+            "Error: encrypted shortcuts can't keep their prompts in separate files; turn encryption off with `qwk --encrypt off` first",
+        ));
+    }
+    let aliases_file = get_aliases_file();
+    let prompts_dir = aliases_file.with_file_name(PROMPTS_DIR);
+    if settings.prompt_files {
+        status!("The prompts are already kept in {}", prompts_dir.display());
+        return Ok(());
+    }
+
+    let aliases = load_aliases_or_fail()?;
+    if let Err(e) = create_aliases_backup() {
+        return Err(QwkError::io("Error creating backup", e));
+    }
+    if let Err(e) = set_profile_setting("prompt_files", toml::Value::Boolean(true)) {
+        return Err(QwkError::io("Error updating settings", e));
+    }
+    if let Err(e) = save_aliases(&aliases) {
+        return Err(QwkError::io("Error saving aliases", e));
+    }
+    status!(
+        "Moved {} prompts to {}",
+        aliases.len(),
+        prompts_dir.display()
+    );
+    Ok(())
+}
+
+pub fn sync_config(pull: bool, push: bool) -> Result<(), QwkError> {
+    let Some(dir) = sync::config_repo_dir() else {
+        return Err(QwkError::new(format!(
+            "{} is not in a git repository; run `git init` there to use --sync",
+            get_config_dir().display()
+        )));
+    };
+    let git_error = |e: GitError| QwkError::new(format!("Error: {}", e));
+
+    match sync::commit_changes(&dir).map_err(git_error)? {
+        Some(message) => status!("Committed: {}", message),
+        None => status!("Nothing to commit"),
+    }
+    if pull {
+        sync::pull(&dir).map_err(git_error)?;
+    }
+    if push {
+        sync::push(&dir).map_err(git_error)?;
+    }
+    Ok(())
+}
+
+/// Commits after a command that changed the shortcuts, when `auto_commit` is on. The
+/// command itself has succeeded by then, so failing to commit is only a warning.
+fn auto_commit() {
+    if !load_settings().is_ok_and(|settings| settings.auto_commit) {
+        return;
+    }
+    let Some(dir) = sync::config_repo_dir() else {
+        eprintln!(
+            "Warning: auto_commit is on, but {} is not in a git repository",
+            get_config_dir().display()
+        );
+        return;
+    };
+    match sync::commit_changes(&dir) {
+        Ok(Some(message)) => status!("Committed: {}", message),
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: {}", e),
+    }
+}
+
+/// The backups of the active profile, including the `backup_on_write` snapshots, newest
+/// first.
+fn find_backups_or_fail() -> Result<Vec<Backup>, QwkError> {
+    let mut backups = Vec::new();
+    for dir in [get_backup_dir(), get_auto_backup_dir()] {
+        match find_backups(&dir) {
+            Ok(found) => backups.extend(found),
+            Err(e) => {
+                return Err(QwkError::io("Error finding backups", e));
             }
         }
     }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken_at));
+    Ok(backups)
+}
+
+/// A backup's path relative to the backup directory, e.g. `backups/auto/aliases_backup_…`.
+fn backup_name(backup: &Backup) -> String {
+    let dir = get_backup_dir();
+    backup
+        .path
+        .strip_prefix(&dir)
+        .unwrap_or(&backup.path)
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn list_backups(format: ListFormat) -> Result<(), QwkError> {
+    let backups = find_backups_or_fail()?;
+    // A backup that vanished or can't be read shows as empty and corrupted. Encrypted
+    // ones aren't counted, as that would mean asking for the passphrase.
+    let details: Vec<_> = backups
+        .iter()
+        .map(|backup| {
+            let size = std::fs::metadata(&backup.path).map_or(0, |metadata| metadata.len());
+            let encrypted = crypto::is_encrypted_file(&backup.path);
+            let count = if encrypted {
+                None
+            } else {
+                count_aliases_in(&backup.path)
+            };
+            (backup, size, encrypted, count)
+        })
+        .collect();
+
+    if format == ListFormat::Json {
+        let backups: Vec<_> = details
+            .into_iter()
+            .enumerate()
+            .map(|(i, (backup, size, encrypted, aliases))| {
+                serde_json::json!({
+                    "index": i + 1,
+                    "name": backup_name(backup),
+                    "path": backup.path,
+                    "created_at": backup.taken_at,
+                    "size": size,
+                    "aliases": aliases,
+                    "encrypted": encrypted,
+                    "corrupted": aliases.is_none() && !encrypted,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&backups).unwrap());
+        return Ok(());
+    }
+
+    if details.is_empty() {
+        println!("No backups.");
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now();
+    let rows: Vec<Vec<String>> = details
+        .into_iter()
+        .enumerate()
+        .map(|(i, (backup, size, encrypted, aliases))| {
+            let aliases = match aliases {
+                Some(count) => count.to_string(),
+                None if encrypted => "encrypted".to_string(),
+                None => "corrupted".to_string(),
+            };
+            vec![
+                (i + 1).to_string(),
+                backup_name(backup),
+                format_local_time(backup.taken_at),
+                format_age(backup.taken_at, now),
+                format_size(size),
+                aliases,
+            ]
+        })
+        .collect();
+    for line in render_table(&["#", "NAME", "DATE", "AGE", "SIZE", "SHORTCUTS"], &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+pub fn restore_backup(selector: Option<&str>, yes: bool) -> Result<(), QwkError> {
+    let backups = find_backups_or_fail()?;
+    let backup = select_backup(&backups, selector).map_err(|e| QwkError::new(e.to_string()))?;
+    let name = backup_name(backup);
+
+    // Loaded before backing up the current shortcuts, whose backup may get the same name
+    // within the same second
+    let restored = load_aliases_from(&backup.path).map_err(|e| {
+        QwkError::new(format!(
+            "Error: {}\nRefusing to restore a backup that can't be loaded.",
+            e
+        ))
+    })?;
+
+    let question = format!(
+        "This will replace the current shortcuts with the {} in {} from {} (a backup will be created). Are you sure?",
+        restored.len(),
+        name,
+        format_age(backup.taken_at, chrono::Utc::now())
+    );
+    if !yes && load_settings_or_fail()?.confirm_destructive && !confirm(&question) {
+        eprintln!("Restore cancelled.");
+        return Ok(());
+    }
+
+    match create_aliases_backup() {
+        Ok(Some(backup_path)) => status!("Backup created: {}", backup_path),
+        Ok(None) => {}
+        Err(e) => {
+            return Err(QwkError::io("Error creating backup", e));
+        }
+    }
+    record_undo_or_fail(&format!("restore {}", name))?;
+    if let Err(e) = save_aliases(&restored) {
+        return Err(QwkError::io("Error restoring shortcuts", e));
+    }
+    let noun = if restored.len() == 1 {
+        "shortcut"
+    } else {
+        "shortcuts"
+    };
+    record_history(HistoryEntry::new("restore", vec![]).with_source(name.clone()));
+    status!("Restored {} {} from {}", restored.len(), noun, name);
+    Ok(())
+}
+
+pub fn prune_backups(keep: Option<usize>, dry_run: bool) -> Result<(), QwkError> {
+    let keep = match keep {
+        Some(keep) => keep,
+        None => load_settings_or_fail()?.keep_backups,
+    };
+    let plan = plan_prune_backups(&get_backup_dir(), keep)
+        .map_err(|e| QwkError::io("Error finding backups", e))?;
+
+    if dry_run {
+        print_dry_run(&plan);
+        return Ok(());
+    }
+    if plan.is_empty() {
+        status!("No backups to prune.");
+        return Ok(());
+    }
+
+    for action in &plan {
+        if let Err(e) = apply_plan(std::slice::from_ref(action)) {
+            return Err(QwkError::io("Error pruning backups", e));
+        }
+        if let PlannedAction::Remove { path, .. } = action {
+            status!("Removed {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+pub fn archive_alias(alias: &str, force: bool) -> Result<(), QwkError> {
+    let mut aliases = load_aliases_or_fail()?;
+    let mut archive = load_archive_or_fail()?;
+    match move_alias(&mut aliases, &mut archive, alias, force) {
+        Ok(()) => {}
+        Err(MoveAliasError::NotFound) => {
+            return Err(QwkError::alias_not_found(alias));
+        }
+        Err(MoveAliasError::Exists) => {
+            return Err(QwkError::new(format!(
+                "Shortcut '{}' is already archived; use --force to replace the archived copy",
+                alias
+            )));
+        }
+    }
+
+    record_undo_or_fail(&format!("archive '{}'", alias))?;
+    // Archive first so a failure in between leaves a copy rather than nothing
+    if let Err(e) = save_archive(&archive).and_then(|()| save_aliases(&aliases)) {
+        return Err(QwkError::io("Error archiving shortcut", e));
+    }
+    record_history(HistoryEntry::new("archive", vec![alias.to_string()]));
+    status!("Shortcut '{}' archived", alias);
+    Ok(())
+}
+
+pub fn unarchive_alias(alias: &str, force: bool) -> Result<(), QwkError> {
+    let mut aliases = load_aliases_or_fail()?;
+    let mut archive = load_archive_or_fail()?;
+    match move_alias(&mut archive, &mut aliases, alias, force) {
+        Ok(()) => {}
+        Err(MoveAliasError::NotFound) => {
+            return Err(QwkError::new(format!(
+                "Shortcut '{}' is not archived",
+                alias
+            )));
+        }
+        Err(MoveAliasError::Exists) => {
+            return Err(QwkError::AliasExists(alias.to_string()));
+        }
+    }
+
+    record_undo_or_fail(&format!("unarchive '{}'", alias))?;
+    if let Err(e) = save_aliases(&aliases).and_then(|()| save_archive(&archive)) {
+        return Err(QwkError::io("Error restoring shortcut", e));
+    }
+    record_history(HistoryEntry::new("unarchive", vec![alias.to_string()]));
+    status!("Shortcut '{}' restored from the archive", alias);
+    Ok(())
+}
+
+pub fn list_trash() -> Result<(), QwkError> {
+    let trash = load_trash()?;
+    if trash.is_empty() {
+        println!("The trash is empty.");
+        return Ok(());
+    }
+    let now = chrono::Utc::now();
+    let rows: Vec<Vec<String>> = trash
+        .iter()
+        .rev()
+        .map(|trashed| {
+            vec![
+                trashed.name.clone(),
+                format_age(trashed.deleted_at, now),
+                truncate_prompt(&trashed.entry.prompt, 50),
+            ]
+        })
+        .collect();
+    for line in render_table(&["NAME", "REMOVED", "PROMPT"], &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+pub fn restore_removed_alias(alias: &str, force: bool) -> Result<(), QwkError> {
+    let mut aliases = load_aliases_or_fail()?;
+    let mut trash = load_trash()?;
+    match restore_removed(&mut aliases, &mut trash, alias, force) {
+        Ok(()) => {}
+        Err(MoveAliasError::NotFound) => {
+            return Err(QwkError::new(format!(
+                "Shortcut '{}' is not in the trash (see qwk --trash)",
+                alias
+            )));
+        }
+        Err(MoveAliasError::Exists) => {
+            return Err(QwkError::AliasExists(alias.to_string()));
+        }
+    }
+
+    record_undo_or_fail(&format!("restore-removed '{}'", alias))?;
+    // Restored first so a failure in between leaves a copy rather than nothing
+    if let Err(e) = save_aliases(&aliases).and_then(|()| save_trash(&trash)) {
+        return Err(QwkError::io("Error restoring shortcut", e));
+    }
+    record_history(HistoryEntry::new(
+        "restore-removed",
+        vec![alias.to_string()],
+    ));
+    status!("Shortcut '{}' restored from the trash", alias);
+    Ok(())
+}
+
+pub fn set_pinned(alias: &str, pinned: bool) -> Result<(), QwkError> {
+    let mut aliases = load_aliases_or_fail()?;
+    let Some(entry) = aliases.get_mut(alias) else {
+        return Err(QwkError::alias_not_found(alias));
+    };
+
+    let state = if pinned { "pinned" } else { "unpinned" };
+    if entry.pinned == pinned {
+        status!("Shortcut '{}' is already {}", alias, state);
+        return Ok(());
+    }
+
+    entry.pinned = pinned;
+    record_undo_or_fail(&format!(
+        "{} '{}'",
+        if pinned { "pin" } else { "unpin" },
+        alias
+    ))?;
+    if let Err(e) = save_aliases(&aliases) {
+        return Err(QwkError::io("Error saving aliases", e));
+    }
+    let operation = if pinned { "pin" } else { "unpin" };
+    record_history(HistoryEntry::new(operation, vec![alias.to_string()]));
+    status!("Shortcut '{}' {}", alias, state);
+    Ok(())
+}
+
+pub fn show_stats(json: bool) -> Result<(), QwkError> {
+    let aliases = load_aliases_or_fail()?;
+    let names: Vec<&String> = aliases.keys().collect();
+    let stats = usage_stats(&names, &load_usage());
+
+    if json {
+        let stats: Vec<_> = stats
+            .into_iter()
+            .map(|(alias, record)| {
+                serde_json::json!({
+                    "alias": alias,
+                    "count": record.count,
+                    "last_used": record.last_used,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return Ok(());
+    }
+
+    if stats.is_empty() {
+        println!("No shortcuts available.");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = stats
+        .into_iter()
+        .map(|(alias, record)| {
+            let last_used = record
+                .last_used
+                .map_or("never".to_string(), format_local_time);
+            vec![alias, record.count.to_string(), last_used]
+        })
+        .collect();
+    for line in render_table(&["ALIAS", "RUNS", "LAST RUN"], &rows) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Prompts by alias name, for resolving `{{alias:name}}`.
+fn alias_prompts(aliases: &HashMap<String, AliasEntry>) -> HashMap<String, String> {
+    aliases
+        .iter()
+        .map(|(name, entry)| (name.clone(), entry.prompt.clone()))
+        .collect()
+}
+
+pub fn show_alias(alias: &str, vars_only: bool, resolved: bool) -> Result<(), QwkError> {
+    let aliases = load_effective_aliases_or_fail()?;
+    let Some(entry) = aliases.get(alias) else {
+        let matches = closest_matches(alias, aliases.keys().map(String::as_str));
+        return Err(QwkError::AliasNotFound {
+            name: alias.to_string(),
+            suggestions: matches.into_iter().map(String::from).collect(),
+        });
+    };
+
+    if resolved {
+        let context = TemplateContext {
+            aliases: alias_prompts(&aliases),
+            aliases_only: true,
+            ..Default::default()
+        };
+        match render_alias(alias, &context) {
+            Ok(prompt) => println!("{}", prompt),
+            Err(e) => {
+                return Err(QwkError::new(format!(
+                    "Error: shortcut '{}' has {}.",
+                    alias, e
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    if !vars_only {
+        println!("{}", entry.prompt);
+        return Ok(());
+    }
+
+    let vars = template_vars(&entry.prompt);
+    if vars.is_empty() {
+        println!("Shortcut '{}' has no template variables.", alias);
+        return Ok(());
+    }
+
+    println!("Variables:");
+    for var in vars {
+        match var.default {
+            Some(default) => println!("  {} (default: {:?})", var.name, default),
+            None => println!("  {}", var.name),
+        }
+    }
+    Ok(())
+}
+
+pub fn list_tags() -> Result<(), QwkError> {
+    let aliases = load_aliases_or_fail()?;
+    let counts = count_tags(&aliases);
+
+    if counts.is_empty() {
+        println!("No tags defined.");
+        return Ok(());
+    }
+
+    println!("Tags:");
+    for (tag, count) in counts {
+        println!("  {} ({})", tag, count);
+    }
+    Ok(())
+}
+
+/// How `--import` reads its file and merges it into the current shortcuts.
+pub struct ImportOptions {
+    /// Guessed from the file extension when not given
+    pub format: Option<TransferFormat>,
+    pub strategy: ImportStrategy,
+    pub include_agent: bool,
+    pub dry_run: bool,
+    pub prefix: Option<String>,
+}
+
+/// Exit code of `--import --dry-run` when the import wouldn't change anything.
+pub const IMPORT_NO_CHANGES_EXIT_CODE: i32 = 3;
+
+pub fn import_aliases(path: Option<PathBuf>, options: ImportOptions) -> Result<i32, QwkError> {
+    let ImportOptions {
+        format,
+        strategy,
+        include_agent,
+        dry_run,
+        prefix,
+    } = options;
+
+    if strategy == ImportStrategy::Prompt && !dry_run && !io::stdin().is_terminal() {
+        return Err(QwkError::new(
+            "--strategy prompt needs a terminal to ask on; use skip or overwrite instead",
+        ));
+    }
+
+    let format = format.or_else(|| {
+        path.as_deref()
+            .map(|path| match DocumentFormat::from_path(path) {
+                DocumentFormat::Json => TransferFormat::Json,
+                DocumentFormat::Yaml => TransferFormat::Yaml,
+                DocumentFormat::Toml => TransferFormat::Toml,
+                DocumentFormat::Markdown => TransferFormat::Markdown,
+            })
+    });
+    let Some(format) = format else {
+        return Err(QwkError::new(
+            "--import needs a file to import from, or --format llm",
+        ));
+    };
+
+    let (path, mut entries, agent) = match format.document_format() {
+        Some(document_format) => {
+            let Some(path) = path else {
+                return Err(QwkError::new("--import needs a file to import from"));
+            };
+            let prefix = prefix.unwrap_or_default();
+            let document = read_document_or_fail(&path, document_format)?;
+            let entries = document
+                .aliases
+                .into_iter()
+                .map(|(alias, entry)| (format!("{}{}", prefix, alias), entry))
+                .collect();
+            (path, entries, document.agent)
+        }
+        None => {
+            if include_agent {
+                return Err(QwkError::new(
+                    "--include-agent can't be used with --format llm",
+                ));
+            }
+            let path = path.unwrap_or_else(default_templates_dir);
+            let prefix = prefix.as_deref().unwrap_or(LLM_PREFIX);
+            let ImportedTemplates { entries, warnings } = match import_templates(&path, prefix) {
+                Ok(result) => result,
+                Err(e) => {
+                    return Err(QwkError::new(format!(
+                        "Error reading {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            };
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            if entries.is_empty() {
+                status!("No templates found in {}", path.display());
+                if dry_run {
+                    return Ok(IMPORT_NO_CHANGES_EXIT_CODE);
+                }
+                return Ok(0);
+            }
+            (path, entries, None)
+        }
+    };
+
+    let agent = if include_agent {
+        if agent.is_none() {
+            eprintln!("Warning: {} has no agent to import", path.display());
+        }
+        agent.filter(|agent| *agent != get_agent())
+    } else {
+        None
+    };
+
+    let mut aliases = load_aliases_or_fail()?;
+    if format == TransferFormat::Markdown {
+        // Markdown only holds the prompts, so existing aliases keep their other fields
+        entries = entries
+            .into_iter()
+            .map(|(alias, entry)| match aliases.get(&alias) {
+                Some(existing) => {
+                    let entry = AliasEntry {
+                        prompt: entry.prompt,
+                        ..existing.clone()
+                    };
+                    (alias, entry)
+                }
+                None => (alias, entry),
+            })
+            .collect();
+    }
+    if dry_run {
+        if !print_import_preview(&aliases, &entries, strategy, agent.as_deref()) {
+            return Ok(IMPORT_NO_CHANGES_EXIT_CODE);
+        }
+        return Ok(0);
+    }
+
+    let before = aliases.clone();
+    let summary = merge_imported(
+        &mut aliases,
+        entries,
+        |alias, existing, imported| match strategy {
+            ImportStrategy::Skip => false,
+            ImportStrategy::Overwrite => true,
+            ImportStrategy::Prompt => {
+                eprintln!("'{}' already exists:", alias);
+                eprintln!("  current:  {}", truncate_prompt(&existing.prompt, 60));
+                eprintln!("  imported: {}", truncate_prompt(&imported.prompt, 60));
+                confirm("Overwrite it?")
+            }
+        },
+    );
+
+    if summary.changed() || agent.is_some() {
+        match create_aliases_backup() {
+            Ok(Some(backup_path)) => {
+                status!("Backup created: {}", backup_path);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return Err(QwkError::io("Error creating backup", e));
+            }
+        }
+    }
+
+    if summary.changed() {
+        record_undo_or_fail(&format!("import from {}", path.display()))?;
+    }
+    if summary.changed()
+        && let Err(e) = save_aliases(&aliases)
+    {
+        return Err(QwkError::io("Error saving aliases", e));
+    }
+
+    if let Some(agent) = agent {
+        if let Err(e) = set_agent(&agent) {
+            return Err(QwkError::io("Error setting agent", e));
+        }
+        status!("Agent set to '{}'", agent);
+    }
+
+    if summary.changed() {
+        let mut changed: Vec<String> = aliases
+            .iter()
+            .filter(|(name, entry)| {
+                before
+                    .get(*name)
+                    .is_none_or(|previous| !previous.same_content(entry))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        changed.sort();
+        record_history(
+            HistoryEntry::new("import", changed).with_source(path.display().to_string()),
+        );
+    }
+    status!(
+        "Imported from {}: {} added, {} overwritten, {} skipped",
+        path.display(),
+        summary.added,
+        summary.overwritten,
+        summary.skipped
+    );
+    Ok(0)
+}
+
+/// Prints what an import would add, change and leave alone. Returns whether it would
+/// change anything.
+fn print_import_preview(
+    aliases: &HashMap<String, AliasEntry>,
+    entries: &[(String, AliasEntry)],
+    strategy: ImportStrategy,
+    agent: Option<&str>,
+) -> bool {
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut identical = Vec::new();
+    for (alias, entry) in entries {
+        match classify_imported(aliases, alias, entry) {
+            ImportChange::Added => added.push((alias, entry)),
+            ImportChange::Changed => changed.push((alias, entry)),
+            ImportChange::Identical => identical.push(alias),
+        }
+    }
+
+    println!("Dry run, nothing will be changed:");
+    if !added.is_empty() {
+        println!("Would add ({}):", added.len());
+        for (alias, entry) in &added {
+            println!("  + {}: {}", alias, truncate_prompt(&entry.prompt, 60));
+        }
+    }
+    if !changed.is_empty() {
+        let heading = match strategy {
+            ImportStrategy::Skip => "Differ, would be kept by --strategy skip",
+            ImportStrategy::Overwrite => "Would overwrite",
+            ImportStrategy::Prompt => "Would ask about",
+        };
+        println!("{} ({}):", heading, changed.len());
+        for (alias, entry) in &changed {
+            let before = &aliases[alias.as_str()].prompt;
+            if *before == entry.prompt {
+                println!("  ~ {} (prompt unchanged)", alias);
+            } else {
+                println!("  ~ {}", alias);
+                println!("      before: {}", truncate_prompt(before, 60));
+                println!("      after:  {}", truncate_prompt(&entry.prompt, 60));
+            }
+        }
+    }
+    if !identical.is_empty() {
+        println!("Identical ({}):", identical.len());
+        for alias in &identical {
+            println!("  = {}", alias);
+        }
+    }
+    if let Some(agent) = agent {
+        println!("Would set the agent: {} -> {}", get_agent(), agent);
+    }
+
+    let changes = !added.is_empty()
+        || (!changed.is_empty() && strategy != ImportStrategy::Skip)
+        || agent.is_some();
+    if !changes {
+        println!("Nothing to import.");
+    }
+    changes
+}
+
+fn read_document_or_fail(path: &Path, format: DocumentFormat) -> Result<ExportDocument, QwkError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| QwkError::new(format!("Error reading {}: {}", path.display(), e)))?;
+    let mut document = parse_document(&content, format)
+        .map_err(|e| QwkError::new(format!("Error parsing {}: {}", path.display(), e)))?;
+    // An aliases.json that keeps its prompts in files brings them along
+    let mut aliases: HashMap<_, _> = std::mem::take(&mut document.aliases).into_iter().collect();
+    resolve_prompt_files(path.parent().unwrap_or(Path::new("")), &mut aliases);
+    document.aliases = aliases.into_iter().collect();
+    Ok(document)
+}
+
+/// Writes the shortcuts and agent as one document to `path`, or to stdout without one. The
+/// llm format instead writes a template per alias into the directory `path`.
+pub fn export_aliases(
+    path: Option<PathBuf>,
+    format: TransferFormat,
+    prefix: Option<String>,
+) -> Result<(), QwkError> {
+    let aliases = load_aliases_or_fail()?;
+
+    let Some(document_format) = format.document_format() else {
+        let Some(path) = path else {
+            return Err(QwkError::new(
+                "--export --format llm needs a directory to write the templates to",
+            ));
+        };
+        let prefix = prefix.as_deref().unwrap_or(LLM_PREFIX);
+        match export_templates(&aliases, &path, prefix) {
+            Ok(written) => status!("Exported {} templates to {}", written.len(), path.display()),
+            Err(e) => {
+                return Err(QwkError::new(format!("Error exporting: {}", e)));
+            }
+        }
+        return Ok(());
+    };
+
+    let document = ExportDocument::new(&aliases, Some(get_agent()));
+    let content = render_document(&document, document_format)
+        .map_err(|e| QwkError::new(format!("Error exporting: {}", e)))?;
+
+    match path {
+        Some(path) => {
+            if let Err(e) = fs::write(&path, content) {
+                return Err(QwkError::new(format!(
+                    "Error writing {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+            status!("Exported {} shortcuts to {}", aliases.len(), path.display());
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Reads stdin for `--set`, failing with code 130 on Ctrl-C like a shell would. On a terminal
+/// the user gets instructions instead of a silently blocking read.
+fn read_stdin_prompt_or_fail() -> Result<String, QwkError> {
+    if io::stdin().is_terminal() {
+        return read_prompt_interactive(io::stdin().lock(), io::stderr(), true)
+            .map_err(|e| QwkError::io("Error reading prompt", e));
+    }
+
+    read_prompt_from_stdin(load_settings_or_fail()?.max_prompt_bytes)
+}
+
+/// Takes the day's backup on the first run of the day. Failing only warns, as it's nothing
+/// the user asked for.
+fn take_daily_backup() {
+    let today = chrono::Local::now().date_naive();
+    if !daily_backup_due(today) {
+        return;
+    }
+    // A broken config.toml is reported by the command itself
+    let Ok(settings) = load_settings() else {
+        return;
+    };
+    if let Err(e) = create_daily_backup(today, settings.daily_backups) {
+        eprintln!("Warning: could not take the daily backup: {}", e);
+    }
+}
+
+fn load_settings_or_fail() -> Result<Settings, QwkError> {
+    load_settings().map_err(|e| QwkError::new(format!("Error loading settings: {}", e)))
+}
+
+fn load_aliases_or_fail() -> Result<HashMap<String, AliasEntry>, QwkError> {
+    Ok(load_aliases()?)
+}
+
+fn load_archive_or_fail() -> Result<HashMap<String, AliasEntry>, QwkError> {
+    Ok(load_archive()?)
+}
+
+/// The aliases that can be run here, including those of the project's `.qwk.json`.
+fn load_effective_aliases_or_fail() -> Result<HashMap<String, AliasEntry>, QwkError> {
+    Ok(load_effective_aliases()?.aliases)
+}
+
+/// Saves the `.qwk.json` at `path` as is, or the profile's shortcuts, which may be encrypted.
+fn save_aliases_file(
+    path: &Path,
+    aliases: &HashMap<String, AliasEntry>,
+    local: bool,
+) -> io::Result<()> {
+    if local {
+        save_aliases_to(path, aliases)
+    } else {
+        save_aliases(aliases)
+    }
+}
+
+/// The `.qwk.json` that `--local` edits: the nearest one, or with `create` a new one in
+/// the current directory.
+fn local_aliases_file_or_fail(create: bool) -> Result<PathBuf, QwkError> {
+    if !local_aliases_enabled() {
+        return Err(QwkError::new(
+            "--local can't be used with --no-local or QWK_NO_LOCAL",
+        ));
+    }
+    if let Some(path) = get_local_aliases_file() {
+        return Ok(path);
+    }
+    if !create {
+        return Err(QwkError::new(format!(
+            "No {} found in this directory or its parents",
+            LOCAL_ALIASES_FILE
+        )));
+    }
+    let dir =
+        env::current_dir().map_err(|e| QwkError::io("Error reading the current directory", e))?;
+    Ok(dir.join(LOCAL_ALIASES_FILE))
+}
+
+/// Reads the entries of `--set --batch` untrimmed, so that the line numbers of errors are
+/// those of the input. On a terminal only Ctrl-D ends them, as a lone `.` may be part of a
+/// prompt.
+fn read_batch_input_or_fail() -> Result<String, QwkError> {
+    if io::stdin().is_terminal() {
+        eprintln!(
+            "Enter the shortcuts, each a line '@name' followed by its prompt; finish with Ctrl-D:"
+        );
+    }
+    read_text_from_stdin(load_settings_or_fail()?.max_prompt_bytes)
+}
+
+pub fn set_aliases_batch() -> Result<(), QwkError> {
+    let input = read_batch_input_or_fail()?;
+
+    let entries = match parse_batch_prompts(&input) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(QwkError::new(format!("Invalid batch input: {}", e)));
+        }
+    };
+    let max_prompt_bytes = load_settings_or_fail()?.max_prompt_bytes;
+    for (alias, prompt) in &entries {
+        if let Err(e) = check_prompt_size(prompt, max_prompt_bytes) {
+            return Err(QwkError::new(format!("Error in '{}': {}", alias, e)));
+        }
+    }
+
+    match create_aliases_backup() {
+        Ok(Some(backup_path)) => {
+            status!("Backup created: {}", backup_path);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return Err(QwkError::io("Error creating backup", e));
+        }
+    }
+
+    let mut aliases = load_aliases_or_fail()?;
+    let mut created = 0;
+    let mut updated = 0;
+    let mut names: Vec<String> = entries.iter().map(|(alias, _)| alias.clone()).collect();
+    names.sort();
+    names.dedup();
+    for (alias, prompt) in entries {
+        match aliases.get_mut(&alias) {
+            Some(entry) => {
+                entry.prompt = prompt;
+                entry.touch();
+                updated += 1;
+            }
+            None => {
+                let mut entry = AliasEntry::new(prompt);
+                entry.touch();
+                aliases.insert(alias, entry);
+                created += 1;
+            }
+        }
+    }
+
+    record_undo_or_fail("set --batch")?;
+    if let Err(e) = save_aliases(&aliases) {
+        return Err(QwkError::io("Error saving aliases", e));
+    }
+
+    record_history(HistoryEntry::new("set", names));
+    status!("{} aliases created, {} updated", created, updated);
+    Ok(())
+}
+
+/// Why the agent program `program` can't be run, if it can't be found, with a guess at
+/// what was meant.
+fn missing_agent_message(program: &str) -> Option<String> {
+    if find_executable(program).is_some() {
+        return None;
+    }
+    if program.contains('/') || program.contains(std::path::MAIN_SEPARATOR) {
+        return Some(format!("agent '{}' not found or not executable", program));
+    }
+    Some(
+        match suggest_executable(program, env::var_os("PATH").as_deref()) {
+            Some(name) => format!(
+                "agent '{}' not found in PATH (did you mean '{}'?)",
+                program, name
+            ),
+            None => format!("agent '{}' not found in PATH", program),
+        },
+    )
+}
+
+/// Warns when the program of `agent_str` isn't on PATH, or with `strict` refuses it.
+fn check_agent_program(agent_str: &str, strict: bool) -> Result<(), QwkError> {
+    let (program, _) = parse_agent_command(agent_str);
+    let Some(message) = missing_agent_message(&program) else {
+        return Ok(());
+    };
+    if strict {
+        return Err(QwkError::new(format!("Error: {}", message)));
+    }
+    eprintln!(
+        "Warning: {}. Shortcuts will fail until it's installed (--force skips this check)",
+        message
+    );
+    Ok(())
+}
+
+/// Warns (without refusing) when a newly configured agent carries dangerous default args.
+pub fn warn_if_dangerous_agent(agent_str: &str) {
+    let patterns = match load_settings().and_then(|settings| dangerous_arg_patterns(&settings)) {
+        Ok(patterns) => patterns,
+        Err(e) => {
+            eprintln!("Warning: could not check agent arguments: {}", e);
+            return;
+        }
+    };
+
+    let (_, agent_default_args) = parse_agent_command(agent_str);
+    warn_dangerous_args(&find_dangerous_args(&agent_default_args, &patterns));
+}
+
+/// Arguments given after the shortcut name on the fast path.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShortcutArgs {
+    /// Template values from `--var key=value`
+    pub vars: HashMap<String, String>,
+    /// Values for `$1`..`$9` and `$@`
+    pub positional: Vec<String>,
+    /// `--allow-missing-env`
+    pub allow_missing_env: bool,
+    /// `--no-stdin`: don't read piped input into the prompt
+    pub no_stdin: bool,
+    /// `--with <name>`: the named agent to run this once with
+    pub with_agent: Option<String>,
+    /// `--stdin-prompt`: write the prompt to the agent's stdin this time
+    pub stdin_prompt: bool,
+    /// `--cwd <path>`: the directory to run the agent in, instead of the alias's own
+    pub cwd: Option<String>,
+    /// `--timeout <secs>`: how long the agent may run, instead of `agent_timeout_secs`
+    pub timeout_secs: Option<u64>,
+    /// `--output <path>`: the file to write the agent's stdout to
+    pub output: Option<String>,
+    /// `--append`: add to the `--output` file instead of replacing it
+    pub append: bool,
+    /// `--tee`: show the agent's stdout as well as writing it to the `--output` file
+    pub tee: bool,
+    /// `--dry-run`: print how the agent would run instead of running it
+    pub dry_run: bool,
+    /// `--yes`: run a shortcut set with `--confirm` without asking
+    pub yes: bool,
+    /// `--preview`: show the rendered prompt and the agent command, then ask before running
+    pub preview: bool,
+    /// `--notify`: send a desktop notification when a run longer than `notify_after_secs`
+    /// ends
+    pub notify: bool,
+    /// Everything after `--`, passed through to the agent
+    pub agent_args: Vec<String>,
+}
+
+fn parse_timeout(secs: &str) -> Result<u64, String> {
+    secs.parse()
+        .map_err(|_| format!("Invalid --timeout '{}': expected whole seconds", secs))
+}
+
+fn parse_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid --var '{}': expected key=value", value)),
+    }
+}
+
+/// Splits `qwk <shortcut> [<option> | <arg>]... [-- <agent-args>]`. `args` starts after
+/// the shortcut name. Before the separator, options are `--var key=value`,
+/// `--allow-missing-env`, `--no-stdin`, `--with <agent>`, `--stdin-prompt`,
+/// `--cwd <path>`, `--timeout <secs>`, `--output <path>` with `--append` or `--tee`,
+/// `--yes`, `--preview`, `--notify` and `--dry-run`; anything else not starting with `--` is a positional argument,
+/// which extends the prompt if it has no `$1`..`$9` or `$@`.
+pub fn parse_shortcut_args(shortcut: &str, args: &[String]) -> Result<ShortcutArgs, String> {
+    let mut parsed = ShortcutArgs::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let (key, value) = if arg == "--" {
+            parsed.agent_args.extend(iter.cloned());
+            break;
+        } else if arg == "--var" {
+            let value = iter
+                .next()
+                .ok_or_else(|| "--var requires a value of the form key=value".to_string())?;
+            parse_var(value)?
+        } else if let Some(value) = arg.strip_prefix("--var=") {
+            parse_var(value)?
+        } else if arg == "--allow-missing-env" {
+            parsed.allow_missing_env = true;
+            continue;
+        } else if arg == "--no-stdin" {
+            parsed.no_stdin = true;
+            continue;
+        } else if arg == "--stdin-prompt" {
+            parsed.stdin_prompt = true;
+            continue;
+        } else if arg == "--with" {
+            let name = iter
+                .next()
+                .ok_or_else(|| "--with requires the name of an agent".to_string())?;
+            parsed.with_agent = Some(name.clone());
+            continue;
+        } else if let Some(name) = arg.strip_prefix("--with=") {
+            parsed.with_agent = Some(name.to_string());
+            continue;
+        } else if arg == "--cwd" {
+            let path = iter
+                .next()
+                .ok_or_else(|| "--cwd requires a directory".to_string())?;
+            parsed.cwd = Some(path.clone());
+            continue;
+        } else if let Some(path) = arg.strip_prefix("--cwd=") {
+            parsed.cwd = Some(path.to_string());
+            continue;
+        } else if arg == "--dry-run" {
+            parsed.dry_run = true;
+            continue;
+        } else if arg == "--yes" {
+            parsed.yes = true;
+            continue;
+        } else if arg == "--preview" {
+            parsed.preview = true;
+            continue;
+        } else if arg == "--notify" {
+            parsed.notify = true;
+            continue;
+        } else if arg == "--timeout" {
+            let secs = iter
+                .next()
+                .ok_or_else(|| "--timeout requires a number of seconds".to_string())?;
+            parsed.timeout_secs = Some(parse_timeout(secs)?);
+            continue;
+        } else if let Some(secs) = arg.strip_prefix("--timeout=") {
+            parsed.timeout_secs = Some(parse_timeout(secs)?);
+            continue;
+        } else if arg == "--output" {
+            let path = iter
+                .next()
+                .ok_or_else(|| "--output requires a file".to_string())?;
+            parsed.output = Some(path.clone());
+            continue;
+        } else if let Some(path) = arg.strip_prefix("--output=") {
+            parsed.output = Some(path.to_string());
+            continue;
+        } else if arg == "--append" {
+            parsed.append = true;
+            continue;
+        } else if arg == "--tee" {
+            parsed.tee = true;
+            continue;
+        } else if arg.starts_with("--") {
+            return Err(format!(
+                "Invalid usage. Use 'qwk {} -- {}' to pass arguments to the agent",
+                shortcut, arg
+            ));
+        } else {
+            parsed.positional.push(arg.clone());
+            continue;
+        };
+        parsed.vars.insert(key, value);
+    }
+
+    if parsed.output.as_deref().is_some_and(str::is_empty) {
+        return Err("--output requires a file".to_string());
+    }
+    if (parsed.append || parsed.tee) && parsed.output.is_none() {
+        return Err("--append and --tee go with --output <file>".to_string());
+    }
+    Ok(parsed)
+}
+
+/// Reports what running a shortcut does for `--verbose`, on stderr so that the agent's
+/// stdout stays clean for piping.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Verbose(pub bool);
+
+impl Verbose {
+    fn log(self, message: fmt::Arguments) {
+        if self.0 {
+            eprintln!("qwk: {}", message);
+        }
+    }
+}
+
+/// Runs `shortcut` with `args`, given as they would be after `qwk <shortcut>`, and returns
+/// the exit code to pass on, or the error that kept the agent from running. For running
+/// shortcuts from other programs, since it doesn't look at the process's own arguments.
+pub fn run_alias(shortcut: &str, args: &[String], verbose: Verbose) -> Result<i32, QwkError> {
+    let _lock = RUN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut full_args = vec!["qwk".to_string(), shortcut.to_string()];
+    full_args.extend(args.iter().cloned());
+    run_shortcut(shortcut, &full_args, verbose, false)
+}
+
+/// The arguments of `qwk run <shortcut> ...` as the bare `qwk <shortcut> ...` takes them,
+/// or `None` for any other command line. A `--` before the name lets it start with a dash.
+pub fn explicit_run_args(args: &[String]) -> Option<Vec<String>> {
+    if !matches!(args.get(1).map(String::as_str), Some("run" | "--run")) {
+        return None;
+    }
+    let rest = match args.get(2).map(String::as_str) {
+        Some("--") => &args[3..],
+        // Left for clap to show the help, or to report the missing name
+        Some("-h" | "--help") | None => return None,
+        Some(_) => &args[2..],
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    let mut run_args = vec![args[0].clone()];
+    run_args.extend(rest.iter().cloned());
+    Some(run_args)
+}
+
+/// Runs `shortcut` with the rest of `args` as the last thing qwk does, returning the
+/// agent's exit code to exit with. With `may_exec`, the `qwk` binary's own process may be
+/// replaced with the agent.
+pub fn execute_shortcut(
+    shortcut: &str,
+    args: &[String],
+    verbose: Verbose,
+    may_exec: bool,
+) -> Result<ExitCode, QwkError> {
+    run_shortcut(shortcut, args, verbose, may_exec).map(as_exit_code)
+}
+
+/// Runs `shortcut` with `args[2..]`, which is how it's given on the command line, and
+/// returns the exit code to pass on. Errors before the agent runs come back instead.
+/// With `may_exec`, nothing follows the run, so `exec_agent` may replace qwk with the agent.
+fn run_shortcut(
+    shortcut: &str,
+    args: &[String],
+    verbose: Verbose,
+    may_exec: bool,
+) -> Result<i32, QwkError> {
+    let effective = load_effective_aliases()?;
+    let aliases = effective.aliases;
+
+    if let Some(entry) = aliases.get(shortcut) {
+        let file = match effective.local.contains(shortcut) {
+            true => get_local_aliases_file().unwrap_or_default(),
+            false => get_aliases_file(),
+        };
+        verbose.log(format_args!("alias '{}' from {}", shortcut, file.display()));
+        let ShortcutArgs {
+            vars,
+            positional,
+            allow_missing_env,
+            no_stdin,
+            with_agent,
+            stdin_prompt,
+            cwd,
+            timeout_secs,
+            output,
+            append,
+            tee,
+            dry_run,
+            yes,
+            preview,
+            notify,
+            agent_args: per_call_args,
+        } = parse_shortcut_args(shortcut, &args[2..]).map_err(|e| QwkError::new(e.to_string()))?;
+
+        let (agent, source) = match with_agent {
+            Some(name) => {
+                let mut agents = load_agents()?;
+                let agent = agents
+                    .remove(&name)
+                    .ok_or_else(|| QwkError::AgentNotFound(name.clone()))?;
+                (
+                    agent,
+                    format!("the named agent '{}', chosen with --with", name),
+                )
+            }
+            None => {
+                let (agent, source) = resolve_agent();
+                (agent, describe_agent_source(&source))
+            }
+        };
+        verbose.log(format_args!("agent '{}' ({})", agent.command, source));
+        let cwd = cwd
+            .or_else(|| entry.cwd.clone())
+            .map(|cwd| agent_dir_or_fail(&cwd))
+            .transpose()?;
+
+        // Arguments fill in `$1`..`$9` and `$@`, or else extend the prompt. Included
+        // aliases may take them too, so look at the prompt with its references expanded
+        let prompts = alias_prompts(&aliases);
+        let expanded = render_alias(
+            shortcut,
+            &TemplateContext {
+                aliases: prompts.clone(),
+                aliases_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_or_else(|_| entry.prompt.clone());
+        let (positional, extra) = if positional.is_empty() || uses_positional_args(&expanded) {
+            (positional, None)
+        } else {
+            (Vec::new(), Some(positional.join(" ")))
+        };
+
+        let settings = load_settings_or_fail()?;
+
+        // A terminal is left alone so interactive agents can still read from it
+        let stdin = if no_stdin || io::stdin().is_terminal() || !stdin_is_input() {
+            None
+        } else {
+            Some(read_prompt_from_stdin(settings.max_prompt_bytes)?).filter(|s| !s.is_empty())
+        };
+
+        let context = TemplateContext {
+            vars,
+            positional,
+            extra,
+            allow_missing_env,
+            stdin,
+            now: None,
+            env: None,
+            max_file_bytes: settings.max_file_bytes,
+            aliases: prompts,
+            aliases_only: false,
+        };
+        let prompt = render_alias(shortcut, &context).map_err(|e| {
+            let hint = match e {
+                RenderError::MissingVars(_) => {
+                    " Pass them with --var name=value or as arguments after the shortcut name"
+                }
+                RenderError::MissingEnv(_) => {
+                    " Set them, or use --allow-missing-env to substitute empty strings"
+                }
+                RenderError::InvalidDateFormat(_) => {
+                    " See https://docs.rs/chrono/latest/chrono/format/strftime/ for the supported formats"
+                }
+                RenderError::File { .. }
+                | RenderError::UnknownAlias(_)
+                | RenderError::AliasCycle(_)
+                | RenderError::AliasTooDeep(_) => "",
+            };
+            QwkError::new(format!("Error: shortcut '{}' has {}.{}", shortcut, e, hint))
+        })?;
+
+        let timeout = match timeout_secs.unwrap_or(settings.agent_timeout_secs) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let fallbacks = settings.agent_fallbacks.iter().map(AgentSpec::new);
+        // The alias's own hooks replace those in config.toml
+        let pre_hook = entry.pre_hook.clone().or(settings.pre_hook.clone());
+        let post_hook = entry.post_hook.clone().or(settings.post_hook.clone());
+        if dry_run {
+            let candidates: Vec<AgentSpec> = std::iter::once(agent).chain(fallbacks).collect();
+            print_agent_dry_run(
+                &candidates,
+                &per_call_args,
+                &prompt,
+                stdin_prompt,
+                cwd,
+                timeout,
+                output
+                    .as_deref()
+                    .map(|path| (expand_tilde(path), append, tee)),
+            )?;
+            if let Some(hook) = &pre_hook {
+                println!("Pre-hook: {}", hook);
+            }
+            if let Some(hook) = &post_hook {
+                println!("Post-hook: {}", hook);
+            }
+            return Ok(0);
+        }
+
+        // Before anything is asked or run, so a refused agent leaves no trace
+        check_agent_args_or_fail(&settings, &agent, &per_call_args)?;
+
+        if preview {
+            print_preview(&prompt, &agent, &per_call_args, stdin_prompt);
+            // Without a terminal it only renders, so scripts can use it to see the prompt
+            if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+                return Ok(0);
+            }
+            if !confirm("Run?") {
+                eprintln!("Not run.");
+                return Ok(0);
+            }
+        } else if entry.confirm && !yes {
+            confirm_run_or_fail(shortcut, &prompt, &agent, &per_call_args)?;
+        }
+
+        // Before the agent runs, so that a bad path doesn't cost a run
+        let stdout = match &output {
+            Some(path) => {
+                let path = expand_tilde(path);
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(&path)
+                    .map_err(|e| {
+                        QwkError::new(format!("Error: could not open {}: {}", path.display(), e))
+                    })?;
+                verbose.log(format_args!(
+                    "with its output written to {}",
+                    path.display()
+                ));
+                if tee {
+                    AgentStdout::Tee(file)
+                } else {
+                    AgentStdout::File(file)
+                }
+            }
+            None => AgentStdout::Inherit,
+        };
+
+        if let Some(hook) = &pre_hook {
+            verbose.log(format_args!("running the pre-hook {}", hook));
+            let env = [("QWK_ALIAS", shortcut.to_string())];
+            let result = run_hook(hook, &prompt, cwd.as_deref(), &env);
+            if let Some(failure) = describe_hook_failure(&result) {
+                if !settings.ignore_hook_failure {
+                    eprintln!(
+                        "Error: the pre-hook '{}' {}, so '{}' was not run (ignore_hook_failure = true runs it anyway)",
+                        hook, failure, shortcut
+                    );
+                    return Ok(match result {
+                        Ok(status) => exit_code(&status),
+                        Err(_) => 1,
+                    });
+                }
+                eprintln!("Warning: the pre-hook '{}' {}", hook, failure);
+            }
+        }
+
+        // Usage tracking is best-effort and must never block a run
+        let _ = record_usage(shortcut);
+
+        let mut candidates = std::iter::once(agent).chain(fallbacks).peekable();
+        let mut is_fallback = false;
+        while let Some(candidate) = candidates.next() {
+            let run = AgentRun::new(&candidate, &per_call_args, &prompt, stdin_prompt)
+                .map_err(|e| QwkError::new(format!("Error: {}", e)))?;
+            // The per-call args have been warned about with the first agent
+            if is_fallback {
+                check_agent_args_or_fail(&settings, &candidate, &[])?;
+            }
+            is_fallback = true;
+
+            let mut cmd = run.command();
+            if let Some(cwd) = &cwd {
+                cmd.current_dir(cwd);
+            }
+            if verbose.0 {
+                let argv = join_agent_command(&run.program, &run.args)
+                    .unwrap_or_else(|_| format!("{:?} {:?}", run.program, run.args));
+                verbose.log(format_args!("running {}", argv));
+                if let Some(cwd) = &cwd {
+                    verbose.log(format_args!("in {}", cwd.display()));
+                }
+                if !run.env.is_empty() {
+                    let env = masked_env_lines(&run.env).join(" ");
+                    verbose.log(format_args!("with {}", env));
+                }
+                if run.stdin_prompt {
+                    verbose.log(format_args!("with the prompt on stdin"));
+                }
+            }
+            // Only when there's nothing left to do once the agent exits: no timeout to
+            // enforce, no output to copy, no prompt to write, no run to log, no post-hook
+            // and no notification
+            let exec = cfg!(unix)
+                && may_exec
+                && settings.exec_agent
+                && timeout.is_none()
+                && output.is_none()
+                && !run.stdin_prompt
+                && !settings.track_runs
+                && post_hook.is_none()
+                && !notify;
+            let started = Instant::now();
+            // Unless told otherwise, an interactive agent keeps the terminal
+            let status = if exec {
+                verbose.log(format_args!("replacing qwk with the agent (exec_agent)"));
+                // Only returns if the agent couldn't be started
+                Err(exec_agent(&mut cmd))
+            } else {
+                run_agent(
+                    &mut cmd,
+                    run.stdin_prompt.then_some(prompt.as_str()),
+                    timeout,
+                    &stdout,
+                )
+            };
+
+            let elapsed = started.elapsed().as_secs_f64();
+            match &status {
+                Ok(Some(exit_status)) => match exit_status.code() {
+                    Some(code) => verbose.log(format_args!(
+                        "exited with code {} after {:.2}s",
+                        code, elapsed
+                    )),
+                    None => verbose.log(format_args!(
+                        "ended by a signal after {:.2}s, exiting with {}",
+                        elapsed,
+                        exit_code(exit_status)
+                    )),
+                },
+                Ok(None) => verbose.log(format_args!("stopped after {:.2}s", elapsed)),
+                Err(e) => verbose.log(format_args!("failed to start: {}", e)),
+            }
+
+            let logged_code = match &status {
+                Ok(Some(exit_status)) => Some(exit_code(exit_status)),
+                Ok(None) => Some(TIMEOUT_EXIT_CODE),
+                Err(_) => None,
+            };
+            if settings.track_runs
+                && let Some(code) = logged_code
+            {
+                record_run(RunEntry::new(
+                    shortcut,
+                    &per_call_args,
+                    code,
+                    started.elapsed(),
+                ));
+            }
+            // After an agent that ran, however it ended; it can't change how qwk exits
+            if let (Some(hook), Some(code)) = (&post_hook, logged_code) {
+                verbose.log(format_args!("running the post-hook {}", hook));
+                let env = [
+                    ("QWK_ALIAS", shortcut.to_string()),
+                    ("QWK_EXIT_CODE", code.to_string()),
+                    ("QWK_DURATION_MS", started.elapsed().as_millis().to_string()),
+                ];
+                let result = run_hook(hook, &prompt, cwd.as_deref(), &env);
+                if let Some(failure) = describe_hook_failure(&result) {
+                    eprintln!("Warning: the post-hook '{}' {}", hook, failure);
+                }
+            }
+            // Quick runs don't need one; the terminal's still in front of whoever ran them
+            if notify
+                && let Some(code) = logged_code
+                && started.elapsed() >= Duration::from_secs(settings.notify_after_secs)
+            {
+                let (title, body) = notification_text(shortcut, code, started.elapsed());
+                if let Err(e) = send_notification(&title, &body) {
+                    eprintln!("Warning: could not send a notification: {}", e);
+                }
+            }
+
+            match status {
+                Ok(Some(exit_status)) => return Ok(exit_code(&exit_status)),
+                Ok(None) => {
+                    eprintln!(
+                        "Error: the agent was stopped after running for {}s (--timeout 0 lets it run)",
+                        timeout.unwrap_or_default().as_secs()
+                    );
+                    return Ok(TIMEOUT_EXIT_CODE);
+                }
+                // Only an agent that couldn't start falls through; one that ran has had its say
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    let message = missing_agent_message(&run.program)
+                        .unwrap_or_else(|| format!("agent '{}' not found", run.program));
+                    match candidates.peek() {
+                        Some(next) => eprintln!("{}; falling back to '{}'", message, next.command),
+                        None => eprintln!("Error: {}. Set another with qwk --agent", message),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error executing agent '{}': {}", run.program, e);
+                    return Ok(1);
+                }
+            }
+        }
+        // None of the candidates is installed
+        Ok(1)
+    } else {
+        let matches = closest_matches(shortcut, aliases.keys().map(String::as_str));
+        let error = QwkError::AliasNotFound {
+            name: shortcut.to_string(),
+            suggestions: matches.iter().map(|name| name.to_string()).collect(),
+        };
+        // A single likely typo can be run instead, when there's someone to ask
+        if let [suggestion] = matches[..]
+            && io::stdin().is_terminal()
+        {
+            eprintln!("{}", error);
+            if confirm(&format!("Run '{}' instead?", suggestion)) {
+                let mut args = args.to_vec();
+                args[1] = suggestion.to_string();
+                return run_shortcut(suggestion, &args, verbose, may_exec);
+            }
+            // The error has been shown; only its exit code is left
+            return Ok(error.code());
+        }
+        Err(error)
+    }
+}
+
+/// Runs a `pre_hook` or `post_hook` command, split like an agent command, in the agent's
+/// directory with `env` added. It only gets the prompt where `{prompt}` asks for it, and
+/// its output goes to stderr so that stdout only has the agent's.
+fn run_hook(
+    hook: &str,
+    prompt: &str,
+    cwd: Option<&Path>,
+    env: &[(&str, String)],
+) -> io::Result<ExitStatus> {
+    let (program, args) = parse_agent_command(hook);
+    let mut cmd = Command::new(program);
+    cmd.args(
+        args.iter()
+            .map(|arg| arg.replace(PROMPT_PLACEHOLDER, prompt)),
+    )
+    .envs(selection_env())
+    .envs(env.iter().map(|(key, value)| (key, value)))
+    .stdin(Stdio::null())
+    .stdout(io::stderr());
+    if let Some(cwd) = cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd.status()
+}
+
+/// What went wrong with a hook, e.g. "exited with code 1", or `None` if it succeeded.
+fn describe_hook_failure(result: &io::Result<ExitStatus>) -> Option<String> {
+    match result {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("exited with code {}", exit_code(status))),
+        Err(e) => Some(format!("could not be started ({})", e)),
+    }
+}
+
+/// Shows what a shortcut set with `--confirm` is about to run and asks whether to go ahead,
+/// failing unless the answer is yes. Without a terminal to ask on, it refuses to run.
+fn confirm_run_or_fail(
+    shortcut: &str,
+    prompt: &str,
+    agent: &AgentSpec,
+    per_call_args: &[String],
+) -> Result<(), QwkError> {
+    if !io::stdin().is_terminal() {
+        return Err(QwkError::new(format!(
+            "Error: '{}' asks before it runs, and there's no terminal to ask on. Pass --yes to run it anyway",
+            shortcut
+        )));
+    }
+    let mut command = agent.command.clone();
+    if let Ok(args) = shlex::try_join(per_call_args.iter().map(String::as_str))
+        && !args.is_empty()
+    {
+        command = format!("{} {}", command, args);
+    }
+    eprintln!("Shortcut: {}", shortcut);
+    eprintln!("Prompt: {}", truncate_prompt(prompt, 60));
+    eprintln!("Agent: {}", command);
+    if !confirm("Run?") {
+        return Err(QwkError::new("Not run."));
+    }
+    Ok(())
+}
+
+/// Prints the rendered `prompt` for `--preview`, then the command that would send it, with
+/// the prompt left out of it.
+fn print_preview(prompt: &str, agent: &AgentSpec, per_call_args: &[String], stdin_prompt: bool) {
+    let mut text = prompt.to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    print_paged(&text);
+    println!("---");
+    let command = AgentRun::new(agent, per_call_args, "<prompt>", stdin_prompt)
+        .ok()
+        .and_then(|run| {
+            let command = join_agent_command(&run.program, &run.args).ok()?;
+            Some(match run.stdin_prompt {
+                true => format!("{} < <prompt>", command),
+                false => command,
+            })
+        })
+        .unwrap_or_else(|| agent.command.clone());
+    println!("Command: {}", command);
+}
+
+/// Prints `text`, through `$PAGER` when stdout is a terminal that it doesn't fit on. Without
+/// a pager, or one that won't start, it's printed as it is.
+fn print_paged(text: &str) {
+    let rows = terminal_size::terminal_size().map(|(_, height)| height.0 as usize);
+    let pager = env::var("PAGER")
+        .ok()
+        .and_then(|pager| shlex::split(&pager))
+        .filter(|words| !words.is_empty());
+    if io::stdout().is_terminal()
+        && rows.is_some_and(|rows| text.lines().count() >= rows)
+        && let Some(words) = pager
+        && let Ok(mut child) = Command::new(&words[0])
+            .args(&words[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+    {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A pager quit before the end closes the pipe, which is fine
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        let _ = child.wait();
+        return;
+    }
+    print!("{}", text);
+}
+
+/// How a shortcut runs an agent, once its prompt is known.
+struct AgentRun {
+    program: String,
+    /// The agent's default arguments, then those given after `--`, then the prompt unless
+    /// the default arguments place it themselves or it goes to stdin
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    /// Whether the prompt is written to stdin instead
+    stdin_prompt: bool,
+    /// Whether the last argument is a script for `cmd /C`, which has quoting rules of its own
+    #[cfg(windows)]
+    shell: bool,
+}
+
+impl AgentRun {
+    fn new(
+        agent: &AgentSpec,
+        per_call_args: &[String],
+        prompt: &str,
+        stdin_prompt: bool,
+    ) -> Result<Self, String> {
+        let stdin_prompt = stdin_prompt || agent.stdin_prompt;
+        if agent.shell {
+            if stdin_prompt && agent.command.contains(PROMPT_PLACEHOLDER) {
+                return Err(format!(
+                    "the agent '{}' places the prompt with {}, so it can't also be written to stdin",
+                    agent.command, PROMPT_PLACEHOLDER
+                ));
+            }
+            let prompt = (!stdin_prompt).then_some(prompt);
+            let (program, args, mut env) = shell_agent_call(&agent.command, per_call_args, prompt);
+            env.extend(agent.env.clone());
+            return Ok(AgentRun {
+                program,
+                args,
+                env,
+                stdin_prompt,
+                #[cfg(windows)]
+                shell: true,
+            });
+        }
+
+        let (program, default_args) = parse_agent_command(&agent.command);
+        let args = if stdin_prompt {
+            if default_args
+                .iter()
+                .any(|arg| arg.contains(PROMPT_PLACEHOLDER))
+            {
+                return Err(format!(
+                    "the agent '{}' places the prompt with {}, so it can't also be written to stdin",
+                    agent.command, PROMPT_PLACEHOLDER
+                ));
+            }
+            default_args.iter().chain(per_call_args).cloned().collect()
+        } else {
+            agent_call_args(&default_args, per_call_args, prompt)
+        };
+        Ok(AgentRun {
+            program,
+            args,
+            env: agent.env.clone(),
+            stdin_prompt,
+            #[cfg(windows)]
+            shell: false,
+        })
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.envs(selection_env()).envs(&self.env);
+        #[cfg(windows)]
+        if self.shell
+            && let Some((script, flags)) = self.args.split_last()
+        {
+            use std::os::windows::process::CommandExt;
+            // With /S, cmd drops the outer quotes and takes the rest as is
+            cmd.args(flags).raw_arg(format!("\"{}\"", script));
+            return cmd;
+        }
+        cmd.args(&self.args);
+        cmd
+    }
+}
+
+/// Prints how `qwk <shortcut> --dry-run` would run the agent: with the first of the
+/// `candidates` that's installed, or else the first of all. Each argument gets a line of
+/// its own, quoted like a Rust string so that whitespace and newlines show.
+fn print_agent_dry_run(
+    candidates: &[AgentSpec],
+    per_call_args: &[String],
+    prompt: &str,
+    stdin_prompt: bool,
+    cwd: Option<PathBuf>,
+    timeout: Option<Duration>,
+    output: Option<(PathBuf, bool, bool)>,
+) -> Result<(), QwkError> {
+    let installed = |agent: &AgentSpec| {
+        let (program, _) = parse_agent_command(&agent.command);
+        find_executable(&program).is_some()
+    };
+    let chosen = candidates.iter().position(installed);
+    for skipped in &candidates[..chosen.unwrap_or(1)] {
+        let (program, _) = parse_agent_command(&skipped.command);
+        println!("Not found in PATH: {}", program);
+    }
+    let agent = &candidates[chosen.unwrap_or(0)];
+    let run = AgentRun::new(agent, per_call_args, prompt, stdin_prompt)
+        .map_err(|e| QwkError::new(format!("Error: {}", e)))?;
+
+    println!("Program: {:?}", run.program);
+    if run.args.is_empty() {
+        println!("Arguments: none");
+    } else {
+        println!("Arguments:");
+    }
+    for arg in &run.args {
+        println!("  {:?}", arg);
+    }
+    match join_agent_command(&run.program, &run.args) {
+        Ok(command) => println!("Command: {}", command),
+        Err(e) => println!("Command: can't be written as one line ({})", e),
+    }
+    let dir = cwd.or_else(|| env::current_dir().ok()).unwrap_or_default();
+    println!("Directory: {}", dir.display());
+    if !run.env.is_empty() {
+        println!("Environment: {}", masked_env_lines(&run.env).join(" "));
+    }
+    if run.stdin_prompt {
+        println!("Stdin: the prompt, {} bytes", prompt.len());
+    }
+    if let Some(timeout) = timeout {
+        println!("Timeout: {}s", timeout.as_secs());
+    }
+    if let Some((path, append, tee)) = output {
+        let how = if append { "appended to" } else { "written to" };
+        let shown = if tee { ", and shown" } else { "" };
+        println!("Output: {} {}{}", how, path.display(), shown);
+    }
+    Ok(())
+}
+
+/// Warns about dangerous arguments given for the call and, with `forbid_dangerous_args`
+/// set, refuses dangerous default arguments of `agent`.
+fn check_agent_args_or_fail(
+    settings: &Settings,
+    agent: &AgentSpec,
+    per_call_args: &[String],
+) -> Result<(), QwkError> {
+    let patterns = dangerous_arg_patterns(settings)
+        .map_err(|e| QwkError::new(format!("Error loading settings: {}", e)))?;
+    let (_, default_args) = parse_agent_command(&agent.command);
+    match check_execution_args(
+        &default_args,
+        per_call_args,
+        &patterns,
+        settings.forbid_dangerous_args,
+    ) {
+        Ok(warnings) => warn_dangerous_args(&warnings),
+        Err(e) => {
+            return Err(QwkError::new(e.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// `code` for the process to exit with. A code that doesn't fit in a byte, which only
+/// Windows has, becomes a plain failure.
+pub fn as_exit_code(code: i32) -> ExitCode {
+    u8::try_from(code).map_or(ExitCode::FAILURE, ExitCode::from)
+}
+
+/// Global flags given ahead of everything else on the command line.
+#[derive(Debug, Default, PartialEq)]
+pub struct GlobalArgs {
+    pub profile: Option<String>,
+    pub no_local: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+}
+
+/// Removes leading `--profile NAME`, `--profile=NAME`, `--no-local`, `-v`/`--verbose` and
+/// `-q`/`--quiet` flags from `args`, so that the fast path sees the shortcut name where it expects it.
+pub fn take_global_args(args: &mut Vec<String>) -> GlobalArgs {
+    let mut global = GlobalArgs::default();
+    while let Some(arg) = args.get(1) {
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            global.profile = Some(name.to_string());
+            args.remove(1);
+        } else if arg == "--profile" && args.len() > 2 {
+            args.remove(1);
+            global.profile = Some(args.remove(1));
+        } else if arg == "--no-local" {
+            args.remove(1);
+            global.no_local = true;
+        } else if arg == "-v" || arg == "--verbose" {
+            args.remove(1);
+            global.verbose = true;
+        } else if arg == "-q" || arg == "--quiet" {
+            args.remove(1);
+            global.quiet = true;
+        } else {
+            break;
+        }
+    }
+    global
+}
+
+/// Runs `qwk --complete`, which `args` are for.
+fn complete_or_fail(args: &[String]) -> Result<(), QwkError> {
+    if let Some(Commands::Complete {
+        partial,
+        with_descriptions,
+        shell,
+        previous,
+        words,
+    }) = Cli::try_parse_from(args).map_err(QwkError::Usage)?.command
+    {
+        generate_completions(partial, with_descriptions, shell, previous, words);
+    }
+    Ok(())
+}
+
+/// Exits when the profile from `--profile` or `QWK_PROFILE` can't be a directory name.
+fn check_profile_or_fail() -> Result<(), QwkError> {
+    if let Some(name) = get_profile()
+        && let Err(e) = validate_profile_name(&name)
+    {
+        return Err(QwkError::new(e.to_string()));
+    }
+    Ok(())
+}
+
+fn preset_command_or_fail(name: &str) -> Result<String, QwkError> {
+    match find_agent_preset(name) {
+        Some(preset) => Ok(preset.command.to_string()),
+        None => {
+            let names: Vec<_> = AGENT_PRESETS.iter().map(|preset| preset.name).collect();
+            Err(QwkError::new(format!(
+                "Unknown preset '{}'; the presets are: {}",
+                name,
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+pub fn list_agent_presets() {
+    let rows: Vec<Vec<String>> = AGENT_PRESETS
+        .iter()
+        .map(|preset| {
+            vec![
+                preset.name.to_string(),
+                preset.command.to_string(),
+                preset.description.to_string(),
+            ]
+        })
+        .collect();
+    for line in render_table(&["NAME", "COMMAND", "DESCRIPTION"], &rows) {
+        println!("{}", line);
+    }
+}
+
+/// The `cwd` to store for `--set --cwd <path>`: relative paths are made absolute, so the
+/// alias works from anywhere, while `~` is kept to expand wherever it runs.
+fn alias_cwd(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    let stored = if path.starts_with('~') || Path::new(path).is_absolute() {
+        path.to_string()
+    } else {
+        let current = env::current_dir().unwrap_or_default().join(path);
+        current.to_string_lossy().into_owned()
+    };
+    if !expand_tilde(&stored).is_dir() {
+        eprintln!(
+            "Warning: {} is not a directory; the shortcut will fail until it is",
+            stored
+        );
+    }
+    Some(stored)
+}
+
+/// The directory to run the agent in, with `~` expanded, failing if there's no such
+/// directory rather than letting the agent start somewhere else.
+fn agent_dir_or_fail(path: &str) -> Result<PathBuf, QwkError> {
+    let dir = expand_tilde(path);
+    if !dir.is_dir() {
+        let problem = if dir.exists() {
+            "is not a directory"
+        } else {
+            "does not exist"
+        };
+        return Err(QwkError::new(format!(
+            "Error: the working directory {} {}",
+            dir.display(),
+            problem
+        )));
+    }
+    Ok(dir)
+}
+
+/// Removes the agent set with `--agent` or `--agent-use`, saying nothing if none was set.
+pub fn unset_agent_or_fail() -> Result<(), QwkError> {
+    match unset_agent() {
+        Ok(true) => status!("Agent unset; the agent is now '{}'", get_agent()),
+        Ok(false) => {}
+        Err(e) => {
+            return Err(QwkError::io("Error unsetting agent", e));
+        }
+    }
+    Ok(())
+}
+
+/// Where the agent from `resolve_agent` is set, for `--agent` and `--verbose`.
+fn describe_agent_source(source: &AgentSource) -> String {
+    match source {
+        AgentSource::Named(name) => format!("the named agent '{}', chosen with --agent-use", name),
+        AgentSource::Settings => format!(
+            "set with --agent in {}",
+            settings_file_naming("default_agent").display()
+        ),
+        AgentSource::LegacyFile => format!(
+            "set in {}, as older versions did",
+            get_agent_file().display()
+        ),
+        AgentSource::Default => "the default; set another with --agent".to_string(),
+    }
+}
+
+pub fn show_agent(porcelain: bool) {
+    let (agent, source) = resolve_agent();
+    if porcelain {
+        println!("{}", agent.command);
+        return;
+    }
+    println!(
+        "Agent: {} ({})",
+        agent.command,
+        describe_agent_source(&source)
+    );
+    if agent.shell {
+        println!("Runs as: a shell script");
+    } else {
+        let (command, args) = parse_agent_command(&agent.command);
+        println!("Program: {:?}", command);
+        println!("Arguments: {:?}", args);
+    }
+    if agent.stdin_prompt {
+        println!("Prompt: written to stdin");
+    }
+    if !agent.env.is_empty() {
+        println!("Environment: {}", masked_env_lines(&agent.env).join(" "));
+    }
+    let fallbacks = load_settings().map(|settings| settings.agent_fallbacks);
+    if let Ok(fallbacks) = fallbacks
+        && !fallbacks.is_empty()
+    {
+        println!("Fallbacks: {}", fallbacks.join(", "));
+    }
+}
+
+/// Appends `fallbacks` to the agent's fallbacks, after removing those there with `clear`.
+pub fn add_agent_fallbacks(fallbacks: &[String], clear: bool) -> Result<(), QwkError> {
+    if fallbacks.iter().any(|command| command.trim().is_empty()) {
+        return Err(QwkError::new("The agent command can't be empty"));
+    }
+    let mut list = if clear {
+        Vec::new()
+    } else {
+        load_settings_or_fail()?.agent_fallbacks
+    };
+    for command in fallbacks {
+        warn_if_dangerous_agent(command);
+        list.push(command.clone());
+    }
+    if let Err(e) = set_agent_fallbacks(&list) {
+        return Err(QwkError::io("Error setting agent", e));
+    }
+    if list.is_empty() {
+        println!("No fallback agents");
+    } else {
+        println!("Fallback agents: {}", list.join(", "));
+    }
+    Ok(())
+}
+
+/// Splits an `--add-arg` or `--remove-arg` value the way the agent command is split.
+fn split_args_or_fail(value: &str) -> Result<Vec<String>, QwkError> {
+    shlex::split(value)
+        .ok_or_else(|| QwkError::new(format!("Invalid arguments '{}': unbalanced quotes", value)))
+}
+
+/// Removes `remove` and then appends `add` to the arguments of the agent in use, saving it
+/// where it came from, and shows the result.
+pub fn edit_agent_args(add: &[String], remove: &[String]) -> Result<(), QwkError> {
+    let (agent, source) = resolve_agent();
+    if agent.shell {
+        return Err(QwkError::new(format!(
+            "The agent '{}' is a shell script, which has no arguments to edit; set it again with qwk --agent --shell",
+            agent.command
+        )));
+    }
+    let (program, mut args) = parse_agent_command(&agent.command);
+    for value in remove {
+        if !remove_agent_args(&mut args, &split_args_or_fail(value)?) {
+            return Err(QwkError::new(format!(
+                "The agent's arguments {:?} don't include '{}'",
+                args, value
+            )));
+        }
+    }
+    for value in add {
+        args.extend(split_args_or_fail(value)?);
+    }
+    let command = join_agent_command(&program, &args)
+        .map_err(|e| QwkError::new(format!("Invalid arguments: {}", e)))?;
+    warn_if_dangerous_agent(&command);
+
+    let saved = match source {
+        AgentSource::Named(name) => {
+            let mut agents = load_agents()?;
+            if let Some(named) = agents.get_mut(&name) {
+                named.command = command;
+            }
+            save_agents(&agents)
+        }
+        _ => set_agent_spec(&AgentSpec { command, ..agent }),
+    };
+    if let Err(e) = saved {
+        return Err(QwkError::io("Error setting agent", e));
+    }
+    show_agent(false);
+    Ok(())
+}
+
+/// The settings file the merged setting `key` comes from: the profile's own file if it
+/// has it, else the global one.
+fn settings_file_naming(key: &str) -> PathBuf {
+    let profile_file = get_profile_settings_file();
+    let in_profile_file = fs::read_to_string(&profile_file)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .is_some_and(|table| table.contains_key(key));
+    if in_profile_file {
+        profile_file
+    } else {
+        get_settings_file()
+    }
+}
+
+pub fn add_named_agent(
+    name: &str,
+    command: &str,
+    stdin_prompt: bool,
+    shell: bool,
+) -> Result<(), QwkError> {
+    if let Err(e) = validate_agent_name(name) {
+        return Err(QwkError::new(e.to_string()));
+    }
+    if command.trim().is_empty() {
+        return Err(QwkError::new("The agent command can't be empty"));
+    }
+    warn_if_dangerous_agent(command);
+    check_agent_program(command, false)?;
+
+    let mut agents = load_agents()?;
+    // The environment is managed with --agent-env, so a new command keeps it
+    let env = agents
+        .get(name)
+        .map(|agent| agent.env.clone())
+        .unwrap_or_default();
+    let spec = AgentSpec {
+        command: command.to_string(),
+        stdin_prompt,
+        shell,
+        env,
+    };
+    let replaced = agents.insert(name.to_string(), spec).is_some();
+    if let Err(e) = save_agents(&agents) {
+        return Err(QwkError::io("Error saving agents", e));
+    }
+    let verb = if replaced { "updated" } else { "added" };
+    status!("Agent '{}' {}: {}", name, verb, command);
+    Ok(())
+}
+
+/// `KEY=value` lines for `env`, with the values masked.
+fn masked_env_lines(env: &BTreeMap<String, String>) -> Vec<String> {
+    env.iter()
+        .map(|(key, value)| format!("{}={}", key, mask_secret(value)))
+        .collect()
+}
+
+pub fn manage_agent_env(
+    action: EnvAction,
+    variable: Option<&str>,
+    named: Option<&str>,
+) -> Result<(), QwkError> {
+    let mut agents = load_agents()?;
+    // The named agent to change, or `None` for the one in config.toml
+    let name = match named {
+        Some(name) if !agents.contains_key(name) => {
+            return Err(QwkError::AgentNotFound(name.to_string()));
+        }
+        Some(name) => Some(name.to_string()),
+        None => match resolve_agent().1 {
+            AgentSource::Named(name) => Some(name),
+            _ => None,
+        },
+    };
+    let label = match &name {
+        Some(name) => format!("agent '{}'", name),
+        None => "the agent".to_string(),
+    };
+    let mut env = match &name {
+        Some(name) => agents[name].env.clone(),
+        None => load_settings_or_fail()?.agent_env,
+    };
+
+    match (action, variable) {
+        (EnvAction::List, _) => {
+            if env.is_empty() {
+                println!("No environment variables set for {}", label);
+            }
+            for line in masked_env_lines(&env) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        (EnvAction::Set, Some(variable)) => {
+            let Some((key, value)) = variable.split_once('=') else {
+                return Err(QwkError::new(format!(
+                    "Invalid variable '{}': expected KEY=VALUE",
+                    variable
+                )));
+            };
+            if let Err(e) = validate_env_name(key) {
+                return Err(QwkError::new(e.to_string()));
+            }
+            env.insert(key.to_string(), value.to_string());
+            status!("Set {}={} for {}", key, mask_secret(value), label);
+        }
+        (EnvAction::Unset, Some(key)) => {
+            if env.remove(key).is_none() {
+                return Err(QwkError::new(format!("{} is not set for {}", key, label)));
+            }
+            status!("Unset {} for {}", key, label);
+        }
+        (EnvAction::Set, None) => {
+            return Err(QwkError::new("qwk --agent-env set requires KEY=VALUE"));
+        }
+        (EnvAction::Unset, None) => {
+            return Err(QwkError::new(
+                "qwk --agent-env unset requires the KEY to remove",
+            ));
+        }
+    }
+
+    let saved = match name {
+        Some(name) => {
+            if let Some(agent) = agents.get_mut(&name) {
+                agent.env = env;
+            }
+            save_agents(&agents)
+        }
+        None => set_agent_env(&env),
+    };
+    if let Err(e) = saved {
+        return Err(QwkError::io("Error saving agent environment", e));
+    }
+    Ok(())
+}
+
+pub fn use_named_agent(name: &str) -> Result<(), QwkError> {
+    let agent = use_agent(name)?;
+    status!("Using agent '{}': {}", name, agent.command);
+    Ok(())
+}
+
+pub fn list_named_agents() -> Result<(), QwkError> {
+    let agents = load_agents()?;
+    if agents.is_empty() {
+        println!("No named agents. Add one with: qwk --agent-add <name> <command>");
+        println!("Agent: {}", get_agent());
+        return Ok(());
+    }
+    let active = load_settings_or_fail()?
+        .active_agent
+        .filter(|name| agents.contains_key(name));
+    let rows: Vec<Vec<String>> = agents
+        .iter()
+        .map(|(name, agent)| {
+            let marker = if active.as_ref() == Some(name) {
+                "*"
+            } else {
+                ""
+            };
+            let prompt = match (agent.stdin_prompt, agent.shell) {
+                (true, false) => "stdin",
+                (false, false) => "argument",
+                (true, true) => "stdin (shell)",
+                (false, true) => "argument (shell)",
+            };
+            vec![
+                marker.to_string(),
+                name.clone(),
+                agent.command.clone(),
+                prompt.to_string(),
+            ]
+        })
+        .collect();
+    for line in render_table(&["", "NAME", "COMMAND", "PROMPT"], &rows) {
+        println!("{}", line);
+    }
+    if active.is_none() {
+        println!("Not using a named agent; the agent is: {}", get_agent());
+    }
+    Ok(())
+}
+
+pub fn list_profile_names() -> Result<(), QwkError> {
+    let names = list_profiles().map_err(|e| QwkError::io("Error listing profiles", e))?;
+    let active = get_profile();
+    let marker = |is_active: bool| if is_active { "* " } else { "  " };
+    println!("{}{}", marker(active.is_none()), DEFAULT_PROFILE);
+    for name in names {
+        println!("{}{}", marker(active.as_ref() == Some(&name)), name);
+    }
+    Ok(())
+}
+
+/// Held by each run of qwk in the process, whose profile, `.qwk.json` setting and quiet mode
+/// are process-wide, so that runs on different threads take turns.
+static RUN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Runs qwk with `args`, the program name first, and returns the code to exit with. An
+/// error comes back for the caller to `report` rather than ending the process, and the
+/// agent always runs as a child, whatever `exec_agent` says.
+pub fn run_with_args<I: IntoIterator<Item = String>>(args: I) -> Result<ExitCode, QwkError> {
+    run_locked(args.into_iter().collect(), false)
+}
+
+/// Runs qwk as the `qwk` binary does: like `run_with_args`, except that with `exec_agent`
+/// set, `qwk <shortcut>` may replace the process with the agent on Unix.
+pub fn run_main<I: IntoIterator<Item = String>>(args: I) -> Result<ExitCode, QwkError> {
+    run_locked(args.into_iter().collect(), true)
+}
+
+/// Runs qwk with the arguments of the process, printing any error, and returns the code
+/// to exit with.
+#[deprecated(note = "use `run_with_args` and `QwkError::report`")]
+pub fn run() -> ExitCode {
+    match run_with_args(env::args()) {
+        Ok(code) => code,
+        Err(e) => e.report(),
+    }
+}
+
+fn run_locked(args: Vec<String>, may_exec: bool) -> Result<ExitCode, QwkError> {
+    let _lock = RUN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = run_args(args, may_exec);
+    clear_selection();
+    result
+}
+
+fn run_args(mut args: Vec<String>, may_exec: bool) -> Result<ExitCode, QwkError> {
+    let cli_args = args.clone();
+    let global = take_global_args(&mut args);
+    if let Some(profile) = &global.profile {
+        select_profile(profile);
+    }
+    if global.no_local {
+        disable_local_aliases();
+    }
+    set_quiet(global.quiet);
+    check_profile_or_fail()?;
+
+    // Completion runs on every TAB, so it goes straight to the candidates: no first-run
+    // setup, no backup, and no parsing of the other commands
+    if args.get(1).is_some_and(|arg| arg == "--complete") {
+        complete_or_fail(&args)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    // Setting completion up or removing it is what the first run would offer, and a static
+    // script is printed while building packages, far from any terminal
+    if !matches!(
+        args.get(1).map(String::as_str),
+        Some("--setup-completion" | "--remove-completion" | "--completions")
+    ) {
+        handle_first_run();
+        take_daily_backup();
+    }
+
+    // `qwk run foo`, which reaches shortcuts named like commands
+    if let Some(run_args) = explicit_run_args(&args) {
+        let verbose = Verbose(global.verbose || verbose_from_env());
+        return execute_shortcut(&run_args[1], &run_args, verbose, may_exec);
+    }
+
+    // Handle direct shortcut execution (qwk foo) or (qwk foo -- agent-args)
+    if args.len() >= 2 && !args[1].starts_with("--") && args[1] != "run" {
+        let shortcut = &args[1];
+        let verbose = Verbose(global.verbose || verbose_from_env());
+        return execute_shortcut(shortcut, &args, verbose, may_exec);
+    }
+
+    // Parse with clap for other commands
+    let cli = Cli::try_parse_from(&cli_args).map_err(QwkError::Usage)?;
+    if let Some(profile) = &cli.profile {
+        select_profile(profile);
+        check_profile_or_fail()?;
+    }
+    if cli.no_local {
+        disable_local_aliases();
+    }
+    if cli.quiet {
+        set_quiet(true);
+    }
+
+    let modifies_config = cli.command.as_ref().is_some_and(Commands::modifies_config);
+    let mut code = 0;
+
+    match cli.command {
+        Some(Commands::Set { batch: true, .. }) => {
+            set_aliases_batch()?;
+        }
+
+        Some(Commands::Set {
+            alias,
+            prompt,
+            from_file,
+            from_clipboard,
+            description,
+            tags,
+            cwd,
+            confirm,
+            pre_hook,
+            post_hook,
+            local,
+            ..
+        }) => {
+            // clap guarantees an alias unless --batch is given
+            let alias = alias.unwrap_or_default();
+            let max_prompt_bytes = load_settings_or_fail()?.max_prompt_bytes;
+            let prompt_text = if let Some(p) = prompt {
+                p
+            } else if let Some(path) = from_file {
+                read_prompt_from_file(&path, max_prompt_bytes)
+                    .map_err(|e| QwkError::new(format!("Error reading prompt: {}", e)))?
+            } else if from_clipboard {
+                read_prompt_from_clipboard()
+                    .map_err(|e| QwkError::new(format!("Error reading prompt: {}", e)))?
+            } else {
+                read_stdin_prompt_or_fail()?
+            };
+            if let Err(e) = check_prompt_size(&prompt_text, max_prompt_bytes) {
+                return Err(QwkError::new(format!("Error: {}", e)));
+            }
+
+            let aliases_file = if local {
+                local_aliases_file_or_fail(true)?
+            } else {
+                get_aliases_file()
+            };
+            let mut aliases = load_aliases_from(&aliases_file)?;
+            let history = HistoryEntry::new("set", vec![alias.clone()]).with_prompt(&prompt_text);
+            let entry = aliases.entry(alias.clone()).or_default();
+            entry.prompt = prompt_text;
+            entry.touch();
+            if description.is_some() {
+                entry.description = description;
+            }
+            if !tags.is_empty() {
+                entry.tags = tags;
+            }
+            if let Some(cwd) = cwd {
+                entry.cwd = alias_cwd(&cwd);
+            }
+            if let Some(confirm) = confirm {
+                entry.confirm = confirm;
+            }
+            if let Some(hook) = pre_hook {
+                entry.pre_hook = Some(hook).filter(|hook| !hook.trim().is_empty());
+            }
+            if let Some(hook) = post_hook {
+                entry.post_hook = Some(hook).filter(|hook| !hook.trim().is_empty());
+            }
+
+            if !local {
+                record_undo_or_fail(&format!("set '{}'", alias))?;
+            }
+            if let Err(e) = save_aliases_file(&aliases_file, &aliases, local) {
+                return Err(QwkError::io("Error saving alias", e));
+            }
+
+            if !local {
+                record_history(history);
+            }
+            if local {
+                status!(
+                    "Alias '{}' set successfully in {}",
+                    alias,
+                    aliases_file.display()
+                );
+            } else {
+                status!("Alias '{}' set successfully", alias);
+            }
+        }
+
+        Some(Commands::Agent { unset: true, .. }) => {
+            unset_agent_or_fail()?;
+        }
+
+        Some(Commands::Agent {
+            add_arg,
+            remove_arg,
+            ..
+        }) if !add_arg.is_empty() || !remove_arg.is_empty() => {
+            edit_agent_args(&add_arg, &remove_arg)?;
+        }
+
+        Some(Commands::Agent {
+            fallback,
+            clear_fallbacks,
+            ..
+        }) if !fallback.is_empty() || clear_fallbacks => {
+            add_agent_fallbacks(&fallback, clear_fallbacks)?;
+        }
+
+        Some(Commands::Agent {
+            command: None,
+            preset: None,
+            porcelain,
+            ..
+        }) => {
+            show_agent(porcelain);
+        }
+
+        Some(Commands::Agent {
+            command,
+            preset,
+            force,
+            check,
+            stdin_prompt,
+            shell,
+            ..
+        }) => {
+            let command = match preset {
+                Some(name) => preset_command_or_fail(&name)?,
+                None => command.unwrap_or_default(),
+            };
+            warn_if_dangerous_agent(&command);
+            if !force {
+                check_agent_program(&command, check)?;
+            }
+
+            let spec = AgentSpec {
+                command,
+                stdin_prompt,
+                shell,
+                ..Default::default()
+            };
+            if let Err(e) = set_agent_spec(&spec) {
+                return Err(QwkError::io("Error setting agent", e));
+            }
+
+            let mut options = Vec::new();
+            if shell {
+                options.push("run by the shell");
+            }
+            if stdin_prompt {
+                options.push("with the prompt on stdin");
+            }
+            if options.is_empty() {
+                status!("Agent set to '{}'", spec.command);
+            } else {
+                status!("Agent set to '{}', {}", spec.command, options.join(", "));
+            }
+        }
+
+        Some(Commands::Presets) => {
+            list_agent_presets();
+        }
+
+        Some(Commands::AgentAdd {
+            name,
+            command,
+            stdin_prompt,
+            shell,
+        }) => {
+            add_named_agent(&name, &command, stdin_prompt, shell)?;
+        }
+
+        Some(Commands::AgentUse { name }) => {
+            use_named_agent(&name)?;
+        }
+
+        Some(Commands::Agents) => {
+            list_named_agents()?;
+        }
+
+        Some(Commands::AgentEnv {
+            action,
+            variable,
+            agent,
+        }) => {
+            manage_agent_env(action, variable.as_deref(), agent.as_deref())?;
+        }
+
+        Some(Commands::List {
+            sort,
+            names,
+            tags,
+            long,
+            no_summary,
+            archived,
+            force_empty,
+            width,
+        }) => {
+            if !profile_exists() {
+                return Err(QwkError::new(format!(
+                    "Profile '{}' does not exist. Set a shortcut in it to create it.",
+                    get_profile().unwrap_or_default()
+                )));
+            }
+            list_aliases(ListOptions {
+                sort,
+                names_only: names,
+                tags: &tags,
+                long,
+                summary: !no_summary,
+                archived,
+                force_empty,
+                preview_width: match width {
+                    Some(width) => width,
+                    None => load_settings_or_fail()?.list_preview_width,
+                },
+            })?;
+        }
+
+        Some(Commands::Count) => {
+            println!("{}", load_effective_aliases_or_fail()?.len());
+        }
+
+        Some(Commands::Stats { json }) => {
+            show_stats(json)?;
+        }
+
+        Some(Commands::Show {
+            alias,
+            vars,
+            resolved,
+        }) => {
+            show_alias(&alias, vars, resolved)?;
+        }
+
+        Some(Commands::Tags) => {
+            list_tags()?;
+        }
+
+        Some(Commands::Import {
+            path,
+            format,
+            strategy,
+            include_agent,
+            dry_run,
+            prefix,
+        }) => {
+            code = import_aliases(
+                path,
+                ImportOptions {
+                    format,
+                    strategy,
+                    include_agent,
+                    dry_run,
+                    prefix,
+                },
+            )?;
+        }
+
+        Some(Commands::Export {
+            path,
+            format,
+            output,
+            prefix,
+        }) => {
+            export_aliases(path.or(output), format, prefix)?;
+        }
+
+        Some(Commands::Complete {
+            partial,
+            with_descriptions,
+            shell,
+            previous,
+            words,
+        }) => {
+            generate_completions(partial, with_descriptions, shell, previous, words);
+        }
+
+        Some(Commands::SetupCompletion { shell, print }) => {
+            if let Err(e) = setup_completion(shell, print) {
+                return Err(QwkError::io("Error setting up autocompletion", e));
+            }
+        }
+
+        Some(Commands::Completions { shell }) => {
+            print!("{}", static_completion_script(shell));
+        }
+
+        Some(Commands::RemoveCompletion { shell }) => {
+            if let Err(e) = remove_completion(shell) {
+                return Err(QwkError::io("Error removing autocompletion", e));
+            }
+        }
+
+        Some(Commands::Backups { format }) => {
+            list_backups(format)?;
+        }
+
+        Some(Commands::Restore { backup, yes }) => {
+            restore_backup(backup.as_deref(), yes)?;
+        }
+
+        Some(Commands::PruneBackups { keep, dry_run }) => {
+            prune_backups(keep, dry_run)?;
+        }
+
+        Some(Commands::Profiles) => {
+            list_profile_names()?;
+        }
+
+        Some(Commands::Encrypt { state }) => {
+            set_encryption(state == Toggle::On)?;
+        }
+
+        Some(Commands::MigrateToFiles) => {
+            migrate_to_prompt_files()?;
+        }
+
+        Some(Commands::Undo) => {
+            undo_last_command()?;
+        }
+
+        Some(Commands::Trash) => {
+            list_trash()?;
+        }
+
+        Some(Commands::RestoreRemoved { alias, force }) => {
+            restore_removed_alias(&alias, force)?;
+        }
+
+        Some(Commands::History { alias, limit }) => {
+            show_history(alias.as_deref(), limit)?;
+        }
+
+        Some(Commands::Runs { alias, limit }) => {
+            show_runs(alias.as_deref(), limit)?;
+        }
+
+        Some(Commands::Chain {
+            aliases,
+            keep_going,
+            agent_args,
+        }) => {
+            let verbose = Verbose(global.verbose || cli.verbose || verbose_from_env());
+            code = run_chain(&aliases, keep_going, &agent_args, verbose)?;
+        }
+
+        // `explicit_run_args` takes `qwk run` before clap sees it, so this is a fallback;
+        // clap would drop a `--` right after the name
+        Some(Commands::Run { shortcut, args }) => {
+            let verbose = Verbose(global.verbose || cli.verbose || verbose_from_env());
+            let mut full_args = vec!["qwk".to_string(), shortcut.clone()];
+            full_args.extend(args);
+            code = run_shortcut(&shortcut, &full_args, verbose, false)?;
+        }
+
+        Some(Commands::Last { dry_run }) => {
+            let verbose = Verbose(global.verbose || cli.verbose || verbose_from_env());
+            code = run_last(dry_run, verbose)?;
+        }
+
+        Some(Commands::Sync { pull, push }) => {
+            sync_config(pull, push)?;
+        }
+
+        Some(Commands::ConfigPath) => {
+            println!("{}", get_settings_file().display());
+        }
+
+        Some(Commands::Archive { alias, force }) => {
+            archive_alias(&alias, force)?;
+        }
+
+        Some(Commands::Unarchive { alias, force }) => {
+            unarchive_alias(&alias, force)?;
+        }
+
+        Some(Commands::Pin { alias }) => {
+            set_pinned(&alias, true)?;
+        }
+
+        Some(Commands::Unpin { alias }) => {
+            set_pinned(&alias, false)?;
+        }
+
+        Some(Commands::Remove { alias, local }) => {
+            let aliases_file = if local {
+                local_aliases_file_or_fail(false)?
+            } else {
+                get_aliases_file()
+            };
+            let mut aliases = load_aliases_from(&aliases_file)?;
+
+            if let Some(entry) = aliases.remove(&alias) {
+                if !local {
+                    let mut trash = load_trash()?;
+                    record_undo_or_fail(&format!("remove '{}'", alias))?;
+                    // Trashed first so a failure in between leaves a copy rather than nothing
+                    trash_alias(&mut trash, &alias, entry, chrono::Utc::now());
+                    if let Err(e) = save_trash(&trash) {
+                        return Err(QwkError::io("Error saving the trash", e));
+                    }
+                }
+                if let Err(e) = save_aliases_file(&aliases_file, &aliases, local) {
+                    return Err(QwkError::io("Error saving aliases after removal", e));
+                }
+                if !local {
+                    record_history(HistoryEntry::new("remove", vec![alias.clone()]));
+                }
+                status!("Shortcut '{}' removed successfully", alias);
+            } else {
+                let matches = closest_matches(&alias, aliases.keys().map(String::as_str));
+                eprintln!(
+                    "Shortcut '{}' does not exist{}",
+                    alias,
+                    did_you_mean(&matches)
+                );
+            }
+        }
+
+        Some(Commands::Reset { dry_run, yes }) => {
+            let plan = plan_reset(
+                &get_aliases_file(),
+                &get_backup_file(&get_current_datetime()),
+            );
+
+            if dry_run {
+                print_dry_run(&plan);
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            if !yes && load_settings_or_fail()?.confirm_destructive {
+                let stdin = io::stdin();
+                let is_tty = stdin.is_terminal();
+                match confirm_reset(&mut stdin.lock(), is_tty) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        eprintln!("Reset cancelled.");
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                    Err(ConfirmError::NotATerminal) => {
+                        return Err(QwkError::new(
+                            "Error: --reset asks before removing all shortcuts, and stdin isn't a terminal to answer on. Pass --yes to reset anyway",
+                        ));
+                    }
+                    Err(e) => {
+                        return Err(QwkError::new(format!("Error: {}", e)));
+                    }
+                }
+            }
+
+            if !plan.is_empty() {
+                record_undo_or_fail("reset")?;
+            }
+            if let Err(e) = apply_plan(&plan) {
+                return Err(QwkError::io("Error resetting shortcuts", e));
+            }
+
+            match plan.first() {
+                Some(PlannedAction::Backup { to, .. }) => {
+                    status!("Backup created: {}", to.display());
+                }
+                _ => {
+                    status!("No existing aliases file to backup.");
+                }
+            }
+
+            if !plan.is_empty() {
+                record_history(HistoryEntry::new("reset", vec![]));
+            }
+            status!("All shortcuts have been reset.");
+        }
+
+        None => {
+            if let Some(shortcut) = cli.shortcut {
+                // This case is handled above, but included for completeness
+                return Err(QwkError::alias_not_found(shortcut));
+            } else {
+                // Show help if no command provided
+                let mut cmd = Cli::command();
+                cmd.print_help().unwrap();
+            }
+        }
+    }
+
+    if modifies_config {
+        auto_commit();
+    }
+    Ok(as_exit_code(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_shortcut_args() {
+        assert_eq!(
+            parse_shortcut_args("summ", &[]).unwrap(),
+            ShortcutArgs::default()
+        );
+
+        let parsed = parse_shortcut_args(
+            "summ",
+            &strings(&[
+                "--var",
+                "language=rust",
+                "--var=aspect=a=b",
+                "--",
+                "--var",
+                "x=y",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(parsed.vars.len(), 2);
+        assert_eq!(parsed.vars["language"], "rust");
+        assert_eq!(parsed.vars["aspect"], "a=b");
+        assert_eq!(parsed.agent_args, strings(&["--var", "x=y"]));
+
+        let parsed = parse_shortcut_args(
+            "explain",
+            &strings(&["cannot borrow", "--var", "k=v", "-x", "--", "-y"]),
+        )
+        .unwrap();
+        assert!(!parsed.allow_missing_env);
+        assert_eq!(parsed.positional, strings(&["cannot borrow", "-x"]));
+        assert_eq!(parsed.agent_args, strings(&["-y"]));
+
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--allow-missing-env", "--no-stdin"])).unwrap();
+        assert!(parsed.allow_missing_env);
+        assert!(parsed.no_stdin);
+
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--with", "fast", "notes.md"])).unwrap();
+        assert_eq!(parsed.with_agent.as_deref(), Some("fast"));
+        assert_eq!(parsed.positional, strings(&["notes.md"]));
+        let parsed = parse_shortcut_args("summ", &strings(&["--with=local"])).unwrap();
+        assert_eq!(parsed.with_agent.as_deref(), Some("local"));
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--stdin-prompt", "--", "-q"])).unwrap();
+        assert!(parsed.stdin_prompt);
+        assert_eq!(parsed.agent_args, ["-q"]);
+        let parsed = parse_shortcut_args("summ", &strings(&["--cwd", "~/code", "x"])).unwrap();
+        assert_eq!(parsed.cwd.as_deref(), Some("~/code"));
+        assert_eq!(parsed.positional, ["x"]);
+        let parsed = parse_shortcut_args("summ", &strings(&["--cwd=/tmp"])).unwrap();
+        assert_eq!(parsed.cwd.as_deref(), Some("/tmp"));
+        assert!(parse_shortcut_args("summ", &strings(&["--cwd"])).is_err());
+        let parsed = parse_shortcut_args("summ", &strings(&["--timeout", "30"])).unwrap();
+        assert_eq!(parsed.timeout_secs, Some(30));
+        let parsed = parse_shortcut_args("summ", &strings(&["--timeout=0"])).unwrap();
+        assert_eq!(parsed.timeout_secs, Some(0));
+        assert!(parse_shortcut_args("summ", &strings(&["--timeout", "1.5"])).is_err());
+        assert!(parse_shortcut_args("summ", &strings(&["--timeout"])).is_err());
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--dry-run", "--", "--dry-run"])).unwrap();
+        assert!(parsed.dry_run);
+        assert_eq!(parsed.agent_args, ["--dry-run"]);
+        assert!(parse_shortcut_args("summ", &strings(&["--with"])).is_err());
+
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--output", "out.md", "--tee"])).unwrap();
+        assert_eq!(parsed.output.as_deref(), Some("out.md"));
+        assert!(parsed.tee && !parsed.append);
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--output=out.md", "--append"])).unwrap();
+        assert!(parsed.append);
+        assert!(parse_shortcut_args("summ", &strings(&["--append"])).is_err());
+        assert!(
+            parse_shortcut_args("summ", &strings(&["--yes"]))
+                .unwrap()
+                .yes
+        );
+        let parsed =
+            parse_shortcut_args("summ", &strings(&["--preview", "x", "--notify"])).unwrap();
+        assert!(parsed.preview && parsed.notify);
+        assert_eq!(parsed.positional, ["x"]);
+        assert!(parse_shortcut_args("summ", &strings(&["--output="])).is_err());
+
+        assert!(parse_shortcut_args("summ", &strings(&["--var"])).is_err());
+        assert!(parse_shortcut_args("summ", &strings(&["--var", "novalue"])).is_err());
+        assert!(parse_shortcut_args("summ", &strings(&["--var", "=x"])).is_err());
+        assert!(parse_shortcut_args("summ", &strings(&["--model"])).is_err());
+    }
+
+    #[test]
+    fn test_explicit_run_args() {
+        assert_eq!(
+            explicit_run_args(&strings(&["qwk", "run", "summ", "x", "--", "-n"])),
+            Some(strings(&["qwk", "summ", "x", "--", "-n"]))
+        );
+        assert_eq!(
+            explicit_run_args(&strings(&["qwk", "--run", "--", "--odd", "--", "-n"])),
+            Some(strings(&["qwk", "--odd", "--", "-n"]))
+        );
+        for args in [
+            strings(&["qwk", "summ", "run"]),
+            strings(&["qwk", "run"]),
+            strings(&["qwk", "run", "--"]),
+            strings(&["qwk", "run", "--help"]),
+        ] {
+            assert_eq!(explicit_run_args(&args), None, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_qwk_error_codes() {
+        assert_eq!(QwkError::new("Error").code(), 1);
+        assert_eq!(QwkError::with_code(130, "Cancelled.").code(), 130);
+        let Err(usage) = Cli::try_parse_from(["qwk", "--no-such-flag"]) else {
+            panic!("--no-such-flag parsed");
+        };
+        assert_eq!(QwkError::Usage(usage).code(), 2);
+
+        assert_eq!(as_exit_code(0), ExitCode::SUCCESS);
+        assert_eq!(as_exit_code(124), ExitCode::from(124));
+        assert_eq!(as_exit_code(-1), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn test_take_global_args() {
+        let mut args = strings(&["qwk", "--profile", "work", "review", "--", "-x"]);
+        assert_eq!(
+            take_global_args(&mut args),
+            GlobalArgs {
+                profile: Some("work".to_string()),
+                no_local: false,
+                verbose: false,
+                quiet: false,
+            }
+        );
+        assert_eq!(args, strings(&["qwk", "review", "--", "-x"]));
+
+        let mut args = strings(&["qwk", "-v", "--verbose", "review", "-v"]);
+        assert!(take_global_args(&mut args).verbose);
+        assert_eq!(args, strings(&["qwk", "review", "-v"]));
+
+        let mut args = strings(&["qwk", "-q", "--set", "summ", "Summarize"]);
+        assert!(take_global_args(&mut args).quiet);
+        assert_eq!(args, strings(&["qwk", "--set", "summ", "Summarize"]));
+
+        let mut args = strings(&["qwk", "--no-local", "--profile=work", "--list"]);
+        assert_eq!(
+            take_global_args(&mut args),
+            GlobalArgs {
+                profile: Some("work".to_string()),
+                no_local: true,
+                verbose: false,
+                quiet: false,
+            }
+        );
+        assert_eq!(args, strings(&["qwk", "--list"]));
+
+        // Left for clap to handle or reject
+        for args in [
+            strings(&["qwk", "--list", "--profile", "work", "--no-local"]),
+            strings(&["qwk", "review", "--no-local"]),
+            strings(&["qwk", "--profile"]),
+            strings(&["qwk"]),
+        ] {
+            let mut taken = args.clone();
+            assert_eq!(take_global_args(&mut taken), GlobalArgs::default());
+            assert_eq!(taken, args);
+        }
+    }
+
+    #[test]
+    fn test_format_list_summary() {
+        assert_eq!(
+            format_list_summary(12, "claude --model sonnet"),
+            "12 shortcuts, agent: claude --model sonnet"
+        );
+        assert_eq!(format_list_summary(1, "codex"), "1 shortcut, agent: codex");
+        assert_eq!(
+            format_list_summary(0, "claude"),
+            "0 shortcuts, agent: claude"
+        );
+    }
+
+    #[test]
+    fn test_sort_alias_names() {
+        let names = ["alpha", "beta", "gamma", "delta"].map(String::from);
+        let mut usage = HashMap::new();
+        usage.insert(
+            "gamma".to_string(),
+            UsageRecord {
+                count: 1,
+                last_used: Some(Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap()),
+            },
+        );
+        usage.insert(
+            "beta".to_string(),
+            UsageRecord {
+                count: 5,
+                last_used: Some(Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap()),
+            },
+        );
+
+        let sorted = |sort| {
+            let mut sorted: Vec<_> = names.iter().collect();
+            sort_alias_names(&mut sorted, &usage, sort);
+            sorted.into_iter().map(String::as_str).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(ListSort::Name), ["alpha", "beta", "delta", "gamma"]);
+        assert_eq!(
+            sorted(ListSort::Recent),
+            ["gamma", "beta", "alpha", "delta"]
+        );
+        assert_eq!(sorted(ListSort::Used), ["beta", "gamma", "alpha", "delta"]);
+    }
+
+    #[test]
+    fn test_group_alias_names() {
+        let names = ["alpha", "work/standup", "home/todo", "work/retro", "zeta"];
+        assert_eq!(
+            group_alias_names(&names),
+            vec![
+                ListItem::Alias("alpha"),
+                ListItem::Group("work", vec!["work/standup", "work/retro"]),
+                ListItem::Group("home", vec!["home/todo"]),
+                ListItem::Alias("zeta"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        crate::config::with_temp_config_dir(|dir| {
+            let mut review = AliasEntry::new("Review $file");
+            review.tags = vec!["code".to_string()];
+            let aliases = HashMap::from([
+                ("summ".to_string(), AliasEntry::new("Summarize this")),
+                ("review".to_string(), review),
+            ]);
+            save_aliases(&aliases).unwrap();
+
+            for (format, file) in [
+                (TransferFormat::Json, "export.json"),
+                (TransferFormat::Yaml, "export.yaml"),
+                (TransferFormat::Toml, "export.toml"),
+            ] {
+                let path = dir.join(file);
+                export_aliases(Some(path.clone()), format, None).unwrap();
+                fs::remove_file(get_aliases_file()).unwrap();
+
+                import_aliases(
+                    Some(path),
+                    ImportOptions {
+                        format: None,
+                        strategy: ImportStrategy::Overwrite,
+                        include_agent: false,
+                        dry_run: false,
+                        prefix: None,
+                    },
+                )
+                .unwrap();
+                let imported = load_aliases().unwrap();
+                assert_eq!(imported.len(), 2, "{:?}", format);
+                assert_eq!(imported["summ"].prompt, "Summarize this");
+                assert_eq!(imported["review"].tags, ["code"]);
+            }
+        });
+    }
+
+    #[test]
+    fn test_import_strategy_and_agent() {
+        crate::config::with_temp_config_dir(|dir| {
+            let path = dir.join("theirs.json");
+            fs::write(
+                &path,
+                r#"{"version": 2, "agent": "codex", "aliases": {"summ": {"prompt": "Theirs"}, "new": {"prompt": "New"}}}"#,
+            )
+            .unwrap();
+            let import = |strategy, include_agent| {
+                import_aliases(
+                    Some(path.clone()),
+                    ImportOptions {
+                        format: None,
+                        strategy,
+                        include_agent,
+                        dry_run: false,
+                        prefix: None,
+                    },
+                )
+                .unwrap()
+            };
+
+            save_aliases(&HashMap::from([(
+                "summ".to_string(),
+                AliasEntry::new("Mine"),
+            )]))
+            .unwrap();
+            import(ImportStrategy::Skip, false);
+            let aliases = load_aliases().unwrap();
+            assert_eq!(aliases["summ"].prompt, "Mine");
+            assert_eq!(aliases["new"].prompt, "New");
+            assert_eq!(get_agent(), "claude");
+            assert_eq!(find_backups(&get_backup_dir()).unwrap().len(), 1);
+
+            import(ImportStrategy::Overwrite, true);
+            assert_eq!(load_aliases().unwrap()["summ"].prompt, "Theirs");
+            assert_eq!(get_agent(), "codex");
+        });
+    }
+
+    #[test]
+    fn test_import_dry_run_touches_nothing() {
+        crate::config::with_temp_config_dir(|dir| {
+            let path = dir.join("theirs.yaml");
+            fs::write(&path, "summ: Theirs\nnew: New\n").unwrap();
+            save_aliases(&HashMap::from([(
+                "summ".to_string(),
+                AliasEntry::new("Mine"),
+            )]))
+            .unwrap();
+            let before = fs::read_to_string(get_aliases_file()).unwrap();
+
+            import_aliases(
+                Some(path),
+                ImportOptions {
+                    format: None,
+                    strategy: ImportStrategy::Overwrite,
+                    include_agent: true,
+                    dry_run: true,
+                    prefix: None,
+                },
+            )
+            .unwrap();
+            assert_eq!(fs::read_to_string(get_aliases_file()).unwrap(), before);
+            assert!(find_backups(&get_backup_dir()).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_undo_reverts_the_last_command_once() {
+        crate::config::with_temp_config_dir(|_| {
+            save_aliases(&HashMap::from([(
+                "summ".to_string(),
+                AliasEntry::new("Summarize"),
+            )]))
+            .unwrap();
+
+            set_pinned("summ", true).unwrap();
+            assert!(load_aliases().unwrap()["summ"].pinned);
+            assert_eq!(load_undo_entry().unwrap().unwrap().command, "pin 'summ'");
+
+            undo_last_command().unwrap();
+            assert!(!load_aliases().unwrap()["summ"].pinned);
+            assert_eq!(load_undo_entry().unwrap(), None);
+
+            // A second undo has nothing left to revert
+            undo_last_command().unwrap();
+            assert_eq!(load_aliases().unwrap()["summ"].prompt, "Summarize");
+        });
+    }
 }
@@ -1,102 +1,627 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use crate::config::{ensure_config_dir, get_config_dir, load_aliases};
+use clap::CommandFactory;
 
-#[derive(Debug)]
+use crate::cli::{Cli, take_global_args};
+use crate::config::{
+    AGENT_PRESETS, AliasEntry, DEFAULT_PROFILE, ensure_config_dir, get_config_dir, list_profiles,
+    load_agents, load_effective_alias_names, load_effective_aliases,
+};
+use crate::crypto;
+use crate::output::status;
+use crate::utils::{confirm_default_yes, find_executable, truncate_prompt};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Shell {
     Bash,
     Zsh,
     Fish,
+    PowerShell,
+}
+
+impl Shell {
+    pub const ALL: [Shell; 4] = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell];
+
+    /// The name `--shell` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "powershell",
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Shell::ALL
+            .into_iter()
+            .find(|shell| shell.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<_> = Shell::ALL.iter().map(|shell| shell.name()).collect();
+                format!(
+                    "unknown shell '{}'; the supported shells are {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Width a description is cut to next to a candidate.
+const COMPLETION_DESCRIPTION_LENGTH: usize = 40;
+
+/// Formats a candidate for zsh's `_describe`, which splits on the first unescaped colon.
+pub fn format_described_completion(name: &str, description: Option<&str>) -> String {
+    let name = name.replace(':', "\\:");
+    match description {
+        Some(description) => format!(
+            "{}:{}",
+            name,
+            truncate_prompt(description, COMPLETION_DESCRIPTION_LENGTH)
+        ),
+        None => name,
+    }
+}
+
+/// Formats a candidate for fish, which takes a description after a tab. Tabs and newlines
+/// would end the name or the candidate early, so they become spaces.
+pub fn format_fish_completion(name: &str, description: Option<&str>) -> String {
+    let name = name.replace(['\t', '\n', '\r'], " ");
+    match description {
+        Some(description) => format!(
+            "{}\t{}",
+            name,
+            truncate_prompt(description, COMPLETION_DESCRIPTION_LENGTH)
+        ),
+        None => name,
+    }
+}
+
+/// Alias candidates for a partial word. Namespaced aliases (`group/name`) complete to
+/// their `group/` first and only list members once the partial input contains a slash.
+pub fn alias_completion_candidates<'a>(names: &[&'a str], partial: &str) -> Vec<&'a str> {
+    let mut candidates: Vec<&str> = names
+        .iter()
+        .map(|name| match name.split_once('/') {
+            Some((group, _)) if !partial.contains('/') => &name[..group.len() + 1],
+            _ => name,
+        })
+        .filter(|candidate| candidate.starts_with(partial))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Sorts candidates by name with pinned aliases first, since some shells pick the first
+/// candidate on a single TAB.
+pub fn sort_completions(completions: &mut [&str], is_pinned: impl Fn(&str) -> bool) {
+    completions.sort_by_key(|completion| (!is_pinned(completion), *completion));
+}
+
+/// Profile names for the word after `--profile`, the default profile first.
+pub fn profile_completion_candidates<'a>(profiles: &'a [String], partial: &str) -> Vec<&'a str> {
+    std::iter::once(DEFAULT_PROFILE)
+        .chain(profiles.iter().map(String::as_str))
+        .filter(|name| name.starts_with(partial))
+        .collect()
+}
+
+/// Commands whose first argument is the name of an existing shortcut.
+const ALIAS_COMMANDS: &[&str] = &[
+    "--show",
+    "--remove",
+    "--pin",
+    "--unpin",
+    "--archive",
+    "--history",
+    "--runs",
+    "run",
+    "--run",
+];
+
+/// Flags that can follow `--agent`.
+const AGENT_FLAGS: &[&str] = &[
+    "--add-arg",
+    "--check",
+    "--clear-fallbacks",
+    "--fallback",
+    "--force",
+    "--porcelain",
+    "--preset",
+    "--remove-arg",
+    "--shell",
+    "--stdin-prompt",
+    "--unset",
+];
+
+/// The commands offered as the first word.
+const COMMANDS: &[&str] = &[
+    "--set",
+    "--agent",
+    "--agent-add",
+    "--agent-use",
+    "--agents",
+    "--agent-env",
+    "--presets",
+    "--list",
+    "--show",
+    "--count",
+    "--pin",
+    "--unpin",
+    "--archive",
+    "--unarchive",
+    "--tags",
+    "--stats",
+    "--remove",
+    "--reset",
+    "--backups",
+    "--restore",
+    "--prune-backups",
+    "--encrypt",
+    "--migrate-to-files",
+    "--undo",
+    "--trash",
+    "--restore-removed",
+    "--history",
+    "--runs",
+    "--last",
+    "--run",
+    "--chain",
+    "--sync",
+    "--import",
+    "--export",
+    "--profile",
+    "--profiles",
+    "--no-local",
+    "--setup-completion",
+    "--remove-completion",
+    "--help",
+];
+
+/// What completion reads from the config directory, loaded once per TAB.
+#[derive(Debug, Default)]
+pub struct CompletionData {
+    pub aliases: HashMap<String, AliasEntry>,
+    pub profiles: Vec<String>,
+    pub agents: Vec<String>,
+    /// The known agent programs found on `PATH`
+    pub programs: Vec<String>,
+}
+
+/// Programs offered after `--agent` when they're on `PATH`: those of the presets, and a few
+/// more that take a prompt as their last argument.
+fn known_agent_programs() -> impl Iterator<Item = &'static str> {
+    AGENT_PRESETS
+        .iter()
+        .filter_map(|preset| preset.command.split_whitespace().next())
+        .chain(["sgpt"])
+}
+
+impl CompletionData {
+    /// Reads only what completing the word after `previous` needs, since it happens on every
+    /// TAB: the prompt files only when they're shown, with `describe`. Completion has
+    /// nowhere to report a corrupted file, so what can't be read is left out instead.
+    pub fn load_for(previous: Option<&str>, describe: bool) -> Self {
+        let mut data = CompletionData::default();
+        match previous {
+            Some("--profile") => data.profiles = list_profiles().unwrap_or_default(),
+            Some("--agent-use" | "--with" | "--for") => {
+                data.agents = load_agents().unwrap_or_default().into_keys().collect();
+            }
+            Some("--agent") => {
+                data.programs = known_agent_programs()
+                    .filter(|program| find_executable(program).is_some())
+                    .map(str::to_string)
+                    .collect();
+            }
+            Some("--preset" | "--agent-env") => {}
+            _ if describe => data.aliases = load_effective_aliases().unwrap_or_default().aliases,
+            _ => data.aliases = load_effective_alias_names().unwrap_or_default().aliases,
+        }
+        data
+    }
+}
+
+fn starting_with<'a>(names: impl IntoIterator<Item = &'a str>, partial: &str) -> Vec<String> {
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(partial))
+        .map(str::to_string)
+        .collect()
 }
 
-pub fn generate_completions(partial: Option<String>) {
-    let aliases = load_aliases();
-    let mut completions = Vec::new();
+/// Shortcut names for `partial`, pinned ones first, described in the way of `shell` when it
+/// shows descriptions. `commands` are offered after the shortcuts.
+fn alias_completions(
+    data: &CompletionData,
+    partial: &str,
+    commands: &[&str],
+    shell: Option<Shell>,
+) -> Vec<String> {
+    let names: Vec<&str> = data.aliases.keys().map(String::as_str).collect();
+    let mut completions = alias_completion_candidates(&names, partial);
+    completions.extend(
+        commands
+            .iter()
+            .copied()
+            .filter(|command| command.starts_with(partial)),
+    );
+
+    // A group counts as pinned when any of its members is
+    let mut pinned = HashSet::new();
+    for (name, _) in data.aliases.iter().filter(|(_, entry)| entry.pinned) {
+        pinned.insert(name.as_str());
+        if let Some((group, _)) = name.split_once('/') {
+            pinned.insert(&name[..group.len() + 1]);
+        }
+    }
+    sort_completions(&mut completions, |completion| pinned.contains(completion));
+    completions
+        .into_iter()
+        .map(|completion| {
+            // A shortcut without a description of its own is shown with its prompt
+            let description = data
+                .aliases
+                .get(completion)
+                .map(|entry| entry.description.as_deref().unwrap_or(&entry.prompt))
+                .filter(|description| !description.is_empty());
+            match shell {
+                Some(Shell::Zsh) => format_described_completion(completion, description),
+                Some(Shell::Fish) => format_fish_completion(completion, description),
+                _ => completion.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// The candidates for `partial`, given the `words` of the command line before it, starting
+/// with `qwk` itself, and described for `shell` when it shows descriptions. Completion
+/// scripts from before `words` was passed, still found in rc files, only give the word
+/// before it as `previous`.
+pub fn completion_candidates(
+    words: &[String],
+    previous: Option<&str>,
+    partial: &str,
+    shell: Option<Shell>,
+    data: &CompletionData,
+) -> Vec<String> {
+    let previous = words.last().map(String::as_str).or(previous);
+
+    match previous {
+        Some("--profile") => {
+            return profile_completion_candidates(&data.profiles, partial)
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+        }
+        Some("--agent") => {
+            let programs = data.programs.iter().map(String::as_str);
+            return starting_with(programs.chain(AGENT_FLAGS.iter().copied()), partial);
+        }
+        Some("--preset") => {
+            return starting_with(AGENT_PRESETS.iter().map(|preset| preset.name), partial);
+        }
+        Some("--agent-env") => return starting_with(["list", "set", "unset"], partial),
+        Some("--agent-use" | "--with" | "--for") => {
+            return starting_with(data.agents.iter().map(String::as_str), partial);
+        }
+        _ => {}
+    }
+
+    // Where the word being completed is, leaving out the global flags before the command
+    let mut line = words.to_vec();
+    take_global_args(&mut line);
+    let (command, position) = match previous {
+        _ if !words.is_empty() => (line.get(1).map(String::as_str), line.len()),
+        Some(previous) if ALIAS_COMMANDS.contains(&previous) => (Some(previous), 2),
+        _ => (None, 1),
+    };
 
-    // Add command completions
-    let commands = vec![
-        "--set",
-        "--agent",
-        "--list",
-        "--remove",
-        "--reset",
-        "--setup-completion",
-        "--help",
-    ];
+    match (command, position) {
+        (None, _) => alias_completions(data, partial, COMMANDS, shell),
+        (Some(command), 2) if ALIAS_COMMANDS.contains(&command) => {
+            alias_completions(data, partial, &[], shell)
+        }
+        // The arguments of a shortcut or of another command's options aren't known here
+        _ => vec![],
+    }
+}
 
-    // Add alias completions
-    for alias in aliases.keys() {
-        completions.push(alias.as_str());
+/// The text of `word` as typed on a bash or fish command line, which may still be missing
+/// its closing quote. A word that can't be read is taken as it is.
+pub fn unquote_word(word: &str) -> String {
+    for closing in ["", "'", "\""] {
+        if let Some(mut parts) = shlex::split(&format!("{}{}", word, closing))
+            && parts.len() == 1
+        {
+            return parts.remove(0);
+        }
     }
+    word.to_string()
+}
 
-    // Add command completions
-    completions.extend(commands);
+/// `candidate` quoted for bash, which puts it on the command line as it is.
+pub fn quote_for_bash(candidate: &str) -> String {
+    shlex::try_quote(candidate).map_or_else(|_| candidate.to_string(), |quoted| quoted.into_owned())
+}
 
-    // Filter by partial input if provided
-    if let Some(partial_input) = partial {
-        if !partial_input.is_empty() {
-            completions.retain(|completion| completion.starts_with(&partial_input));
+/// Prints the candidates for the word being completed, one per line, for the completion
+/// scripts. They pass the command line up to that word after `--`, which also works for a
+/// word such as `--` itself; older scripts pass it as `partial` instead. `--with-descriptions`
+/// is what older zsh scripts pass for `--shell zsh`.
+pub fn generate_completions(
+    partial: Option<String>,
+    with_descriptions: bool,
+    shell: Option<Shell>,
+    previous: Option<String>,
+    mut words: Vec<String>,
+) {
+    // Asking for a passphrase would hang the shell mid-completion
+    crypto::disable_passphrase_prompt();
+    let shell = shell.or(with_descriptions.then_some(Shell::Zsh));
+    // Bash passes the words as typed, quotes and all, and fish the one being completed
+    match shell {
+        Some(Shell::Bash) => words.iter_mut().for_each(|word| *word = unquote_word(word)),
+        Some(Shell::Fish) => {
+            if let Some(current) = words.last_mut() {
+                *current = unquote_word(current);
+            }
         }
+        _ => {}
     }
+    let (partial, words) = match words.split_last() {
+        Some((current, before)) => (current.clone(), before),
+        None => (partial.unwrap_or_default(), &[][..]),
+    };
+    let previous = words.last().map(String::as_str).or(previous.as_deref());
+    let describe = matches!(shell, Some(Shell::Zsh | Shell::Fish));
+    let data = CompletionData::load_for(previous, describe);
 
-    // Sort and output
-    completions.sort();
+    let mut completions = completion_candidates(words, previous, &partial, shell, &data);
+    if shell == Some(Shell::Bash) {
+        completions = completions.iter().map(|c| quote_for_bash(c)).collect();
+    }
+    let mut out = io::BufWriter::new(io::stdout().lock());
     for completion in completions {
-        println!("{}", completion);
+        // The shell may stop reading early, which is no reason to panic
+        if writeln!(out, "{}", completion).is_err() {
+            return;
+        }
     }
+    let _ = out.flush();
 }
 
-pub fn detect_shell() -> Option<Shell> {
-    if let Ok(shell) = env::var("SHELL") {
+/// Detects the shell from `$SHELL`, falling back to `PSModulePath` for PowerShell, which
+/// doesn't set `$SHELL`. Windows has a system-wide `PSModulePath` even in cmd, but only
+/// PowerShell adds module directories under the user's profile to it.
+pub fn shell_from_env(
+    shell: Option<&str>,
+    ps_module_path: Option<&str>,
+    user_home: Option<&Path>,
+) -> Option<Shell> {
+    if let Some(shell) = shell {
         if shell.contains("bash") {
-            Some(Shell::Bash)
+            return Some(Shell::Bash);
         } else if shell.contains("zsh") {
-            Some(Shell::Zsh)
+            return Some(Shell::Zsh);
         } else if shell.contains("fish") {
-            Some(Shell::Fish)
-        } else {
-            None
+            return Some(Shell::Fish);
         }
-    } else {
-        None
     }
+
+    let (ps_module_path, user_home) = (ps_module_path?, user_home?);
+    env::split_paths(ps_module_path)
+        .any(|dir| dir.starts_with(user_home))
+        .then_some(Shell::PowerShell)
+}
+
+/// The shell a process name stands for, as `/proc/<pid>/comm` or `ps -o comm=` give it:
+/// `fish`, `-zsh` for a login shell, or a whole path on macOS.
+pub fn shell_from_process_name(name: &str) -> Option<Shell> {
+    let name = name.trim().rsplit(['/', '\\']).next()?;
+    let name = name.trim_start_matches('-');
+    match name.strip_suffix(".exe").unwrap_or(name) {
+        "bash" => Some(Shell::Bash),
+        "zsh" => Some(Shell::Zsh),
+        "fish" => Some(Shell::Fish),
+        "pwsh" | "powershell" => Some(Shell::PowerShell),
+        _ => None,
+    }
+}
+
+/// The name of the process qwk was started from.
+#[cfg(target_os = "linux")]
+fn parent_process_name() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    fs::read_to_string(format!("/proc/{}/comm", ppid)).ok()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn parent_process_name() -> Option<String> {
+    let ppid = std::os::unix::process::parent_id();
+    let output = std::process::Command::new("ps")
+        .args(["-o", "comm=", "-p", &ppid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(not(unix))]
+fn parent_process_name() -> Option<String> {
+    None
+}
+
+/// Where the shell to set completion up for was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellSource {
+    /// qwk was run from it
+    ParentProcess,
+    /// It's the login shell
+    ShellVar,
+    PsModulePath,
+}
+
+impl fmt::Display for ShellSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ShellSource::ParentProcess => "the parent process",
+            ShellSource::ShellVar => "$SHELL",
+            ShellSource::PsModulePath => "PSModulePath",
+        })
+    }
+}
+
+/// Detects the shell qwk is typed into, which isn't always the login shell in `$SHELL`:
+/// that of the parent process when it's one, or else the one the environment names.
+pub fn detect_shell_with_source() -> Option<(Shell, ShellSource)> {
+    if let Some(shell) = parent_process_name()
+        .as_deref()
+        .and_then(shell_from_process_name)
+    {
+        return Some((shell, ShellSource::ParentProcess));
+    }
+    if let Some(shell) = shell_from_env(env::var("SHELL").ok().as_deref(), None, None) {
+        return Some((shell, ShellSource::ShellVar));
+    }
+    shell_from_env(
+        None,
+        env::var("PSModulePath").ok().as_deref(),
+        dirs::home_dir().as_deref(),
+    )
+    .map(|shell| (shell, ShellSource::PsModulePath))
+}
+
+pub fn detect_shell() -> Option<Shell> {
+    detect_shell_with_source().map(|(shell, _)| shell)
+}
+
+/// `shell` if given, or else the detected one, saying where it was found.
+fn shell_or_detected(shell: Option<Shell>) -> io::Result<Shell> {
+    if let Some(shell) = shell {
+        return Ok(shell);
+    }
+    let (shell, source) = detect_shell_with_source().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not detect current shell; name it with --shell",
+        )
+    })?;
+    status!("Detected {} from {}", shell, source);
+    Ok(shell)
 }
 
 pub fn get_completion_script(shell: &Shell) -> String {
     match shell {
         Shell::Bash => r#"
 _qwk_complete() {
-    local cur="${COMP_WORDS[COMP_CWORD]}"
-    COMPREPLY=($(qwk --complete "$cur" 2>/dev/null))
+    # Keep qwk's order so pinned shortcuts come first (bash 4.4+)
+    compopt -o nosort 2>/dev/null
+    # One candidate per line, already quoted by qwk, so that names with spaces or globs in
+    # them stay whole (read rather than mapfile, which bash 3 lacks)
+    COMPREPLY=()
+    local candidate
+    while IFS= read -r candidate; do
+        COMPREPLY+=("$candidate")
+    done < <(qwk --complete --shell bash -- "${COMP_WORDS[@]:0:COMP_CWORD+1}" 2>/dev/null)
+    # Don't add a space after a group so its members can be completed next
+    if [[ ${#COMPREPLY[@]} -eq 1 && ${COMPREPLY[0]} == */ ]]; then
+        compopt -o nospace
+    fi
 }
 complete -F _qwk_complete qwk
 "#
         .to_string(),
         Shell::Zsh => r#"
 _qwk_complete() {
-    local completions
-    completions=($(qwk --complete "$1" 2>/dev/null))
-    compadd -a completions
+    local -a completions groups
+    # (Q) unquotes the words as typed; compadd quotes the candidates itself
+    completions=("${(@f)$(qwk --complete --shell zsh -- "${(@Q)words[1,CURRENT]}" 2>/dev/null)}")
+    groups=(${(M)completions:#*/})
+    completions=(${completions:#*/})
+    _describe -V 'qwk shortcuts' completions
+    (( ${#groups} )) && compadd -S '' -- $groups
 }
 compdef _qwk_complete qwk
 "#
         .to_string(),
         Shell::Fish => r#"
 function __qwk_complete
-    qwk --complete (commandline -ct) 2>/dev/null
+    qwk --complete --shell fish -- (commandline -opc) (commandline -ct) 2>/dev/null
 end
-complete -c qwk -f -a "(__qwk_complete)"
+complete -c qwk -f -k -a "(__qwk_complete)"
+"#
+        .to_string(),
+        Shell::PowerShell => r#"
+Register-ArgumentCompleter -Native -CommandName qwk -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements |
+        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |
+        ForEach-Object { $_.ToString() })
+    $partial = $wordToComplete.Trim([char]39, [char]34)
+    qwk --complete -- @words "$partial" 2>$null | ForEach-Object {
+        $text = if ($_ -match '[\s''"`$;,(){}@#|&<>]') { "'" + ($_ -replace "'", "''") + "'" } else { $_ }
+        [System.Management.Automation.CompletionResult]::new($text, $_, 'ParameterValue', $_)
+    }
+}
 "#
         .to_string(),
     }
 }
 
+/// The commands as the static scripts should see them. Each is typed as its long flag, as
+/// in `qwk --set`, while the generators only know subcommands by name, so the flag becomes
+/// the name; and `qwk help` would run a shortcut.
+fn static_completion_command() -> clap::Command {
+    Cli::command()
+        .disable_help_subcommand(true)
+        .mut_subcommands(|command| match command.get_long_flag() {
+            Some(flag) => {
+                let name = format!("--{}", flag);
+                command.name(name)
+            }
+            None => command,
+        })
+}
+
+/// The static script of `qwk --completions`, generated from the commands and options
+/// alone. It runs nothing while completing, so it can't offer shortcut names.
+pub fn static_completion_script(shell: clap_complete::Shell) -> String {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut static_completion_command(), "qwk", &mut script);
+    let script = String::from_utf8_lossy(&script).into_owned();
+    match shell {
+        // fish finds the command with argparse, which would stop at `--set` as an unknown
+        // option rather than take it for the command
+        clap_complete::Shell::Fish => script.replace("argparse -s ", "argparse -s -i "),
+        _ => script,
+    }
+}
+
 pub fn get_shell_rc_file(shell: &Shell) -> Option<PathBuf> {
-    let home = env::var("HOME").ok()?;
-    let home_path = PathBuf::from(home);
+    let home_path = dirs::home_dir()?;
 
     match shell {
         Shell::Bash => {
@@ -114,31 +639,220 @@ pub fn get_shell_rc_file(shell: &Shell) -> Option<PathBuf> {
             fs::create_dir_all(&fish_config_dir).ok()?;
             Some(fish_config_dir.join("config.fish"))
         }
+        // Installing into $PROFILE isn't supported yet
+        Shell::PowerShell => None,
+    }
+}
+
+/// The lines around the completion script in an rc file, so that it can be found again.
+pub const COMPLETION_BEGIN_MARKER: &str = "# >>> qwk completion >>>";
+pub const COMPLETION_END_MARKER: &str = "# <<< qwk completion <<<";
+/// How completion is set up, written after the begin marker so that what an earlier qwk
+/// set up can be replaced. Version 3 sources the script from qwk's config directory;
+/// version 2 had it inline, passing the whole command line, and version 1 passed only the
+/// word before the cursor.
+pub const COMPLETION_SCRIPT_VERSION: u32 = 3;
+const COMPLETION_VERSION_PREFIX: &str = "# qwk-completion-version:";
+/// The line earlier versions wrote before the script, with no line after it.
+const LEGACY_COMPLETION_COMMENT: &str = "# qwk autocompletion setup";
+/// How the scripts written by every version end: the line that registers them.
+const COMPLETION_LAST_LINES: &[&str] = &[
+    "complete -F _qwk_complete qwk",
+    "compdef _qwk_complete qwk",
+    "complete -c qwk ",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionStatus {
+    NotInstalled,
+    /// Set up with the script of this version, or of a later qwk
+    Installed(u32),
+    /// Set up by an earlier qwk, with a script that can be replaced
+    Outdated,
+}
+
+/// The lines of the completion blocks that qwk wrote to `lines`, marked or from earlier
+/// versions. A legacy block only counts when the line that ends its script follows, so
+/// that nothing else is taken for part of it.
+fn completion_blocks(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        let end = if line == COMPLETION_BEGIN_MARKER {
+            lines[i..]
+                .iter()
+                .position(|line| line.trim_end() == COMPLETION_END_MARKER)
+        } else if line == LEGACY_COMPLETION_COMMENT {
+            lines[i..].iter().position(|line| {
+                COMPLETION_LAST_LINES
+                    .iter()
+                    .any(|last| line.starts_with(last))
+            })
+        } else {
+            None
+        };
+        match end {
+            Some(end) => {
+                blocks.push(i..i + end + 1);
+                i += end + 1;
+            }
+            None => i += 1,
+        }
+    }
+    blocks
+}
+
+/// The version of the script in a block, 1 for one from before versions were written.
+fn completion_block_version(block: &[&str]) -> u32 {
+    block
+        .get(1)
+        .and_then(|line| line.strip_prefix(COMPLETION_VERSION_PREFIX))
+        .and_then(|version| version.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+/// Whether the rc file `content` sets up completion, and with which script. One added by
+/// hand, without qwk's markers, is taken as current since it can't be replaced.
+pub fn completion_status_of(content: &str) -> CompletionStatus {
+    let lines: Vec<&str> = content.lines().collect();
+    let version = completion_blocks(&lines)
+        .into_iter()
+        .map(|block| completion_block_version(&lines[block]))
+        .max();
+    match version {
+        Some(version) if version >= COMPLETION_SCRIPT_VERSION => {
+            CompletionStatus::Installed(version)
+        }
+        Some(_) => CompletionStatus::Outdated,
+        None if content.contains("_qwk_complete") => {
+            CompletionStatus::Installed(COMPLETION_SCRIPT_VERSION)
+        }
+        None => CompletionStatus::NotInstalled,
+    }
+}
+
+pub fn completion_status(shell: &Shell) -> CompletionStatus {
+    match get_shell_rc_file(shell).and_then(|rc_file| fs::read_to_string(rc_file).ok()) {
+        Some(content) => completion_status_of(&content),
+        None => CompletionStatus::NotInstalled,
+    }
+}
+
+/// `content` with its completion blocks replaced: the first by `replacement`, if given,
+/// and the rest removed. `None` when it has none.
+pub fn replace_completion_blocks(content: &str, replacement: Option<&str>) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = completion_blocks(&lines);
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mut kept = Vec::with_capacity(lines.len());
+    let mut start = 0;
+    for (i, block) in blocks.into_iter().enumerate() {
+        kept.extend_from_slice(&lines[start..block.start]);
+        match replacement {
+            Some(replacement) if i == 0 => kept.extend(replacement.lines()),
+            // Along with the blank line that was added before it
+            _ => {
+                if kept.last().is_some_and(|line| line.trim().is_empty()) {
+                    kept.pop();
+                }
+            }
+        }
+        start = block.end;
     }
+    kept.extend_from_slice(&lines[start..]);
+
+    let mut replaced = kept.join("\n");
+    if !replaced.is_empty() {
+        replaced.push('\n');
+    }
+    Some(replaced)
+}
+
+/// `content` without the completion blocks that qwk wrote to it, or `None` when it has none.
+pub fn strip_completion_blocks(content: &str) -> Option<String> {
+    replace_completion_blocks(content, None)
 }
 
-pub fn is_completion_installed(shell: &Shell) -> bool {
-    let rc_file = match get_shell_rc_file(shell) {
-        Some(file) => file,
-        None => return false,
+/// Where the completion script for `shell` is kept, for the rc file to source.
+pub fn get_completion_script_file(shell: &Shell) -> PathBuf {
+    let extension = match shell {
+        Shell::PowerShell => "ps1",
+        shell => shell.name(),
     };
+    get_config_dir()
+        .join("completions")
+        .join(format!("qwk.{}", extension))
+}
+
+/// Writes the script for `shell` to its file, returning whether that changed it.
+fn write_completion_script(shell: &Shell) -> io::Result<bool> {
+    let path = get_completion_script_file(shell);
+    let script = get_completion_script(shell).trim_start().to_string();
+    if fs::read_to_string(&path).is_ok_and(|existing| existing == script) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, script)?;
+    Ok(true)
+}
+
+/// The lines added to the rc file: a marked block that sources `script_file`.
+fn completion_block(script_file: &Path) -> String {
+    let path = script_file.to_string_lossy();
+    // Only a path with a NUL in it can't be quoted, and no file has one
+    let path =
+        shlex::try_quote(&path).map_or_else(|_| path.to_string(), |quoted| quoted.into_owned());
+    format!(
+        "{}\n{} {}\nsource {}\n{}",
+        COMPLETION_BEGIN_MARKER,
+        COMPLETION_VERSION_PREFIX,
+        COMPLETION_SCRIPT_VERSION,
+        path,
+        COMPLETION_END_MARKER
+    )
+}
+
+fn rc_file_for_setup(shell: &Shell) -> io::Result<PathBuf> {
+    get_shell_rc_file(shell).ok_or_else(|| match shell {
+        Shell::PowerShell => io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Automatic setup isn't supported for PowerShell yet",
+        ),
+        _ => io::Error::new(io::ErrorKind::NotFound, "Could not determine shell RC file"),
+    })
+}
 
-    if !rc_file.exists() {
-        return false;
+fn read_rc_file(rc_file: &Path) -> io::Result<String> {
+    match fs::read_to_string(rc_file) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
     }
+}
 
-    let content = fs::read_to_string(&rc_file).unwrap_or_default();
-    content.contains("_qwk_complete") || content.contains("__qwk_complete")
+/// Copies `rc_file` to `<rc file>.qwk.bak` before qwk changes what's already in it.
+fn back_up_rc_file(rc_file: &Path) -> io::Result<PathBuf> {
+    let mut backup = rc_file.as_os_str().to_owned();
+    backup.push(".qwk.bak");
+    let backup = PathBuf::from(backup);
+    fs::copy(rc_file, &backup)?;
+    Ok(backup)
 }
 
 pub fn install_completion(shell: &Shell) -> io::Result<()> {
-    let rc_file = get_shell_rc_file(shell).ok_or_else(|| {
-        io::Error::new(io::ErrorKind::NotFound, "Could not determine shell RC file")
-    })?;
+    let rc_file = rc_file_for_setup(shell)?;
 
-    let completion_script = get_completion_script(shell);
-    let comment = "# qwk autocompletion setup";
-    let full_addition = format!("{}\n{}", comment, completion_script);
+    let separator = match read_rc_file(&rc_file)?.as_str() {
+        "" => "",
+        content if content.ends_with('\n') => "\n",
+        _ => "\n\n",
+    };
 
     // Append to RC file
     let mut file = fs::OpenOptions::new()
@@ -146,38 +860,120 @@ pub fn install_completion(shell: &Shell) -> io::Result<()> {
         .append(true)
         .open(&rc_file)?;
 
-    writeln!(file, "{}", full_addition)?;
+    write_completion_script(shell)?;
+    writeln!(
+        file,
+        "{}{}",
+        separator,
+        completion_block(&get_completion_script_file(shell))
+    )?;
 
     Ok(())
 }
 
-pub fn setup_completion_for_current_shell() -> io::Result<()> {
-    let shell = detect_shell()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not detect current shell"))?;
+/// Replaces what an earlier qwk added to the rc file of `shell`, such as a whole script,
+/// with the current block, where the old one was. Returns the rc file and its backup.
+pub fn upgrade_completion(shell: &Shell) -> io::Result<(PathBuf, PathBuf)> {
+    let rc_file = rc_file_for_setup(shell)?;
+    let content = read_rc_file(&rc_file)?;
+    let block = completion_block(&get_completion_script_file(shell));
+    let upgraded = replace_completion_blocks(&content, Some(&block)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No autocompletion script in {}", rc_file.display()),
+        )
+    })?;
+
+    write_completion_script(shell)?;
+    let backup = back_up_rc_file(&rc_file)?;
+    fs::write(&rc_file, upgraded)?;
+    Ok((rc_file, backup))
+}
 
-    if is_completion_installed(&shell) {
-        println!("Autocompletion is already set up for {:?}", shell);
+/// Takes completion out of the rc file of `shell`, or of the one qwk is run from, after
+/// copying the file to `<rc file>.qwk.bak`, and deletes the script it sourced.
+pub fn remove_completion(shell: Option<Shell>) -> io::Result<()> {
+    let shell = shell_or_detected(shell)?;
+    let Some(rc_file) = get_shell_rc_file(&shell) else {
+        status!("Nothing to remove for {}", shell);
         return Ok(());
+    };
+
+    match fs::remove_file(get_completion_script_file(&shell)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
     }
+    let Some(stripped) = strip_completion_blocks(&read_rc_file(&rc_file)?) else {
+        status!("Nothing to remove from {}", rc_file.display());
+        return Ok(());
+    };
 
-    install_completion(&shell)?;
+    let backup = back_up_rc_file(&rc_file)?;
+    fs::write(&rc_file, stripped)?;
 
-    let shell_name = match shell {
-        Shell::Bash => "bash",
-        Shell::Zsh => "zsh",
-        Shell::Fish => "fish",
-    };
+    status!(
+        "Removed autocompletion from {} (the old file is at {})",
+        rc_file.display(),
+        backup.display()
+    );
+    status!("Restart your shell for it to take effect.");
+    Ok(())
+}
 
-    println!("Autocompletion set up for {}!", shell_name);
+fn print_activation_hint(shell: &Shell) {
     match shell {
         Shell::Fish => {
-            println!("Restart your shell or run 'source ~/.config/fish/config.fish' to activate.")
+            status!("Restart your shell or run 'source ~/.config/fish/config.fish' to activate.")
         }
-        _ => println!(
+        _ => status!(
             "Restart your shell or run 'source ~/.{}rc' to activate.",
-            shell_name
+            shell.name()
         ),
     }
+}
+
+pub fn setup_completion_for_current_shell() -> io::Result<()> {
+    setup_completion(None, false)
+}
+
+/// Sets up completion for `shell`, or for the one qwk is run from when not given, and
+/// brings a script that an earlier qwk set up up to date. With `print`, the script is
+/// printed for the user to add themselves and no rc file is touched.
+pub fn setup_completion(shell: Option<Shell>, print: bool) -> io::Result<()> {
+    let shell = shell_or_detected(shell)?;
+
+    if print {
+        print!("{}", get_completion_script(&shell).trim_start());
+        return Ok(());
+    }
+
+    match completion_status(&shell) {
+        CompletionStatus::Installed(_) => {
+            // The rc file only sources the script, which is kept up to date here
+            if write_completion_script(&shell)? {
+                status!(
+                    "Updated the autocompletion script in {}",
+                    get_completion_script_file(&shell).display()
+                );
+            } else {
+                status!("Autocompletion is already set up for {}", shell);
+            }
+        }
+        CompletionStatus::Outdated => {
+            let (rc_file, backup) = upgrade_completion(&shell)?;
+            status!(
+                "Updated autocompletion in {} for this version of qwk (the old file is at {})",
+                rc_file.display(),
+                backup.display()
+            );
+            print_activation_hint(&shell);
+        }
+        CompletionStatus::NotInstalled => {
+            install_completion(&shell)?;
+            status!("Autocompletion set up for {}!", shell);
+            print_activation_hint(&shell);
+        }
+    }
 
     Ok(())
 }
@@ -195,16 +991,86 @@ pub fn mark_first_run_complete() -> io::Result<()> {
     Ok(())
 }
 
-pub fn handle_first_run() {
-    if is_first_run() {
-        println!("Welcome to qwk! Setting up autocompletion...");
+/// Environment variable that turns off the first-run setup, e.g. in CI images and cron jobs.
+pub const NO_FIRST_RUN_ENV: &str = "QWK_NO_FIRST_RUN";
+/// Environment variable that has the first run set completion up without asking, even
+/// without a terminal, for provisioning scripts.
+pub const AUTO_SETUP_ENV: &str = "QWK_AUTO_SETUP";
+
+/// What the first run does about completion.
+#[derive(Debug, PartialEq)]
+enum FirstRun {
+    /// Nothing, leaving it to a later run
+    Skip,
+    Ask,
+    /// Set it up without asking
+    Auto,
+}
+
+/// What the first-run setup does now: ask only someone at a terminal, so not with stdout
+/// redirected, nor with `CI` set. `QWK_AUTO_SETUP` sets completion up anyway, and
+/// `QWK_NO_FIRST_RUN` turns it all off; each counts when set to anything but empty or `0`.
+/// `var` looks up an environment variable.
+fn first_run_mode(stdout_is_terminal: bool, var: impl Fn(&str) -> Option<OsString>) -> FirstRun {
+    let set = |name| var(name).is_some_and(|value| !value.is_empty() && value != "0");
+    if set(NO_FIRST_RUN_ENV) {
+        FirstRun::Skip
+    } else if set(AUTO_SETUP_ENV) {
+        FirstRun::Auto
+    } else if stdout_is_terminal && !set("CI") {
+        FirstRun::Ask
+    } else {
+        FirstRun::Skip
+    }
+}
+
+/// `path` with the home directory written as `~`, as people write it in shell.
+fn display_home_relative(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Asks before adding completion to the shell's rc file, unless `ask` is false, and says
+/// how to add it later when the answer is no or there's no terminal to ask on.
+fn offer_completion_setup(ask: bool) {
+    let rc_file = detect_shell()
+        // A script that's only outdated waits for --setup-completion, rather than nagging
+        .filter(|shell| completion_status(shell) == CompletionStatus::NotInstalled)
+        .and_then(|shell| get_shell_rc_file(&shell));
+    let Some(rc_file) = rc_file else {
+        return;
+    };
+    let question = format!(
+        "Set up shell completion by adding a line to {}?",
+        display_home_relative(&rc_file)
+    );
+    if !ask || confirm_default_yes(&question) {
         if let Err(e) = setup_completion_for_current_shell() {
-            eprintln!("Note: Could not set up autocompletion automatically: {}", e);
-            eprintln!("You can set it up manually later with: qwk --setup-completion");
-        }
-        if let Err(e) = mark_first_run_complete() {
-            eprintln!("Warning: Could not mark first run as complete: {}", e);
+            eprintln!("Note: Could not set up autocompletion: {}", e);
+            eprintln!("You can set it up later with: qwk --setup-completion");
         }
+    } else {
+        status!("Set up tab completion later with: qwk --setup-completion");
+    }
+}
+
+/// Welcomes a new user and offers to set up completion, once, whatever the answer. Without
+/// a terminal it does nothing, leaving that to the first run that has one.
+pub fn handle_first_run() {
+    if !is_first_run() {
+        return;
+    }
+    let ask = match first_run_mode(io::stdout().is_terminal(), |name| env::var_os(name)) {
+        FirstRun::Skip => return,
+        FirstRun::Ask => true,
+        FirstRun::Auto => false,
+    };
+    status!("Welcome to qwk!");
+    offer_completion_setup(ask);
+    if let Err(e) = mark_first_run_complete() {
+        eprintln!("Warning: Could not mark first run as complete: {}", e);
     }
 }
 
@@ -213,30 +1079,115 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_shell_detection() {
-        // Test bash detection
-        unsafe {
-            std::env::set_var("SHELL", "/bin/bash");
-        }
-        if let Some(shell) = detect_shell() {
-            assert!(matches!(shell, Shell::Bash));
-        }
+    fn test_static_completion_script() {
+        let bash = static_completion_script(clap_complete::Shell::Bash);
+        assert!(bash.contains("qwk,--set)"), "{}", bash);
+        assert!(!bash.contains("qwk,help)"), "{}", bash);
 
-        // Test zsh detection
-        unsafe {
-            std::env::set_var("SHELL", "/usr/local/bin/zsh");
-        }
-        if let Some(shell) = detect_shell() {
-            assert!(matches!(shell, Shell::Zsh));
-        }
+        let fish = static_completion_script(clap_complete::Shell::Fish);
+        assert!(fish.contains("argparse -s -i "), "{}", fish);
+        assert!(fish.contains("__fish_qwk_using_subcommand --set\" -l from-file"));
+    }
 
-        // Test fish detection
-        unsafe {
-            std::env::set_var("SHELL", "/usr/bin/fish");
+    #[test]
+    fn test_first_run_mode() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        assert_eq!(first_run_mode(true, env(&[])), FirstRun::Ask);
+        assert_eq!(first_run_mode(false, env(&[])), FirstRun::Skip);
+        assert_eq!(first_run_mode(true, env(&[("CI", "true")])), FirstRun::Skip);
+        assert_eq!(
+            first_run_mode(true, env(&[(NO_FIRST_RUN_ENV, "1")])),
+            FirstRun::Skip
+        );
+        assert_eq!(
+            first_run_mode(true, env(&[("CI", ""), (NO_FIRST_RUN_ENV, "0")])),
+            FirstRun::Ask
+        );
+        // For provisioning, with no one to ask
+        assert_eq!(
+            first_run_mode(false, env(&[("CI", "1"), (AUTO_SETUP_ENV, "1")])),
+            FirstRun::Auto
+        );
+        assert_eq!(
+            first_run_mode(
+                false,
+                env(&[(AUTO_SETUP_ENV, "1"), (NO_FIRST_RUN_ENV, "1")])
+            ),
+            FirstRun::Skip
+        );
+    }
+
+    #[test]
+    fn test_shell_from_process_name() {
+        // As /proc/<pid>/comm has them
+        assert_eq!(shell_from_process_name("fish\n"), Some(Shell::Fish));
+        assert_eq!(shell_from_process_name("bash\n"), Some(Shell::Bash));
+        assert_eq!(shell_from_process_name("pwsh\n"), Some(Shell::PowerShell));
+        // As ps has them on macOS, for a login shell and another one
+        assert_eq!(shell_from_process_name("-zsh\n"), Some(Shell::Zsh));
+        assert_eq!(
+            shell_from_process_name("/opt/homebrew/bin/fish\n"),
+            Some(Shell::Fish)
+        );
+        assert_eq!(
+            shell_from_process_name("powershell.exe"),
+            Some(Shell::PowerShell)
+        );
+        for name in [
+            "sh\n",
+            "cargo\n",
+            "tmux: server",
+            "bash-language-server",
+            "",
+        ] {
+            assert_eq!(shell_from_process_name(name), None, "{}", name);
         }
-        if let Some(shell) = detect_shell() {
-            assert!(matches!(shell, Shell::Fish));
+    }
+
+    #[test]
+    fn test_shell_names() {
+        for shell in Shell::ALL {
+            assert_eq!(shell.to_string().parse::<Shell>(), Ok(shell));
         }
+        assert_eq!("Fish".parse::<Shell>(), Ok(Shell::Fish));
+        let error = "tcsh".parse::<Shell>().unwrap_err();
+        assert!(error.contains("'tcsh'"), "{}", error);
+        assert!(error.contains("bash, zsh, fish, powershell"), "{}", error);
+    }
+
+    #[test]
+    fn test_shell_from_env() {
+        let home = Path::new("/home/user");
+        assert!(matches!(
+            shell_from_env(Some("/bin/zsh"), None, Some(home)),
+            Some(Shell::Zsh)
+        ));
+        assert!(shell_from_env(Some("/bin/tcsh"), None, Some(home)).is_none());
+        assert!(shell_from_env(None, None, Some(home)).is_none());
+
+        // PowerShell puts per-user module directories first; cmd only has the system ones
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let system = ["/opt/powershell/Modules", "/usr/share/Modules"].join(separator);
+        let session = format!(
+            "/home/user/Documents/PowerShell/Modules{}{}",
+            separator, system
+        );
+        assert!(matches!(
+            shell_from_env(None, Some(&session), Some(home)),
+            Some(Shell::PowerShell)
+        ));
+        assert!(shell_from_env(None, Some(&system), Some(home)).is_none());
+        // Git Bash and WSL set $SHELL, which wins
+        assert!(matches!(
+            shell_from_env(Some("/usr/bin/bash"), Some(&session), Some(home)),
+            Some(Shell::Bash)
+        ));
     }
 
     #[test]
@@ -244,13 +1195,282 @@ mod tests {
         let bash_script = get_completion_script(&Shell::Bash);
         assert!(bash_script.contains("_qwk_complete"));
         assert!(bash_script.contains("COMP_WORDS"));
+        assert!(bash_script.contains("qwk --complete --shell bash -- "));
 
         let zsh_script = get_completion_script(&Shell::Zsh);
         assert!(zsh_script.contains("_qwk_complete"));
         assert!(zsh_script.contains("compdef"));
+        assert!(zsh_script.contains("_describe"));
 
         let fish_script = get_completion_script(&Shell::Fish);
         assert!(fish_script.contains("__qwk_complete"));
-        assert!(fish_script.contains("commandline"));
+        assert!(fish_script.contains("qwk --complete --shell fish -- (commandline -opc)"));
+
+        let powershell_script = get_completion_script(&Shell::PowerShell);
+        assert!(powershell_script.contains("Register-ArgumentCompleter"));
+        assert!(powershell_script.contains("--complete"));
+    }
+
+    #[test]
+    fn test_strip_completion_blocks() {
+        let marked = format!(
+            "export A=1\n\n{}\n{}\n{}\nexport B=2\n",
+            COMPLETION_BEGIN_MARKER,
+            get_completion_script(&Shell::Bash).trim(),
+            COMPLETION_END_MARKER
+        );
+        assert_eq!(
+            strip_completion_blocks(&marked).as_deref(),
+            Some("export A=1\nexport B=2\n")
+        );
+
+        // As written by earlier versions, whose scripts differ from today's
+        let legacy = "export A=1\n# qwk autocompletion setup\n\n_qwk_complete() {\n    COMPREPLY=($(qwk --complete \"$cur\"))\n}\ncomplete -F _qwk_complete qwk\n\n";
+        assert_eq!(
+            strip_completion_blocks(legacy).as_deref(),
+            Some("export A=1\n\n")
+        );
+        let fish = "# qwk autocompletion setup\n\nfunction __qwk_complete\nend\ncomplete -c qwk -f -a \"(__qwk_complete)\"\n";
+        assert_eq!(strip_completion_blocks(fish).as_deref(), Some(""));
+
+        // Without the end of its script, nothing is known to be qwk's
+        assert_eq!(
+            strip_completion_blocks("# qwk autocompletion setup\nexport A=1\n"),
+            None
+        );
+        assert_eq!(strip_completion_blocks("export A=1\n"), None);
+    }
+
+    #[test]
+    fn test_completion_status_of() {
+        let current = format!(
+            "export A=1\n{}\n",
+            completion_block(Path::new("/home/me/.config/qwk/completions/qwk.zsh"))
+        );
+        assert_eq!(
+            completion_status_of(&current),
+            CompletionStatus::Installed(COMPLETION_SCRIPT_VERSION)
+        );
+        let later = current.replace(
+            &format!(
+                "{} {}",
+                COMPLETION_VERSION_PREFIX, COMPLETION_SCRIPT_VERSION
+            ),
+            &format!("{} 9", COMPLETION_VERSION_PREFIX),
+        );
+        assert_eq!(completion_status_of(&later), CompletionStatus::Installed(9));
+
+        let unversioned = format!(
+            "{}\ncompdef _qwk_complete qwk\n{}\n",
+            COMPLETION_BEGIN_MARKER, COMPLETION_END_MARKER
+        );
+        assert_eq!(
+            completion_status_of(&unversioned),
+            CompletionStatus::Outdated
+        );
+        let legacy = "# qwk autocompletion setup\n\ncompdef _qwk_complete qwk\n";
+        assert_eq!(completion_status_of(legacy), CompletionStatus::Outdated);
+
+        // Added by hand, so there's no knowing where it ends
+        assert_eq!(
+            completion_status_of("source ~/qwk.zsh # for _qwk_complete\n"),
+            CompletionStatus::Installed(COMPLETION_SCRIPT_VERSION)
+        );
+        assert_eq!(
+            completion_status_of("export A=1\n"),
+            CompletionStatus::NotInstalled
+        );
+    }
+
+    #[test]
+    fn test_replace_completion_blocks() {
+        let legacy =
+            "export A=1\n\n# qwk autocompletion setup\n\ncompdef _qwk_complete qwk\nexport B=2\n";
+        assert_eq!(
+            replace_completion_blocks(legacy, Some("# new\nscript")).as_deref(),
+            Some("export A=1\n\n# new\nscript\nexport B=2\n")
+        );
+        // A second copy goes, rather than being replaced too
+        let twice = format!("{}\n{}", legacy, legacy);
+        assert_eq!(
+            replace_completion_blocks(&twice, Some("# new")).as_deref(),
+            Some("export A=1\n\n# new\nexport B=2\n\nexport A=1\nexport B=2\n")
+        );
+        assert_eq!(
+            replace_completion_blocks("export A=1\n", Some("# new")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_described_completion() {
+        assert_eq!(format_described_completion("review", None), "review");
+        assert_eq!(
+            format_described_completion("review", Some("summarize\nPR diffs")),
+            "review:summarize PR diffs"
+        );
+        assert_eq!(
+            format_described_completion("work:standup", Some("daily")),
+            "work\\:standup:daily"
+        );
+    }
+
+    #[test]
+    fn test_sort_completions() {
+        let mut completions = vec!["--set", "zeta", "review", "alpha", "work/"];
+        sort_completions(&mut completions, |name| name == "review" || name == "zeta");
+        assert_eq!(
+            completions,
+            vec!["review", "zeta", "--set", "alpha", "work/"]
+        );
+    }
+
+    #[test]
+    fn test_alias_completion_candidates() {
+        let names = ["review", "work/standup", "work/retro", "home/todo"];
+
+        assert_eq!(
+            alias_completion_candidates(&names, ""),
+            vec!["home/", "review", "work/"]
+        );
+        assert_eq!(alias_completion_candidates(&names, "wor"), vec!["work/"]);
+        assert_eq!(
+            alias_completion_candidates(&names, "work/"),
+            vec!["work/retro", "work/standup"]
+        );
+        assert_eq!(
+            alias_completion_candidates(&names, "work/s"),
+            vec!["work/standup"]
+        );
+        assert!(alias_completion_candidates(&names, "x").is_empty());
+    }
+
+    #[test]
+    fn test_completion_candidates() {
+        let mut pinned = AliasEntry::new("Review the diff");
+        pinned.pinned = true;
+        let data = CompletionData {
+            aliases: HashMap::from([
+                ("summ".to_string(), AliasEntry::new("Summarize")),
+                ("review".to_string(), pinned),
+            ]),
+            profiles: vec!["work".to_string()],
+            agents: vec!["claude".to_string()],
+            programs: vec!["llm".to_string()],
+        };
+        let complete = |words: &[&str], partial: &str| {
+            let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+            completion_candidates(&words, None, partial, None, &data)
+        };
+
+        let first = complete(&["qwk"], "");
+        assert_eq!(first[0], "review");
+        assert!(first.contains(&"summ".to_string()) && first.contains(&"--list".to_string()));
+        assert_eq!(complete(&["qwk", "-q"], "--li"), ["--list"]);
+
+        // Only shortcuts where a command takes one
+        assert_eq!(complete(&["qwk", "--remove"], ""), ["review", "summ"]);
+        assert_eq!(
+            complete(&["qwk", "--profile", "work", "--show"], "s"),
+            ["summ"]
+        );
+        assert_eq!(complete(&["qwk", "run"], ""), ["review", "summ"]);
+        assert!(complete(&["qwk", "--remove", "summ"], "").is_empty());
+        assert!(complete(&["qwk", "summ"], "").is_empty());
+
+        assert_eq!(complete(&["qwk", "--agent", "--preset"], "cl"), ["claude"]);
+        let agent = complete(&["qwk", "--agent"], "");
+        assert_eq!(agent[0], "llm");
+        assert!(agent.contains(&"--preset".to_string()));
+        assert_eq!(complete(&["qwk", "--agent"], "--pr"), ["--preset"]);
+        assert_eq!(complete(&["qwk", "--profile"], ""), ["default", "work"]);
+        assert_eq!(complete(&["qwk", "--agent-use"], ""), ["claude"]);
+        assert_eq!(
+            completion_candidates(&[], Some("--remove"), "", None, &data),
+            ["review", "summ"]
+        );
+        assert!(completion_candidates(&[], Some("summ"), "", None, &data).len() > 2);
+    }
+
+    #[test]
+    fn test_completion_descriptions() {
+        let mut described = AliasEntry::new("Review the diff");
+        described.description = Some("Code review".to_string());
+        let data = CompletionData {
+            aliases: HashMap::from([
+                ("review".to_string(), described),
+                (
+                    "work:standup".to_string(),
+                    AliasEntry::new(
+                        "Write my standup update from yesterday's commits and today's plan",
+                    ),
+                ),
+            ]),
+            ..CompletionData::default()
+        };
+        let words = ["qwk".to_string(), "--show".to_string()];
+        assert_eq!(
+            completion_candidates(&words, None, "", Some(Shell::Zsh), &data),
+            [
+                "review:Code review",
+                "work\\:standup:Write my standup update from yesterda...",
+            ]
+        );
+        assert_eq!(
+            completion_candidates(&words, None, "", Some(Shell::Bash), &data),
+            ["review", "work:standup"]
+        );
+        assert_eq!(
+            completion_candidates(&words, None, "rev", Some(Shell::Fish), &data),
+            ["review\tCode review"]
+        );
+    }
+
+    #[test]
+    fn test_unquote_word() {
+        assert_eq!(unquote_word("my\\ al"), "my al");
+        assert_eq!(unquote_word("'my al"), "my al");
+        assert_eq!(unquote_word("\"it's"), "it's");
+        assert_eq!(unquote_word("'glob*'"), "glob*");
+        assert_eq!(unquote_word("--list"), "--list");
+        assert_eq!(unquote_word(""), "");
+    }
+
+    #[test]
+    fn test_quote_for_bash() {
+        assert_eq!(quote_for_bash("summ"), "summ");
+        assert_eq!(quote_for_bash("work/"), "work/");
+        assert_eq!(quote_for_bash("-dash"), "-dash");
+        assert_eq!(quote_for_bash("my alias"), "'my alias'");
+        assert_eq!(quote_for_bash("glob*"), "'glob*'");
+        assert_eq!(quote_for_bash("$HOME"), "'$HOME'");
+        for name in ["it's", "say \"hi\"", "a\\b"] {
+            assert_eq!(unquote_word(&quote_for_bash(name)), name);
+        }
+    }
+
+    #[test]
+    fn test_format_fish_completion() {
+        assert_eq!(format_fish_completion("review", None), "review");
+        assert_eq!(
+            format_fish_completion("review", Some("Review\tthe\n\ndiff")),
+            "review\tReview the diff"
+        );
+        assert_eq!(
+            format_fish_completion("work:standup", Some("daily")),
+            "work:standup\tdaily"
+        );
+        assert_eq!(format_fish_completion("odd\tname", None), "odd name");
+    }
+
+    #[test]
+    fn test_profile_completion_candidates() {
+        let profiles = ["personal".to_string(), "work".to_string()];
+        assert_eq!(
+            profile_completion_candidates(&profiles, ""),
+            vec!["default", "personal", "work"]
+        );
+        assert_eq!(profile_completion_candidates(&profiles, "w"), vec!["work"]);
+        assert_eq!(profile_completion_candidates(&[], ""), vec!["default"]);
     }
 }
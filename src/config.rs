@@ -1,14 +1,226 @@
-use std::collections::HashMap;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::utils::get_current_datetime;
+use crate::crypto::{self, CryptoError};
+use crate::error::QwkError;
+use crate::maintenance::{apply_plan, find_backups, plan_prune_backups};
+use crate::output::set_quiet;
+use crate::prompt_files;
+use crate::utils::{DATETIME_FORMAT, get_current_datetime, parse_datetime};
 
+/// Where the config directory lives when `XDG_CONFIG_HOME` isn't set.
+#[cfg(all(target_os = "macos", feature = "native-dirs"))]
+fn default_config_base(_home: Option<&Path>) -> Option<PathBuf> {
+    dirs::config_dir()
+}
+
+/// Where the config directory lives: `%APPDATA%` on Windows, which has no `$HOME`.
+#[cfg(windows)]
+fn default_config_base(_home: Option<&Path>) -> Option<PathBuf> {
+    dirs::config_dir()
+}
+
+/// Where the config directory lives when `XDG_CONFIG_HOME` isn't set.
+#[cfg(not(any(windows, all(target_os = "macos", feature = "native-dirs"))))]
+fn default_config_base(home: Option<&Path>) -> Option<PathBuf> {
+    home.map(|home| home.join(".config"))
+}
+
+/// Environment variable naming the config directory itself, ahead of any other location.
+pub const CONFIG_DIR_ENV: &str = "QWK_CONFIG_DIR";
+
+/// Picks `explicit` (`QWK_CONFIG_DIR`) when set and non-empty, else `qwk` under
+/// `XDG_CONFIG_HOME` (used verbatim when set and non-empty) or under `default_base`. An
+/// existing `legacy` directory wins over the last two while they don't exist, so installs
+/// from before `XDG_CONFIG_HOME` was honored keep their aliases.
+fn resolve_config_dir(
+    explicit: Option<OsString>,
+    xdg_config_home: Option<OsString>,
+    default_base: Option<PathBuf>,
+    legacy: Option<PathBuf>,
+) -> Option<PathBuf> {
+    if let Some(dir) = explicit.filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+
+    let preferred = match xdg_config_home {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("qwk")),
+        _ => default_base.map(|base| base.join("qwk")),
+    };
+    match (preferred, legacy) {
+        (Some(preferred), Some(legacy)) if !preferred.exists() && legacy.exists() => Some(legacy),
+        (Some(preferred), _) => Some(preferred),
+        (None, legacy) => legacy,
+    }
+}
+
+/// The directory holding every file qwk keeps; all path helpers go through it.
 pub fn get_config_dir() -> PathBuf {
-    let home = env::var("HOME").expect("HOME environment variable not set");
-    PathBuf::from(home).join(".config").join("qwk")
+    let home = dirs::home_dir();
+    let legacy = home.as_ref().map(|home| home.join(".config").join("qwk"));
+    resolve_config_dir(
+        env::var_os(CONFIG_DIR_ENV),
+        env::var_os("XDG_CONFIG_HOME"),
+        default_config_base(home.as_deref()),
+        legacy,
+    )
+    .expect("Could not determine the config directory")
+}
+
+/// Environment variable selecting the profile, which `--profile` overrides.
+pub const PROFILE_ENV: &str = "QWK_PROFILE";
+
+/// Name that always refers to the unnamed default profile.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The profile `--profile` selected, which takes the place of `QWK_PROFILE`.
+static SELECTED_PROFILE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether `--no-local` was given, which works like `QWK_NO_LOCAL`.
+static NO_LOCAL: AtomicBool = AtomicBool::new(false);
+
+/// The active named profile, or `None` for the default one.
+pub fn get_profile() -> Option<String> {
+    let selected = SELECTED_PROFILE.lock().unwrap().clone();
+    selected
+        .or_else(|| env::var(PROFILE_ENV).ok())
+        .filter(|name| !name.is_empty() && name != DEFAULT_PROFILE)
+}
+
+/// Makes `name` the active profile, whatever `QWK_PROFILE` says, until `clear_selection`.
+pub fn select_profile(name: &str) {
+    *SELECTED_PROFILE.lock().unwrap() = Some(name.to_string());
+}
+
+/// Turns off `.qwk.json` discovery, like `QWK_NO_LOCAL`, until `clear_selection`.
+pub fn disable_local_aliases() {
+    NO_LOCAL.store(true, Ordering::SeqCst);
+}
+
+/// Goes back to the profile and `.qwk.json` setting of the environment, and turns quiet
+/// mode off, so that what one command selected doesn't carry over to the next run in the
+/// same process.
+pub fn clear_selection() {
+    *SELECTED_PROFILE.lock().unwrap() = None;
+    NO_LOCAL.store(false, Ordering::SeqCst);
+    set_quiet(false);
+}
+
+/// The environment for agents and hooks, so that a `qwk` they run uses the same profile
+/// and `.qwk.json` setting as the one that started them.
+pub fn selection_env() -> Vec<(&'static str, String)> {
+    let mut vars = Vec::new();
+    if let Some(profile) = SELECTED_PROFILE.lock().unwrap().clone() {
+        vars.push((PROFILE_ENV, profile));
+    }
+    if NO_LOCAL.load(Ordering::SeqCst) {
+        vars.push((NO_LOCAL_ENV, "1".to_string()));
+    }
+    vars
+}
+
+/// Profile names become directory names, so they are limited to letters, digits, `-`, `_`
+/// and `.`, and can't start with a dot.
+pub fn validate_profile_name(name: &str) -> Result<(), String> {
+    validate_name("profile", name)
+}
+
+/// Checks a profile or agent name, `kind`, against the rules of `validate_profile_name`.
+fn validate_name(kind: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("The {} name can't be empty", kind));
+    }
+    if name.starts_with('.') {
+        return Err(format!(
+            "Invalid {} name '{}': can't start with '.'",
+            kind, name
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(format!(
+            "Invalid {} name '{}': '{}' isn't allowed, use letters, digits, '-', '_' or '.'",
+            kind, name, c
+        ));
+    }
+    Ok(())
+}
+
+pub fn get_profiles_dir() -> PathBuf {
+    get_config_dir().join("profiles")
+}
+
+/// The directory holding the aliases, agent and backups of the active profile. The default
+/// profile uses the config directory itself, as installs from before profiles did.
+pub fn get_profile_dir() -> PathBuf {
+    match get_profile() {
+        Some(name) => get_profiles_dir().join(name),
+        None => get_config_dir(),
+    }
+}
+
+/// Whether the active profile has been created, which happens on its first save. The
+/// default profile always exists.
+pub fn profile_exists() -> bool {
+    get_profile().is_none() || get_profile_dir().is_dir()
+}
+
+/// Names of the named profiles, sorted.
+pub fn list_profiles() -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(get_profiles_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str()
+            && validate_profile_name(name).is_ok()
+        {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Runs `f` with `QWK_CONFIG_DIR` pointing at a fresh temporary directory and no profile
+/// selected, one test at a time since the environment is shared by the whole test binary.
+#[cfg(test)]
+pub(crate) fn with_temp_config_dir<T>(f: impl FnOnce(&Path) -> T) -> T {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    unsafe {
+        env::set_var(CONFIG_DIR_ENV, temp_dir.path());
+        env::remove_var(PROFILE_ENV);
+        env::remove_var(crypto::PASSPHRASE_ENV);
+        env::remove_var(ALIASES_FILE_ENV);
+    }
+    let result = f(temp_dir.path());
+    unsafe {
+        env::remove_var(CONFIG_DIR_ENV);
+        env::remove_var(PROFILE_ENV);
+        env::remove_var(crypto::PASSPHRASE_ENV);
+        env::remove_var(ALIASES_FILE_ENV);
+    }
+    result
 }
 
 pub fn ensure_config_dir() -> io::Result<PathBuf> {
@@ -17,113 +229,2390 @@ pub fn ensure_config_dir() -> io::Result<PathBuf> {
     Ok(config_dir)
 }
 
+/// Creates the directory of the active profile, which is how a named profile comes to exist.
+pub fn ensure_profile_dir() -> io::Result<PathBuf> {
+    let profile_dir = get_profile_dir();
+    fs::create_dir_all(&profile_dir)?;
+    Ok(profile_dir)
+}
+
+/// Environment variable naming an aliases file to use instead of the profile's, e.g. for
+/// scripts and tests. Its backups go next to it.
+pub const ALIASES_FILE_ENV: &str = "QWK_ALIASES_FILE";
+
 pub fn get_aliases_file() -> PathBuf {
-    get_config_dir().join("aliases.json")
+    match env::var_os(ALIASES_FILE_ENV) {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => get_profile_dir().join("aliases.json"),
+    }
+}
+
+/// The directory backups of the aliases go to: the one holding the aliases file, which is
+/// the profile directory unless `QWK_ALIASES_FILE` points elsewhere.
+pub fn get_backup_dir() -> PathBuf {
+    match get_aliases_file().parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
 }
 
+/// Aliases moved out of the way with `--archive`, in the same format as `aliases.json`.
+pub fn get_archive_file() -> PathBuf {
+    get_profile_dir().join("archive.json")
+}
+
+/// Where `--agent` stored the agent command before it moved to `config.toml`. Only read
+/// as a fallback; the next `set_agent` migrates it.
 pub fn get_agent_file() -> PathBuf {
     get_config_dir().join("agent")
 }
 
-pub fn load_aliases() -> HashMap<String, String> {
-    let aliases_file = get_aliases_file();
-    if aliases_file.exists() {
-        let content = fs::read_to_string(&aliases_file).unwrap_or_default();
-        serde_json::from_str(&content).unwrap_or_default()
-    } else {
-        HashMap::new()
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AliasEntry {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prompt: String,
+    /// File holding the prompt, relative to the aliases file. Only found on disk: loading
+    /// reads the file into `prompt`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Where an imported alias came from, e.g. `llm:~/.config/io.datasette.llm/templates/x.yaml`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Fields from an import format that qwk has no use for but keeps for exporting back
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Listed and completed before the other aliases
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Directory the agent runs in, which may start with `~`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Ask before running it, unless `--yes` is given
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub confirm: bool,
+    /// Command run before the agent, instead of `Settings::pre_hook`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    /// Command run after the agent, instead of `Settings::post_hook`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+}
+
+impl AliasEntry {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        AliasEntry {
+            prompt: prompt.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Marks the entry as modified now, also setting the creation time of new entries.
+    pub fn touch(&mut self) {
+        let now = Utc::now();
+        self.created_at.get_or_insert(now);
+        self.updated_at = Some(now);
+    }
+
+    /// Whether the entry carries every one of `tags`.
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// Whether the entries differ in nothing but their timestamps.
+    pub fn same_content(&self, other: &AliasEntry) -> bool {
+        let strip = |entry: &AliasEntry| AliasEntry {
+            created_at: None,
+            updated_at: None,
+            ..entry.clone()
+        };
+        strip(self) == strip(other)
     }
 }
 
-pub fn save_aliases(aliases: &HashMap<String, String>) -> io::Result<()> {
-    ensure_config_dir()?;
-    let aliases_file = get_aliases_file();
-    let content = serde_json::to_string_pretty(aliases)?;
-    fs::write(aliases_file, content)
+/// Counts how many aliases carry each tag, ordered by tag name.
+pub fn count_tags(aliases: &HashMap<String, AliasEntry>) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for entry in aliases.values() {
+        for tag in &entry.tags {
+            *counts.entry(tag.as_str()).or_insert(0) += 1;
+        }
+    }
+    counts
 }
 
-pub fn get_agent() -> String {
-    let agent_file = get_agent_file();
-    if agent_file.exists() {
-        fs::read_to_string(&agent_file)
-            .unwrap_or_else(|_| "claude".to_string())
-            .trim()
-            .to_string()
+/// On-disk shape of a single alias. Older files map names straight to prompt strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAlias {
+    Legacy(String),
+    Entry(Box<AliasEntry>),
+}
+
+/// Format version written by `save_aliases`. Version 1 is the bare map of names to aliases
+/// that predates the versioned envelope.
+pub const ALIASES_FORMAT_VERSION: u32 = 2;
+
+/// On-disk shape of a versioned aliases file.
+#[derive(Deserialize)]
+struct VersionedAliases {
+    version: u32,
+    aliases: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct VersionedAliasesRef<'a> {
+    version: u32,
+    /// Sorted by name so that saving the same aliases always writes the same bytes
+    aliases: BTreeMap<&'a str, &'a AliasEntry>,
+}
+
+#[derive(Debug)]
+pub enum ParseAliasesError {
+    Json(serde_json::Error),
+    /// The file was written by a newer qwk, whose fields this one would drop
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ParseAliasesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAliasesError::Json(e) => e.fmt(f),
+            ParseAliasesError::UnsupportedVersion(version) => write!(
+                f,
+                "format version {} is not supported (this qwk reads up to version {})",
+                version, ALIASES_FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ParseAliasesError {
+    fn from(e: serde_json::Error) -> Self {
+        ParseAliasesError::Json(e)
+    }
+}
+
+/// Upgrades the aliases of a file in format `version` to the current format.
+fn migrate(
+    version: u32,
+    aliases: serde_json::Value,
+) -> Result<HashMap<String, AliasEntry>, ParseAliasesError> {
+    match version {
+        1 => {
+            let stored: HashMap<String, StoredAlias> = serde_json::from_value(aliases)?;
+            Ok(stored
+                .into_iter()
+                .map(|(name, alias)| {
+                    let entry = match alias {
+                        StoredAlias::Legacy(prompt) => AliasEntry::new(prompt),
+                        StoredAlias::Entry(entry) => *entry,
+                    };
+                    (name, entry)
+                })
+                .collect())
+        }
+        ALIASES_FORMAT_VERSION => Ok(serde_json::from_value(aliases)?),
+        _ => Err(ParseAliasesError::UnsupportedVersion(version)),
+    }
+}
+
+/// Parses an aliases file of any supported version. Older versions are only upgraded in
+/// memory; the file is rewritten in the current format on the next save.
+pub fn parse_aliases(content: &str) -> Result<HashMap<String, AliasEntry>, ParseAliasesError> {
+    parse_aliases_value(serde_json::from_str(content)?)
+}
+
+/// Whether `value` is a versioned envelope rather than a version 1 map of names to aliases.
+pub(crate) fn is_versioned(value: &serde_json::Value) -> bool {
+    // A version 1 alias named "version" maps to a string or an object, never a number
+    value
+        .get("version")
+        .is_some_and(|version| version.is_number())
+}
+
+/// Like `parse_aliases`, for a document already read by another format's parser.
+pub(crate) fn parse_aliases_value(
+    value: serde_json::Value,
+) -> Result<HashMap<String, AliasEntry>, ParseAliasesError> {
+    if is_versioned(&value) {
+        let file: VersionedAliases = serde_json::from_value(value)?;
+        migrate(file.version, file.aliases)
     } else {
-        "claude".to_string()
+        migrate(1, value)
     }
 }
 
-pub fn set_agent(command: &str) -> io::Result<()> {
-    ensure_config_dir()?;
-    let agent_file = get_agent_file();
-    fs::write(agent_file, command)
+/// Why a shortcuts file that exists couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The file isn't valid JSON, e.g. it was truncated or edited by hand
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The file is in a format version this qwk doesn't know
+    UnsupportedVersion {
+        path: PathBuf,
+        version: u32,
+    },
+    /// The file is encrypted and couldn't be decrypted
+    Decrypt {
+        path: PathBuf,
+        source: CryptoError,
+    },
 }
 
-pub fn create_aliases_backup() -> io::Result<Option<String>> {
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(f, "could not read {}: {}", path.display(), source)
+            }
+            ConfigError::Parse { path, source } => {
+                write!(
+                    f,
+                    "{} is not a valid shortcuts file: {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::UnsupportedVersion { path, version } => {
+                write!(
+                    f,
+                    "{} uses format version {}, but this qwk only reads up to version {}; \
+                     upgrade qwk to use it",
+                    path.display(),
+                    version,
+                    ALIASES_FORMAT_VERSION
+                )
+            }
+            ConfigError::Decrypt { path, source } => {
+                write!(f, "could not decrypt {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigError {
+    /// The file that couldn't be loaded.
+    pub fn path(&self) -> &Path {
+        match self {
+            ConfigError::Read { path, .. }
+            | ConfigError::Parse { path, .. }
+            | ConfigError::UnsupportedVersion { path, .. }
+            | ConfigError::Decrypt { path, .. } => path,
+        }
+    }
+}
+
+/// Loads the shortcuts stored at `path`. A missing file holds no shortcuts, but one that
+/// can't be read or parsed is an error so that it never gets overwritten with an empty map.
+pub fn load_aliases_from(path: &Path) -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let data = fs::read(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    decode_aliases(path, data)
+}
+
+/// `load_aliases_from` without reading the prompt files, so entries that refer to one have
+/// an empty prompt. For reading just the names, as completion does.
+fn load_alias_names_from(path: &Path) -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    match fs::read(path) {
+        Ok(data) => parse_aliases_data(path, data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(source) => Err(ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+/// Parses the contents of a shortcuts file, decrypting them first if they are encrypted,
+/// and reads the prompts kept in their own files, relative to `path`. The data may come
+/// from elsewhere than `path`, such as git.
+pub fn decode_aliases(
+    path: &Path,
+    data: Vec<u8>,
+) -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    let mut aliases = parse_aliases_data(path, data)?;
+    prompt_files::resolve_prompt_files(path.parent().unwrap_or(Path::new("")), &mut aliases);
+    Ok(aliases)
+}
+
+/// `decode_aliases` without reading the prompt files, leaving their references in place.
+fn parse_aliases_data(
+    path: &Path,
+    data: Vec<u8>,
+) -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    let content = decrypt_profile_data(path, data)?;
+    parse_aliases(&content).map_err(|e| match e {
+        ParseAliasesError::Json(source) => ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        },
+        ParseAliasesError::UnsupportedVersion(version) => ConfigError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+        },
+    })
+}
+
+/// The text of a profile file read from `path`, decrypted first if it's encrypted.
+fn decrypt_profile_data(path: &Path, mut data: Vec<u8>) -> Result<String, ConfigError> {
+    let read_error = |source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+    if crypto::is_encrypted(&data) {
+        let decrypt_error = |source| ConfigError::Decrypt {
+            path: path.to_path_buf(),
+            source,
+        };
+        let passphrase = crypto::passphrase(false).map_err(decrypt_error)?;
+        data = crypto::decrypt(&data, &passphrase).map_err(|e| {
+            crypto::forget_passphrase();
+            decrypt_error(e)
+        })?;
+    }
+    String::from_utf8(data).map_err(|e| read_error(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+pub fn load_aliases() -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    load_aliases_from(&get_aliases_file())
+}
+
+pub fn load_archive() -> Result<HashMap<String, AliasEntry>, ConfigError> {
+    load_aliases_from(&get_archive_file())
+}
+
+/// Name of the project-local aliases file, in the same format as `aliases.json`.
+pub const LOCAL_ALIASES_FILE: &str = ".qwk.json";
+
+/// Environment variable that turns on `--verbose`.
+pub const VERBOSE_ENV: &str = "QWK_VERBOSE";
+
+/// Whether `QWK_VERBOSE` asks for `--verbose`: set to anything but empty or `0`.
+pub fn verbose_from_env() -> bool {
+    env::var_os(VERBOSE_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Environment variable that turns off looking for `.qwk.json`, like `--no-local`.
+pub const NO_LOCAL_ENV: &str = "QWK_NO_LOCAL";
+
+pub fn local_aliases_enabled() -> bool {
+    !NO_LOCAL.load(Ordering::SeqCst)
+        && env::var_os(NO_LOCAL_ENV).is_none_or(|value| value.is_empty())
+}
+
+/// The nearest `.qwk.json` in `start` or one of its ancestors.
+pub fn find_local_aliases_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(LOCAL_ALIASES_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The `.qwk.json` that applies in the current directory, unless looking for one is off.
+pub fn get_local_aliases_file() -> Option<PathBuf> {
+    if !local_aliases_enabled() {
+        return None;
+    }
+    find_local_aliases_file(&env::current_dir().ok()?)
+}
+
+/// The aliases that apply in the current directory.
+#[derive(Debug, Default)]
+pub struct EffectiveAliases {
+    pub aliases: HashMap<String, AliasEntry>,
+    /// Names whose entry comes from the project-local file
+    pub local: HashSet<String>,
+}
+
+/// Puts the `local` aliases over the `global` ones of the same name.
+pub fn merge_local_aliases(
+    global: HashMap<String, AliasEntry>,
+    local: HashMap<String, AliasEntry>,
+) -> EffectiveAliases {
+    let mut aliases = global;
+    let local_names = local.keys().cloned().collect();
+    aliases.extend(local);
+    EffectiveAliases {
+        aliases,
+        local: local_names,
+    }
+}
+
+/// The aliases of the active profile with those of the nearest `.qwk.json` over them.
+pub fn load_effective_aliases() -> Result<EffectiveAliases, ConfigError> {
+    let global = load_aliases()?;
+    let local = match get_local_aliases_file() {
+        Some(path) => load_aliases_from(&path)?,
+        None => HashMap::new(),
+    };
+    Ok(merge_local_aliases(global, local))
+}
+
+/// `load_effective_aliases` without reading the prompt files.
+pub fn load_effective_alias_names() -> Result<EffectiveAliases, ConfigError> {
+    let global = load_alias_names_from(&get_aliases_file())?;
+    let local = match get_local_aliases_file() {
+        Some(path) => load_alias_names_from(&path)?,
+        None => HashMap::new(),
+    };
+    Ok(merge_local_aliases(global, local))
+}
+
+/// Gives entries loaded from older files, which carry no timestamps, the current time.
+pub fn fill_missing_timestamps(aliases: &mut HashMap<String, AliasEntry>, now: DateTime<Utc>) {
+    for entry in aliases.values_mut() {
+        entry.created_at.get_or_insert(now);
+        entry.updated_at.get_or_insert(now);
+    }
+}
+
+/// How `write_aliases` stores the prompts.
+enum Storage<'a> {
+    Inline,
+    Encrypted {
+        passphrase: &'a str,
+    },
+    /// Each prompt in its own file under `prompts/` next to the aliases file
+    PromptFiles,
+}
+
+fn write_aliases(
+    path: &Path,
+    aliases: &HashMap<String, AliasEntry>,
+    storage: Storage,
+) -> io::Result<()> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    if !base.as_os_str().is_empty() {
+        fs::create_dir_all(base)?;
+    }
+    let mut aliases = match storage {
+        // The prompt files are all in place before the aliases file refers to them
+        Storage::PromptFiles => prompt_files::write_prompt_files(base, aliases)?,
+        _ => aliases.clone(),
+    };
+    fill_missing_timestamps(&mut aliases, Utc::now());
+    let content = serde_json::to_string_pretty(&VersionedAliasesRef {
+        version: ALIASES_FORMAT_VERSION,
+        aliases: aliases
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+            .collect(),
+    })?;
+    match storage {
+        Storage::Inline => fs::write(path, content),
+        Storage::Encrypted { passphrase } => {
+            fs::write(path, crypto::encrypt(content.as_bytes(), passphrase))
+        }
+        Storage::PromptFiles => {
+            fs::write(path, content)?;
+            prompt_files::remove_unused_prompt_files(base, &aliases)
+        }
+    }
+}
+
+/// Writes `aliases` to `path` in plain JSON, as for a project's `.qwk.json`.
+pub fn save_aliases_to(path: &Path, aliases: &HashMap<String, AliasEntry>) -> io::Result<()> {
+    write_aliases(path, aliases, Storage::Inline)
+}
+
+/// Writes a shortcuts file of the active profile, encrypted when the `encrypt` setting is
+/// on. A new encrypted file gets its passphrase typed twice. With `prompt_files` the
+/// prompts go to their own files when the `prompt_files` setting is on.
+fn save_profile_aliases(
+    path: &Path,
+    aliases: &HashMap<String, AliasEntry>,
+    prompt_files: bool,
+) -> io::Result<()> {
+    // Never fall back to plain text because config.toml is broken
+    let settings = load_settings().map_err(io::Error::other)?;
+    if settings.encrypt {
+        let passphrase = crypto::passphrase(!crypto::is_encrypted_file(path))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        return write_aliases(
+            path,
+            aliases,
+            Storage::Encrypted {
+                passphrase: &passphrase,
+            },
+        );
+    }
+    if prompt_files && settings.prompt_files {
+        return write_aliases(path, aliases, Storage::PromptFiles);
+    }
+    write_aliases(path, aliases, Storage::Inline)
+}
+
+/// Whether going from `before` to `after` only adds aliases, so that nothing is lost.
+pub fn only_adds(
+    before: &HashMap<String, AliasEntry>,
+    after: &HashMap<String, AliasEntry>,
+) -> bool {
+    before.iter().all(|(name, entry)| {
+        after
+            .get(name)
+            .is_some_and(|saved| saved.same_content(entry))
+    })
+}
+
+/// With `backup_on_write`, snapshots the aliases file into `backups/auto/` before `aliases`
+/// replace it, unless they only add to it. A file that can't be loaded is snapshotted too.
+fn backup_before_write(aliases: &HashMap<String, AliasEntry>) -> io::Result<()> {
     let aliases_file = get_aliases_file();
-    if !aliases_file.exists() {
-        return Ok(None);
+    if !aliases_file.exists() || !load_settings().is_ok_and(|settings| settings.backup_on_write) {
+        return Ok(());
     }
+    if load_aliases_from(&aliases_file).is_ok_and(|before| only_adds(&before, aliases)) {
+        return Ok(());
+    }
+    let dir = get_auto_backup_dir();
+    fs::create_dir_all(&dir)?;
+    let datetime = unused_backup_datetime(&dir, get_current_datetime());
+    copy_aliases_file(&aliases_file, &backup_file_in(&dir, &datetime))?;
+    apply_plan(&plan_prune_backups(&dir, AUTO_BACKUPS_KEPT)?)
+}
 
-    let config_dir = ensure_config_dir()?;
-    let datetime = get_current_datetime();
-    let backup_file = config_dir.join(format!("aliases_backup_{}.json", datetime));
+pub fn save_aliases(aliases: &HashMap<String, AliasEntry>) -> io::Result<()> {
+    backup_before_write(aliases)?;
+    save_profile_aliases(&get_aliases_file(), aliases, true)
+}
 
-    fs::copy(&aliases_file, &backup_file)?;
-    Ok(Some(backup_file.to_string_lossy().to_string()))
+/// Saves the archive, which always keeps its prompts inline: archived aliases are out of
+/// the way, and their files would share `prompts/` with the active ones.
+pub fn save_archive(archive: &HashMap<String, AliasEntry>) -> io::Result<()> {
+    save_profile_aliases(&get_archive_file(), archive, false)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
-    use tempfile::TempDir;
+/// The contents of a copy of the shortcuts file at `path`, as for a backup. A file that
+/// keeps its prompts in their own files gets them inline, so the copy stands on its own;
+/// encrypted or unreadable files are taken as they are.
+pub fn aliases_file_snapshot(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if crypto::is_encrypted(&data) {
+        return Ok(data);
+    }
+    match parse_aliases_data(path, data.clone()) {
+        Ok(mut aliases) if aliases.values().any(|entry| entry.prompt_file.is_some()) => {
+            prompt_files::resolve_prompt_files(
+                path.parent().unwrap_or(Path::new("")),
+                &mut aliases,
+            );
+            let content = serde_json::to_string_pretty(&VersionedAliasesRef {
+                version: ALIASES_FORMAT_VERSION,
+                aliases: aliases
+                    .iter()
+                    .map(|(name, entry)| (name.as_str(), entry))
+                    .collect(),
+            })?;
+            Ok(content.into_bytes())
+        }
+        _ => Ok(data),
+    }
+}
+
+/// Copies a shortcuts file to `to` with `aliases_file_snapshot`.
+pub fn copy_aliases_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::write(to, aliases_file_snapshot(from)?)
+}
+
+/// Removed aliases `trash.json` keeps, dropping the oldest.
+pub const TRASH_CAPACITY: usize = 50;
+
+/// An alias taken out by `--remove`, kept for `--restore-removed`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashedAlias {
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub entry: AliasEntry,
+}
+
+pub fn get_trash_file() -> PathBuf {
+    get_profile_dir().join("trash.json")
+}
+
+/// The removed aliases, oldest first. Like the shortcuts, a trash file that can't be read
+/// is an error rather than empty, so that it never gets overwritten.
+pub fn load_trash() -> Result<Vec<TrashedAlias>, ConfigError> {
+    let path = get_trash_file();
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(source) => return Err(ConfigError::Read { path, source }),
+    };
+    let content = decrypt_profile_data(&path, data)?;
+    serde_json::from_str(&content).map_err(|source| ConfigError::Parse { path, source })
+}
 
-    fn setup_test_config(temp_dir: &TempDir) -> PathBuf {
-        temp_dir.path().join("qwk")
+/// Writes the trash, encrypted like the shortcuts when the `encrypt` setting is on.
+pub fn save_trash(trash: &[TrashedAlias]) -> io::Result<()> {
+    let path = get_trash_file();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_json::to_string_pretty(trash)?;
+    let settings = load_settings().map_err(io::Error::other)?;
+    if settings.encrypt {
+        let passphrase = crypto::passphrase(!crypto::is_encrypted_file(&path))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        return fs::write(path, crypto::encrypt(content.as_bytes(), &passphrase));
     }
+    fs::write(path, content)
+}
 
-    #[test]
-    fn test_alias_storage_and_retrieval() {
-        let temp_dir = TempDir::new().unwrap();
-        let config_dir = setup_test_config(&temp_dir);
-        fs::create_dir_all(&config_dir).unwrap();
+/// Adds `entry`, removed from the aliases as `name`, to the trash, keeping the newest
+/// `TRASH_CAPACITY`.
+pub fn trash_alias(
+    trash: &mut Vec<TrashedAlias>,
+    name: &str,
+    entry: AliasEntry,
+    deleted_at: DateTime<Utc>,
+) {
+    trash.push(TrashedAlias {
+        name: name.to_string(),
+        deleted_at,
+        entry,
+    });
+    let excess = trash.len().saturating_sub(TRASH_CAPACITY);
+    trash.drain(..excess);
+}
+
+/// Puts the most recently removed alias called `name` back into `aliases`. An alias of the
+/// same name is only replaced with `force`.
+pub fn restore_removed(
+    aliases: &mut HashMap<String, AliasEntry>,
+    trash: &mut Vec<TrashedAlias>,
+    name: &str,
+    force: bool,
+) -> Result<(), MoveAliasError> {
+    let index = trash
+        .iter()
+        .rposition(|trashed| trashed.name == name)
+        .ok_or(MoveAliasError::NotFound)?;
+    if aliases.contains_key(name) && !force {
+        return Err(MoveAliasError::Exists);
+    }
+    let trashed = trash.remove(index);
+    aliases.insert(trashed.name, trashed.entry);
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MoveAliasError {
+    /// There's no alias of that name to move
+    NotFound,
+    /// The destination already has an alias of that name
+    Exists,
+}
+
+/// Moves alias `name` between the aliases and the archive. An alias of the same name at the
+/// destination is only replaced with `force`.
+pub fn move_alias(
+    from: &mut HashMap<String, AliasEntry>,
+    to: &mut HashMap<String, AliasEntry>,
+    name: &str,
+    force: bool,
+) -> Result<(), MoveAliasError> {
+    if to.contains_key(name) && !force && from.contains_key(name) {
+        return Err(MoveAliasError::Exists);
+    }
+    let entry = from.remove(name).ok_or(MoveAliasError::NotFound)?;
+    to.insert(name.to_string(), entry);
+    Ok(())
+}
+
+/// Agent used when neither `config.toml` nor the legacy agent file names one.
+pub const DEFAULT_AGENT: &str = "claude";
+
+/// A known-good agent command for a common AI CLI, set with `--agent --preset <name>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgentPreset {
+    pub name: &'static str,
+    pub command: &'static str,
+    pub description: &'static str,
+}
+
+/// The presets `--presets` lists. Each command takes the prompt as its last argument.
+pub const AGENT_PRESETS: &[AgentPreset] = &[
+    AgentPreset {
+        name: "aichat",
+        command: "aichat",
+        description: "aichat, answering once with its default model",
+    },
+    AgentPreset {
+        name: "claude",
+        command: "claude",
+        description: "Claude Code, starting an interactive session with the prompt",
+    },
+    AgentPreset {
+        name: "codex",
+        command: "codex",
+        description: "OpenAI Codex CLI, starting an interactive session with the prompt",
+    },
+    AgentPreset {
+        name: "gemini",
+        command: "gemini -i",
+        description: "Gemini CLI, running the prompt and staying interactive",
+    },
+    AgentPreset {
+        name: "llm",
+        command: "llm",
+        description: "Simon Willison's llm, answering once with its default model",
+    },
+    AgentPreset {
+        name: "ollama",
+        command: "ollama run llama3",
+        description: "Ollama, answering once with a local llama3",
+    },
+];
+
+pub fn find_agent_preset(name: &str) -> Option<&'static AgentPreset> {
+    AGENT_PRESETS.iter().find(|preset| preset.name == name)
+}
 
-        let aliases_file = config_dir.join("aliases.json");
+fn read_legacy_agent() -> Option<String> {
+    fs::read_to_string(get_agent_file())
+        .ok()
+        .map(|agent| agent.trim().to_string())
+}
 
-        // Test empty case
-        let empty_aliases = load_aliases_from_file(&aliases_file);
-        assert!(empty_aliases.is_empty());
+/// An agent command and the options it runs with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentSpec {
+    pub command: String,
+    /// Write the prompt to the agent's stdin instead of passing it as an argument
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stdin_prompt: bool,
+    /// Run the command as a shell script, e.g. a pipeline, instead of splitting it into a
+    /// program and its arguments
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell: bool,
+    /// Environment variables set for the agent, e.g. the address of its server
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
 
-        // Test saving and loading
-        let mut test_aliases = HashMap::new();
-        test_aliases.insert("test1".to_string(), "prompt1".to_string());
-        test_aliases.insert("test2".to_string(), "prompt2".to_string());
+impl AgentSpec {
+    pub fn new(command: impl Into<String>) -> Self {
+        AgentSpec {
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+}
 
-        save_aliases_to_file(&aliases_file, &test_aliases).unwrap();
+/// A named agent in `agents.json`: just the command unless it has options.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredAgent {
+    Command(String),
+    Spec(AgentSpec),
+}
 
-        let loaded_aliases = load_aliases_from_file(&aliases_file);
-        assert_eq!(loaded_aliases.len(), 2);
-        assert_eq!(loaded_aliases.get("test1"), Some(&"prompt1".to_string()));
-        assert_eq!(loaded_aliases.get("test2"), Some(&"prompt2".to_string()));
+impl From<StoredAgent> for AgentSpec {
+    fn from(stored: StoredAgent) -> Self {
+        match stored {
+            StoredAgent::Command(command) => AgentSpec::new(command),
+            StoredAgent::Spec(spec) => spec,
+        }
     }
+}
 
-    // Helper functions for testing
-    fn load_aliases_from_file(file_path: &PathBuf) -> HashMap<String, String> {
-        if file_path.exists() {
-            let content = fs::read_to_string(file_path).unwrap_or_default();
-            serde_json::from_str(&content).unwrap_or_default()
+impl From<&AgentSpec> for StoredAgent {
+    fn from(spec: &AgentSpec) -> Self {
+        if *spec == AgentSpec::new(spec.command.clone()) {
+            StoredAgent::Command(spec.command.clone())
         } else {
-            HashMap::new()
+            StoredAgent::Spec(spec.clone())
         }
     }
+}
+
+/// Where the agent command returned by `get_agent` comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentSource {
+    /// The named agent chosen with `--agent-use`
+    Named(String),
+    /// `default_agent` in `config.toml`, set with `--agent`
+    Settings,
+    /// The agent file of older versions
+    LegacyFile,
+    /// Nothing set, so `DEFAULT_AGENT`
+    Default,
+}
+
+/// The agent and where it comes from: the named agent chosen with `--agent-use`, or else
+/// `default_agent` in `config.toml`. Falls back to the legacy agent file of older installs
+/// and then to `DEFAULT_AGENT`.
+pub fn resolve_agent() -> (AgentSpec, AgentSource) {
+    // A malformed config.toml is reported by the commands that need the rest of it
+    let settings = load_settings().ok();
+    if let Some(name) = settings
+        .as_ref()
+        .and_then(|settings| settings.active_agent.clone())
+        && let Some(spec) = load_agents()
+            .ok()
+            .and_then(|mut agents| agents.remove(&name))
+    {
+        return (spec, AgentSource::Named(name));
+    }
+    let settings = settings.unwrap_or_default();
+    let (command, source) = match (settings.default_agent, read_legacy_agent()) {
+        (Some(command), _) => (command, AgentSource::Settings),
+        (None, Some(command)) => (command, AgentSource::LegacyFile),
+        (None, None) => (DEFAULT_AGENT.to_string(), AgentSource::Default),
+    };
+    let spec = AgentSpec {
+        command,
+        stdin_prompt: settings.agent_stdin_prompt,
+        shell: settings.agent_shell,
+        env: settings.agent_env,
+    };
+    (spec, source)
+}
+
+/// The command of the agent from `resolve_agent`.
+pub fn get_agent() -> String {
+    resolve_agent().0.command
+}
+
+/// `set_agent_spec` for a command without options.
+pub fn set_agent(command: &str) -> io::Result<()> {
+    set_agent_spec(&AgentSpec::new(command))
+}
+
+/// Stores the agent as `default_agent` and its options in the active profile's settings
+/// file and removes the legacy agent file. The other settings are kept, but comments in the
+/// file are not; so are the agent's environment variables, which `set_agent_env` sets. A
+/// named agent chosen with `--agent-use` is deselected, so the command takes effect.
+pub fn set_agent_spec(spec: &AgentSpec) -> io::Result<()> {
+    set_profile_setting("default_agent", toml::Value::String(spec.command.clone()))?;
+    if spec.stdin_prompt {
+        set_profile_setting("agent_stdin_prompt", toml::Value::Boolean(true))?;
+    } else {
+        unset_profile_setting("agent_stdin_prompt")?;
+    }
+    if spec.shell {
+        set_profile_setting("agent_shell", toml::Value::Boolean(true))?;
+    } else {
+        unset_profile_setting("agent_shell")?;
+    }
+    unset_profile_setting("active_agent")?;
+
+    match fs::remove_file(get_agent_file()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the environment variables of the agent that isn't a named one in the active
+/// profile's settings file, replacing those there.
+pub fn set_agent_env(env: &BTreeMap<String, String>) -> io::Result<()> {
+    if env.is_empty() {
+        unset_profile_setting("agent_env")?;
+        return Ok(());
+    }
+    let table = env
+        .iter()
+        .map(|(key, value)| (key.clone(), toml::Value::String(value.clone())))
+        .collect();
+    set_profile_setting("agent_env", toml::Value::Table(table))
+}
+
+/// Stores the agent commands tried in turn when the agent's program isn't found, in the
+/// active profile's settings file.
+pub fn set_agent_fallbacks(fallbacks: &[String]) -> io::Result<()> {
+    if fallbacks.is_empty() {
+        unset_profile_setting("agent_fallbacks")?;
+        return Ok(());
+    }
+    let list = fallbacks
+        .iter()
+        .map(|command| toml::Value::String(command.clone()))
+        .collect();
+    set_profile_setting("agent_fallbacks", toml::Value::Array(list))
+}
+
+/// Environment variable names go into `key=value` pairs and C strings.
+pub fn validate_env_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("The variable name can't be empty".to_string());
+    }
+    if name.contains(['=', '\0']) {
+        return Err(format!(
+            "Invalid variable name '{}': it can't contain '=' or NUL",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Removes the agent set in the active profile's settings file, whether a command or a
+/// named agent, and the legacy agent file, going back to the global agent or
+/// `DEFAULT_AGENT`. Returns whether there was anything to remove.
+pub fn unset_agent() -> io::Result<bool> {
+    let mut removed = false;
+    for key in AGENT_SETTINGS {
+        removed |= unset_profile_setting(key)?;
+    }
+    match fs::remove_file(get_agent_file()) {
+        Ok(()) => removed = true,
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    Ok(removed)
+}
+
+/// Sets one key of the active profile's settings file, keeping the other settings but not
+/// the comments.
+pub fn set_profile_setting(key: &str, value: toml::Value) -> io::Result<()> {
+    ensure_profile_dir()?;
+    let settings_file = get_profile_settings_file();
+    let mut table = load_settings_table(&settings_file)?;
+    table.insert(key.to_string(), value);
+    fs::write(
+        &settings_file,
+        toml::to_string(&table).map_err(io::Error::other)?,
+    )
+}
+
+/// Removes one key of the active profile's settings file. Returns whether it was there.
+pub fn unset_profile_setting(key: &str) -> io::Result<bool> {
+    let settings_file = get_profile_settings_file();
+    let mut table = load_settings_table(&settings_file)?;
+    if table.remove(key).is_none() {
+        return Ok(false);
+    }
+    fs::write(
+        &settings_file,
+        toml::to_string(&table).map_err(io::Error::other)?,
+    )?;
+    Ok(true)
+}
+
+/// Named agent commands registered with `--agent-add`, shared by all profiles.
+pub fn get_agents_file() -> PathBuf {
+    get_config_dir().join("agents.json")
+}
+
+/// The named agents by name. A missing file means none; one that can't be read is an error.
+pub fn load_agents() -> Result<BTreeMap<String, AgentSpec>, ConfigError> {
+    let path = get_agents_file();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(source) => return Err(ConfigError::Read { path, source }),
+    };
+    let stored: BTreeMap<String, StoredAgent> =
+        serde_json::from_str(&content).map_err(|source| ConfigError::Parse { path, source })?;
+    Ok(stored
+        .into_iter()
+        .map(|(name, agent)| (name, agent.into()))
+        .collect())
+}
+
+pub fn save_agents(agents: &BTreeMap<String, AgentSpec>) -> io::Result<()> {
+    ensure_config_dir()?;
+    let stored: BTreeMap<&String, StoredAgent> = agents
+        .iter()
+        .map(|(name, spec)| (name, spec.into()))
+        .collect();
+    let content = serde_json::to_string_pretty(&stored)?;
+    fs::write(get_agents_file(), content + "\n")
+}
+
+/// Agent names are typed after `--with`, so they follow the rules of profile names.
+pub fn validate_agent_name(name: &str) -> Result<(), String> {
+    validate_name("agent", name)
+}
+
+/// Makes the named agent the one shortcuts run with in the active profile, returning it.
+pub fn use_agent(name: &str) -> Result<AgentSpec, QwkError> {
+    let mut agents = load_agents()?;
+    let agent = agents
+        .remove(name)
+        .ok_or_else(|| QwkError::AgentNotFound(name.to_string()))?;
+    set_profile_setting("active_agent", toml::Value::String(name.to_string()))
+        .map_err(|e| QwkError::io("Error setting agent", e))?;
+    Ok(agent)
+}
+
+/// The settings that together choose the agent.
+const AGENT_SETTINGS: &[&str] = &[
+    "default_agent",
+    "active_agent",
+    "agent_stdin_prompt",
+    "agent_shell",
+    "agent_env",
+    "agent_fallbacks",
+];
+
+/// Default for `Settings::max_prompt_bytes`.
+pub const DEFAULT_MAX_PROMPT_BYTES: usize = 512 * 1024;
+
+/// Default for `Settings::max_file_bytes`.
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Default for `Settings::list_preview_width`.
+pub const DEFAULT_LIST_PREVIEW_WIDTH: usize = 60;
+
+/// Default for `Settings::daily_backups`.
+pub const DEFAULT_DAILY_BACKUPS: usize = 14;
+
+/// Default for `Settings::notify_after_secs`.
+pub const DEFAULT_NOTIFY_AFTER_SECS: u64 = 30;
+
+/// Default for `Settings::keep_backups`.
+pub const DEFAULT_KEEP_BACKUPS: usize = 10;
+
+/// Tool-wide options read from `config.toml`. Every field is optional in the file, and
+/// command-line flags override them.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Refuse to run shortcuts when the agent's default arguments match a dangerous pattern
+    pub forbid_dangerous_args: bool,
+    /// Regexes matched against each agent argument; replaces the built-in list when set
+    pub dangerous_arg_patterns: Option<Vec<String>>,
+    /// Largest prompt `--set` accepts, in bytes
+    pub max_prompt_bytes: usize,
+    /// Largest file a `{{file:path}}` placeholder may inline, in bytes
+    pub max_file_bytes: u64,
+    /// Characters of each prompt shown by `--list`
+    pub list_preview_width: usize,
+    /// Ask before `--reset` removes all shortcuts
+    pub confirm_destructive: bool,
+    /// Agent command shortcuts run with; `--agent` sets it
+    pub default_agent: Option<String>,
+    /// Named agent from `agents.json` shortcuts run with instead of `default_agent`;
+    /// `--agent-use` sets it
+    pub active_agent: Option<String>,
+    /// Write the prompt to the stdin of `default_agent` instead of passing it as an
+    /// argument; `--agent --stdin-prompt` sets it
+    pub agent_stdin_prompt: bool,
+    /// Run `default_agent` as a shell script instead of splitting it into a program and its
+    /// arguments; `--agent --shell` sets it
+    pub agent_shell: bool,
+    /// Environment variables set for `default_agent`; `--agent-env` manages them
+    pub agent_env: BTreeMap<String, String>,
+    /// Agent commands tried in turn when the agent's program isn't found; `--agent
+    /// --fallback` adds them
+    pub agent_fallbacks: Vec<String>,
+    /// Backups `--prune-backups` keeps, most recent first
+    pub keep_backups: usize,
+    /// Store `aliases.json`, `archive.json` and `trash.json` encrypted; `--encrypt` sets it
+    pub encrypt: bool,
+    /// Commit the shortcuts to git after every command that changes them, like `--sync`
+    pub auto_commit: bool,
+    /// Keep each prompt in its own file under `prompts/`; `--migrate-to-files` sets it
+    pub prompt_files: bool,
+    /// Snapshot `aliases.json` into `backups/auto/` before saves that change or remove aliases
+    pub backup_on_write: bool,
+    /// Daily backups kept in `backups/daily/`, taken on the first run of each day; 0 turns
+    /// them off
+    pub daily_backups: usize,
+    /// Seconds a shortcut's agent may run before it's stopped; 0 lets it run for as long as
+    /// it takes. `qwk <shortcut> --timeout` overrides it
+    pub agent_timeout_secs: u64,
+    /// Log every shortcut run to `runs.jsonl` for `--runs`
+    pub track_runs: bool,
+    /// On Unix, replace qwk with the agent instead of running it as a child, for runs that
+    /// need nothing done once the agent exits
+    pub exec_agent: bool,
+    /// Command run before the agent of every shortcut without a `pre_hook` of its own; the
+    /// agent only runs if it succeeds
+    pub pre_hook: Option<String>,
+    /// Command run after the agent of every shortcut without a `post_hook` of its own
+    pub post_hook: Option<String>,
+    /// Run the agent even when the pre-hook fails, only warning about it
+    pub ignore_hook_failure: bool,
+    /// Seconds a run given `--notify` must take before it ends with a notification
+    pub notify_after_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            forbid_dangerous_args: false,
+            dangerous_arg_patterns: None,
+            max_prompt_bytes: DEFAULT_MAX_PROMPT_BYTES,
+            max_file_bytes: DEFAULT_MAX_FILE_BYTES,
+            list_preview_width: DEFAULT_LIST_PREVIEW_WIDTH,
+            confirm_destructive: true,
+            default_agent: None,
+            active_agent: None,
+            agent_stdin_prompt: false,
+            agent_shell: false,
+            agent_env: BTreeMap::new(),
+            agent_fallbacks: Vec::new(),
+            keep_backups: DEFAULT_KEEP_BACKUPS,
+            encrypt: false,
+            auto_commit: false,
+            prompt_files: false,
+            backup_on_write: false,
+            daily_backups: DEFAULT_DAILY_BACKUPS,
+            agent_timeout_secs: 0,
+            track_runs: true,
+            exec_agent: false,
+            pre_hook: None,
+            post_hook: None,
+            ignore_hook_failure: false,
+            notify_after_secs: DEFAULT_NOTIFY_AFTER_SECS,
+        }
+    }
+}
+
+pub fn get_settings_file() -> PathBuf {
+    get_config_dir().join("config.toml")
+}
+
+/// The settings file of the active profile, whose keys override `get_settings_file`. It's
+/// the same file for the default profile.
+pub fn get_profile_settings_file() -> PathBuf {
+    get_profile_dir().join("config.toml")
+}
+
+pub fn parse_settings(content: &str) -> Result<Settings, String> {
+    toml::from_str(content).map_err(|e| e.to_string())
+}
+
+pub fn load_settings() -> Result<Settings, String> {
+    let mut table = toml::Table::new();
+    let mut files = vec![get_settings_file()];
+    if get_profile().is_some() {
+        files.push(get_profile_settings_file());
+    }
+    for settings_file in files {
+        if !settings_file.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&settings_file)
+            .map_err(|e| format!("Could not read {}: {}", settings_file.display(), e))?;
+        // Checked on its own first so that errors name the file they are in
+        parse_settings(&content)
+            .map_err(|e| format!("Invalid {}: {}", settings_file.display(), e))?;
+        let overrides: toml::Table = toml::from_str(&content).map_err(|e| e.to_string())?;
+        // An agent chosen in a profile replaces the one chosen globally, either way it's named
+        if overrides.contains_key("default_agent") || overrides.contains_key("active_agent") {
+            for key in AGENT_SETTINGS {
+                table.remove(*key);
+            }
+        }
+        table.extend(overrides);
+    }
+    table.try_into().map_err(|e: toml::de::Error| e.to_string())
+}
+
+/// Reads `config.toml` as a plain table, so that rewriting it keeps every key. A missing
+/// file is an empty table; a malformed one is an error rather than something to overwrite.
+fn load_settings_table(path: &Path) -> io::Result<toml::Table> {
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid {}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Where `backup_on_write` snapshots go, apart from the other backups so that they can
+/// rotate on their own.
+pub fn get_auto_backup_dir() -> PathBuf {
+    get_backup_dir().join("backups").join("auto")
+}
+
+/// Snapshots `backup_on_write` keeps, most recent first.
+pub const AUTO_BACKUPS_KEPT: usize = 20;
+
+fn backup_file_in(dir: &Path, datetime: &str) -> PathBuf {
+    dir.join(format!("aliases_backup_{}.json", datetime))
+}
+
+/// Path of the backup that would be written at `datetime` (see `get_current_datetime`).
+pub fn get_backup_file(datetime: &str) -> PathBuf {
+    backup_file_in(&get_backup_dir(), datetime)
+}
+
+/// Path of the `config.toml` backup written next to `get_backup_file(datetime)`.
+pub fn get_settings_backup_file(datetime: &str) -> PathBuf {
+    get_backup_dir().join(format!("config_backup_{}.toml", datetime))
+}
+
+/// `datetime`, or the second after the newest backup in `dir` if that isn't earlier, so
+/// that a backup never replaces another and always sorts as the newest, even when several
+/// are taken within a second.
+fn unused_backup_datetime(dir: &Path, datetime: String) -> String {
+    let Some(time) = parse_datetime(&datetime) else {
+        return datetime;
+    };
+    match find_backups(dir)
+        .ok()
+        .and_then(|backups| backups.first().cloned())
+    {
+        Some(newest) if newest.taken_at >= time => (newest.taken_at + chrono::Duration::seconds(1))
+            .format(DATETIME_FORMAT)
+            .to_string(),
+        _ => datetime,
+    }
+}
+
+/// Where the daily backups go, one `aliases_YYYYMMDD.json` per day.
+pub fn get_daily_backup_dir() -> PathBuf {
+    get_backup_dir().join("backups").join("daily")
+}
+
+/// Touched whenever the daily backup has been seen to, so that later runs on the same day
+/// only need to look at its modification time.
+fn daily_backup_stamp() -> PathBuf {
+    get_daily_backup_dir().join(".last_check")
+}
+
+/// Whether today's daily backup may still be due. This is all most runs do, so it costs a
+/// single `stat`.
+pub fn daily_backup_due(today: NaiveDate) -> bool {
+    fs::metadata(daily_backup_stamp())
+        .and_then(|metadata| metadata.modified())
+        .map_or(true, |modified| {
+            DateTime::<Local>::from(modified).date_naive() != today
+        })
+}
+
+/// The daily backups, oldest first.
+fn find_daily_backups(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_daily = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("aliases_")?.strip_suffix(".json"))
+            .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y%m%d").is_ok());
+        if is_daily {
+            backups.push(path);
+        }
+    }
+    // The dates in the names sort like the days they stand for
+    backups.sort();
+    Ok(backups)
+}
+
+/// Backs up the aliases as `aliases_YYYYMMDD.json` for `today`, unless that exists or
+/// they haven't changed since the last daily backup, keeping the newest `keep`. Returns
+/// the new backup, if one was written.
+pub fn create_daily_backup(today: NaiveDate, keep: usize) -> io::Result<Option<PathBuf>> {
+    // A named profile only comes to be with its first save, not with a stamp
+    if !profile_exists() {
+        return Ok(None);
+    }
+    let dir = get_daily_backup_dir();
+    fs::create_dir_all(&dir)?;
+    let aliases_file = get_aliases_file();
+    let backup_file = dir.join(format!("aliases_{}.json", today.format("%Y%m%d")));
+
+    let mut written = None;
+    if keep > 0 && aliases_file.exists() && !backup_file.exists() {
+        let snapshot = aliases_file_snapshot(&aliases_file)?;
+        let backups = find_daily_backups(&dir)?;
+        let unchanged = backups
+            .last()
+            .is_some_and(|latest| fs::read(latest).is_ok_and(|data| data == snapshot));
+        if !unchanged {
+            fs::write(&backup_file, snapshot)?;
+            let backups = find_daily_backups(&dir)?;
+            for old in &backups[..backups.len().saturating_sub(keep)] {
+                fs::remove_file(old)?;
+            }
+            written = Some(backup_file);
+        }
+    }
+    // Also with backups off or nothing to back up, so that the rest of today's runs stop at
+    // `daily_backup_due` rather than loading the settings again
+    fs::write(daily_backup_stamp(), today.to_string())?;
+    Ok(written)
+}
+
+/// Backs up the aliases along with the settings, which hold the agent. An install that
+/// still has the legacy agent file gets a settings backup naming that agent.
+pub fn create_aliases_backup() -> io::Result<Option<String>> {
+    let aliases_file = get_aliases_file();
+    if !aliases_file.exists() {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(get_backup_dir())?;
+    let datetime = unused_backup_datetime(&get_backup_dir(), get_current_datetime());
+    let backup_file = get_backup_file(&datetime);
+
+    copy_aliases_file(&aliases_file, &backup_file)?;
+
+    let settings_file = get_profile_settings_file();
+    if settings_file.exists() {
+        fs::copy(&settings_file, get_settings_backup_file(&datetime))?;
+    } else if let Some(agent) = read_legacy_agent() {
+        let mut table = toml::Table::new();
+        table.insert("default_agent".to_string(), toml::Value::String(agent));
+        let content = toml::to_string(&table).map_err(io::Error::other)?;
+        fs::write(get_settings_backup_file(&datetime), content)?;
+    }
+    Ok(Some(backup_file.to_string_lossy().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_alias_storage_and_retrieval() {
+        with_temp_config_dir(|config_dir| {
+            assert_eq!(get_aliases_file(), config_dir.join("aliases.json"));
+
+            // Test empty case
+            let empty_aliases = load_aliases().unwrap();
+            assert!(empty_aliases.is_empty());
+
+            // Test saving and loading
+            let mut test_aliases = HashMap::new();
+            test_aliases.insert("test1".to_string(), AliasEntry::new("prompt1"));
+            test_aliases.insert("test2".to_string(), AliasEntry::new("prompt2"));
+
+            save_aliases(&test_aliases).unwrap();
+
+            let loaded_aliases = load_aliases().unwrap();
+            assert_eq!(loaded_aliases.len(), 2);
+            assert_eq!(loaded_aliases["test1"].prompt, "prompt1");
+            assert_eq!(loaded_aliases["test2"].prompt, "prompt2");
+
+            // The archive lives next to the aliases and doesn't share entries with them
+            save_archive(&test_aliases).unwrap();
+            assert_eq!(get_archive_file(), config_dir.join("archive.json"));
+            assert_eq!(load_archive().unwrap().len(), 2);
+            assert_eq!(load_aliases().unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_load_aliases_rejects_corrupted_file() {
+        with_temp_config_dir(|config_dir| {
+            let path = config_dir.join("aliases.json");
+            fs::create_dir_all(config_dir).unwrap();
+
+            // Truncated mid-write
+            fs::write(&path, r#"{"review": {"prompt": "Review th"#).unwrap();
+            let err = load_aliases().unwrap_err();
+            assert!(matches!(&err, ConfigError::Parse { path: p, .. } if *p == path));
+            assert!(err.to_string().contains("aliases.json"));
+
+            // Valid JSON of the wrong shape
+            fs::write(&path, r#"["review"]"#).unwrap();
+            assert!(matches!(load_aliases(), Err(ConfigError::Parse { .. })));
+
+            // Not UTF-8
+            fs::write(&path, [0xff, 0xfe, 0x00]).unwrap();
+            assert!(matches!(load_aliases(), Err(ConfigError::Read { .. })));
+
+            // The archive is checked the same way
+            fs::write(config_dir.join("archive.json"), "").unwrap();
+            assert!(matches!(load_archive(), Err(ConfigError::Parse { .. })));
+        });
+    }
+
+    #[test]
+    fn test_parse_aliases_migrates_legacy_format() {
+        let content = r#"{
+            "old": "legacy prompt",
+            "new": {"prompt": "new prompt", "description": "summarize PR diffs"}
+        }"#;
+        let aliases = parse_aliases(content).unwrap();
+
+        assert_eq!(aliases.get("old"), Some(&AliasEntry::new("legacy prompt")));
+        assert_eq!(
+            aliases.get("new"),
+            Some(&AliasEntry {
+                description: Some("summarize PR diffs".to_string()),
+                ..AliasEntry::new("new prompt")
+            })
+        );
+
+        // Round trip through the current format
+        let saved: HashMap<String, AliasEntry> =
+            serde_json::from_str(&serde_json::to_string(&aliases).unwrap()).unwrap();
+        assert_eq!(saved, aliases);
+    }
+
+    #[test]
+    fn test_aliases_format_versions() {
+        with_temp_config_dir(|config_dir| {
+            let path = config_dir.join("aliases.json");
+            fs::create_dir_all(config_dir).unwrap();
+
+            // A version 1 file is upgraded in memory and left alone until the next save
+            let legacy = r#"{"version": "legacy prompt", "review": {"prompt": "Review"}}"#;
+            fs::write(&path, legacy).unwrap();
+            let aliases = load_aliases().unwrap();
+            assert_eq!(aliases["version"].prompt, "legacy prompt");
+            assert_eq!(aliases["review"].prompt, "Review");
+            assert_eq!(fs::read_to_string(&path).unwrap(), legacy);
+
+            save_aliases(&aliases).unwrap();
+            let saved: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(saved["version"], ALIASES_FORMAT_VERSION);
+            assert_eq!(saved["aliases"]["version"]["prompt"], "legacy prompt");
+            let reloaded = load_aliases().unwrap();
+            assert_eq!(reloaded.len(), 2);
+            assert_eq!(reloaded["version"].prompt, "legacy prompt");
+
+            // A file from a newer qwk is refused rather than loaded without its new fields
+            let future = r#"{"version": 3, "aliases": {"review": {"prompt": "Review"}}}"#;
+            fs::write(&path, future).unwrap();
+            let err = load_aliases().unwrap_err();
+            assert!(matches!(
+                err,
+                ConfigError::UnsupportedVersion { version: 3, .. }
+            ));
+            assert!(err.to_string().contains("version 3"));
+
+            // The envelope itself still has to be well formed
+            fs::write(&path, r#"{"version": 2}"#).unwrap();
+            assert!(matches!(load_aliases(), Err(ConfigError::Parse { .. })));
+        });
+    }
+
+    #[test]
+    fn test_local_aliases() {
+        let temp = tempfile::tempdir().unwrap();
+        let project = temp.path().join("project");
+        let nested = project.join("src/bin");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_local_aliases_file(&nested), None);
+
+        let local_file = project.join(LOCAL_ALIASES_FILE);
+        let local = HashMap::from([("review".to_string(), AliasEntry::new("local review"))]);
+        save_aliases_to(&local_file, &local).unwrap();
+        assert_eq!(find_local_aliases_file(&nested), Some(local_file.clone()));
+        assert_eq!(find_local_aliases_file(&project), Some(local_file.clone()));
+        assert_eq!(find_local_aliases_file(temp.path()), None);
+
+        // The nearest file is the one that applies
+        let inner_file = nested.join(LOCAL_ALIASES_FILE);
+        fs::write(&inner_file, "{}").unwrap();
+        assert_eq!(find_local_aliases_file(&nested), Some(inner_file));
+
+        let global = HashMap::from([
+            ("review".to_string(), AliasEntry::new("global review")),
+            ("docs".to_string(), AliasEntry::new("global docs")),
+        ]);
+        let effective = merge_local_aliases(global, load_aliases_from(&local_file).unwrap());
+        assert_eq!(effective.aliases.len(), 2);
+        assert_eq!(effective.aliases["review"].prompt, "local review");
+        assert_eq!(effective.aliases["docs"].prompt, "global docs");
+        assert_eq!(effective.local, HashSet::from(["review".to_string()]));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let mut aliases = parse_aliases(r#"{"old": "legacy prompt"}"#).unwrap();
+        assert_eq!(aliases["old"].created_at, None);
+
+        let then = Utc::now() - chrono::Duration::days(1);
+        fill_missing_timestamps(&mut aliases, then);
+        assert_eq!(aliases["old"].created_at, Some(then));
+        assert_eq!(aliases["old"].updated_at, Some(then));
+
+        // Touching keeps the creation time and refreshes the modification time
+        let entry = aliases.get_mut("old").unwrap();
+        entry.touch();
+        assert_eq!(entry.created_at, Some(then));
+        assert!(entry.updated_at.unwrap() > then);
+
+        let mut entry = AliasEntry::new("new");
+        entry.touch();
+        assert_eq!(entry.created_at, entry.updated_at);
+
+        // Timestamps are stored as RFC3339 strings
+        let json = serde_json::to_value(&entry).unwrap();
+        let stored = json["created_at"].as_str().unwrap();
+        assert!(DateTime::parse_from_rfc3339(stored).is_ok());
+    }
+
+    #[test]
+    fn test_tags() {
+        let tagged = |tags: &[&str]| AliasEntry {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..AliasEntry::new("prompt")
+        };
+        let mut aliases = HashMap::new();
+        aliases.insert("a".to_string(), tagged(&["coding", "review"]));
+        aliases.insert("b".to_string(), tagged(&["review"]));
+        aliases.insert("c".to_string(), tagged(&[]));
+
+        let counts: Vec<_> = count_tags(&aliases).into_iter().collect();
+        assert_eq!(counts, vec![("coding", 1), ("review", 2)]);
+
+        let review = vec!["review".to_string()];
+        let both = vec!["review".to_string(), "coding".to_string()];
+        assert!(aliases["a"].has_tags(&both));
+        assert!(!aliases["b"].has_tags(&both));
+        assert!(aliases["b"].has_tags(&review));
+        assert!(!aliases["c"].has_tags(&review));
+        assert!(aliases["c"].has_tags(&[]));
+    }
+
+    #[test]
+    fn test_resolve_config_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let xdg = temp.path().join("xdg");
+        let home_config = temp.path().join("home/.config");
+        let legacy = home_config.join("qwk");
+        let resolve = |xdg_config_home: Option<&Path>| {
+            resolve_config_dir(
+                None,
+                xdg_config_home.map(|dir| dir.as_os_str().to_os_string()),
+                Some(home_config.clone()),
+                Some(legacy.clone()),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(resolve(None), legacy);
+        assert_eq!(resolve(Some(Path::new(""))), legacy);
+        assert_eq!(resolve(Some(&xdg)), xdg.join("qwk"));
+
+        // An existing install keeps its directory until the XDG one exists
+        fs::create_dir_all(&legacy).unwrap();
+        assert_eq!(resolve(Some(&xdg)), legacy);
+        fs::create_dir_all(xdg.join("qwk")).unwrap();
+        assert_eq!(resolve(Some(&xdg)), xdg.join("qwk"));
+
+        assert_eq!(resolve_config_dir(None, None, None, None), None);
+
+        // QWK_CONFIG_DIR is used as is, ahead of everything else
+        let explicit = temp.path().join("dotfiles/qwk-config");
+        assert_eq!(
+            resolve_config_dir(
+                Some(explicit.clone().into_os_string()),
+                Some(xdg.into_os_string()),
+                Some(home_config.clone()),
+                Some(legacy.clone()),
+            ),
+            Some(explicit)
+        );
+        assert_eq!(
+            resolve_config_dir(Some(OsString::new()), None, Some(home_config), None),
+            Some(legacy)
+        );
+    }
+
+    #[test]
+    fn test_move_alias() {
+        let mut aliases = HashMap::new();
+        aliases.insert("old".to_string(), AliasEntry::new("old prompt"));
+        aliases.insert("keep".to_string(), AliasEntry::new("kept"));
+        let mut archive = HashMap::new();
+
+        move_alias(&mut aliases, &mut archive, "old", false).unwrap();
+        assert!(!aliases.contains_key("old"));
+        assert_eq!(archive["old"].prompt, "old prompt");
+        assert_eq!(
+            move_alias(&mut aliases, &mut archive, "old", false),
+            Err(MoveAliasError::NotFound)
+        );
+
+        // Unarchiving over a newer alias of the same name needs force
+        aliases.insert("old".to_string(), AliasEntry::new("new prompt"));
+        assert_eq!(
+            move_alias(&mut archive, &mut aliases, "old", false),
+            Err(MoveAliasError::Exists)
+        );
+        assert_eq!(aliases["old"].prompt, "new prompt");
+        assert!(archive.contains_key("old"));
+
+        move_alias(&mut archive, &mut aliases, "old", true).unwrap();
+        assert_eq!(aliases["old"].prompt, "old prompt");
+        assert!(archive.is_empty());
+    }
+
+    #[test]
+    fn test_get_agent_sources() {
+        with_temp_config_dir(|config_dir| {
+            assert_eq!(
+                resolve_agent(),
+                (AgentSpec::new(DEFAULT_AGENT), AgentSource::Default)
+            );
+            fs::create_dir_all(config_dir).unwrap();
+            let settings_file = config_dir.join("config.toml");
+            let legacy_file = config_dir.join("agent");
+            assert_eq!(get_settings_file(), settings_file);
+            assert_eq!(get_agent_file(), legacy_file);
+
+            // Only the legacy file, as written by older versions
+            fs::write(&legacy_file, "codex --full-auto\n").unwrap();
+            assert_eq!(
+                resolve_agent(),
+                (AgentSpec::new("codex --full-auto"), AgentSource::LegacyFile)
+            );
+
+            // Both present: config.toml wins
+            fs::write(
+                &settings_file,
+                "max_file_bytes = 10\ndefault_agent = \"claude\"\n",
+            )
+            .unwrap();
+            assert_eq!(get_agent(), "claude");
+
+            // Setting the agent migrates it into config.toml, keeping the other settings
+            set_agent("claude --model sonnet").unwrap();
+            assert!(!legacy_file.exists());
+            assert_eq!(get_agent(), "claude --model sonnet");
+            let settings = load_settings().unwrap();
+            assert_eq!(settings.max_file_bytes, 10);
+            assert_eq!(
+                settings.default_agent.as_deref(),
+                Some("claude --model sonnet")
+            );
+
+            // Only config.toml
+            fs::write(&settings_file, "default_agent = \"aider\"").unwrap();
+            assert_eq!(
+                resolve_agent(),
+                (AgentSpec::new("aider"), AgentSource::Settings)
+            );
+
+            // A malformed config.toml is never overwritten
+            fs::write(&settings_file, "default_agent = ").unwrap();
+            assert!(set_agent("claude").is_err());
+            assert_eq!(
+                fs::read_to_string(&settings_file).unwrap(),
+                "default_agent = "
+            );
+        });
+    }
+
+    #[test]
+    fn test_named_agents() {
+        with_temp_config_dir(|config_dir| {
+            assert!(load_agents().unwrap().is_empty());
+            let agents = BTreeMap::from([
+                ("fast".to_string(), AgentSpec::new("claude --model haiku")),
+                ("local".to_string(), AgentSpec::new("ollama run llama3")),
+            ]);
+            save_agents(&agents).unwrap();
+            assert_eq!(get_agents_file(), config_dir.join("agents.json"));
+            assert_eq!(load_agents().unwrap(), agents);
+
+            set_agent("codex").unwrap();
+            assert!(matches!(
+                use_agent("slow"),
+                Err(QwkError::AgentNotFound(name)) if name == "slow"
+            ));
+            use_agent("fast").unwrap();
+            assert_eq!(
+                resolve_agent(),
+                (
+                    AgentSpec::new("claude --model haiku"),
+                    AgentSource::Named("fast".to_string())
+                )
+            );
+
+            // An agent that's gone falls back to the command
+            save_agents(&BTreeMap::new()).unwrap();
+            assert_eq!(get_agent(), "codex");
+            save_agents(&agents).unwrap();
+
+            // Setting a command deselects the named agent
+            set_agent("aider").unwrap();
+            assert_eq!(load_settings().unwrap().active_agent, None);
+            assert_eq!(get_agent(), "aider");
+
+            // A profile's own agent command wins over a named agent chosen globally
+            use_agent("local").unwrap();
+            unsafe { env::set_var(PROFILE_ENV, "work") };
+            assert_eq!(get_agent(), "ollama run llama3");
+            set_agent("codex").unwrap();
+            assert_eq!(get_agent(), "codex");
+            use_agent("fast").unwrap();
+            assert_eq!(get_agent(), "claude --model haiku");
+
+            fs::write(get_agents_file(), "{").unwrap();
+            assert!(matches!(
+                use_agent("fast"),
+                Err(QwkError::ConfigCorrupt { path, .. }) if path == get_agents_file()
+            ));
+        });
+    }
+
+    #[test]
+    fn test_unset_agent() {
+        with_temp_config_dir(|config_dir| {
+            assert!(!unset_agent().unwrap());
+
+            fs::create_dir_all(config_dir).unwrap();
+            fs::write(config_dir.join("agent"), "aider\n").unwrap();
+            fs::write(
+                config_dir.join("config.toml"),
+                "max_file_bytes = 10\ndefault_agent = \"codex\"\n",
+            )
+            .unwrap();
+            save_agents(&BTreeMap::from([(
+                "fast".to_string(),
+                AgentSpec::new("echo"),
+            )]))
+            .unwrap();
+            use_agent("fast").unwrap();
+
+            assert!(unset_agent().unwrap());
+            assert_eq!(
+                resolve_agent(),
+                (AgentSpec::new(DEFAULT_AGENT), AgentSource::Default)
+            );
+            assert_eq!(load_settings().unwrap().max_file_bytes, 10);
+            assert!(!unset_agent().unwrap());
+
+            // Unsetting a profile's agent goes back to the global one
+            set_agent("codex").unwrap();
+            unsafe { env::set_var(PROFILE_ENV, "work") };
+            set_agent("claude --model opus").unwrap();
+            assert!(unset_agent().unwrap());
+            assert_eq!(
+                resolve_agent(),
+                (AgentSpec::new("codex"), AgentSource::Settings)
+            );
+        });
+    }
+
+    #[test]
+    fn test_agent_stdin_prompt() {
+        with_temp_config_dir(|config_dir| {
+            let spec = AgentSpec {
+                command: "llm".to_string(),
+                stdin_prompt: true,
+                ..Default::default()
+            };
+            set_agent_spec(&spec).unwrap();
+            assert_eq!(resolve_agent(), (spec, AgentSource::Settings));
+
+            // Setting another agent drops the option, as does a profile's own agent
+            set_agent_spec(&AgentSpec {
+                command: "llm".to_string(),
+                stdin_prompt: true,
+                ..Default::default()
+            })
+            .unwrap();
+            unsafe { env::set_var(PROFILE_ENV, "work") };
+            set_agent("codex").unwrap();
+            assert_eq!(resolve_agent().0, AgentSpec::new("codex"));
+            unsafe { env::remove_var(PROFILE_ENV) };
+            assert!(resolve_agent().0.stdin_prompt);
+            set_agent("llm").unwrap();
+            assert!(!load_settings().unwrap().agent_stdin_prompt);
+
+            // Named agents without options stay plain strings in agents.json
+            fs::write(
+                config_dir.join("agents.json"),
+                r#"{"fast": "claude", "pipe": {"command": "llm", "stdin_prompt": true}}"#,
+            )
+            .unwrap();
+            let agents = load_agents().unwrap();
+            assert_eq!(agents["fast"], AgentSpec::new("claude"));
+            assert!(agents["pipe"].stdin_prompt);
+            save_agents(&agents).unwrap();
+            let content = fs::read_to_string(get_agents_file()).unwrap();
+            assert!(content.contains(r#""fast": "claude""#), "{}", content);
+            assert_eq!(load_agents().unwrap(), agents);
+        });
+    }
+
+    #[test]
+    fn test_agent_shell() {
+        with_temp_config_dir(|_| {
+            let spec = AgentSpec {
+                command: "llm | glow -".to_string(),
+                shell: true,
+                ..Default::default()
+            };
+            set_agent_spec(&spec).unwrap();
+            assert_eq!(resolve_agent().0, spec);
+            assert!(load_settings().unwrap().agent_shell);
+
+            // Opt-in only: another agent runs without the shell again
+            set_agent("claude").unwrap();
+            assert!(!resolve_agent().0.shell);
+            assert!(!load_settings().unwrap().agent_shell);
+        });
+    }
+
+    #[test]
+    fn test_agent_env() {
+        with_temp_config_dir(|_| {
+            let env = BTreeMap::from([("OLLAMA_HOST".to_string(), "http://gpu:11434".to_string())]);
+            set_agent_env(&env).unwrap();
+            // The default agent gets them too
+            assert_eq!(resolve_agent().0.env, env);
+
+            // Changing the command keeps them; a profile's own agent doesn't inherit them
+            set_agent("ollama run llama3").unwrap();
+            assert_eq!(resolve_agent().0.env, env);
+            unsafe { env::set_var(PROFILE_ENV, "work") };
+            set_agent("claude").unwrap();
+            assert!(resolve_agent().0.env.is_empty());
+            unsafe { env::remove_var(PROFILE_ENV) };
+
+            // --agent --unset removes them with the command
+            assert!(unset_agent().unwrap());
+            assert!(resolve_agent().0.env.is_empty());
+            set_agent_env(&env).unwrap();
+            set_agent_env(&BTreeMap::new()).unwrap();
+            assert!(!unset_agent().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_agent_fallbacks() {
+        with_temp_config_dir(|_| {
+            let fallbacks = vec!["llm".to_string(), "ollama run llama3".to_string()];
+            set_agent_fallbacks(&fallbacks).unwrap();
+            assert_eq!(load_settings().unwrap().agent_fallbacks, fallbacks);
+            set_agent("codex").unwrap();
+            assert_eq!(load_settings().unwrap().agent_fallbacks, fallbacks);
+            set_agent_fallbacks(&[]).unwrap();
+            assert!(load_settings().unwrap().agent_fallbacks.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_validate_env_name() {
+        assert!(validate_env_name("ANTHROPIC_BASE_URL").is_ok());
+        assert!(validate_env_name("").is_err());
+        assert!(validate_env_name("A=B").is_err());
+    }
+
+    #[test]
+    fn test_agent_presets() {
+        assert_eq!(
+            find_agent_preset("ollama").unwrap().command,
+            "ollama run llama3"
+        );
+        assert_eq!(find_agent_preset("claude").unwrap().command, DEFAULT_AGENT);
+        assert_eq!(find_agent_preset("gpt"), None);
+        let names: Vec<_> = AGENT_PRESETS.iter().map(|preset| preset.name).collect();
+        assert!(names.is_sorted(), "{:?}", names);
+    }
+
+    #[test]
+    fn test_validate_agent_name() {
+        assert!(validate_agent_name("fast").is_ok());
+        let error = validate_agent_name("my agent").unwrap_err();
+        assert!(error.starts_with("Invalid agent name"), "{}", error);
+    }
+
+    #[test]
+    fn test_backups_never_replace_each_other() {
+        with_temp_config_dir(|config_dir| {
+            fs::create_dir_all(config_dir).unwrap();
+            assert_eq!(
+                unused_backup_datetime(&get_backup_dir(), "20240501_100200".to_string()),
+                "20240501_100200"
+            );
+            fs::write(get_backup_file("20240501_100200"), "{}").unwrap();
+            fs::write(get_backup_file("20240501_100201"), "{}").unwrap();
+            assert_eq!(
+                unused_backup_datetime(&get_backup_dir(), "20240501_100200".to_string()),
+                "20240501_100202"
+            );
+
+            save_aliases(&HashMap::new()).unwrap();
+            let first = create_aliases_backup().unwrap().unwrap();
+            let second = create_aliases_backup().unwrap().unwrap();
+            assert_ne!(first, second);
+        });
+    }
+
+    #[test]
+    fn test_profiles() {
+        with_temp_config_dir(|config_dir| {
+            let work_dir = config_dir.join("profiles").join("work");
+            save_aliases(&HashMap::from([(
+                "g".to_string(),
+                AliasEntry::new("global"),
+            )]))
+            .unwrap();
+            fs::write(config_dir.join("config.toml"), "max_file_bytes = 10\n").unwrap();
+            assert!(profile_exists());
+            assert!(list_profiles().unwrap().is_empty());
+
+            unsafe {
+                env::set_var(PROFILE_ENV, "work");
+            }
+            assert_eq!(get_aliases_file(), work_dir.join("aliases.json"));
+            assert_eq!(get_archive_file(), work_dir.join("archive.json"));
+            assert_eq!(
+                get_backup_file("20240501_100200"),
+                work_dir.join("aliases_backup_20240501_100200.json")
+            );
+            assert!(!profile_exists());
+            assert!(load_aliases().unwrap().is_empty());
+
+            // Created by its first save, with its own aliases and agent
+            save_aliases(&HashMap::from([("w".to_string(), AliasEntry::new("work"))])).unwrap();
+            set_agent("codex").unwrap();
+            assert!(profile_exists());
+            assert_eq!(list_profiles().unwrap(), vec!["work"]);
+            assert_eq!(load_aliases().unwrap()["w"].prompt, "work");
+            assert_eq!(get_agent(), "codex");
+            // Other settings come from the global file
+            assert_eq!(load_settings().unwrap().max_file_bytes, 10);
+
+            // "default" is the unnamed profile, with the layout from before profiles
+            unsafe {
+                env::set_var(PROFILE_ENV, DEFAULT_PROFILE);
+            }
+            assert_eq!(get_aliases_file(), config_dir.join("aliases.json"));
+            assert_eq!(load_aliases().unwrap()["g"].prompt, "global");
+            assert_eq!(get_agent(), DEFAULT_AGENT);
+        });
+    }
+
+    #[test]
+    fn test_selection() {
+        with_temp_config_dir(|_| {
+            unsafe {
+                env::set_var(PROFILE_ENV, "work");
+            }
+            select_profile(DEFAULT_PROFILE);
+            disable_local_aliases();
+            assert_eq!(get_profile(), None);
+            assert!(!local_aliases_enabled());
+            assert_eq!(
+                selection_env(),
+                vec![
+                    (PROFILE_ENV, DEFAULT_PROFILE.to_string()),
+                    (NO_LOCAL_ENV, "1".to_string())
+                ]
+            );
+
+            // What was selected is forgotten, not written to the environment
+            clear_selection();
+            assert_eq!(get_profile(), Some("work".to_string()));
+            assert!(local_aliases_enabled());
+            assert!(selection_env().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_validate_profile_name() {
+        assert!(validate_profile_name("work").is_ok());
+        assert!(validate_profile_name("client-a_2.0").is_ok());
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("..").is_err());
+        assert!(validate_profile_name(".hidden").is_err());
+        assert!(validate_profile_name("a/b").is_err());
+        assert!(validate_profile_name("a\\b").is_err());
+    }
+
+    #[test]
+    fn test_backup_captures_agent() {
+        with_temp_config_dir(|config_dir| {
+            assert_eq!(create_aliases_backup().unwrap(), None);
+
+            fs::create_dir_all(config_dir).unwrap();
+            save_aliases(&HashMap::from([("a".to_string(), AliasEntry::new("one"))])).unwrap();
+            fs::write(config_dir.join("agent"), "codex\n").unwrap();
+
+            let backup = PathBuf::from(create_aliases_backup().unwrap().unwrap());
+            let datetime = backup
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .trim_start_matches("aliases_backup_")
+                .trim_end_matches(".json")
+                .to_string();
+            let settings_backup = get_settings_backup_file(&datetime);
+            let settings = parse_settings(&fs::read_to_string(settings_backup).unwrap()).unwrap();
+            assert_eq!(settings.default_agent.as_deref(), Some("codex"));
+        });
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let settings = parse_settings("").unwrap();
+        assert!(!settings.forbid_dangerous_args);
+        assert!(settings.dangerous_arg_patterns.is_none());
+        assert_eq!(settings.max_prompt_bytes, DEFAULT_MAX_PROMPT_BYTES);
+        assert_eq!(settings.max_file_bytes, DEFAULT_MAX_FILE_BYTES);
+        assert_eq!(settings.list_preview_width, DEFAULT_LIST_PREVIEW_WIDTH);
+        assert!(settings.confirm_destructive);
+        assert!(settings.default_agent.is_none());
+        assert_eq!(settings.keep_backups, DEFAULT_KEEP_BACKUPS);
+
+        let settings = parse_settings(
+            "forbid_dangerous_args = true\ndangerous_arg_patterns = [\"^--yolo$\"]\n",
+        )
+        .unwrap();
+        assert!(settings.forbid_dangerous_args);
+        assert_eq!(
+            settings.dangerous_arg_patterns,
+            Some(vec!["^--yolo$".to_string()])
+        );
+
+        assert!(parse_settings("forbid_dangerous_args = \"yes\"").is_err());
+
+        let settings = parse_settings(
+            "list_preview_width = 100\nconfirm_destructive = false\ndefault_agent = \"codex\"\n",
+        )
+        .unwrap();
+        assert_eq!(settings.list_preview_width, 100);
+        assert!(!settings.confirm_destructive);
+        assert_eq!(settings.default_agent.as_deref(), Some("codex"));
+
+        // Errors name the offending key, including misspelled ones
+        let err = parse_settings("list_preview_width = \"wide\"").unwrap_err();
+        assert!(err.contains("list_preview_width"), "{}", err);
+        let err = parse_settings("confirm_destrutcive = false").unwrap_err();
+        assert!(err.contains("confirm_destrutcive"), "{}", err);
+    }
+
+    #[test]
+    fn test_encrypted_aliases() {
+        with_temp_config_dir(|_| {
+            unsafe { env::set_var(crypto::PASSPHRASE_ENV, "hunter2") };
+            set_profile_setting("encrypt", toml::Value::Boolean(true)).unwrap();
+
+            let aliases = HashMap::from([("summ".to_string(), AliasEntry::new("Secret prompt"))]);
+            save_aliases(&aliases).unwrap();
+            let data = fs::read(get_aliases_file()).unwrap();
+            assert!(crypto::is_encrypted(&data));
+            assert!(!String::from_utf8_lossy(&data).contains("Secret prompt"));
+            assert_eq!(load_aliases().unwrap()["summ"].prompt, "Secret prompt");
+
+            // A wrong passphrase is an error, never an empty map
+            unsafe { env::set_var(crypto::PASSPHRASE_ENV, "hunter3") };
+            let err = load_aliases().unwrap_err();
+            assert!(matches!(
+                err,
+                ConfigError::Decrypt {
+                    source: CryptoError::WrongPassphrase,
+                    ..
+                }
+            ));
+
+            // Turning encryption off writes plain JSON again
+            unsafe { env::set_var(crypto::PASSPHRASE_ENV, "hunter2") };
+            let aliases = load_aliases().unwrap();
+            set_profile_setting("encrypt", toml::Value::Boolean(false)).unwrap();
+            save_aliases(&aliases).unwrap();
+            let content = fs::read_to_string(get_aliases_file()).unwrap();
+            assert!(content.contains("Secret prompt"));
+        });
+    }
+
+    #[test]
+    fn test_prompt_files() {
+        with_temp_config_dir(|dir| {
+            let mut aliases = HashMap::from([
+                ("summ".to_string(), AliasEntry::new("Summarize this")),
+                ("work/standup".to_string(), AliasEntry::new("Standup notes")),
+            ]);
+            save_aliases(&aliases).unwrap();
+            set_profile_setting("prompt_files", toml::Value::Boolean(true)).unwrap();
+            save_aliases(&aliases).unwrap();
+
+            let content = fs::read_to_string(get_aliases_file()).unwrap();
+            assert!(content.contains(r#""prompt_file": "prompts/work/standup.md""#));
+            assert!(!content.contains("Summarize this"));
+            assert_eq!(
+                fs::read_to_string(dir.join("prompts/summ.md")).unwrap(),
+                "Summarize this"
+            );
+            let loaded = load_aliases().unwrap();
+            assert_eq!(loaded["summ"].prompt, "Summarize this");
+            assert_eq!(loaded["summ"].prompt_file, None);
+
+            // Backups carry the prompts themselves
+            let backup = create_aliases_backup().unwrap().unwrap();
+            assert!(
+                fs::read_to_string(backup)
+                    .unwrap()
+                    .contains("Standup notes")
+            );
+
+            aliases.remove("work/standup");
+            save_aliases(&aliases).unwrap();
+            assert!(!dir.join("prompts/work").exists());
+
+            // A lost prompt file leaves that one prompt empty
+            fs::remove_file(dir.join("prompts/summ.md")).unwrap();
+            assert_eq!(load_aliases().unwrap()["summ"].prompt, "");
+
+            // The archive keeps its prompts inline
+            save_archive(&aliases).unwrap();
+            assert!(
+                fs::read_to_string(get_archive_file())
+                    .unwrap()
+                    .contains("Summarize this")
+            );
+        });
+    }
+
+    #[test]
+    fn test_backup_on_write() {
+        with_temp_config_dir(|_| {
+            let auto_backups = || find_backups(&get_auto_backup_dir()).unwrap();
+            let mut aliases = HashMap::from([("summ".to_string(), AliasEntry::new("Summarize"))]);
+            save_aliases(&aliases).unwrap();
+            set_profile_setting("backup_on_write", toml::Value::Boolean(true)).unwrap();
+
+            // Adding an alias, or saving without changes, loses nothing
+            aliases.insert("review".to_string(), AliasEntry::new("Review"));
+            save_aliases(&aliases).unwrap();
+            save_aliases(&aliases).unwrap();
+            assert!(auto_backups().is_empty());
+
+            aliases.insert("summ".to_string(), AliasEntry::new("Summarize briefly"));
+            save_aliases(&aliases).unwrap();
+            let backups = auto_backups();
+            assert_eq!(backups.len(), 1);
+            assert_eq!(
+                load_aliases_from(&backups[0].path).unwrap()["summ"].prompt,
+                "Summarize"
+            );
+
+            for i in 0..AUTO_BACKUPS_KEPT + 2 {
+                aliases.insert(
+                    "summ".to_string(),
+                    AliasEntry::new(format!("Version {}", i)),
+                );
+                save_aliases(&aliases).unwrap();
+            }
+            let backups = auto_backups();
+            assert_eq!(backups.len(), AUTO_BACKUPS_KEPT);
+            let newest = load_aliases_from(&backups[0].path).unwrap();
+            assert_eq!(
+                newest["summ"].prompt,
+                format!("Version {}", AUTO_BACKUPS_KEPT)
+            );
+        });
+    }
+
+    #[test]
+    fn test_daily_backups() {
+        with_temp_config_dir(|_| {
+            let day = |n| NaiveDate::from_ymd_opt(2024, 5, n).unwrap();
+            assert!(daily_backup_due(Local::now().date_naive()));
+            assert_eq!(create_daily_backup(day(1), 2).unwrap(), None);
+            assert!(!daily_backup_due(Local::now().date_naive()));
+
+            let mut aliases = HashMap::from([("summ".to_string(), AliasEntry::new("One"))]);
+            save_aliases(&aliases).unwrap();
+            let first = create_daily_backup(day(1), 2).unwrap().unwrap();
+            assert!(first.ends_with("backups/daily/aliases_20240501.json"));
+            assert!(!daily_backup_due(Local::now().date_naive()));
+
+            // Unchanged shortcuts aren't backed up again on a later day
+            assert_eq!(create_daily_backup(day(2), 2).unwrap(), None);
+
+            for (n, prompt) in [(3, "Two"), (4, "Three")] {
+                aliases.insert("summ".to_string(), AliasEntry::new(prompt));
+                save_aliases(&aliases).unwrap();
+                assert!(create_daily_backup(day(n), 2).unwrap().is_some());
+                // Only once a day
+                assert_eq!(create_daily_backup(day(n), 2).unwrap(), None);
+            }
+            let backups = find_daily_backups(&get_daily_backup_dir()).unwrap();
+            let names: Vec<_> = backups
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            assert_eq!(names, ["aliases_20240503.json", "aliases_20240504.json"]);
+
+            fs::remove_file(daily_backup_stamp()).unwrap();
+            assert_eq!(create_daily_backup(day(5), 0).unwrap(), None);
+            assert!(!daily_backup_due(Local::now().date_naive()));
+
+            // A profile that hasn't been created yet is left that way
+            select_profile("work");
+            assert_eq!(create_daily_backup(day(5), 2).unwrap(), None);
+            assert!(!profile_exists());
+            clear_selection();
+        });
+    }
+
+    #[test]
+    fn test_trash() {
+        let at = |day| Utc.with_ymd_and_hms(2024, 5, day, 0, 0, 0).unwrap();
+        let mut trash = Vec::new();
+        trash_alias(&mut trash, "summ", AliasEntry::new("Old summary"), at(1));
+        trash_alias(&mut trash, "summ", AliasEntry::new("New summary"), at(2));
+        trash_alias(&mut trash, "review", AliasEntry::new("Review"), at(3));
+
+        let mut aliases = HashMap::from([("review".to_string(), AliasEntry::new("Mine"))]);
+        assert_eq!(
+            restore_removed(&mut aliases, &mut trash, "missing", false),
+            Err(MoveAliasError::NotFound)
+        );
+        assert_eq!(
+            restore_removed(&mut aliases, &mut trash, "review", false),
+            Err(MoveAliasError::Exists)
+        );
+        assert_eq!(trash.len(), 3);
+
+        // The most recent removal comes back first
+        restore_removed(&mut aliases, &mut trash, "summ", false).unwrap();
+        assert_eq!(aliases["summ"].prompt, "New summary");
+        restore_removed(&mut aliases, &mut trash, "review", true).unwrap();
+        assert_eq!(aliases["review"].prompt, "Review");
+        let names: Vec<_> = trash.iter().map(|trashed| trashed.name.as_str()).collect();
+        assert_eq!(names, ["summ"]);
+        assert_eq!(trash[0].entry.prompt, "Old summary");
+    }
+
+    #[test]
+    fn test_trash_capacity() {
+        let mut trash = Vec::new();
+        for i in 0..TRASH_CAPACITY + 5 {
+            trash_alias(
+                &mut trash,
+                &format!("a{}", i),
+                AliasEntry::new("x"),
+                Utc::now(),
+            );
+        }
+        assert_eq!(trash.len(), TRASH_CAPACITY);
+        assert_eq!(trash[0].name, "a5");
+    }
+
+    #[test]
+    fn test_trash_storage() {
+        with_temp_config_dir(|dir| {
+            assert!(load_trash().unwrap().is_empty());
+
+            let mut trash = Vec::new();
+            trash_alias(&mut trash, "summ", AliasEntry::new("Summarize"), Utc::now());
+            save_trash(&trash).unwrap();
+            assert_eq!(get_trash_file(), dir.join("trash.json"));
+            let content = fs::read_to_string(get_trash_file()).unwrap();
+            assert!(content.contains(r#""name": "summ""#), "{}", content);
+            assert!(content.contains(r#""prompt": "Summarize""#), "{}", content);
+            assert_eq!(load_trash().unwrap(), trash);
+
+            unsafe { env::set_var(crypto::PASSPHRASE_ENV, "hunter2") };
+            set_profile_setting("encrypt", toml::Value::Boolean(true)).unwrap();
+            save_trash(&trash).unwrap();
+            assert!(crypto::is_encrypted_file(&get_trash_file()));
+            assert_eq!(load_trash().unwrap(), trash);
+
+            fs::write(get_trash_file(), "[{").unwrap();
+            assert!(matches!(load_trash(), Err(ConfigError::Parse { .. })));
+        });
+    }
+
+    #[test]
+    fn test_only_adds() {
+        let before = HashMap::from([("summ".to_string(), AliasEntry::new("Summarize"))]);
+        let mut after = before.clone();
+        assert!(only_adds(&before, &after));
+        after.insert("review".to_string(), AliasEntry::new("Review"));
+        assert!(only_adds(&before, &after));
+        after.get_mut("summ").unwrap().tags.push("text".to_string());
+        assert!(!only_adds(&before, &after));
+        assert!(!only_adds(&before, &HashMap::new()));
+    }
+
+    #[test]
+    fn test_aliases_file_override() {
+        with_temp_config_dir(|dir| {
+            let elsewhere = dir.join("elsewhere").join("mine.json");
+            unsafe { env::set_var(ALIASES_FILE_ENV, &elsewhere) };
+            assert_eq!(get_aliases_file(), elsewhere);
+            assert_eq!(get_backup_dir(), dir.join("elsewhere"));
+
+            let aliases = HashMap::from([("summ".to_string(), AliasEntry::new("Summarize"))]);
+            save_aliases(&aliases).unwrap();
+            assert!(elsewhere.exists());
+            assert!(!dir.join("aliases.json").exists());
+            assert_eq!(load_aliases().unwrap()["summ"].prompt, "Summarize");
+
+            let backup = create_aliases_backup().unwrap().unwrap();
+            assert!(Path::new(&backup).starts_with(dir.join("elsewhere")));
+
+            // Other files stay where they were
+            assert_eq!(get_archive_file(), dir.join("archive.json"));
+            set_agent("codex").unwrap();
+            assert!(dir.join("config.toml").exists());
+        });
+    }
+
+    #[test]
+    fn test_saved_aliases_are_sorted() {
+        with_temp_config_dir(|_| {
+            let time = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+            let mut entry = AliasEntry::new("Prompt");
+            entry.created_at = Some(time);
+            entry.updated_at = Some(time);
+            entry
+                .metadata
+                .insert("zeta".to_string(), serde_json::json!({"b": 1, "a": 2}));
+            entry
+                .metadata
+                .insert("aardvark".to_string(), serde_json::json!(true));
+            let names = ["mid", "zed", "alpha", "beta/x", "Upper"];
+
+            let mut contents = Vec::new();
+            for order in [names.to_vec(), names.iter().rev().copied().collect()] {
+                let mut aliases = HashMap::new();
+                for name in order {
+                    aliases.insert(name.to_string(), entry.clone());
+                }
+                save_aliases(&aliases).unwrap();
+                contents.push(fs::read(get_aliases_file()).unwrap());
+            }
+            assert_eq!(contents[0], contents[1]);
 
-    fn save_aliases_to_file(
-        file_path: &PathBuf,
-        aliases: &HashMap<String, String>,
-    ) -> io::Result<()> {
-        let content = serde_json::to_string_pretty(aliases)?;
-        fs::write(file_path, content)
+            let content = String::from_utf8(contents.remove(0)).unwrap();
+            let positions: Vec<_> = ["\"Upper\"", "\"alpha\"", "\"beta/x\"", "\"mid\"", "\"zed\""]
+                .iter()
+                .map(|name| content.find(name).unwrap())
+                .collect();
+            assert!(positions.is_sorted(), "{}", content);
+        });
     }
 }
@@ -0,0 +1,195 @@
+//! Optional encryption of the shortcuts files at rest: XChaCha20-Poly1305 with a key
+//! derived from a passphrase by Argon2id. An encrypted file is the magic bytes, the salt,
+//! the nonce and the ciphertext, in that order.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable holding the passphrase, so scripts don't get asked for it.
+pub const PASSPHRASE_ENV: &str = "QWK_PASSPHRASE";
+
+/// Start of every encrypted file; the last byte is the format version.
+const MAGIC: &[u8; 8] = b"QWKENC\x00\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// The passphrase typed in this run, so that a command loading and saving asks only once.
+/// Kept in memory rather than in `QWK_PASSPHRASE`, which the agent would inherit.
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+static PROMPT_ALLOWED: AtomicBool = AtomicBool::new(true);
+
+#[derive(Debug, PartialEq)]
+pub enum CryptoError {
+    /// No `QWK_PASSPHRASE` and no terminal to ask on, or the user gave up
+    NoPassphrase(String),
+    /// Too short to hold the header, so not something qwk wrote
+    Malformed,
+    /// The ciphertext doesn't authenticate under the derived key
+    WrongPassphrase,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::NoPassphrase(reason) => write!(
+                f,
+                "a passphrase is needed ({}); set {} to provide it without a terminal",
+                reason, PASSPHRASE_ENV
+            ),
+            CryptoError::Malformed => write!(f, "the encrypted file is truncated"),
+            CryptoError::WrongPassphrase => {
+                write!(f, "wrong passphrase, or the file has been tampered with")
+            }
+        }
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Whether the file at `path` starts like an encrypted one, reading only its header.
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| is_encrypted(&magic))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .expect("the salt and key lengths are valid for Argon2");
+    key
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).expect("the operating system's random number generator failed");
+    bytes
+}
+
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Vec<u8> {
+    let salt: [u8; SALT_LEN] = random_bytes();
+    let nonce: [u8; NONCE_LEN] = random_bytes();
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt));
+    let ciphertext = cipher
+        .encrypt(&XNonce::from(nonce), plaintext)
+        .expect("encrypting into memory doesn't fail");
+
+    let mut data = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    data
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, CryptoError> {
+    if !is_encrypted(data) || data.len() < HEADER_LEN {
+        return Err(CryptoError::Malformed);
+    }
+    let (salt, rest) = data[MAGIC.len()..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+
+    XChaCha20Poly1305::new(&derive_key(passphrase, salt))
+        .decrypt(&XNonce::from(nonce), ciphertext)
+        .map_err(|_| CryptoError::WrongPassphrase)
+}
+
+/// Stops `passphrase` from asking on the terminal, for completions that must never block.
+pub fn disable_passphrase_prompt() {
+    PROMPT_ALLOWED.store(false, Ordering::Relaxed);
+}
+
+/// Drops the remembered passphrase, e.g. after it turned out to be wrong.
+pub fn forget_passphrase() {
+    *PASSPHRASE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// The passphrase from `QWK_PASSPHRASE`, or the one asked for on the terminal, once per
+/// run. With `new` a typed passphrase is asked for twice, as it is about to be used to
+/// encrypt.
+pub fn passphrase(new: bool) -> Result<String, CryptoError> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV)
+        && !passphrase.is_empty()
+    {
+        return Ok(passphrase);
+    }
+
+    let mut remembered = PASSPHRASE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(passphrase) = remembered.as_ref() {
+        return Ok(passphrase.clone());
+    }
+    if !PROMPT_ALLOWED.load(Ordering::Relaxed) {
+        return Err(CryptoError::NoPassphrase(
+            "not asking in this context".to_string(),
+        ));
+    }
+
+    let ask = |question: &str| {
+        rpassword::prompt_password(question).map_err(|e| CryptoError::NoPassphrase(e.to_string()))
+    };
+    let passphrase = ask("Passphrase for the qwk shortcuts: ")?;
+    if passphrase.is_empty() {
+        return Err(CryptoError::NoPassphrase("it was empty".to_string()));
+    }
+    if new && ask("Repeat the passphrase: ")? != passphrase {
+        return Err(CryptoError::NoPassphrase(
+            "the passphrases didn't match".to_string(),
+        ));
+    }
+
+    *remembered = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = encrypt(br#"{"version": 2, "aliases": {}}"#, "hunter2");
+        assert!(is_encrypted(&data));
+        assert!(!data.windows(7).any(|window| window == b"aliases"));
+        assert_eq!(
+            decrypt(&data, "hunter2").unwrap(),
+            br#"{"version": 2, "aliases": {}}"#
+        );
+
+        // A fresh salt and nonce every time
+        assert_ne!(encrypt(b"same", "hunter2"), encrypt(b"same", "hunter2"));
+    }
+
+    #[test]
+    fn test_decrypt_failures() {
+        let data = encrypt(b"secret prompts", "hunter2");
+        assert_eq!(decrypt(&data, "hunter3"), Err(CryptoError::WrongPassphrase));
+
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            decrypt(&tampered, "hunter2"),
+            Err(CryptoError::WrongPassphrase)
+        );
+
+        assert_eq!(
+            decrypt(&data[..HEADER_LEN - 1], "hunter2"),
+            Err(CryptoError::Malformed)
+        );
+        assert_eq!(decrypt(b"{}", "hunter2"), Err(CryptoError::Malformed));
+    }
+}
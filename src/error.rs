@@ -0,0 +1,246 @@
+//! What stops a qwk command before it's done. Each kind of failure a script or an embedding
+//! program may want to tell apart has its own variant and exit code; the rest are messages
+//! to show as they are.
+
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use thiserror::Error;
+
+use crate::cli::as_exit_code;
+use crate::config::ConfigError;
+use crate::utils::PromptReadError;
+
+/// Exit code for a shortcut that doesn't exist, sysexits' `EX_NOINPUT`.
+pub const ALIAS_NOT_FOUND_EXIT_CODE: i32 = 66;
+/// Exit code for an agent that isn't configured, sysexits' `EX_UNAVAILABLE`.
+pub const AGENT_NOT_FOUND_EXIT_CODE: i32 = 69;
+/// Exit code for a shortcut that would be replaced without `--force`, sysexits' `EX_CANTCREAT`.
+pub const ALIAS_EXISTS_EXIT_CODE: i32 = 73;
+/// Exit code for a file that couldn't be read or written, sysexits' `EX_IOERR`.
+pub const IO_EXIT_CODE: i32 = 74;
+/// Exit code for an encrypted file that couldn't be decrypted, sysexits' `EX_NOPERM`.
+pub const CONFIG_DECRYPT_EXIT_CODE: i32 = 77;
+/// Exit code for a config file that can't be loaded, sysexits' `EX_CONFIG`.
+pub const CONFIG_CORRUPT_EXIT_CODE: i32 = 78;
+/// Exit code after Ctrl-C, the same as a shell's.
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+#[derive(Debug, Error)]
+pub enum QwkError {
+    /// There's no shortcut called `name`; `suggestions` are the names it's a likely typo of
+    #[error("Shortcut '{name}' not found{}", did_you_mean(suggestions))]
+    AliasNotFound {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// Shortcuts asked for together, as by `--chain`, that don't exist; none of them was run
+    #[error(
+        "Shortcut{} not found: {}; nothing was run",
+        if .0.len() == 1 { "" } else { "s" },
+        .0.join(", ")
+    )]
+    AliasesNotFound(Vec<String>),
+    /// A shortcut of this name is already there, and `--force` wasn't given to replace it
+    #[error("Shortcut '{0}' already exists; use --force to replace it")]
+    AliasExists(String),
+    /// A config file exists but couldn't be read or parsed. It's left as it is rather than
+    /// overwritten, for the user to fix or reset.
+    #[error(
+        "Error: {source}\nFix the file by hand, or run `qwk --reset` to back it up and start over."
+    )]
+    ConfigCorrupt {
+        path: PathBuf,
+        #[source]
+        source: ConfigError,
+    },
+    /// An encrypted config file couldn't be decrypted, e.g. with a wrong passphrase
+    #[error("Error: {0}")]
+    ConfigDecrypt(#[source] ConfigError),
+    /// There's no named agent of this name
+    #[error("Agent '{0}' not found (see qwk --agents)")]
+    AgentNotFound(String),
+    /// Reading or writing a file failed; `context` says what qwk was doing
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The user pressed Ctrl-C
+    #[error("Cancelled.")]
+    Cancelled,
+    /// An error to print on stderr as it is, usually with exit code 1
+    #[error("{message}")]
+    Failed { message: String, code: i32 },
+    /// Arguments clap couldn't parse, which it prints itself
+    #[error("{0}")]
+    Usage(clap::Error),
+}
+
+impl QwkError {
+    pub fn new(message: impl Into<String>) -> Self {
+        QwkError::with_code(1, message)
+    }
+
+    pub fn with_code(code: i32, message: impl Into<String>) -> Self {
+        QwkError::Failed {
+            message: message.into(),
+            code,
+        }
+    }
+
+    /// A shortcut `name` that doesn't exist, with no names to suggest instead.
+    pub fn alias_not_found(name: impl Into<String>) -> Self {
+        QwkError::AliasNotFound {
+            name: name.into(),
+            suggestions: vec![],
+        }
+    }
+
+    /// An I/O error while doing `context`, such as "Error saving aliases".
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        QwkError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// The code qwk exits with for this error. Scripts may tell failures apart by it, so a
+    /// variant's code doesn't change.
+    pub fn code(&self) -> i32 {
+        match self {
+            QwkError::AliasNotFound { .. } | QwkError::AliasesNotFound(_) => {
+                ALIAS_NOT_FOUND_EXIT_CODE
+            }
+            QwkError::AliasExists(_) => ALIAS_EXISTS_EXIT_CODE,
+            QwkError::ConfigCorrupt { .. } => CONFIG_CORRUPT_EXIT_CODE,
+            QwkError::ConfigDecrypt(_) => CONFIG_DECRYPT_EXIT_CODE,
+            QwkError::AgentNotFound(_) => AGENT_NOT_FOUND_EXIT_CODE,
+            QwkError::Io { .. } => IO_EXIT_CODE,
+            QwkError::Cancelled => CANCELLED_EXIT_CODE,
+            QwkError::Failed { code, .. } => *code,
+            QwkError::Usage(e) => e.exit_code(),
+        }
+    }
+
+    /// Prints the error the way qwk does before exiting, returning the code to exit with.
+    pub fn report(&self) -> ExitCode {
+        match self {
+            QwkError::Usage(e) => {
+                let _ = e.print();
+            }
+            e => eprintln!("{}", e),
+        }
+        as_exit_code(self.code())
+    }
+}
+
+impl From<ConfigError> for QwkError {
+    fn from(e: ConfigError) -> Self {
+        match e {
+            ConfigError::Decrypt { .. } => QwkError::ConfigDecrypt(e),
+            e => QwkError::ConfigCorrupt {
+                path: e.path().to_path_buf(),
+                source: e,
+            },
+        }
+    }
+}
+
+impl From<PromptReadError> for QwkError {
+    fn from(e: PromptReadError) -> Self {
+        match e {
+            PromptReadError::Cancelled => QwkError::Cancelled,
+            PromptReadError::Io(source) => QwkError::io("Error reading prompt", source),
+            e => QwkError::new(format!("Error reading prompt: {}", e)),
+        }
+    }
+}
+
+/// The end of "Shortcut 'x' not found" when `matches` are close to the name: "; did you
+/// mean 'a' or 'b'?", or nothing without any.
+pub(crate) fn did_you_mean<S: AsRef<str>>(matches: &[S]) -> String {
+    let quoted: Vec<String> = matches
+        .iter()
+        .map(|name| format!("'{}'", name.as_ref()))
+        .collect();
+    match quoted.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!("; did you mean {}?", last),
+        Some((last, rest)) => format!("; did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(did_you_mean::<&str>(&[]), "");
+        assert_eq!(did_you_mean(&["review"]), "; did you mean 'review'?");
+        assert_eq!(
+            did_you_mean(&["a", "b", "c"]),
+            "; did you mean 'a', 'b' or 'c'?"
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let error = QwkError::AliasNotFound {
+            name: "reivew".to_string(),
+            suggestions: vec!["review".to_string()],
+        };
+        assert_eq!(
+            error.to_string(),
+            "Shortcut 'reivew' not found; did you mean 'review'?"
+        );
+        assert_eq!(
+            QwkError::AliasesNotFound(vec!["a".to_string(), "b".to_string()]).to_string(),
+            "Shortcuts not found: a, b; nothing was run"
+        );
+        assert_eq!(
+            QwkError::AgentNotFound("fast".to_string()).to_string(),
+            "Agent 'fast' not found (see qwk --agents)"
+        );
+        let error = QwkError::io("Error saving aliases", io::Error::other("disk full"));
+        assert_eq!(error.to_string(), "Error saving aliases: disk full");
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let source = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let error = QwkError::from(ConfigError::Parse {
+            path: PathBuf::from("/config/aliases.json"),
+            source,
+        });
+        let QwkError::ConfigCorrupt { path, .. } = &error else {
+            panic!("{:?}", error);
+        };
+        assert_eq!(path, Path::new("/config/aliases.json"));
+        assert!(
+            error
+                .to_string()
+                .starts_with("Error: /config/aliases.json is not")
+        );
+        assert!(error.to_string().ends_with("start over."));
+        assert_eq!(error.code(), CONFIG_CORRUPT_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(QwkError::new("Error").code(), 1);
+        assert_eq!(QwkError::with_code(3, "Error").code(), 3);
+        assert_eq!(QwkError::alias_not_found("x").code(), 66);
+        assert_eq!(QwkError::AliasExists("x".to_string()).code(), 73);
+        assert_eq!(QwkError::AgentNotFound("x".to_string()).code(), 69);
+        assert_eq!(QwkError::from(PromptReadError::Cancelled).code(), 130);
+        assert_eq!(
+            QwkError::from(PromptReadError::Io(io::Error::other("broken"))).code(),
+            74
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! The portable document written by `--export` and read by `--import`: every alias and the
+//! agent, in the same versioned envelope as `aliases.json`, as JSON, YAML or TOML, or just
+//! the prompts as Markdown sections.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::config::{ALIASES_FORMAT_VERSION, AliasEntry, is_versioned, parse_aliases_value};
+use crate::markdown::{parse_markdown, render_markdown};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Json,
+    Yaml,
+    Toml,
+    Markdown,
+}
+
+impl DocumentFormat {
+    /// Guesses the format from the file extension, falling back to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => DocumentFormat::Yaml,
+            Some("toml") => DocumentFormat::Toml,
+            Some("md" | "markdown") => DocumentFormat::Markdown,
+            _ => DocumentFormat::Json,
+        }
+    }
+}
+
+/// Aliases are kept in a `BTreeMap` so that exports come out sorted and diff cleanly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportDocument {
+    pub version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    pub aliases: BTreeMap<String, AliasEntry>,
+}
+
+impl ExportDocument {
+    pub fn new(aliases: &HashMap<String, AliasEntry>, agent: Option<String>) -> Self {
+        ExportDocument {
+            version: ALIASES_FORMAT_VERSION,
+            agent,
+            aliases: aliases
+                .iter()
+                .map(|(name, entry)| (name.clone(), entry.clone()))
+                .collect(),
+        }
+    }
+}
+
+pub fn render_document(
+    document: &ExportDocument,
+    format: DocumentFormat,
+) -> Result<String, String> {
+    match format {
+        DocumentFormat::Json => serde_json::to_string_pretty(document)
+            .map(|content| content + "\n")
+            .map_err(|e| e.to_string()),
+        DocumentFormat::Yaml => serde_yaml::to_string(document).map_err(|e| e.to_string()),
+        DocumentFormat::Toml => toml::to_string(document).map_err(|e| e.to_string()),
+        DocumentFormat::Markdown => render_markdown(&document.aliases),
+    }
+}
+
+/// Parses an exported document, or a bare `aliases.json` of any supported version, which
+/// carries no agent.
+pub fn parse_document(content: &str, format: DocumentFormat) -> Result<ExportDocument, String> {
+    let value: Value = match format {
+        DocumentFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string())?,
+        DocumentFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string())?,
+        DocumentFormat::Toml => toml::from_str(content).map_err(|e| e.to_string())?,
+        // Just the prompts, without a version or agent
+        DocumentFormat::Markdown => {
+            return Ok(ExportDocument {
+                version: ALIASES_FORMAT_VERSION,
+                agent: None,
+                aliases: parse_markdown(content)?.into_iter().collect(),
+            });
+        }
+    };
+
+    let agent = if is_versioned(&value) {
+        value.get("agent").and_then(Value::as_str).map(String::from)
+    } else {
+        None
+    };
+    let aliases = parse_aliases_value(value).map_err(|e| e.to_string())?;
+
+    Ok(ExportDocument {
+        version: ALIASES_FORMAT_VERSION,
+        agent,
+        aliases: aliases.into_iter().collect(),
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+}
+
+impl ImportSummary {
+    pub fn changed(&self) -> bool {
+        self.added + self.overwritten > 0
+    }
+}
+
+/// How an imported alias compares to the existing one of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportChange {
+    Added,
+    Changed,
+    /// Same as the existing alias apart from its timestamps
+    Identical,
+}
+
+pub fn classify_imported(
+    aliases: &HashMap<String, AliasEntry>,
+    alias: &str,
+    entry: &AliasEntry,
+) -> ImportChange {
+    match aliases.get(alias) {
+        None => ImportChange::Added,
+        Some(existing) if existing.same_content(entry) => ImportChange::Identical,
+        Some(_) => ImportChange::Changed,
+    }
+}
+
+/// Merges `imported` into `aliases`. `overwrite` is asked about every alias that differs
+/// from an existing one of the same name; identical ones are skipped without asking.
+pub fn merge_imported(
+    aliases: &mut HashMap<String, AliasEntry>,
+    imported: Vec<(String, AliasEntry)>,
+    mut overwrite: impl FnMut(&str, &AliasEntry, &AliasEntry) -> bool,
+) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    for (alias, mut entry) in imported {
+        match aliases.get(&alias) {
+            None => summary.added += 1,
+            Some(existing) if existing.same_content(&entry) => {
+                summary.skipped += 1;
+                continue;
+            }
+            Some(existing) if overwrite(&alias, existing, &entry) => {
+                entry.created_at = existing.created_at;
+                summary.overwritten += 1;
+            }
+            Some(_) => {
+                summary.skipped += 1;
+                continue;
+            }
+        }
+        entry.touch();
+        aliases.insert(alias, entry);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    const FORMATS: [DocumentFormat; 3] = [
+        DocumentFormat::Json,
+        DocumentFormat::Yaml,
+        DocumentFormat::Toml,
+    ];
+
+    fn sample_aliases() -> HashMap<String, AliasEntry> {
+        let mut review = AliasEntry::new("Review $file for bugs");
+        review.description = Some("Code review".to_string());
+        review.tags = vec!["code".to_string(), "review".to_string()];
+        review.pinned = true;
+        review.created_at = Some(Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap());
+        review.updated_at = Some(Utc.with_ymd_and_hms(2025, 2, 3, 4, 5, 6).unwrap());
+        review.metadata.insert(
+            "llm".to_string(),
+            serde_json::json!({ "model": "gpt-4o-mini", "options": { "temperature": 0.2 } }),
+        );
+
+        HashMap::from([
+            ("summ".to_string(), AliasEntry::new("Summarize this")),
+            ("review".to_string(), review),
+            (
+                "git/commit".to_string(),
+                AliasEntry::new("Write a commit message"),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let document =
+            ExportDocument::new(&sample_aliases(), Some("codex --full-auto".to_string()));
+        for format in FORMATS {
+            let content = render_document(&document, format).unwrap();
+            assert_eq!(
+                parse_document(&content, format).unwrap(),
+                document,
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_is_sorted_and_deterministic() {
+        let aliases = sample_aliases();
+        let document = ExportDocument::new(&aliases, None);
+        for format in FORMATS {
+            let content = render_document(&document, format).unwrap();
+            assert_eq!(
+                render_document(&ExportDocument::new(&aliases.clone(), None), format).unwrap(),
+                content
+            );
+
+            let positions: Vec<_> = ["git/commit", "review", "summ"]
+                .iter()
+                .map(|name| content.find(name).unwrap())
+                .collect();
+            assert!(positions.is_sorted(), "{:?}: {}", format, content);
+            assert!(!content.contains("agent"));
+        }
+    }
+
+    #[test]
+    fn test_parse_aliases_file() {
+        let legacy = r#"{"summ": "Summarize this", "agent": {"prompt": "Act as an agent"}}"#;
+        let document = parse_document(legacy, DocumentFormat::Json).unwrap();
+        assert_eq!(document.agent, None);
+        assert_eq!(document.aliases["summ"].prompt, "Summarize this");
+        assert_eq!(document.aliases["agent"].prompt, "Act as an agent");
+
+        let current = r#"{"version": 2, "aliases": {"summ": {"prompt": "Summarize this"}}}"#;
+        let document = parse_document(current, DocumentFormat::Json).unwrap();
+        assert_eq!(document.agent, None);
+        assert_eq!(document.aliases.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_version() {
+        let newer = "version = 99\n\n[aliases.summ]\nprompt = \"Summarize this\"\n";
+        let error = parse_document(newer, DocumentFormat::Toml).unwrap_err();
+        assert!(error.contains("version 99"), "{}", error);
+
+        assert!(parse_document("not: [valid", DocumentFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            DocumentFormat::from_path(Path::new("a.yml")),
+            DocumentFormat::Yaml
+        );
+        assert_eq!(
+            DocumentFormat::from_path(Path::new("a.toml")),
+            DocumentFormat::Toml
+        );
+        assert_eq!(
+            DocumentFormat::from_path(Path::new("PROMPTS.md")),
+            DocumentFormat::Markdown
+        );
+        assert_eq!(
+            DocumentFormat::from_path(Path::new("aliases.json")),
+            DocumentFormat::Json
+        );
+        assert_eq!(
+            DocumentFormat::from_path(Path::new("shortcuts")),
+            DocumentFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_classify_imported() {
+        let mut existing = AliasEntry::new("Summarize this");
+        existing.created_at = Some(Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap());
+        let aliases = HashMap::from([("summ".to_string(), existing)]);
+
+        let same = AliasEntry::new("Summarize this");
+        let mut tagged = same.clone();
+        tagged.tags = vec!["text".to_string()];
+        assert_eq!(
+            classify_imported(&aliases, "summ", &same),
+            ImportChange::Identical
+        );
+        assert_eq!(
+            classify_imported(&aliases, "summ", &tagged),
+            ImportChange::Changed
+        );
+        assert_eq!(
+            classify_imported(&aliases, "other", &same),
+            ImportChange::Added
+        );
+    }
+
+    #[test]
+    fn test_merge_imported() {
+        let created = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+        let mut mine = AliasEntry::new("My review");
+        mine.created_at = Some(created);
+        let base = HashMap::from([
+            ("review".to_string(), mine),
+            ("summ".to_string(), AliasEntry::new("Summarize this")),
+        ]);
+        let imported = || {
+            vec![
+                ("review".to_string(), AliasEntry::new("Their review")),
+                ("summ".to_string(), AliasEntry::new("Summarize this")),
+                ("new".to_string(), AliasEntry::new("Brand new")),
+            ]
+        };
+
+        let mut aliases = base.clone();
+        let mut asked = Vec::new();
+        let summary = merge_imported(&mut aliases, imported(), |alias, _, _| {
+            asked.push(alias.to_string());
+            false
+        });
+        assert_eq!(asked, ["review"]);
+        assert_eq!(
+            summary,
+            ImportSummary {
+                added: 1,
+                overwritten: 0,
+                skipped: 2
+            }
+        );
+        assert_eq!(aliases["review"].prompt, "My review");
+        assert_eq!(aliases["new"].prompt, "Brand new");
+
+        let mut aliases = base.clone();
+        let summary = merge_imported(&mut aliases, imported(), |_, _, _| true);
+        assert_eq!(
+            summary,
+            ImportSummary {
+                added: 1,
+                overwritten: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(aliases["review"].prompt, "Their review");
+        assert_eq!(aliases["review"].created_at, Some(created));
+        assert!(summary.changed());
+
+        let mut aliases = base;
+        let summary = merge_imported(&mut aliases, vec![], |_, _, _| true);
+        assert!(!summary.changed());
+    }
+}
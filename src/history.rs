@@ -0,0 +1,200 @@
+//! The change journal behind `--history`: one JSON line in `history.log` per command that
+//! changed the shortcuts. Writing it is best effort and never fails the command itself.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{get_config_dir, get_profile};
+
+/// Lines `history.log` is cut down to, dropping the oldest, once it grows past them.
+pub const HISTORY_MAX_LINES: usize = 10_000;
+
+/// Characters of a new prompt kept in its history entry.
+const PROMPT_PREVIEW_LENGTH: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub at: DateTime<Utc>,
+    /// The command, e.g. "set" or "import"
+    pub operation: String,
+    /// The aliases it changed; empty when that isn't known, as for a reset
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// The named profile it changed; `None` for the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The start of the new prompt, for commands that set one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Where the shortcuts came from, e.g. the imported file or the restored backup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl HistoryEntry {
+    /// An entry for `operation` on `aliases` in the active profile, timestamped now.
+    pub fn new(operation: &str, aliases: Vec<String>) -> Self {
+        HistoryEntry {
+            at: Utc::now(),
+            operation: operation.to_string(),
+            aliases,
+            profile: get_profile(),
+            prompt: None,
+            source: None,
+        }
+    }
+
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        let cleaned = prompt.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut preview: String = cleaned.chars().take(PROMPT_PREVIEW_LENGTH).collect();
+        if preview.len() < cleaned.len() {
+            preview.push_str("...");
+        }
+        self.prompt = Some(preview);
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+/// Shared by all profiles, which is why entries name theirs.
+pub fn get_history_file() -> PathBuf {
+    get_config_dir().join("history.log")
+}
+
+fn append_entry(path: &Path, entry: &HistoryEntry, max_lines: usize) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    drop(file);
+
+    let content = fs::read_to_string(path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.len() > max_lines {
+        let mut kept = lines[lines.len() - max_lines..].join("\n");
+        kept.push('\n');
+        fs::write(path, kept)?;
+    }
+    Ok(())
+}
+
+/// Appends `entry` to the history, only warning when that fails.
+pub fn record_history(entry: HistoryEntry) {
+    let path = get_history_file();
+    if let Err(e) = append_entry(&path, &entry, HISTORY_MAX_LINES) {
+        eprintln!("Warning: could not write to {}: {}", path.display(), e);
+    }
+}
+
+/// The entries in `content`, oldest first. Lines that aren't entries, such as one cut short
+/// by a crash, are skipped.
+pub fn parse_history(content: &str) -> Vec<HistoryEntry> {
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub fn load_history() -> io::Result<Vec<HistoryEntry>> {
+    match fs::read_to_string(get_history_file()) {
+        Ok(content) => Ok(parse_history(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// The entries of `profile`, newest first, only those touching `alias` if given, and at
+/// most `limit` of them.
+pub fn select_history<'a>(
+    entries: &'a [HistoryEntry],
+    profile: Option<&str>,
+    alias: Option<&str>,
+    limit: Option<usize>,
+) -> Vec<&'a HistoryEntry> {
+    entries
+        .iter()
+        .rev()
+        .filter(|entry| entry.profile.as_deref() == profile)
+        .filter(|entry| alias.is_none_or(|alias| entry.aliases.iter().any(|name| name == alias)))
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(operation: &str, aliases: &[&str]) -> HistoryEntry {
+        HistoryEntry {
+            profile: None,
+            ..HistoryEntry::new(
+                operation,
+                aliases.iter().map(|name| name.to_string()).collect(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_append_and_rotate() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("qwk").join("history.log");
+        for i in 0..5 {
+            append_entry(&path, &entry("set", &[&format!("a{}", i)]), 3).unwrap();
+        }
+        let entries = parse_history(&fs::read_to_string(&path).unwrap());
+        let names: Vec<_> = entries
+            .iter()
+            .map(|entry| entry.aliases[0].as_str())
+            .collect();
+        assert_eq!(names, ["a2", "a3", "a4"]);
+    }
+
+    #[test]
+    fn test_parse_skips_broken_lines() {
+        let line = serde_json::to_string(&entry("remove", &["summ"]).with_prompt("x")).unwrap();
+        let content = format!("{}\nnot json\n{}\n{{\"at\": ", line, line);
+        assert_eq!(parse_history(&content).len(), 2);
+    }
+
+    #[test]
+    fn test_select_history() {
+        let mut other_profile = entry("set", &["summ"]);
+        other_profile.profile = Some("work".to_string());
+        let entries = vec![
+            entry("set", &["summ"]),
+            entry("set", &["review"]),
+            other_profile,
+            entry("remove", &["summ"]),
+        ];
+
+        let selected = select_history(&entries, None, Some("summ"), None);
+        let operations: Vec<_> = selected
+            .iter()
+            .map(|entry| entry.operation.as_str())
+            .collect();
+        assert_eq!(operations, ["remove", "set"]);
+
+        assert_eq!(select_history(&entries, None, None, Some(2)).len(), 2);
+        assert_eq!(select_history(&entries, Some("work"), None, None).len(), 1);
+    }
+
+    #[test]
+    fn test_prompt_preview() {
+        let short = entry("set", &["summ"]).with_prompt("Summarize\n\n  this");
+        assert_eq!(short.prompt.as_deref(), Some("Summarize this"));
+
+        let long = entry("set", &["summ"]).with_prompt(&"é".repeat(100));
+        let preview = long.prompt.unwrap();
+        assert_eq!(preview.chars().count(), PROMPT_PREVIEW_LENGTH + 3);
+        assert!(preview.ends_with("..."));
+    }
+}
@@ -1,14 +1,41 @@
 pub mod cli;
 pub mod completion;
 pub mod config;
+pub mod crypto;
+pub mod error;
+pub mod export;
+pub mod history;
+pub mod llm;
+pub mod maintenance;
+pub mod markdown;
+pub mod notify;
+pub mod output;
+pub mod process;
+pub mod prompt_files;
+pub mod runs;
+pub mod safety;
+pub mod sync;
+pub mod table;
+pub mod undo;
+pub mod usage;
 pub mod utils;
 
-pub use cli::{Cli, Commands, run};
+#[allow(deprecated)]
+pub use cli::run;
+pub use cli::{
+    Cli, Commands, ListSort, TransferFormat, Verbose, run_alias, run_main, run_with_args,
+};
 pub use completion::{
-    Shell, generate_completions, handle_first_run, setup_completion_for_current_shell,
+    Shell, generate_completions, handle_first_run, remove_completion, setup_completion,
+    setup_completion_for_current_shell,
 };
 pub use config::{
-    create_aliases_backup, ensure_config_dir, get_agent, get_aliases_file, get_config_dir,
-    load_aliases, save_aliases, set_agent,
+    AliasEntry, ConfigError, Settings, count_tags, create_aliases_backup, ensure_config_dir,
+    get_agent, get_aliases_file, get_config_dir, load_aliases, load_settings, save_aliases,
+    set_agent,
+};
+pub use error::QwkError;
+pub use usage::{UsageRecord, load_usage, record_usage};
+pub use utils::{
+    confirm, confirm_reset, get_current_datetime, parse_agent_command, slugify, truncate_prompt,
 };
-pub use utils::{confirm_reset, get_current_datetime, parse_agent_command, truncate_prompt};
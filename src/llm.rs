@@ -0,0 +1,267 @@
+//! Interop with the template files of Simon Willison's `llm` CLI
+//! (`<llm user dir>/templates/*.yaml` with `prompt:`/`system:` fields).
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::AliasEntry;
+use crate::utils::slugify;
+
+/// Metadata key holding the original template fields of an imported alias.
+pub const LLM_METADATA_KEY: &str = "llm";
+
+pub fn default_templates_dir() -> PathBuf {
+    let user_dir = match env::var("LLM_USER_PATH") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        // Where llm's click-based app dir lands on each platform, e.g. %APPDATA% on Windows
+        _ => dirs::config_dir()
+            .expect("Could not determine the config directory")
+            .join("io.datasette.llm"),
+    };
+    user_dir.join("templates")
+}
+
+/// Joins a template's system and user prompts into a single qwk prompt.
+fn combine_prompts(system: Option<&str>, prompt: Option<&str>) -> String {
+    match (system, prompt) {
+        (Some(system), Some(prompt)) => format!("{}\n\n{}", system.trim(), prompt.trim()),
+        (Some(text), None) | (None, Some(text)) => text.trim().to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+pub fn template_to_entry(content: &str, source: &str) -> Result<AliasEntry, String> {
+    let template: Value = serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+    let Value::Object(fields) = template else {
+        return Err("template is not a YAML mapping".to_string());
+    };
+
+    let system = fields.get("system").and_then(Value::as_str);
+    let prompt = fields.get("prompt").and_then(Value::as_str);
+    if system.is_none() && prompt.is_none() {
+        return Err("template has neither a 'prompt' nor a 'system' field".to_string());
+    }
+
+    let mut entry = AliasEntry::new(combine_prompts(system, prompt));
+    entry.source = Some(format!("llm:{}", source));
+    // Keep every original field so model options survive and exports can split the prompt again
+    entry
+        .metadata
+        .insert(LLM_METADATA_KEY.to_string(), Value::Object(fields));
+    Ok(entry)
+}
+
+pub fn entry_to_template(entry: &AliasEntry) -> Result<String, String> {
+    let mut fields = match entry.metadata.get(LLM_METADATA_KEY) {
+        Some(Value::Object(fields)) => fields.clone(),
+        _ => Map::new(),
+    };
+
+    let system = fields.get("system").and_then(Value::as_str);
+    let prompt = fields.get("prompt").and_then(Value::as_str);
+    // Only reuse the original split if the prompt hasn't been edited since the import
+    if combine_prompts(system, prompt) != entry.prompt {
+        fields.remove("system");
+        fields.insert("prompt".to_string(), Value::String(entry.prompt.clone()));
+    }
+
+    serde_yaml::to_string(&Value::Object(fields)).map_err(|e| e.to_string())
+}
+
+fn is_template_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yaml" | "yml")
+    )
+}
+
+pub struct ImportedTemplates {
+    pub entries: Vec<(String, AliasEntry)>,
+    /// Files that aren't valid templates, reported rather than aborting the import
+    pub warnings: Vec<String>,
+}
+
+/// Reads a template file, or every template in a directory, into alias entries named
+/// `<prefix><slugified file stem>`.
+pub fn import_templates(path: &Path, prefix: &str) -> io::Result<ImportedTemplates> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.is_file() && is_template_file(file))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    for file in files {
+        let stem = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = format!("{}{}", prefix, slugify(&stem));
+        let content = fs::read_to_string(&file)?;
+
+        match template_to_entry(&content, &file.display().to_string()) {
+            Ok(entry) => entries.push((name, entry)),
+            Err(e) => warnings.push(format!("Skipping {}: {}", file.display(), e)),
+        }
+    }
+
+    Ok(ImportedTemplates { entries, warnings })
+}
+
+/// Writes every alias to `<dir>/<name>.yaml`, stripping `prefix` from names that carry it.
+/// Returns the written file paths. Nothing is written when two aliases would get the same
+/// file, or one would get no name at all.
+pub fn export_templates(
+    aliases: &HashMap<String, AliasEntry>,
+    dir: &Path,
+    prefix: &str,
+) -> Result<Vec<PathBuf>, String> {
+    let mut names: Vec<_> = aliases.keys().collect();
+    names.sort();
+
+    let mut stems: HashMap<String, &str> = HashMap::new();
+    for name in &names {
+        let stem = slugify(name.strip_prefix(prefix).unwrap_or(name));
+        if stem.is_empty() {
+            return Err(format!(
+                "Can't export '{}': its name has nothing to name a file after",
+                name
+            ));
+        }
+        if let Some(other) = stems.insert(stem.clone(), name) {
+            return Err(format!(
+                "Can't export both '{}' and '{}' to {}.yaml; rename one of them",
+                other, name, stem
+            ));
+        }
+    }
+
+    fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    let mut written = Vec::new();
+    for name in names {
+        let stem = slugify(name.strip_prefix(prefix).unwrap_or(name));
+        let file = dir.join(format!("{}.yaml", stem));
+        let content = entry_to_template(&aliases[name])?;
+        fs::write(&file, content)
+            .map_err(|e| format!("Could not write {}: {}", file.display(), e))?;
+        written.push(file);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const BOTH: &str = "system: You are a terse reviewer.\nprompt: 'Review: $input'\nmodel: gpt-4o-mini\noptions:\n  temperature: 0.2\n";
+    const SYSTEM_ONLY: &str = "system: Translate everything to French.\n";
+
+    #[test]
+    fn test_template_to_entry() {
+        let entry = template_to_entry(BOTH, "review.yaml").unwrap();
+        assert_eq!(entry.prompt, "You are a terse reviewer.\n\nReview: $input");
+        assert_eq!(entry.source.as_deref(), Some("llm:review.yaml"));
+        let fields = &entry.metadata[LLM_METADATA_KEY];
+        assert_eq!(fields["model"], "gpt-4o-mini");
+        assert_eq!(fields["options"]["temperature"], 0.2);
+
+        let entry = template_to_entry(SYSTEM_ONLY, "french.yaml").unwrap();
+        assert_eq!(entry.prompt, "Translate everything to French.");
+
+        assert!(template_to_entry("model: gpt-4o\n", "x.yaml").is_err());
+        assert!(template_to_entry("- a list\n", "x.yaml").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_through_directory() {
+        let source_dir = TempDir::new().unwrap();
+        fs::write(source_dir.path().join("Code Review.yaml"), BOTH).unwrap();
+        fs::write(source_dir.path().join("french.yml"), SYSTEM_ONLY).unwrap();
+        fs::write(source_dir.path().join("broken.yaml"), "prompt: [unclosed").unwrap();
+        fs::write(source_dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let ImportedTemplates { entries, warnings } =
+            import_templates(source_dir.path(), "llm.").unwrap();
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["llm.code-review", "llm.french"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("broken.yaml"));
+
+        let aliases: HashMap<String, AliasEntry> = entries.into_iter().collect();
+        let export_dir = TempDir::new().unwrap();
+        let written = export_templates(&aliases, export_dir.path(), "llm.").unwrap();
+        assert_eq!(written.len(), 2);
+
+        let exported: Value = serde_yaml::from_str(
+            &fs::read_to_string(export_dir.path().join("code-review.yaml")).unwrap(),
+        )
+        .unwrap();
+        let original: Value = serde_yaml::from_str(BOTH).unwrap();
+        assert_eq!(exported, original);
+
+        let exported: Value = serde_yaml::from_str(
+            &fs::read_to_string(export_dir.path().join("french.yaml")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            exported,
+            serde_yaml::from_str::<Value>(SYSTEM_ONLY).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_export_refuses_clashing_names() {
+        let export_dir = TempDir::new().unwrap();
+        let export = |names: &[&str]| {
+            let aliases: HashMap<String, AliasEntry> = names
+                .iter()
+                .map(|name| (name.to_string(), AliasEntry::new(*name)))
+                .collect();
+            export_templates(&aliases, export_dir.path(), "llm.")
+        };
+
+        // With the prefix stripped, both would be review.yaml
+        let error = export(&["llm.review", "review"]).unwrap_err();
+        assert!(error.contains("'llm.review' and 'review'"), "{}", error);
+        let error = export(&["group/review", "group-review"]).unwrap_err();
+        assert!(error.contains("group-review.yaml"), "{}", error);
+        let error = export(&["llm.", "summ"]).unwrap_err();
+        assert!(error.contains("'llm.'"), "{}", error);
+        assert_eq!(fs::read_dir(export_dir.path()).unwrap().count(), 0);
+
+        // Names that don't clash round-trip
+        export(&["llm.review", "summ"]).unwrap();
+        let ImportedTemplates { entries, .. } =
+            import_templates(export_dir.path(), "llm.").unwrap();
+        let mut names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["llm.review", "llm.summ"]);
+    }
+
+    #[test]
+    fn test_edited_prompt_replaces_split_on_export() {
+        let mut entry = template_to_entry(BOTH, "review.yaml").unwrap();
+        entry.prompt = "A new prompt".to_string();
+
+        let exported: Value = serde_yaml::from_str(&entry_to_template(&entry).unwrap()).unwrap();
+        assert_eq!(exported["prompt"], "A new prompt");
+        assert!(exported.get("system").is_none());
+        assert_eq!(exported["model"], "gpt-4o-mini");
+
+        // Aliases that never came from llm export as a bare prompt
+        let exported: Value =
+            serde_yaml::from_str(&entry_to_template(&AliasEntry::new("plain")).unwrap()).unwrap();
+        assert_eq!(exported, serde_json::json!({"prompt": "plain"}));
+    }
+}
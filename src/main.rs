@@ -1,5 +1,11 @@
+use std::env;
+use std::process::ExitCode;
+
 use qwak::cli;
 
-fn main() {
-    cli::run();
+fn main() -> ExitCode {
+    match cli::run_main(env::args()) {
+        Ok(code) => code,
+        Err(e) => e.report(),
+    }
 }
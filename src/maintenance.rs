@@ -0,0 +1,416 @@
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{copy_aliases_file, parse_aliases};
+use crate::crypto;
+use crate::table::render_table;
+use crate::utils::{DATETIME_FORMAT, parse_datetime};
+
+/// A single filesystem change of a destructive maintenance command. Commands build a
+/// plan of these first so that `--dry-run` prints exactly what applying would do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannedAction {
+    /// Copy `from` to `to` before anything is removed
+    Backup { from: PathBuf, to: PathBuf },
+    /// Delete `path`; `summary` describes what is lost, e.g. "3 shortcuts"
+    Remove { path: PathBuf, summary: String },
+}
+
+pub fn plan_reset(aliases_file: &Path, backup_file: &Path) -> Vec<PlannedAction> {
+    if !aliases_file.exists() {
+        return vec![];
+    }
+
+    // Resetting is also how a corrupted file gets cleared, so it's backed up as-is
+    let count = count_aliases_in(aliases_file);
+    vec![
+        PlannedAction::Backup {
+            from: aliases_file.to_path_buf(),
+            to: backup_file.to_path_buf(),
+        },
+        PlannedAction::Remove {
+            path: aliases_file.to_path_buf(),
+            summary: match count {
+                Some(1) => "1 shortcut".to_string(),
+                Some(count) => format!("{} shortcuts", count),
+                None if crypto::is_encrypted_file(aliases_file) => {
+                    "encrypted shortcuts file".to_string()
+                }
+                None => "unreadable shortcuts file".to_string(),
+            },
+        },
+    ]
+}
+
+/// Number of aliases in an aliases file or backup, or `None` when it can't be parsed.
+pub fn count_aliases_in(path: &Path) -> Option<usize> {
+    let content = fs::read_to_string(path).ok()?;
+    parse_aliases(&content).ok().map(|aliases| aliases.len())
+}
+
+/// A backup of the aliases, as written by a reset or `create_aliases_backup`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// When the backup was taken, from its file name
+    pub taken_at: DateTime<Utc>,
+}
+
+impl Backup {
+    /// The `config_backup_YYYYMMDD_HHMMSS.toml` written along with this backup.
+    pub fn settings_backup_path(&self) -> PathBuf {
+        self.path.with_file_name(format!(
+            "config_backup_{}.toml",
+            self.taken_at.format(DATETIME_FORMAT)
+        ))
+    }
+}
+
+/// The `YYYYMMDD_HHMMSS` timestamp in a file name matching `aliases_backup_YYYYMMDD_HHMMSS.json`
+/// exactly.
+fn backup_timestamp(file_name: &str) -> Option<DateTime<Utc>> {
+    let timestamp = file_name
+        .strip_prefix("aliases_backup_")?
+        .strip_suffix(".json")?;
+    let (date, time) = timestamp.split_once('_')?;
+    let is_digits = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    if !(is_digits(date, 8) && is_digits(time, 6)) {
+        return None;
+    }
+    parse_datetime(timestamp)
+}
+
+/// The backups in `dir`, newest first. Only files named exactly
+/// `aliases_backup_YYYYMMDD_HHMMSS.json` count.
+pub fn find_backups(dir: &Path) -> io::Result<Vec<Backup>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str()
+            && let Some(taken_at) = backup_timestamp(name)
+        {
+            backups.push(Backup {
+                path: entry.path(),
+                taken_at,
+            });
+        }
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken_at));
+    Ok(backups)
+}
+
+/// Picks a backup from `backups` (newest first, as listed by `--backups`) by its 1-based
+/// position or its file name, with or without `.json`. Without a selector it's the newest.
+pub fn select_backup<'a>(
+    backups: &'a [Backup],
+    selector: Option<&str>,
+) -> Result<&'a Backup, String> {
+    let Some(selector) = selector else {
+        return backups
+            .first()
+            .ok_or_else(|| "There are no backups".to_string());
+    };
+    if let Ok(index) = selector.parse::<usize>() {
+        return index
+            .checked_sub(1)
+            .and_then(|i| backups.get(i))
+            .ok_or_else(|| {
+                format!(
+                    "There is no backup #{}; there are {} (see qwk --backups)",
+                    index,
+                    backups.len()
+                )
+            });
+    }
+    backups
+        .iter()
+        .find(|backup| {
+            backup
+                .path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name == selector || name.strip_suffix(".json") == Some(selector)
+                })
+        })
+        .ok_or_else(|| {
+            format!(
+                "There is no backup named '{}' (see qwk --backups)",
+                selector
+            )
+        })
+}
+
+/// Removes all but the `keep` most recent backups in `dir`, along with the settings backed
+/// up with each of them. Nothing else in `dir` is ever touched.
+pub fn plan_prune_backups(dir: &Path, keep: usize) -> io::Result<Vec<PlannedAction>> {
+    let mut plan = Vec::new();
+    for backup in find_backups(dir)?.into_iter().skip(keep) {
+        let taken = backup.taken_at.format("%Y-%m-%d %H:%M:%S");
+        let settings_backup = backup.settings_backup_path();
+        plan.push(PlannedAction::Remove {
+            path: backup.path,
+            summary: format!("backup from {}", taken),
+        });
+        if settings_backup.is_file() {
+            plan.push(PlannedAction::Remove {
+                path: settings_backup,
+                summary: format!("settings backup from {}", taken),
+            });
+        }
+    }
+    Ok(plan)
+}
+
+pub fn apply_plan(plan: &[PlannedAction]) -> io::Result<()> {
+    for action in plan {
+        match action {
+            PlannedAction::Backup { from, to } => {
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                copy_aliases_file(from, to)?;
+            }
+            PlannedAction::Remove { path, .. } => fs::remove_file(path)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn describe_plan(plan: &[PlannedAction]) -> Vec<String> {
+    if plan.is_empty() {
+        return vec!["Nothing to do.".to_string()];
+    }
+
+    let rows: Vec<Vec<String>> = plan
+        .iter()
+        .map(|action| match action {
+            PlannedAction::Backup { from, to } => vec![
+                "backup".to_string(),
+                from.display().to_string(),
+                format!("to {}", to.display()),
+            ],
+            PlannedAction::Remove { path, summary } => vec![
+                "remove".to_string(),
+                path.display().to_string(),
+                summary.clone(),
+            ],
+        })
+        .collect();
+    render_table(&["ACTION", "PATH", "DETAILS"], &rows)
+}
+
+pub fn print_dry_run(plan: &[PlannedAction]) {
+    println!("Dry run, nothing will be changed:");
+    for line in describe_plan(plan) {
+        println!("  {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reset_plan_matches_apply() {
+        let temp_dir = TempDir::new().unwrap();
+        let aliases_file = temp_dir.path().join("aliases.json");
+        let backup_file = temp_dir.path().join("aliases_backup_20240501_100200.json");
+        let content = r#"{"a": "one", "b": {"prompt": "two"}}"#;
+        fs::write(&aliases_file, content).unwrap();
+
+        let plan = plan_reset(&aliases_file, &backup_file);
+        assert_eq!(
+            plan,
+            vec![
+                PlannedAction::Backup {
+                    from: aliases_file.clone(),
+                    to: backup_file.clone(),
+                },
+                PlannedAction::Remove {
+                    path: aliases_file.clone(),
+                    summary: "2 shortcuts".to_string(),
+                },
+            ]
+        );
+
+        // Planning and describing have no side effects
+        describe_plan(&plan);
+        assert!(aliases_file.exists());
+        assert!(!backup_file.exists());
+
+        apply_plan(&plan).unwrap();
+        assert!(!aliases_file.exists());
+        assert_eq!(fs::read_to_string(&backup_file).unwrap(), content);
+    }
+
+    #[test]
+    fn test_reset_plan_without_aliases_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let plan = plan_reset(
+            &temp_dir.path().join("aliases.json"),
+            &temp_dir.path().join("backup.json"),
+        );
+        assert!(plan.is_empty());
+        assert_eq!(describe_plan(&plan), vec!["Nothing to do."]);
+        apply_plan(&plan).unwrap();
+    }
+
+    #[test]
+    fn test_backup_timestamp() {
+        let aliases = |name| backup_timestamp(name).map(|time| time.to_rfc3339());
+        assert_eq!(
+            aliases("aliases_backup_20240501_100200.json").as_deref(),
+            Some("2024-05-01T10:02:00+00:00")
+        );
+        assert_eq!(aliases("aliases_backup_20241301_100200.json"), None);
+        assert_eq!(aliases("aliases.json"), None);
+        assert_eq!(aliases("aliases_backup_20240501_100200.json.bak"), None);
+        assert_eq!(aliases("aliases_backup_20240501_1002.json"), None);
+        assert_eq!(aliases("aliases_backup_2024050a_100200.json"), None);
+        assert_eq!(aliases("aliases_backup_20240501-100200.json"), None);
+        assert_eq!(aliases("my_aliases_backup_20240501_100200.json"), None);
+    }
+
+    #[test]
+    fn test_prune_backups_plan() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        for name in [
+            "aliases.json",
+            "archive.json",
+            "aliases_backup_20240101_000000.json",
+            "config_backup_20240101_000000.toml",
+            "aliases_backup_20240301_000000.json",
+            "aliases_backup_20240201_000000.json",
+            "aliases_backup_20240401_000000.json",
+            "aliases_backup_notes.json",
+            "aliases_backup_20230101_000000.json.orig",
+        ] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+        // A directory with a backup's name is left alone too
+        fs::create_dir(dir.join("aliases_backup_20200101_000000.json")).unwrap();
+
+        let plan = plan_prune_backups(dir, 2).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                PlannedAction::Remove {
+                    path: dir.join("aliases_backup_20240201_000000.json"),
+                    summary: "backup from 2024-02-01 00:00:00".to_string(),
+                },
+                PlannedAction::Remove {
+                    path: dir.join("aliases_backup_20240101_000000.json"),
+                    summary: "backup from 2024-01-01 00:00:00".to_string(),
+                },
+                PlannedAction::Remove {
+                    path: dir.join("config_backup_20240101_000000.toml"),
+                    summary: "settings backup from 2024-01-01 00:00:00".to_string(),
+                },
+            ]
+        );
+
+        apply_plan(&plan).unwrap();
+        let mut left: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "aliases.json",
+                "aliases_backup_20200101_000000.json",
+                "aliases_backup_20230101_000000.json.orig",
+                "aliases_backup_20240301_000000.json",
+                "aliases_backup_20240401_000000.json",
+                "aliases_backup_notes.json",
+                "archive.json",
+            ]
+        );
+
+        assert!(plan_prune_backups(dir, 10).unwrap().is_empty());
+        assert!(
+            plan_prune_backups(&dir.join("missing"), 0)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_find_backups() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("aliases_backup_20240101_000000.json"), "{}").unwrap();
+        fs::write(
+            dir.join("aliases_backup_20240301_000000.json"),
+            r#"{"a": "one", "b": "two"}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("aliases_backup_20240201_000000.json"), "{\"a\"").unwrap();
+        fs::write(dir.join("aliases.json"), "{}").unwrap();
+
+        let backups = find_backups(dir).unwrap();
+        let names: Vec<_> = backups
+            .iter()
+            .map(|backup| backup.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "aliases_backup_20240301_000000.json",
+                "aliases_backup_20240201_000000.json",
+                "aliases_backup_20240101_000000.json",
+            ]
+        );
+        assert_eq!(
+            backups[0].settings_backup_path(),
+            dir.join("config_backup_20240301_000000.toml")
+        );
+
+        // A corrupted backup is still listed, just without a count
+        let counts: Vec<_> = backups
+            .iter()
+            .map(|backup| count_aliases_in(&backup.path))
+            .collect();
+        assert_eq!(counts, vec![Some(2), None, Some(0)]);
+    }
+
+    #[test]
+    fn test_select_backup() {
+        let backup = |name: &str| Backup {
+            path: PathBuf::from(format!("/qwk/aliases_backup_{}.json", name)),
+            taken_at: parse_datetime(name).unwrap(),
+        };
+        let backups = vec![backup("20240301_000000"), backup("20240201_000000")];
+
+        assert_eq!(select_backup(&backups, None), Ok(&backups[0]));
+        assert_eq!(select_backup(&backups, Some("1")), Ok(&backups[0]));
+        assert_eq!(select_backup(&backups, Some("2")), Ok(&backups[1]));
+        assert_eq!(
+            select_backup(&backups, Some("aliases_backup_20240201_000000.json")),
+            Ok(&backups[1])
+        );
+        assert_eq!(
+            select_backup(&backups, Some("aliases_backup_20240201_000000")),
+            Ok(&backups[1])
+        );
+
+        assert!(select_backup(&backups, Some("0")).is_err());
+        assert!(select_backup(&backups, Some("3")).is_err());
+        assert!(select_backup(&backups, Some("aliases.json")).is_err());
+        assert!(select_backup(&[], None).is_err());
+    }
+}
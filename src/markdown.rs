@@ -0,0 +1,242 @@
+//! Prompts kept in a Markdown file such as a team's `PROMPTS.md`: each `## heading` names
+//! an alias and the section under it is the prompt. Code fences are taken literally, so a
+//! `##` line inside one belongs to the prompt.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::AliasEntry;
+
+/// What a line of the file means to the parser.
+#[derive(Debug, PartialEq)]
+enum Line<'a> {
+    /// A `# heading`, which ends the current section without starting a new one
+    Title,
+    /// A `## heading`, with its text
+    Section(&'a str),
+    Text,
+}
+
+/// Follows the code fences of a Markdown document line by line.
+#[derive(Default)]
+struct Scanner {
+    /// The fence character and length of the open code block, if in one
+    fence: Option<(char, usize)>,
+}
+
+impl Scanner {
+    fn classify<'a>(&mut self, line: &'a str) -> Line<'a> {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let rest = &line[indent..];
+        if indent > 3 {
+            return Line::Text;
+        }
+
+        let marker = rest.chars().next().filter(|c| matches!(c, '`' | '~'));
+        if let Some(c) = marker {
+            let length = rest.chars().take_while(|&next| next == c).count();
+            if length >= 3 {
+                match self.fence {
+                    None => self.fence = Some((c, length)),
+                    Some((open, open_length))
+                        if open == c
+                            && length >= open_length
+                            && rest[length..].trim().is_empty() =>
+                    {
+                        self.fence = None
+                    }
+                    Some(_) => {}
+                }
+                return Line::Text;
+            }
+        }
+        if self.fence.is_some() {
+            return Line::Text;
+        }
+
+        let level = rest.chars().take_while(|&c| c == '#').count();
+        let text = &rest[level..];
+        if !(level == 1 || level == 2) || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+            return Line::Text;
+        }
+        if level == 1 {
+            return Line::Title;
+        }
+        // A closing run of #s is not part of the heading
+        let text = text.trim();
+        let text = match text.trim_end_matches('#') {
+            stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim(),
+            _ => text,
+        };
+        Line::Section(text)
+    }
+}
+
+/// The alias a heading names: lowercased, with runs of whitespace turned into dashes.
+pub fn heading_to_alias(heading: &str) -> String {
+    heading
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// Drops the blank lines around a section body, keeping the indentation of its first line.
+fn trim_blank_lines(lines: &[&str]) -> String {
+    let start = lines.iter().position(|line| !line.trim().is_empty());
+    let end = lines.iter().rposition(|line| !line.trim().is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => lines[start..=end].join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// The prompts of a Markdown document by alias name. Text before the first `## heading` is
+/// ignored, as is a section without a prompt under it.
+pub fn parse_markdown(content: &str) -> Result<HashMap<String, AliasEntry>, String> {
+    let mut sections: Vec<(String, &str, Vec<&str>)> = Vec::new();
+    let mut in_section = false;
+    let mut scanner = Scanner::default();
+    for line in content.lines() {
+        match scanner.classify(line) {
+            Line::Title => in_section = false,
+            Line::Section(heading) => {
+                let alias = heading_to_alias(heading);
+                if alias.is_empty() {
+                    return Err(format!("a '{}' heading has no name", line.trim()));
+                }
+                if let Some((_, first, _)) = sections.iter().find(|(name, _, _)| *name == alias) {
+                    return Err(format!(
+                        "the headings '{}' and '{}' both name the alias '{}'",
+                        first, heading, alias
+                    ));
+                }
+                sections.push((alias, heading, Vec::new()));
+                in_section = true;
+            }
+            Line::Text => {
+                if in_section && let Some((_, _, body)) = sections.last_mut() {
+                    body.push(line);
+                }
+            }
+        }
+    }
+
+    Ok(sections
+        .into_iter()
+        .map(|(alias, _, body)| (alias, trim_blank_lines(&body)))
+        .filter(|(_, prompt)| !prompt.is_empty())
+        .map(|(alias, prompt)| (alias, AliasEntry::new(prompt)))
+        .collect())
+}
+
+/// Whether `prompt` reads back unchanged as the body of a section: it mustn't start a
+/// heading of its own or leave a code fence open.
+fn fits_in_section(prompt: &str) -> bool {
+    let mut scanner = Scanner::default();
+    let fits = prompt
+        .lines()
+        .all(|line| scanner.classify(line) == Line::Text);
+    fits && scanner.fence.is_none()
+}
+
+/// A Markdown document with a `## alias` section per prompt, sorted by name. Only the
+/// prompts are written; the agent, descriptions and tags have nowhere to go.
+pub fn render_markdown(aliases: &BTreeMap<String, AliasEntry>) -> Result<String, String> {
+    let mut sections = Vec::new();
+    for (alias, entry) in aliases {
+        if !fits_in_section(&entry.prompt) {
+            return Err(format!(
+                "the prompt of '{}' has a heading or an unclosed code fence of its own, which would split it when read back",
+                alias
+            ));
+        }
+        sections.push(format!("## {}\n\n{}\n", alias, entry.prompt.trim_end()));
+    }
+    Ok(sections.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPTS_MD: &str = "\
+# Team prompts
+
+Shared by everyone; keep it sorted.
+
+## Code Review
+
+Review the diff for bugs.
+
+```markdown
+## Not a heading
+```
+
+## Summarize   ##
+
+  Summarize this.
+
+
+# Archive
+
+Old stuff nobody uses.
+
+## Empty
+";
+
+    #[test]
+    fn test_parse_markdown() {
+        let aliases = parse_markdown(PROMPTS_MD).unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(
+            aliases["code-review"].prompt,
+            "Review the diff for bugs.\n\n```markdown\n## Not a heading\n```"
+        );
+        assert_eq!(aliases["summarize"].prompt, "  Summarize this.");
+    }
+
+    #[test]
+    fn test_parse_markdown_errors() {
+        let error = parse_markdown("## Review\n\nA\n\n## review\n\nB\n").unwrap_err();
+        assert!(error.contains("'review'"), "{}", error);
+        assert!(parse_markdown("##\n\nA\n").is_err());
+        assert!(parse_markdown("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fences() {
+        // A longer fence isn't closed by a shorter one, nor by one of the other kind
+        let content = "## a\n\n````\n```\n~~~\n## b\n````\n\n## c\n\nC\n";
+        let aliases = parse_markdown(content).unwrap();
+        assert_eq!(aliases["a"].prompt, "````\n```\n~~~\n## b\n````");
+        assert_eq!(aliases["c"].prompt, "C");
+
+        // Indented by four spaces, it's code rather than a heading
+        let aliases = parse_markdown("## a\n\n    ## b\n").unwrap();
+        assert_eq!(aliases["a"].prompt, "    ## b");
+        // ### headings stay inside the prompt
+        let aliases = parse_markdown("## a\n\n### Steps\n").unwrap();
+        assert_eq!(aliases["a"].prompt, "### Steps");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let aliases: BTreeMap<_, _> = parse_markdown(PROMPTS_MD).unwrap().into_iter().collect();
+        let content = render_markdown(&aliases).unwrap();
+        assert!(
+            content.starts_with("## code-review\n\nReview the diff"),
+            "{}",
+            content
+        );
+        let parsed: BTreeMap<_, _> = parse_markdown(&content).unwrap().into_iter().collect();
+        assert_eq!(parsed, aliases);
+    }
+
+    #[test]
+    fn test_render_rejects_headings() {
+        for prompt in ["Intro\n\n## Part two", "# Title", "```\nunclosed"] {
+            let aliases = BTreeMap::from([("a".to_string(), AliasEntry::new(prompt))]);
+            assert!(render_markdown(&aliases).is_err(), "{}", prompt);
+        }
+    }
+}
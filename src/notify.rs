@@ -0,0 +1,91 @@
+//! Desktop notifications for `--notify`, sent through the desktop's own tool: `notify-send`
+//! on Linux and the BSDs, and `osascript` on macOS. Not reaching it only gets a warning.
+
+use std::io;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::utils::format_duration_ms;
+
+/// The title and body of the notification for a run of `alias`.
+pub fn notification_text(alias: &str, exit_code: i32, duration: Duration) -> (String, String) {
+    let outcome = match exit_code {
+        0 => "finished".to_string(),
+        code => format!("failed with exit code {}", code),
+    };
+    (
+        format!("qwk: {} {}", alias, outcome),
+        format!(
+            "'{}' ran for {}",
+            alias,
+            format_duration_ms(duration.as_millis() as u64)
+        ),
+    )
+}
+
+/// `text` as an AppleScript string literal.
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The command that shows a notification with `title` and `body` on this platform.
+fn notification_command(title: &str, body: &str) -> io::Result<Command> {
+    if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(body),
+            applescript_string(title)
+        );
+        let mut cmd = Command::new("osascript");
+        cmd.args(["-e", &script]);
+        Ok(cmd)
+    } else if cfg!(unix) {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--app-name=qwk", "--", title, body]);
+        Ok(cmd)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "notifications aren't supported on this platform",
+        ))
+    }
+}
+
+/// Shows a desktop notification, waiting for the tool that sends it.
+pub fn send_notification(title: &str, body: &str) -> io::Result<()> {
+    let status = notification_command(title, body)?
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "the notification tool exited with {}",
+            status
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_text() {
+        let (title, body) = notification_text("summ", 0, Duration::from_secs(125));
+        assert_eq!(title, "qwk: summ finished");
+        assert_eq!(body, "'summ' ran for 2m 05s");
+
+        let (title, _) = notification_text("summ", 130, Duration::from_secs(1));
+        assert_eq!(title, "qwk: summ failed with exit code 130");
+    }
+
+    #[test]
+    fn test_applescript_string() {
+        assert_eq!(
+            applescript_string(r#"say "hi" \o/"#),
+            r#""say \"hi\" \\o/""#
+        );
+    }
+}
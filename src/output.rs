@@ -0,0 +1,58 @@
+//! Which stream a message goes to. What a command was asked for, such as a list, a prompt
+//! or an agent's output, goes to stdout; status lines about what it did, such as "Alias
+//! 'x' set successfully", go to stderr so that pipes only see the data. `-q/--quiet` drops
+//! the status lines, while errors and warnings are always printed.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Drops status lines for `-q/--quiet`, until `clear_selection` at the end of the run.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Writes the status line `message` to `out` unless `quiet`.
+pub fn write_status(out: &mut impl Write, quiet: bool, message: fmt::Arguments) -> io::Result<()> {
+    if quiet {
+        return Ok(());
+    }
+    writeln!(out, "{}", message)
+}
+
+/// Prints the status line `message` on stderr, unless `--quiet` was given.
+pub fn print_status(message: fmt::Arguments) {
+    // Like eprintln!, except that a closed stderr isn't worth a panic
+    let _ = write_status(&mut io::stderr(), is_quiet(), message);
+}
+
+/// `println!` for status lines: they go to stderr and `--quiet` drops them.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::output::print_status(format_args!($($arg)*))
+    };
+}
+
+pub(crate) use status;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_status() {
+        let mut out = Vec::new();
+        write_status(&mut out, false, format_args!("Alias '{}' set", "summ")).unwrap();
+        assert_eq!(out, b"Alias 'summ' set\n");
+
+        let mut out = Vec::new();
+        write_status(&mut out, true, format_args!("Alias '{}' set", "summ")).unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+//! Running the agent: writing the prompt to its stdin, saving its output, stopping it once
+//! it runs past a timeout, and passing on how it ended.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code of a run stopped by its timeout, the same as GNU `timeout`'s.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// How long an agent asked to terminate gets before it's killed.
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Where `run_agent` sends the agent's stdout.
+#[derive(Debug, Default)]
+pub enum AgentStdout {
+    /// Wherever qwk's own goes, so an interactive agent keeps the terminal
+    #[default]
+    Inherit,
+    /// Only to the file
+    File(File),
+    /// To the file as well as qwk's own stdout, copied as it comes
+    Tee(File),
+}
+
+/// Copies everything `from` sends to both `file` and stdout, flushing as it goes so that a
+/// streaming agent's output shows as it's written.
+fn tee(mut from: impl Read, mut file: File) -> io::Result<()> {
+    let mut buffer = [0; 8192];
+    let mut stdout = io::stdout();
+    loop {
+        let read = match from.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        file.write_all(&buffer[..read])?;
+        stdout.write_all(&buffer[..read])?;
+        stdout.flush()?;
+    }
+}
+
+/// Runs `cmd`, writing `input` to its stdin and then closing it so the program sees the
+/// end of it, and sending its stdout where `stdout` says. With a `timeout`, a program still
+/// running when it expires is asked to terminate and then killed if it hasn't after a grace
+/// period; that returns `None`.
+pub fn run_agent(
+    cmd: &mut Command,
+    input: Option<&str>,
+    timeout: Option<Duration>,
+    stdout: &AgentStdout,
+) -> io::Result<Option<ExitStatus>> {
+    if input.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    match stdout {
+        AgentStdout::Inherit => {}
+        AgentStdout::File(file) => {
+            cmd.stdout(file.try_clone()?);
+        }
+        AgentStdout::Tee(_) => {
+            cmd.stdout(Stdio::piped());
+        }
+    }
+    let mut child = cmd.spawn()?;
+    let _interrupts = ignore_interrupts();
+    let copier = match (stdout, child.stdout.take()) {
+        (AgentStdout::Tee(file), Some(output)) => {
+            let file = file.try_clone()?;
+            Some(thread::spawn(move || tee(output, file)))
+        }
+        _ => None,
+    };
+    // Written from a thread, so that an agent that never reads it can still time out
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            let input = input.to_string();
+            Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+
+    let status = match timeout {
+        Some(timeout) => wait_with_timeout(&mut child, timeout)?,
+        None => Some(child.wait()?),
+    };
+    if status.is_some()
+        && let Some(writer) = writer
+        && let Ok(Err(e)) = writer.join()
+        // An agent that exits without reading all of it reports that itself
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        return Err(e);
+    }
+    // A stopped agent may have left a child of its own holding the pipe, so only wait for
+    // the copy of an agent that exited
+    if status.is_some()
+        && let Some(copier) = copier
+        && let Ok(Err(e)) = copier.join()
+    {
+        return Err(e);
+    }
+    Ok(status)
+}
+
+/// The exit code to pass on for an agent that ended with `status`: its own, or for one
+/// ended by a signal, 128 plus the signal's number as shells report it, so 130 for Ctrl-C.
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// How SIGINT was handled before `set_interrupt_handler`, put back when this is dropped so
+/// that a program embedding qwk gets its own Ctrl-C handling back.
+#[must_use]
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: Option<libc::sighandler_t>,
+}
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            // SAFETY: `previous` came from `signal`, which takes it back as it was
+            unsafe {
+                libc::signal(libc::SIGINT, previous);
+            }
+        }
+    }
+}
+
+/// Runs `handler` on SIGINT instead of what ran before, until the guard is dropped. The
+/// handler may only do what's safe in a signal handler, such as storing to an atomic.
+#[cfg(unix)]
+pub fn set_interrupt_handler(handler: extern "C" fn(libc::c_int)) -> InterruptGuard {
+    // SAFETY: `handler` is a plain function, and callers keep it signal-safe
+    let previous = unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+    InterruptGuard {
+        previous: (previous != libc::SIG_ERR).then_some(previous),
+    }
+}
+
+/// Ctrl-C interrupts the whole foreground process group, qwk as well as the agent. While
+/// the agent is running, qwk leaves it to the agent, which may only cancel what it's doing,
+/// and then exits with its status instead of dying first. A handler that does nothing is
+/// used rather than ignoring the signal, since programs started later would inherit that.
+#[cfg(unix)]
+fn ignore_interrupts() -> InterruptGuard {
+    extern "C" fn on_interrupt(_: libc::c_int) {}
+    set_interrupt_handler(on_interrupt)
+}
+
+#[cfg(not(unix))]
+fn ignore_interrupts() -> InterruptGuard {
+    InterruptGuard {}
+}
+
+/// Replaces qwk with `cmd`, for `exec_agent`: the agent then has qwk's process, terminal
+/// and signals to itself. Only returns, with the reason, if it couldn't be started.
+#[cfg(unix)]
+pub fn exec_agent(cmd: &mut Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    // Nothing written so far may be lost with the process
+    let _ = io::stdout().flush();
+    cmd.exec()
+}
+
+/// There's no exec outside Unix, so the agent always runs as a child there.
+#[cfg(not(unix))]
+pub fn exec_agent(_cmd: &mut Command) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "exec is only available on Unix")
+}
+
+/// Waits for `child` until `deadline`, returning `None` if it's still running then.
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    if let Some(status) = wait_until(child, Instant::now() + timeout)? {
+        return Ok(Some(status));
+    }
+    terminate(child);
+    if wait_until(child, Instant::now() + TERMINATE_GRACE)?.is_none() {
+        // Fails only if it exited just now, which is what's wanted anyway
+        let _ = child.kill();
+        child.wait()?;
+    }
+    Ok(None)
+}
+
+/// Asks `child` to stop, giving it the chance to clean up.
+#[cfg(unix)]
+fn terminate(child: &mut Child) {
+    // SAFETY: kill only sends a signal. The child hasn't been waited for, so its pid
+    // can't have been reused yet.
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+/// Windows has no polite way to ask, so this is the kill right away.
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let status = run_agent(&mut sh("exit 3"), None, None, &AgentStdout::Inherit)
+            .unwrap()
+            .unwrap();
+        assert_eq!(exit_code(&status), 3);
+        let status = run_agent(&mut sh("kill -TERM $$"), None, None, &AgentStdout::Inherit)
+            .unwrap()
+            .unwrap();
+        assert_eq!(exit_code(&status), 128 + libc::SIGTERM);
+        let status = run_agent(&mut sh("kill -INT $$"), None, None, &AgentStdout::Inherit)
+            .unwrap()
+            .unwrap();
+        assert_eq!(exit_code(&status), 130);
+    }
+
+    #[test]
+    fn test_run_agent() {
+        let status = run_agent(&mut sh("exit 3"), None, None, &AgentStdout::Inherit)
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let mut reads_input = sh(r#"read line; test "$line" = hello"#);
+        let status = run_agent(
+            &mut reads_input,
+            Some("hello\n"),
+            None,
+            &AgentStdout::Inherit,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(status.success());
+
+        // Exits before reading its input, which isn't an error of its own
+        let status = run_agent(
+            &mut sh("exit 0"),
+            Some(&"x".repeat(1 << 20)),
+            None,
+            &AgentStdout::Inherit,
+        )
+        .unwrap();
+        assert!(status.unwrap().success());
+    }
+
+    #[test]
+    fn test_output_to_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("answer.md");
+        for stdout in [
+            AgentStdout::File(File::create(&path).unwrap()),
+            AgentStdout::Tee(File::create(&path).unwrap()),
+        ] {
+            let status = run_agent(&mut sh("echo answer; echo note >&2"), None, None, &stdout);
+            assert!(status.unwrap().unwrap().success());
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "answer\n");
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let started = Instant::now();
+        let status = run_agent(
+            &mut sh("exec sleep 5"),
+            None,
+            Some(Duration::from_millis(100)),
+            &AgentStdout::Inherit,
+        );
+        assert_eq!(status.unwrap(), None);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let status = run_agent(
+            &mut sh("exit 0"),
+            None,
+            Some(Duration::from_secs(5)),
+            &AgentStdout::Inherit,
+        )
+        .unwrap();
+        assert!(status.unwrap().success());
+
+        // Not reading its input doesn't keep it from timing out
+        let status = run_agent(
+            &mut sh("exec sleep 5"),
+            Some(&"x".repeat(1 << 20)),
+            Some(Duration::from_millis(100)),
+            &AgentStdout::Inherit,
+        );
+        assert_eq!(status.unwrap(), None);
+    }
+
+    #[test]
+    fn test_timeout_kills_after_grace() {
+        let started = Instant::now();
+        let mut ignores_term = sh(r#"trap "" TERM; while :; do sleep 0.1; done"#);
+        let status = run_agent(
+            &mut ignores_term,
+            None,
+            Some(Duration::from_millis(100)),
+            &AgentStdout::Inherit,
+        );
+        assert_eq!(status.unwrap(), None);
+        assert!(started.elapsed() >= TERMINATE_GRACE);
+    }
+}
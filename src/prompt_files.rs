@@ -0,0 +1,246 @@
+//! Storing each prompt as a Markdown file under `prompts/` next to the aliases file, which
+//! then refers to it with `prompt_file` instead of holding the prompt inline.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::AliasEntry;
+use crate::utils::slugify;
+
+/// Directory next to the aliases file that holds the prompt files. Qwk owns it: saving
+/// deletes the `.md` files in it that no alias refers to any more.
+pub const PROMPTS_DIR: &str = "prompts";
+
+const PROMPT_FILE_EXTENSION: &str = "md";
+
+/// Whether `reference` stays inside the directory it's relative to. Project `.qwk.json`
+/// files come from other people, so they mustn't be able to pull in e.g. `~/.ssh` files.
+fn is_safe_reference(reference: &str) -> bool {
+    let path = Path::new(reference);
+    !reference.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Reads the prompt of every entry that refers to a file, relative to `base`. A missing or
+/// unsafe reference only warns, leaving the prompt empty, so that one lost file doesn't
+/// make every other shortcut unusable.
+pub fn resolve_prompt_files(base: &Path, aliases: &mut HashMap<String, AliasEntry>) {
+    for (name, entry) in aliases.iter_mut() {
+        let Some(reference) = entry.prompt_file.take() else {
+            continue;
+        };
+        if !is_safe_reference(&reference) {
+            eprintln!(
+                "Warning: ignoring prompt file '{}' of '{}': it must be a relative path inside {}",
+                reference,
+                name,
+                base.display()
+            );
+            continue;
+        }
+        match fs::read_to_string(base.join(&reference)) {
+            Ok(prompt) => entry.prompt = prompt,
+            Err(e) => eprintln!(
+                "Warning: could not read the prompt of '{}' from {}: {}",
+                name,
+                base.join(&reference).display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Whether every `/`-separated part of an alias name can be used as a file name as is.
+fn is_plain_name(name: &str) -> bool {
+    name.split('/').all(|part| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && part
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    })
+}
+
+/// The prompt file of each alias, relative to the aliases file: `prompts/<name>.md`, with
+/// groups like `work/standup` as subdirectories. Other names are slugified, and numbered
+/// when two would share a file, also on case-insensitive file systems.
+pub fn prompt_file_paths<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<&'a str, String> {
+    let mut names: Vec<&str> = names.into_iter().collect();
+    // Plain names first so that they keep their own file name
+    names.sort_by_key(|name| (!is_plain_name(name), *name));
+
+    let mut taken = HashSet::new();
+    let mut paths = BTreeMap::new();
+    for name in names {
+        let stem = if is_plain_name(name) {
+            name.to_string()
+        } else {
+            match slugify(name) {
+                slug if slug.is_empty() => "prompt".to_string(),
+                slug => slug,
+            }
+        };
+        let mut candidate = stem.clone();
+        let mut number = 1;
+        while !taken.insert(candidate.to_lowercase()) {
+            number += 1;
+            candidate = format!("{}-{}", stem, number);
+        }
+        paths.insert(
+            name,
+            format!("{}/{}.{}", PROMPTS_DIR, candidate, PROMPT_FILE_EXTENSION),
+        );
+    }
+    paths
+}
+
+/// Writes the prompt of every alias to its file under `base`. Returns the entries to store,
+/// which refer to the files.
+pub fn write_prompt_files(
+    base: &Path,
+    aliases: &HashMap<String, AliasEntry>,
+) -> io::Result<HashMap<String, AliasEntry>> {
+    let paths = prompt_file_paths(aliases.keys().map(String::as_str));
+    let mut stored = HashMap::new();
+    for (name, reference) in &paths {
+        let entry = &aliases[*name];
+        let file = base.join(reference);
+        // Unchanged files are left alone so that their modification times mean something
+        if fs::read_to_string(&file).ok().as_deref() != Some(entry.prompt.as_str()) {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&file, &entry.prompt)?;
+        }
+        stored.insert(
+            name.to_string(),
+            AliasEntry {
+                prompt: String::new(),
+                prompt_file: Some(reference.clone()),
+                ..entry.clone()
+            },
+        );
+    }
+    Ok(stored)
+}
+
+/// Deletes the prompt files under `base` that none of the `stored` entries refer to, once
+/// the aliases file no longer does either.
+pub fn remove_unused_prompt_files(
+    base: &Path,
+    stored: &HashMap<String, AliasEntry>,
+) -> io::Result<()> {
+    let used: HashSet<PathBuf> = stored
+        .values()
+        .filter_map(|entry| entry.prompt_file.as_ref())
+        .map(|reference| base.join(reference))
+        .collect();
+    remove_unused_files(&base.join(PROMPTS_DIR), &used)
+}
+
+/// Deletes the `.md` files under `dir` that aren't in `used`, and directories left empty.
+fn remove_unused_files(dir: &Path, used: &HashSet<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_unused_files(&path, used)?;
+            // Fails, harmlessly, unless the directory is now empty
+            let _ = fs::remove_dir(&path);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == PROMPT_FILE_EXTENSION)
+            && !used.contains(&path)
+        {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_prompt_file_paths() {
+        let paths = prompt_file_paths(["review", "work/standup", "Fix it!", "fix-it", "Review"]);
+        // Would share a file on case-insensitive file systems
+        assert_eq!(paths["Review"], "prompts/Review.md");
+        assert_eq!(paths["review"], "prompts/review-2.md");
+        assert_eq!(paths["work/standup"], "prompts/work/standup.md");
+        assert_eq!(paths["fix-it"], "prompts/fix-it.md");
+        assert_eq!(paths["Fix it!"], "prompts/fix-it-2.md");
+
+        let paths = prompt_file_paths(["../escape", ".hidden", "a//b"]);
+        assert_eq!(paths["../escape"], "prompts/escape.md");
+        assert_eq!(paths[".hidden"], "prompts/hidden.md");
+        assert_eq!(paths["a//b"], "prompts/a-b.md");
+    }
+
+    #[test]
+    fn test_write_and_resolve() {
+        let dir = TempDir::new().unwrap();
+        let mut aliases = HashMap::from([
+            ("summ".to_string(), AliasEntry::new("Summarize\n\nthis")),
+            ("work/standup".to_string(), AliasEntry::new("Standup")),
+        ]);
+
+        let mut stored = write_prompt_files(dir.path(), &aliases).unwrap();
+        assert_eq!(stored["summ"].prompt, "");
+        assert_eq!(
+            stored["summ"].prompt_file.as_deref(),
+            Some("prompts/summ.md")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("prompts/summ.md")).unwrap(),
+            "Summarize\n\nthis"
+        );
+
+        resolve_prompt_files(dir.path(), &mut stored);
+        assert_eq!(stored, aliases);
+
+        // Removed aliases take their files, and emptied directories, with them
+        fs::write(dir.path().join("prompts/notes.txt"), "mine").unwrap();
+        aliases.remove("work/standup");
+        let stored = write_prompt_files(dir.path(), &aliases).unwrap();
+        assert!(dir.path().join("prompts/work/standup.md").exists());
+        remove_unused_prompt_files(dir.path(), &stored).unwrap();
+        assert!(!dir.path().join("prompts/work").exists());
+        assert!(dir.path().join("prompts/notes.txt").exists());
+    }
+
+    #[test]
+    fn test_bad_references_only_warn() {
+        let dir = TempDir::new().unwrap();
+        let secret = dir.path().join("secret.md");
+        fs::write(&secret, "secret").unwrap();
+        let project = dir.path().join("project");
+
+        let reference = |path: &str| AliasEntry {
+            prompt_file: Some(path.to_string()),
+            ..AliasEntry::default()
+        };
+        let mut aliases = HashMap::from([
+            ("missing".to_string(), reference("prompts/missing.md")),
+            ("parent".to_string(), reference("../secret.md")),
+            ("absolute".to_string(), reference(&secret.to_string_lossy())),
+        ]);
+        resolve_prompt_files(&project, &mut aliases);
+        for entry in aliases.values() {
+            assert_eq!(entry.prompt, "");
+            assert_eq!(entry.prompt_file, None);
+        }
+    }
+}
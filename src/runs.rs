@@ -0,0 +1,143 @@
+//! The run log behind `--runs`: one JSON line in `runs.jsonl` per shortcut run, written
+//! once the agent has exited. Like usage tracking it's best effort and never fails the run,
+//! and `track_runs = false` turns it off.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{ensure_profile_dir, get_profile_dir};
+
+/// Size `runs.jsonl` may grow to before its older half is dropped.
+pub const RUNS_MAX_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunEntry {
+    /// When the agent started
+    pub at: DateTime<Utc>,
+    pub alias: String,
+    /// The arguments given after `--`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// The code qwk exited with, e.g. 130 for an agent ended by Ctrl-C or 124 for one
+    /// stopped by its timeout
+    pub exit_code: i32,
+    pub duration_ms: u64,
+}
+
+impl RunEntry {
+    pub fn new(alias: &str, args: &[String], exit_code: i32, duration: Duration) -> Self {
+        RunEntry {
+            at: Utc::now() - duration,
+            alias: alias.to_string(),
+            args: args.to_vec(),
+            exit_code,
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+}
+
+/// Kept per profile, next to its `usage.json`.
+pub fn get_runs_file() -> PathBuf {
+    get_profile_dir().join("runs.jsonl")
+}
+
+fn append_run(path: &Path, entry: &RunEntry, max_bytes: usize) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    drop(file);
+
+    if fs::metadata(path)?.len() as usize > max_bytes {
+        let content = fs::read_to_string(path)?;
+        // Keep the newest whole lines that fit in half of it, so that it isn't cut again on
+        // the very next run
+        let cut = content.len() - max_bytes / 2;
+        let start = content.as_bytes()[cut..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(content.len(), |i| cut + i + 1);
+        fs::write(path, &content[start..])?;
+    }
+    Ok(())
+}
+
+/// Appends `entry` to the run log, only warning when that fails.
+pub fn record_run(entry: RunEntry) {
+    let path = get_runs_file();
+    let appended = ensure_profile_dir().and_then(|_| append_run(&path, &entry, RUNS_MAX_BYTES));
+    if let Err(e) = appended {
+        eprintln!("Warning: could not write to {}: {}", path.display(), e);
+    }
+}
+
+/// The runs in `content`, oldest first, skipping lines that aren't runs.
+pub fn parse_runs(content: &str) -> Vec<RunEntry> {
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub fn load_runs() -> io::Result<Vec<RunEntry>> {
+    match fs::read_to_string(get_runs_file()) {
+        Ok(content) => Ok(parse_runs(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// The runs of `alias` if given, or else all of them, newest first and at most `limit`.
+pub fn select_runs<'a>(
+    runs: &'a [RunEntry],
+    alias: Option<&str>,
+    limit: usize,
+) -> Vec<&'a RunEntry> {
+    runs.iter()
+        .rev()
+        .filter(|run| alias.is_none_or(|alias| run.alias == alias))
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn run(alias: &str) -> RunEntry {
+        RunEntry::new(alias, &[], 0, Duration::from_millis(1500))
+    }
+
+    #[test]
+    fn test_append_and_rotate() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("runs.jsonl");
+        let line_length = serde_json::to_string(&run("a0")).unwrap().len() + 1;
+        for i in 0..10 {
+            append_run(&path, &run(&format!("a{}", i)), line_length * 4).unwrap();
+        }
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.len() <= line_length * 4);
+        let aliases: Vec<_> = parse_runs(&content)
+            .into_iter()
+            .map(|run| run.alias)
+            .collect();
+        // Rotation dropped the oldest runs, whole
+        assert_eq!(aliases.last().map(String::as_str), Some("a9"));
+        assert!(!aliases.contains(&"a0".to_string()));
+        assert_eq!(content.lines().count(), aliases.len());
+    }
+
+    #[test]
+    fn test_select_runs() {
+        let runs = vec![run("summ"), run("review"), run("summ"), run("summ")];
+        assert_eq!(select_runs(&runs, None, 10).len(), 4);
+        assert_eq!(select_runs(&runs, Some("summ"), 2).len(), 2);
+        assert!(std::ptr::eq(select_runs(&runs, None, 1)[0], &runs[3]));
+        assert_eq!(select_runs(&runs, Some("other"), 10).len(), 0);
+        assert_eq!(parse_runs("not json\n").len(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+use regex::Regex;
+
+use crate::config::Settings;
+
+/// Agent arguments that disable an agent's own permission or sandbox checks.
+pub const DEFAULT_DANGEROUS_ARG_PATTERNS: &[&str] = &[
+    // claude
+    r"^--dangerously-skip-permissions$",
+    // codex
+    r"^--dangerously-bypass-approvals-and-sandbox$",
+    r"^--full-auto$",
+    // gemini
+    r"^--yolo$",
+    r"^--approval-mode(=|$)",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct DangerousArg {
+    pub arg: String,
+    pub pattern: String,
+}
+
+pub fn dangerous_arg_patterns(settings: &Settings) -> Result<Vec<Regex>, String> {
+    let patterns: Vec<String> = match &settings.dangerous_arg_patterns {
+        Some(patterns) => patterns.clone(),
+        None => DEFAULT_DANGEROUS_ARG_PATTERNS
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect(),
+    };
+
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .map_err(|e| format!("Invalid dangerous argument pattern '{}': {}", pattern, e))
+        })
+        .collect()
+}
+
+pub fn find_dangerous_args(args: &[String], patterns: &[Regex]) -> Vec<DangerousArg> {
+    args.iter()
+        .filter_map(|arg| {
+            patterns
+                .iter()
+                .find(|pattern| pattern.is_match(arg))
+                .map(|pattern| DangerousArg {
+                    arg: arg.clone(),
+                    pattern: pattern.as_str().to_string(),
+                })
+        })
+        .collect()
+}
+
+pub fn warn_dangerous_args(matches: &[DangerousArg]) {
+    for found in matches {
+        eprintln!(
+            "Warning: agent argument '{}' matches dangerous pattern '{}'",
+            found.arg, found.pattern
+        );
+    }
+}
+
+/// Checks the arguments of an execution. Stored default arguments are refused when
+/// `forbid` is set; per-call arguments are only ever reported so they remain an escape
+/// hatch. Returns the per-call matches to warn about.
+pub fn check_execution_args(
+    default_args: &[String],
+    per_call_args: &[String],
+    patterns: &[Regex],
+    forbid: bool,
+) -> Result<Vec<DangerousArg>, String> {
+    if forbid
+        && let Some(found) = find_dangerous_args(default_args, patterns)
+            .into_iter()
+            .next()
+    {
+        return Err(format!(
+            "Refusing to run: agent argument '{}' matches dangerous pattern '{}' (forbid_dangerous_args is enabled)",
+            found.arg, found.pattern
+        ));
+    }
+
+    Ok(find_dangerous_args(per_call_args, patterns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_dangerous_args_with_defaults() {
+        let patterns = dangerous_arg_patterns(&Settings::default()).unwrap();
+
+        let found = find_dangerous_args(
+            &args(&["--model", "opus", "--dangerously-skip-permissions"]),
+            &patterns,
+        );
+        assert_eq!(
+            found,
+            vec![DangerousArg {
+                arg: "--dangerously-skip-permissions".to_string(),
+                pattern: r"^--dangerously-skip-permissions$".to_string(),
+            }]
+        );
+
+        assert!(find_dangerous_args(&args(&["--yolo-mode", "--verbose"]), &patterns).is_empty());
+        assert_eq!(
+            find_dangerous_args(&args(&["--approval-mode=yolo"]), &patterns).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_configured_patterns_replace_defaults() {
+        let settings = Settings {
+            dangerous_arg_patterns: Some(vec![r"^--unsafe".to_string()]),
+            ..Default::default()
+        };
+        let patterns = dangerous_arg_patterns(&settings).unwrap();
+
+        assert_eq!(
+            find_dangerous_args(&args(&["--unsafe-mode"]), &patterns).len(),
+            1
+        );
+        assert!(find_dangerous_args(&args(&["--yolo"]), &patterns).is_empty());
+
+        let invalid = Settings {
+            dangerous_arg_patterns: Some(vec!["(".to_string()]),
+            ..Default::default()
+        };
+        assert!(dangerous_arg_patterns(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_check_execution_args() {
+        let patterns = dangerous_arg_patterns(&Settings::default()).unwrap();
+        let dangerous = args(&["--dangerously-skip-permissions"]);
+
+        // Without strict mode nothing is refused, but per-call args are still reported
+        assert_eq!(
+            check_execution_args(&dangerous, &[], &patterns, false),
+            Ok(vec![])
+        );
+        assert_eq!(
+            check_execution_args(&dangerous, &dangerous, &patterns, false)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Strict mode refuses stored default args and names the pattern
+        let error = check_execution_args(&dangerous, &[], &patterns, true).unwrap_err();
+        assert!(error.contains("--dangerously-skip-permissions"));
+        assert!(error.contains(r"^--dangerously-skip-permissions$"));
+
+        // Per-call args are only warned about, even in strict mode
+        let warnings = check_execution_args(&[], &dangerous, &patterns, true).unwrap();
+        assert_eq!(warnings.len(), 1);
+    }
+}
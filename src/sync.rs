@@ -0,0 +1,281 @@
+//! `--sync`: committing the shortcuts to the git repository the config directory is in,
+//! by running the `git` binary.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::config::{
+    AliasEntry, decode_aliases, get_agents_file, get_aliases_file, get_archive_file,
+    get_config_dir, get_profile_settings_file, get_settings_file, load_aliases,
+};
+use crate::prompt_files::PROMPTS_DIR;
+
+/// Names listed per kind of change in a commit message before the rest are only counted.
+const MAX_NAMES_IN_MESSAGE: usize = 5;
+
+#[derive(Debug)]
+pub enum GitError {
+    /// git couldn't be run at all, e.g. it isn't installed
+    Spawn(io::Error),
+    /// `git <command>` ran and failed; `stderr` is its own message, kept as is
+    Failed { command: String, stderr: String },
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitError::Spawn(e) => write!(f, "could not run git: {}", e),
+            GitError::Failed { command, stderr } if stderr.is_empty() => {
+                write!(f, "`git {}` failed", command)
+            }
+            GitError::Failed { command, stderr } => {
+                write!(f, "`git {}` failed:\n{}", command, stderr.trim_end())
+            }
+        }
+    }
+}
+
+fn git_command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).args(args);
+    command
+}
+
+/// Runs git in `dir` with its output captured, failing unless it exits successfully.
+fn git(dir: &Path, args: &[&str]) -> Result<Output, GitError> {
+    let output = git_command(dir, args).output().map_err(GitError::Spawn)?;
+    if !output.status.success() {
+        return Err(GitError::Failed {
+            command: args[0].to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(output)
+}
+
+/// Runs git in `dir` on the terminal, for commands like `pull` that report their progress.
+fn git_interactive(dir: &Path, args: &[&str]) -> Result<(), GitError> {
+    let status = git_command(dir, args).status().map_err(GitError::Spawn)?;
+    if !status.success() {
+        // git has already printed why
+        return Err(GitError::Failed {
+            command: args[0].to_string(),
+            stderr: String::new(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether `dir` is inside a git work tree, which may start above it as in a dotfiles repo.
+pub fn is_git_repo(dir: &Path) -> bool {
+    git(dir, &["rev-parse", "--is-inside-work-tree"]).is_ok()
+}
+
+/// The files `--sync` commits, relative to the config directory: the active profile's
+/// shortcuts, prompt files and archive, and the settings files.
+fn synced_files(config_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in [
+        get_aliases_file(),
+        get_aliases_file().with_file_name(PROMPTS_DIR),
+        get_archive_file(),
+        get_settings_file(),
+        get_profile_settings_file(),
+        get_agents_file(),
+    ] {
+        if let Ok(relative) = path.strip_prefix(config_dir)
+            && !files.iter().any(|file| file == relative)
+        {
+            files.push(relative.to_path_buf());
+        }
+    }
+    files
+}
+
+fn list_names(kind: &str, names: &[&str]) -> String {
+    let shown = names[..names.len().min(MAX_NAMES_IN_MESSAGE)].join(", ");
+    match names.len().saturating_sub(MAX_NAMES_IN_MESSAGE) {
+        0 => format!("{}: {}", kind, shown),
+        more => format!("{}: {} and {} more", kind, shown, more),
+    }
+}
+
+/// Describes the change from `before` to `after`, e.g.
+/// "qwk: update aliases (added: foo; removed: bar)".
+pub fn commit_message(
+    before: &HashMap<String, AliasEntry>,
+    after: &HashMap<String, AliasEntry>,
+) -> String {
+    let mut added: Vec<&str> = Vec::new();
+    let mut changed: Vec<&str> = Vec::new();
+    for (name, entry) in after {
+        match before.get(name) {
+            None => added.push(name),
+            Some(previous) if !previous.same_content(entry) => changed.push(name),
+            Some(_) => {}
+        }
+    }
+    let mut removed: Vec<&str> = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+    added.sort();
+    changed.sort();
+    removed.sort();
+
+    let parts: Vec<String> = [("added", added), ("changed", changed), ("removed", removed)]
+        .into_iter()
+        .filter(|(_, names)| !names.is_empty())
+        .map(|(kind, names)| list_names(kind, &names))
+        .collect();
+    if parts.is_empty() {
+        "qwk: update settings".to_string()
+    } else {
+        format!("qwk: update aliases ({})", parts.join("; "))
+    }
+}
+
+/// The shortcuts as of the last commit, or `None` if there isn't one or they can't be read.
+fn committed_aliases(dir: &Path, file: &Path) -> Option<HashMap<String, AliasEntry>> {
+    let object = format!("HEAD:./{}", file.to_string_lossy().replace('\\', "/"));
+    let output = git(dir, &["show", &object]).ok()?;
+    // Prompts kept in their own files are read as they are now, not as committed
+    decode_aliases(&dir.join(file), output.stdout).ok()
+}
+
+fn with_pathspec<'a>(args: &[&'a str], files: &'a [String]) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    args.push("--");
+    args.extend(files.iter().map(String::as_str));
+    args
+}
+
+/// Commits the changes to the synced files, returning the commit message, or `None` when
+/// there was nothing to commit. Other changes in the repository are left alone.
+pub fn commit_changes(dir: &Path) -> Result<Option<String>, GitError> {
+    let files: Vec<String> = synced_files(dir)
+        .into_iter()
+        .filter(|file| {
+            dir.join(file).exists()
+                || git(
+                    dir,
+                    &["ls-files", "--error-unmatch", "--", &file.to_string_lossy()],
+                )
+                .is_ok()
+        })
+        .map(|file| file.to_string_lossy().to_string())
+        .collect();
+    if files.is_empty() {
+        return Ok(None);
+    }
+    git(dir, &with_pathspec(&["add", "--all"], &files))?;
+    let staged = git_command(
+        dir,
+        &with_pathspec(&["diff", "--cached", "--quiet"], &files),
+    )
+    .status()
+    .map_err(GitError::Spawn)?;
+    if staged.success() {
+        return Ok(None);
+    }
+
+    let aliases_file = get_aliases_file();
+    let relative = aliases_file.strip_prefix(dir).unwrap_or(&aliases_file);
+    let message = match (committed_aliases(dir, relative), load_aliases()) {
+        (before, Ok(after)) => commit_message(&before.unwrap_or_default(), &after),
+        (_, Err(_)) => "qwk: update aliases".to_string(),
+    };
+    git(
+        dir,
+        &with_pathspec(&["commit", "--quiet", "--message", &message], &files),
+    )?;
+    Ok(Some(message))
+}
+
+pub fn pull(dir: &Path) -> Result<(), GitError> {
+    git_interactive(dir, &["pull"])
+}
+
+pub fn push(dir: &Path) -> Result<(), GitError> {
+    git_interactive(dir, &["push"])
+}
+
+/// The config directory, if it's in a git repository.
+pub fn config_repo_dir() -> Option<PathBuf> {
+    let dir = get_config_dir();
+    is_git_repo(&dir).then_some(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{save_aliases, with_temp_config_dir};
+
+    fn aliases(names: &[(&str, &str)]) -> HashMap<String, AliasEntry> {
+        names
+            .iter()
+            .map(|(name, prompt)| (name.to_string(), AliasEntry::new(*prompt)))
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_message() {
+        let before = aliases(&[("keep", "Same"), ("edit", "Old"), ("gone", "Bye")]);
+        let after = aliases(&[("keep", "Same"), ("edit", "New"), ("new", "Hi")]);
+        assert_eq!(
+            commit_message(&before, &after),
+            "qwk: update aliases (added: new; changed: edit; removed: gone)"
+        );
+        assert_eq!(commit_message(&before, &before), "qwk: update settings");
+
+        let many: Vec<_> = (1..=7).map(|i| (format!("a{}", i), "x")).collect();
+        let many: Vec<_> = many
+            .iter()
+            .map(|(name, prompt)| (name.as_str(), *prompt))
+            .collect();
+        assert_eq!(
+            commit_message(&HashMap::new(), &aliases(&many)),
+            "qwk: update aliases (added: a1, a2, a3, a4, a5 and 2 more)"
+        );
+    }
+
+    #[test]
+    fn test_commit_changes() {
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+        with_temp_config_dir(|dir| {
+            assert!(!is_git_repo(dir));
+            for args in [
+                &["init", "--quiet"][..],
+                &["config", "user.name", "qwk"],
+                &["config", "user.email", "qwk@example.com"],
+                &["config", "commit.gpgsign", "false"],
+            ] {
+                git(dir, args).unwrap();
+            }
+            assert!(is_git_repo(dir));
+            std::fs::write(dir.join("notes.txt"), "not ours").unwrap();
+
+            save_aliases(&aliases(&[("summ", "Summarize")])).unwrap();
+            assert_eq!(
+                commit_changes(dir).unwrap().as_deref(),
+                Some("qwk: update aliases (added: summ)")
+            );
+            assert_eq!(commit_changes(dir).unwrap(), None);
+
+            save_aliases(&aliases(&[("review", "Review")])).unwrap();
+            assert_eq!(
+                commit_changes(dir).unwrap().as_deref(),
+                Some("qwk: update aliases (added: review; removed: summ)")
+            );
+
+            let status = git(dir, &["status", "--porcelain"]).unwrap();
+            assert_eq!(String::from_utf8_lossy(&status.stdout), "?? notes.txt\n");
+        });
+    }
+}
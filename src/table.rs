@@ -0,0 +1,137 @@
+/// Spaces between two columns of a multi-column layout.
+pub const COLUMN_GAP: usize = 2;
+
+/// Lays `items` out in balanced columns like `ls`, filling column-major, and returns the
+/// rendered lines. Uses as few rows as fit within `width`; items wider than the width fall
+/// back to one per line.
+pub fn layout_columns(items: &[&str], width: usize) -> Vec<String> {
+    if items.is_empty() {
+        return vec![];
+    }
+
+    let lengths: Vec<usize> = items.iter().map(|item| item.chars().count()).collect();
+
+    for rows in 1..=items.len() {
+        let columns = items.len().div_ceil(rows);
+        let column_widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                lengths[column * rows..((column + 1) * rows).min(items.len())]
+                    .iter()
+                    .copied()
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let total = column_widths.iter().sum::<usize>() + COLUMN_GAP * (columns - 1);
+
+        if total <= width || columns == 1 {
+            return (0..rows)
+                .map(|row| {
+                    let mut line = String::new();
+                    for (column, column_width) in column_widths.iter().enumerate() {
+                        let Some(item) = items.get(column * rows + row) else {
+                            break;
+                        };
+                        if column > 0 {
+                            line.push_str(&" ".repeat(COLUMN_GAP));
+                        }
+                        line.push_str(item);
+                        line.push_str(&" ".repeat(column_width - lengths[column * rows + row]));
+                    }
+                    line.trim_end().to_string()
+                })
+                .collect();
+        }
+    }
+
+    unreachable!("a single column always fits")
+}
+
+/// Renders rows under a header with every column but the last padded to a common width.
+pub fn render_table(headers: &[&str], rows: &[Vec<String>]) -> Vec<String> {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let render_row = |cells: Vec<&str>| {
+        let mut line = String::new();
+        for (column, cell) in cells.iter().enumerate() {
+            if column > 0 {
+                line.push_str(&" ".repeat(COLUMN_GAP));
+            }
+            line.push_str(cell);
+            if column + 1 < cells.len() {
+                line.push_str(&" ".repeat(widths[column] - cell.chars().count()));
+            }
+        }
+        line.trim_end().to_string()
+    };
+
+    let mut lines = vec![render_row(headers.to_vec())];
+    lines.extend(
+        rows.iter()
+            .map(|row| render_row(row.iter().map(String::as_str).collect())),
+    );
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_columns_fills_column_major() {
+        let items = ["a", "bb", "c", "dddd", "e"];
+        assert_eq!(layout_columns(&items, 80), vec!["a  bb  c  dddd  e"]);
+        assert_eq!(layout_columns(&items, 12), vec!["a   c     e", "bb  dddd"]);
+        assert_eq!(layout_columns(&items, 8), vec!["a   dddd", "bb  e", "c"]);
+    }
+
+    #[test]
+    fn test_layout_columns_narrow_terminal() {
+        let items = ["review", "docs"];
+        assert_eq!(layout_columns(&items, 12), vec!["review  docs"]);
+        assert_eq!(layout_columns(&items, 11), vec!["review", "docs"]);
+
+        // Names wider than the terminal get one line each
+        let items = ["a-very-long-alias-name", "short"];
+        assert_eq!(
+            layout_columns(&items, 10),
+            vec!["a-very-long-alias-name", "short"]
+        );
+    }
+
+    #[test]
+    fn test_layout_columns_edge_cases() {
+        assert!(layout_columns(&[], 80).is_empty());
+        assert_eq!(layout_columns(&["only"], 2), vec!["only"]);
+        assert_eq!(layout_columns(&["a", "b", "c"], 0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_render_table() {
+        let rows = vec![
+            vec![
+                "remove".to_string(),
+                "aliases.json".to_string(),
+                "3 shortcuts".to_string(),
+            ],
+            vec!["backup".to_string(), "a.json".to_string(), "".to_string()],
+        ];
+        assert_eq!(
+            render_table(&["ACTION", "PATH", "DETAILS"], &rows),
+            vec![
+                "ACTION  PATH          DETAILS",
+                "remove  aliases.json  3 shortcuts",
+                "backup  a.json",
+            ]
+        );
+        assert_eq!(render_table(&["NAME"], &[]), vec!["NAME"]);
+    }
+}
@@ -0,0 +1,124 @@
+//! `--undo`: a copy of the aliases file taken before the last command that changed it, and
+//! a journal entry naming that command. Only one step is kept, and undoing uses it up.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{copy_aliases_file, get_aliases_file};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UndoEntry {
+    /// What the command did, e.g. "set 'review'"
+    pub command: String,
+    pub at: DateTime<Utc>,
+    /// Whether there was an aliases file before the command; if not, undoing removes it
+    pub had_aliases: bool,
+}
+
+fn next_to_aliases_file(suffix: &str) -> PathBuf {
+    let aliases_file = get_aliases_file();
+    let mut name = aliases_file
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    name.push(suffix);
+    aliases_file.with_file_name(name)
+}
+
+/// The aliases file as it was before the last command, e.g. `aliases.json.prev`.
+pub fn get_undo_snapshot_file() -> PathBuf {
+    next_to_aliases_file(".prev")
+}
+
+/// The journal entry describing the last command, e.g. `aliases.json.undo.json`.
+pub fn get_undo_journal_file() -> PathBuf {
+    next_to_aliases_file(".undo.json")
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Snapshots the aliases file before `command` changes it, replacing the previous snapshot.
+pub fn record_undo(command: &str) -> io::Result<()> {
+    let aliases_file = get_aliases_file();
+    let snapshot = get_undo_snapshot_file();
+    let had_aliases = aliases_file.exists();
+    if had_aliases {
+        copy_aliases_file(&aliases_file, &snapshot)?;
+    } else {
+        remove_if_exists(&snapshot)?;
+    }
+    let entry = UndoEntry {
+        command: command.to_string(),
+        at: Utc::now(),
+        had_aliases,
+    };
+    let journal = get_undo_journal_file();
+    if let Some(parent) = journal.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(journal, serde_json::to_string_pretty(&entry)?)
+}
+
+/// The command `--undo` would revert, if there is one.
+pub fn load_undo_entry() -> io::Result<Option<UndoEntry>> {
+    let content = match fs::read_to_string(get_undo_journal_file()) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Forgets the recorded command, once it has been undone.
+pub fn clear_undo() -> io::Result<()> {
+    remove_if_exists(&get_undo_journal_file())?;
+    remove_if_exists(&get_undo_snapshot_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AliasEntry, load_aliases, load_aliases_from, save_aliases, with_temp_config_dir,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_record_and_clear() {
+        with_temp_config_dir(|dir| {
+            assert_eq!(load_undo_entry().unwrap(), None);
+
+            record_undo("set 'summ'").unwrap();
+            let entry = load_undo_entry().unwrap().unwrap();
+            assert_eq!(entry.command, "set 'summ'");
+            assert!(!entry.had_aliases);
+            assert!(!get_undo_snapshot_file().exists());
+
+            let aliases = HashMap::from([("summ".to_string(), AliasEntry::new("Summarize"))]);
+            save_aliases(&aliases).unwrap();
+            record_undo("remove 'summ'").unwrap();
+            assert!(load_undo_entry().unwrap().unwrap().had_aliases);
+            assert_eq!(get_undo_snapshot_file(), dir.join("aliases.json.prev"));
+            assert_eq!(
+                load_aliases_from(&get_undo_snapshot_file()).unwrap(),
+                load_aliases().unwrap()
+            );
+
+            clear_undo().unwrap();
+            assert_eq!(load_undo_entry().unwrap(), None);
+            assert!(!get_undo_snapshot_file().exists());
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::{ensure_profile_dir, get_profile_dir};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub count: u64,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+pub fn get_usage_file() -> PathBuf {
+    get_profile_dir().join("usage.json")
+}
+
+/// Parses the usage file. A corrupted file counts as no usage at all rather than an error,
+/// since usage tracking must never get in the way of running shortcuts.
+pub fn parse_usage(content: &str) -> HashMap<String, UsageRecord> {
+    serde_json::from_str(content).unwrap_or_default()
+}
+
+pub fn load_usage() -> HashMap<String, UsageRecord> {
+    let usage_file = get_usage_file();
+    if usage_file.exists() {
+        let content = fs::read_to_string(&usage_file).unwrap_or_default();
+        parse_usage(&content)
+    } else {
+        HashMap::new()
+    }
+}
+
+pub fn save_usage(usage: &HashMap<String, UsageRecord>) -> io::Result<()> {
+    ensure_profile_dir()?;
+    let content = serde_json::to_string_pretty(usage)?;
+    fs::write(get_usage_file(), content)
+}
+
+/// Usage of every alias in `names`, most used first. Aliases that never ran get zeroes.
+pub fn usage_stats(
+    names: &[&String],
+    usage: &HashMap<String, UsageRecord>,
+) -> Vec<(String, UsageRecord)> {
+    let mut stats: Vec<(String, UsageRecord)> = names
+        .iter()
+        .map(|name| {
+            let record = usage.get(*name).cloned().unwrap_or_default();
+            (name.to_string(), record)
+        })
+        .collect();
+    stats.sort_by(|(a_name, a), (b_name, b)| b.count.cmp(&a.count).then(a_name.cmp(b_name)));
+    stats
+}
+
+pub fn record_usage(alias: &str) -> io::Result<()> {
+    let mut usage = load_usage();
+    let record = usage.entry(alias.to_string()).or_default();
+    record.count += 1;
+    record.last_used = Some(Utc::now());
+    save_usage(&usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_usage_tolerates_corruption() {
+        assert!(parse_usage("").is_empty());
+        assert!(parse_usage("{\"review\": {\"count\": ").is_empty());
+        assert!(parse_usage("[1, 2, 3]").is_empty());
+
+        let usage = parse_usage(r#"{"review": {"count": 3, "last_used": null}}"#);
+        assert_eq!(usage["review"].count, 3);
+    }
+
+    #[test]
+    fn test_usage_stats() {
+        let names = ["alpha", "beta", "gamma"].map(String::from);
+        let names: Vec<&String> = names.iter().collect();
+        let last_used = Some(Utc.with_ymd_and_hms(2024, 5, 1, 10, 2, 0).unwrap());
+        let mut usage = HashMap::new();
+        usage.insert(
+            "gamma".to_string(),
+            UsageRecord {
+                count: 4,
+                last_used,
+            },
+        );
+        // Usage of aliases that no longer exist is ignored
+        usage.insert(
+            "removed".to_string(),
+            UsageRecord {
+                count: 9,
+                last_used,
+            },
+        );
+
+        let stats = usage_stats(&names, &usage);
+        let order: Vec<_> = stats
+            .iter()
+            .map(|(name, record)| (name.as_str(), record.count))
+            .collect();
+        assert_eq!(order, vec![("gamma", 4), ("alpha", 0), ("beta", 0)]);
+        assert_eq!(stats[0].1.last_used, last_used);
+        assert_eq!(stats[1].1.last_used, None);
+    }
+}
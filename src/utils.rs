@@ -1,4 +1,17 @@
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::QwkError;
+#[cfg(unix)]
+use crate::process::{InterruptGuard, set_interrupt_handler};
 
 pub fn parse_agent_command(agent_str: &str) -> (String, Vec<String>) {
     match shlex::split(agent_str) {
@@ -11,6 +24,247 @@ pub fn parse_agent_command(agent_str: &str) -> (String, Vec<String>) {
     }
 }
 
+/// The agent command for `program` and `args`, quoted so that `parse_agent_command` splits
+/// it back into them. Fails on an argument holding a NUL, which no command line can.
+pub fn join_agent_command(program: &str, args: &[String]) -> Result<String, String> {
+    let words = std::iter::once(program).chain(args.iter().map(String::as_str));
+    shlex::try_join(words).map_err(|e| e.to_string())
+}
+
+/// Removes the first run of `args` that equals `remove`, returning whether there was one.
+pub fn remove_agent_args(args: &mut Vec<String>, remove: &[String]) -> bool {
+    if remove.is_empty() {
+        return false;
+    }
+    match args
+        .windows(remove.len())
+        .position(|window| window == remove)
+    {
+        Some(start) => {
+            args.drain(start..start + remove.len());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Placeholder in the agent command for where the prompt goes, when not last.
+pub const PROMPT_PLACEHOLDER: &str = "{prompt}";
+
+/// The arguments the agent runs with: its default arguments, then those given after `--`,
+/// then the prompt. When the default arguments contain `{prompt}`, the prompt is put in
+/// each of those places instead. Substituting after splitting keeps the prompt a single
+/// argument whatever quotes it holds.
+pub fn agent_call_args(
+    default_args: &[String],
+    per_call_args: &[String],
+    prompt: &str,
+) -> Vec<String> {
+    let has_placeholder = default_args
+        .iter()
+        .any(|arg| arg.contains(PROMPT_PLACEHOLDER));
+    let mut args: Vec<String> = default_args
+        .iter()
+        .map(|arg| arg.replace(PROMPT_PLACEHOLDER, prompt))
+        .chain(per_call_args.iter().cloned())
+        .collect();
+    if !has_placeholder {
+        args.push(prompt.to_string());
+    }
+    args
+}
+
+/// How an agent set with `--shell` runs: its command as a script for `sh -c`, or `cmd /C`
+/// on Windows, followed by the arguments given after `--` and then the prompt, unless the
+/// command places it with `{prompt}` or `prompt` is `None` because it goes to stdin. The
+/// prompt and the arguments reach the script as parameters, `$0` and `$@`, or as variables
+/// on Windows, and are never pasted into it, so the shell doesn't parse them.
+///
+/// Returns the program, its arguments and the variables to set.
+pub fn shell_agent_call(
+    command: &str,
+    per_call_args: &[String],
+    prompt: Option<&str>,
+) -> (String, Vec<String>, BTreeMap<String, String>) {
+    #[cfg(unix)]
+    let (prompt_ref, arg_refs, program) = ("\"$0\"", vec!["\"$@\"".to_string()], "sh");
+    #[cfg(not(unix))]
+    let (prompt_ref, arg_refs, program) = (
+        "\"!QWK_PROMPT!\"",
+        (1..=per_call_args.len())
+            .map(|i| format!("\"!QWK_ARG{}!\"", i))
+            .collect::<Vec<_>>(),
+        "cmd",
+    );
+
+    let mut script = command.replace(PROMPT_PLACEHOLDER, prompt_ref);
+    if !per_call_args.is_empty() {
+        for arg_ref in &arg_refs {
+            script.push(' ');
+            script.push_str(arg_ref);
+        }
+    }
+    if prompt.is_some() && !command.contains(PROMPT_PLACEHOLDER) {
+        script.push(' ');
+        script.push_str(prompt_ref);
+    }
+
+    #[cfg(unix)]
+    {
+        // `$0` is the script's name when the prompt goes to stdin
+        let name = prompt.unwrap_or("qwk");
+        let args = ["-c", &script, name]
+            .into_iter()
+            .map(String::from)
+            .chain(per_call_args.iter().cloned())
+            .collect();
+        (program.to_string(), args, BTreeMap::new())
+    }
+    #[cfg(not(unix))]
+    {
+        // Delayed expansion (/V:ON) happens after the line is parsed, so the values of
+        // the variables can hold any characters
+        let mut env: BTreeMap<String, String> = per_call_args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| (format!("QWK_ARG{}", i + 1), arg.clone()))
+            .collect();
+        if let Some(prompt) = prompt {
+            env.insert("QWK_PROMPT".to_string(), prompt.to_string());
+        }
+        let args = ["/D", "/V:ON", "/S", "/C", &script]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        (program.to_string(), args, env)
+    }
+}
+
+/// Whether `path` is a file this user could run.
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// File names `program` may have on disk: on Windows also with each `PATHEXT` extension.
+fn executable_names(program: &str) -> Vec<String> {
+    let mut names = vec![program.to_string()];
+    if cfg!(windows) && Path::new(program).extension().is_none() {
+        let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+        names.extend(
+            extensions
+                .split(';')
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| format!("{}{}", program, extension.to_lowercase())),
+        );
+    }
+    names
+}
+
+/// Looks `program` up like a shell would: a name with a path separator as a path, anything
+/// else in the directories of `path_var` (the value of `PATH`).
+pub fn find_in_path(program: &str, path_var: Option<&std::ffi::OsStr>) -> Option<PathBuf> {
+    if program.contains('/') || program.contains(std::path::MAIN_SEPARATOR) {
+        let path = expand_tilde(program);
+        return is_executable(&path).then_some(path);
+    }
+    env::split_paths(path_var?)
+        .flat_map(|dir| {
+            executable_names(program)
+                .into_iter()
+                .map(move |name| dir.join(name))
+        })
+        .find(|path| is_executable(path))
+}
+
+/// `find_in_path` with the `PATH` of this process.
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    find_in_path(program, env::var_os("PATH").as_deref())
+}
+
+/// Number of single-character insertions, deletions and substitutions turning `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The `candidates` closest to the unknown `name`, if they're close enough to be a likely
+/// typo of it: all of them when several are as close, sorted.
+pub fn closest_matches<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let max_distance = (name.chars().count() / 3).clamp(1, 2);
+    let mut best = max_distance + 1;
+    let mut matches = Vec::new();
+    for candidate in candidates {
+        let distance = edit_distance(name, candidate);
+        if distance == 0 || distance > best {
+            continue;
+        }
+        if distance < best {
+            best = distance;
+            matches.clear();
+        }
+        matches.push(candidate);
+    }
+    matches.sort_unstable();
+    matches.dedup();
+    matches
+}
+
+/// The program on `path_var` whose name is closest to the missing `program`, if one is
+/// close enough to be a likely typo.
+pub fn suggest_executable(program: &str, path_var: Option<&std::ffi::OsStr>) -> Option<String> {
+    if program.contains('/') || program.contains(std::path::MAIN_SEPARATOR) {
+        return None;
+    }
+    let max_distance = (program.chars().count() / 3).clamp(1, 2);
+    let mut best: Option<(usize, String)> = None;
+    for dir in env::split_paths(path_var?) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let stem = if cfg!(windows) {
+                name.rsplit_once('.')
+                    .map_or(name.as_str(), |(stem, _)| stem)
+            } else {
+                name.as_str()
+            };
+            let distance = edit_distance(program, stem);
+            if distance == 0 || distance > max_distance || !is_executable(&entry.path()) {
+                continue;
+            }
+            let candidate = (distance, stem.to_string());
+            if best.as_ref().is_none_or(|best| candidate < *best) {
+                best = Some(candidate);
+            }
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
 pub fn truncate_prompt(prompt: &str, max_length: usize) -> String {
     // Replace newlines and multiple spaces with single spaces for display
     let cleaned = prompt
@@ -22,10 +276,37 @@ pub fn truncate_prompt(prompt: &str, max_length: usize) -> String {
     if cleaned.len() <= max_length {
         cleaned
     } else {
-        format!("{}...", &cleaned[..max_length.saturating_sub(3)])
+        let end = cleaned.floor_char_boundary(max_length.saturating_sub(3));
+        format!("{}...", &cleaned[..end])
     }
 }
 
+/// Characters of a secret that `mask_secret` leaves readable.
+const SECRET_SHOWN_CHARS: usize = 4;
+
+/// `value` with all but its first few characters hidden, for printing values that may be
+/// tokens. Short values are hidden entirely, as their first characters are most of them.
+pub fn mask_secret(value: &str) -> String {
+    if value.chars().count() <= SECRET_SHOWN_CHARS * 2 {
+        return "****".to_string();
+    }
+    let shown: String = value.chars().take(SECRET_SHOWN_CHARS).collect();
+    format!("{}****", shown)
+}
+
+/// Lowercases `text` and joins its alphanumeric runs with dashes, for deriving alias
+/// names from free-form titles.
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Format of `get_current_datetime`, used in backup file names.
+pub const DATETIME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
 pub fn get_current_datetime() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let timestamp = SystemTime::now()
@@ -36,27 +317,941 @@ pub fn get_current_datetime() -> String {
     // Convert to a simple datetime format: YYYYMMDD_HHMMSS
     let datetime =
         chrono::DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_else(chrono::Utc::now);
-    datetime.format("%Y%m%d_%H%M%S").to_string()
+    datetime.format(DATETIME_FORMAT).to_string()
 }
 
-pub fn confirm_reset() -> bool {
-    print!("This will remove all shortcuts (a backup will be created). Are you sure? (y/N): ");
-    io::stdout().flush().unwrap();
+/// Parses a timestamp written by `get_current_datetime`, which is in UTC.
+pub fn parse_datetime(datetime: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+        .ok()
+        .map(|datetime| datetime.and_utc())
+}
 
-    let mut input = String::new();
-    if io::stdin().read_line(&mut input).is_ok() {
-        let input = input.trim().to_lowercase();
-        input == "y" || input == "yes"
-    } else {
-        false
+/// How long before `now` a moment was, e.g. "2 days ago". Moments in the future count as
+/// "just now".
+pub fn format_age(
+    time: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let seconds = (now - time).num_seconds();
+    let (count, unit) = match seconds {
+        ..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+/// A file size for display, e.g. "512 B" or "1.5 KiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// How long something ran, for display, e.g. "850ms", "12.3s" or "2m 05s".
+pub fn format_duration_ms(ms: u64) -> String {
+    match ms {
+        0..1_000 => format!("{}ms", ms),
+        1_000..60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        60_000..3_600_000 => format!("{}m {:02}s", ms / 60_000, ms / 1000 % 60),
+        _ => format!("{}h {:02}m", ms / 3_600_000, ms / 60_000 % 60),
+    }
+}
+
+/// Formats a stored timestamp in local time for display, e.g. `2024-05-01 10:02`.
+pub fn format_local_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Why `confirm_with` got no answer.
+#[derive(Debug)]
+pub enum ConfirmError {
+    /// The input isn't a terminal, so whatever it holds wasn't typed as an answer
+    NotATerminal,
+    Io(io::Error),
+}
+
+impl fmt::Display for ConfirmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfirmError::NotATerminal => write!(f, "stdin isn't a terminal to answer on"),
+            ConfirmError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Asks a yes/no `question` and reads the answer from `input`; anything but "y" or "yes"
+/// is a no. Input that isn't a terminal (`is_tty`) is refused without reading it, so piped
+/// data is never taken for an answer. The question goes to stderr, so it's seen even when
+/// stdout is redirected.
+pub fn confirm_with(
+    question: &str,
+    input: &mut impl BufRead,
+    is_tty: bool,
+) -> Result<bool, ConfirmError> {
+    confirm_with_default(question, false, input, is_tty)
+}
+
+/// Like `confirm_with`, but an empty answer is `default` rather than a no.
+pub fn confirm_with_default(
+    question: &str,
+    default: bool,
+    input: &mut impl BufRead,
+    is_tty: bool,
+) -> Result<bool, ConfirmError> {
+    if !is_tty {
+        return Err(ConfirmError::NotATerminal);
+    }
+    eprint!("{} {}: ", question, if default { "(Y/n)" } else { "(y/N)" });
+    io::stderr().flush().map_err(ConfirmError::Io)?;
+
+    let mut answer = String::new();
+    let read = input.read_line(&mut answer).map_err(ConfirmError::Io)?;
+    let answer = answer.trim().to_lowercase();
+    // End of input, as opposed to an empty line, is always a no
+    if answer.is_empty() && read > 0 {
+        return Ok(default);
+    }
+    Ok(answer == "y" || answer == "yes")
+}
+
+pub fn confirm_reset(input: &mut impl BufRead, is_tty: bool) -> Result<bool, ConfirmError> {
+    confirm_with(
+        "This will remove all shortcuts (a backup will be created). Are you sure?",
+        input,
+        is_tty,
+    )
+}
+
+/// Asks a yes/no `question` on the terminal, taking no answer, as when stdin isn't a
+/// terminal, for a no.
+pub fn confirm(question: &str) -> bool {
+    let stdin = io::stdin();
+    let is_tty = stdin.is_terminal();
+    confirm_with(question, &mut stdin.lock(), is_tty).unwrap_or(false)
+}
+
+/// Like `confirm`, but pressing Enter is a yes. Without a terminal it's still a no.
+pub fn confirm_default_yes(question: &str) -> bool {
+    let stdin = io::stdin();
+    let is_tty = stdin.is_terminal();
+    confirm_with_default(question, true, &mut stdin.lock(), is_tty).unwrap_or(false)
+}
+
+#[derive(Debug)]
+pub enum PromptReadError {
+    /// The user pressed Ctrl-C while the prompt was being read
+    Cancelled,
+    TooLarge {
+        limit: usize,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for PromptReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PromptReadError::Cancelled => write!(f, "cancelled"),
+            PromptReadError::TooLarge { limit } => {
+                write!(
+                    f,
+                    "input exceeds the maximum prompt size of {} bytes",
+                    limit
+                )
+            }
+            PromptReadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for PromptReadError {
+    fn from(e: io::Error) -> Self {
+        PromptReadError::Io(e)
     }
 }
 
-pub fn read_prompt_from_stdin() -> io::Result<String> {
-    use std::io::Read;
-    let mut buffer = String::new();
-    io::stdin().read_to_string(&mut buffer)?;
-    Ok(buffer.trim().to_string())
+/// Reads `reader` to the end in chunks like `read_text_from`, trimming the result like any
+/// stdin prompt.
+pub fn read_prompt_from<R: Read>(
+    reader: R,
+    max_bytes: usize,
+    cancelled: &AtomicBool,
+) -> Result<String, PromptReadError> {
+    Ok(read_text_from(reader, max_bytes, cancelled)?
+        .trim()
+        .to_string())
+}
+
+/// Reads `reader` to the end in chunks, checking `cancelled` between chunks and
+/// refusing input larger than `max_bytes`. The text is returned as it is.
+pub fn read_text_from<R: Read>(
+    mut reader: R,
+    max_bytes: usize,
+    cancelled: &AtomicBool,
+) -> Result<String, PromptReadError> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(PromptReadError::Cancelled);
+        }
+
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if buffer.len() + read > max_bytes {
+            return Err(PromptReadError::TooLarge { limit: max_bytes });
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    Ok(String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+}
+
+static STDIN_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether stdin is a file, pipe or socket that input comes from. A terminal is left to the
+/// agent, and a device such as `/dev/null` has nothing to give. A pipe is read however long
+/// its writer takes to start, so an idle pipe a parent left open needs `--no-stdin`.
+#[cfg(unix)]
+pub fn stdin_is_input() -> bool {
+    // SAFETY: `fstat` only writes to the struct it's given
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        libc::fstat(libc::STDIN_FILENO, &mut stat) == 0
+            && matches!(
+                stat.st_mode & libc::S_IFMT,
+                libc::S_IFREG | libc::S_IFIFO | libc::S_IFSOCK
+            )
+    }
+}
+
+#[cfg(not(unix))]
+pub fn stdin_is_input() -> bool {
+    !io::stdin().is_terminal()
+}
+
+/// Reads a prompt from stdin, trimmed like `read_prompt_from`.
+pub fn read_prompt_from_stdin(max_bytes: usize) -> Result<String, QwkError> {
+    Ok(read_text_from_stdin(max_bytes)?.trim().to_string())
+}
+
+/// Reads stdin to the end as it is. Ctrl-C returns `QwkError::Cancelled` right away instead
+/// of waiting for the blocked read to finish, so callers can clean up before exiting.
+pub fn read_text_from_stdin(max_bytes: usize) -> Result<String, QwkError> {
+    STDIN_CANCELLED.store(false, Ordering::SeqCst);
+    let _interrupts = catch_interrupts();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(read_text_from(io::stdin(), max_bytes, &STDIN_CANCELLED));
+    });
+
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(50)) {
+            Ok(result) => return Ok(result?),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if STDIN_CANCELLED.load(Ordering::SeqCst) {
+                    return Err(QwkError::Cancelled);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(QwkError::io(
+                    "Error reading prompt",
+                    io::Error::other("stdin reader stopped unexpectedly"),
+                ));
+            }
+        }
+    }
+}
+
+/// Makes Ctrl-C cancel the read of stdin until the guard is dropped.
+#[cfg(unix)]
+fn catch_interrupts() -> InterruptGuard {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        STDIN_CANCELLED.store(true, Ordering::SeqCst);
+    }
+    set_interrupt_handler(on_interrupt)
+}
+
+/// Whether a read of stdin is waiting for Ctrl-C to cancel it.
+#[cfg(not(unix))]
+static STDIN_READING: AtomicBool = AtomicBool::new(false);
+
+/// Ends the read that `catch_interrupts` started when dropped.
+#[cfg(not(unix))]
+struct ReadingStdin;
+
+#[cfg(not(unix))]
+impl Drop for ReadingStdin {
+    fn drop(&mut self) {
+        STDIN_READING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Makes Ctrl-C cancel the read of stdin until the guard is dropped. A console handler
+/// can't be taken back once installed, so outside of a read it exits as Ctrl-C would have.
+#[cfg(not(unix))]
+fn catch_interrupts() -> ReadingStdin {
+    static INSTALLED: std::sync::Once = std::sync::Once::new();
+    INSTALLED.call_once(|| {
+        let _ = ctrlc::set_handler(|| {
+            if STDIN_READING.load(Ordering::SeqCst) {
+                STDIN_CANCELLED.store(true, Ordering::SeqCst);
+            } else {
+                std::process::exit(crate::error::CANCELLED_EXIT_CODE);
+            }
+        });
+    });
+    STDIN_READING.store(true, Ordering::SeqCst);
+    ReadingStdin
+}
+
+/// Line that ends interactive prompt entry, as an alternative to EOF.
+pub const INTERACTIVE_TERMINATOR: &str = ".";
+
+/// Reads a multi-line prompt. On a TTY, instructions and a marker per line are written to
+/// `output` and a line containing only `.` ends the prompt; otherwise `reader` is read to
+/// EOF unchanged so piped prompts may contain such lines.
+pub fn read_prompt_interactive<R: BufRead, W: Write>(
+    reader: R,
+    mut output: W,
+    is_tty: bool,
+) -> io::Result<String> {
+    if is_tty {
+        writeln!(
+            output,
+            "Enter your prompt, finish with Ctrl-D or a line containing only '{}':",
+            INTERACTIVE_TERMINATOR
+        )?;
+    }
+
+    let mut lines = Vec::new();
+    let mut lines_in = reader.lines();
+    loop {
+        if is_tty {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        let Some(line) = lines_in.next() else {
+            break;
+        };
+        let line = line?;
+        if is_tty && line.trim_end() == INTERACTIVE_TERMINATOR {
+            break;
+        }
+        lines.push(line);
+    }
+
+    Ok(lines.join("\n").trim().to_string())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RenderError {
+    /// Placeholders that had no value. Positional placeholders are reported as `$1` or `$@`
+    MissingVars(Vec<String>),
+    /// Variables referenced by `{{env:NAME}}` that aren't set
+    MissingEnv(Vec<String>),
+    /// A `{{datetime:FORMAT}}` placeholder whose format isn't a valid strftime string
+    InvalidDateFormat(String),
+    /// A `{{file:path}}` placeholder whose file couldn't be inlined
+    File { placeholder: String, reason: String },
+    /// An `{{alias:name}}` reference to an alias that doesn't exist
+    UnknownAlias(String),
+    /// Aliases that include each other, starting and ending with the same name
+    AliasCycle(Vec<String>),
+    /// A chain of `{{alias:name}}` references nested more than `MAX_ALIAS_DEPTH` deep
+    AliasTooDeep(Vec<String>),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::MissingVars(names) => {
+                write!(f, "missing template variables: {}", names.join(", "))
+            }
+            RenderError::MissingEnv(names) => {
+                write!(f, "environment variables not set: {}", names.join(", "))
+            }
+            RenderError::InvalidDateFormat(placeholder) => {
+                write!(f, "an invalid date format in {}", placeholder)
+            }
+            RenderError::File {
+                placeholder,
+                reason,
+            } => write!(f, "an unreadable file in {}: {}", placeholder, reason),
+            RenderError::UnknownAlias(name) => {
+                write!(f, "a reference to unknown alias '{}'", name)
+            }
+            RenderError::AliasCycle(names) => {
+                write!(f, "an alias cycle: {}", names.join(" -> "))
+            }
+            RenderError::AliasTooDeep(names) => write!(
+                f,
+                "aliases nested more than {} deep: {}",
+                MAX_ALIAS_DEPTH,
+                names.join(" -> ")
+            ),
+        }
+    }
+}
+
+/// Values available to a prompt template when a shortcut runs.
+#[derive(Debug)]
+pub struct TemplateContext {
+    /// Values for `{{name}}`
+    pub vars: HashMap<String, String>,
+    /// Values for `$1`..`$9` and `$@`
+    pub positional: Vec<String>,
+    /// Text given after the shortcut name to a prompt without `$1`..`$9` or `$@`, appended
+    /// to it after a blank line
+    pub extra: Option<String>,
+    /// Substitute an empty string for unset `{{env:NAME}}` variables instead of failing
+    pub allow_missing_env: bool,
+    /// Piped input, used for `{{stdin}}` or appended to the prompt when there's no such
+    /// placeholder
+    pub stdin: Option<String>,
+    /// Time used for `{{date}}`, `{{time}}` and `{{datetime:FORMAT}}`; `None` means now
+    pub now: Option<chrono::DateTime<chrono::Local>>,
+    /// Variables for `{{env:NAME}}`; `None` means the environment of this process
+    pub env: Option<HashMap<String, String>>,
+    /// Largest file a `{{file:path}}` placeholder may inline, in bytes
+    pub max_file_bytes: u64,
+    /// Prompts by alias name, for `{{alias:name}}`
+    pub aliases: HashMap<String, String>,
+    /// Only expand `{{alias:name}}` and leave the placeholders filled at run time as they are
+    pub aliases_only: bool,
+}
+
+impl Default for TemplateContext {
+    fn default() -> Self {
+        TemplateContext {
+            vars: HashMap::new(),
+            positional: Vec::new(),
+            extra: None,
+            allow_missing_env: false,
+            stdin: None,
+            now: None,
+            env: None,
+            max_file_bytes: crate::config::DEFAULT_MAX_FILE_BYTES,
+            aliases: HashMap::new(),
+            aliases_only: false,
+        }
+    }
+}
+
+/// The value of `{{env:name}}`, from `context.env` or else the process's environment.
+fn lookup_env(context: &TemplateContext, name: &str) -> Option<String> {
+    match &context.env {
+        Some(env) => env.get(name).cloned(),
+        None => env::var_os(name).map(|value| value.to_string_lossy().into_owned()),
+    }
+}
+
+/// Placeholder replaced by piped input instead of appending it.
+const STDIN_VAR: &str = "stdin";
+
+/// How deeply `{{alias:name}}` references may nest.
+pub const MAX_ALIAS_DEPTH: usize = 10;
+
+/// strftime formats of the built-in `{{date}}` and `{{time}}` placeholders.
+const BUILTIN_TIME_FORMATS: [(&str, &str); 2] = [("date", "%Y-%m-%d"), ("time", "%H:%M")];
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    /// `{{name}}` or `{{name:default}}`
+    Var(&'a str, Option<&'a str>),
+    /// `{{env:NAME}}`
+    Env(&'a str),
+    /// `{{datetime:FORMAT}}`
+    DateTime(&'a str),
+    /// `{{file:path}}`
+    File(&'a str),
+    /// `{{alias:name}}`
+    Alias(&'a str),
+    /// `$1`..`$9`, 1-based
+    Position(usize),
+    /// `$@`
+    AllArgs,
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Splits a template into literal text and placeholders, each with its source text. A
+/// backslash before `{{` or a positional placeholder makes it literal; brace runs that
+/// don't enclose a valid name are text, so `{{{name}}}` is `{`, `name`, `}`.
+fn tokenize(template: &str) -> Vec<(Token<'_>, &str)> {
+    let bytes = template.as_bytes();
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = i > 0 && bytes[i - 1] == b'\\';
+        let placeholder = match (bytes[i], bytes.get(i + 1)) {
+            (b'{', Some(b'{')) => {
+                let after = &template[i + 2..];
+                let var = after.find("}}").and_then(|end| {
+                    let (name, default) = match after[..end].split_once(':') {
+                        Some((name, default)) => (name.trim(), Some(default)),
+                        None => (after[..end].trim(), None),
+                    };
+                    let token = match default {
+                        Some(var) if name == "env" => Token::Env(var.trim()),
+                        Some(format) if name == "datetime" => Token::DateTime(format),
+                        Some(path) if name == "file" => Token::File(path.trim()),
+                        Some(alias) if name == "alias" => Token::Alias(alias.trim()),
+                        _ => Token::Var(name, default),
+                    };
+                    let valid = match token {
+                        Token::Env(var) | Token::Alias(var) => !var.is_empty(),
+                        Token::DateTime(format) | Token::File(format) => !format.is_empty(),
+                        _ => is_placeholder_name(name),
+                    };
+                    valid.then_some((token, end + 4))
+                });
+                match var {
+                    Some(var) => Some(var),
+                    // An escaped `{{` is literal even when it doesn't start a placeholder
+                    _ if escaped => Some((Token::Text("{{"), 2)),
+                    // Not a placeholder: keep one brace and rescan from the next one
+                    _ => None,
+                }
+            }
+            (b'$', Some(digit @ b'1'..=b'9')) => {
+                Some((Token::Position((digit - b'0') as usize), 2))
+            }
+            (b'$', Some(b'@')) => Some((Token::AllArgs, 2)),
+            _ => None,
+        };
+
+        let Some((token, len)) = placeholder else {
+            i += 1;
+            continue;
+        };
+
+        if escaped {
+            // Escaped: drop the backslash and keep the placeholder text as-is
+            let text = &template[text_start..i - 1];
+            tokens.push((Token::Text(text), text));
+            text_start = i;
+        } else {
+            let text = &template[text_start..i];
+            tokens.push((Token::Text(text), text));
+            tokens.push((token, &template[i..i + len]));
+            text_start = i + len;
+        }
+        i += len;
+    }
+    let text = &template[text_start..];
+    tokens.push((Token::Text(text), text));
+
+    tokens.retain(|(token, _)| *token != Token::Text(""));
+    tokens
+}
+
+/// A placeholder a template expects a value for.
+#[derive(Debug, PartialEq)]
+pub struct TemplateVar {
+    /// Variable name, or `$1`..`$9` / `$@` for positional placeholders
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// The placeholders in `template` in order of first use. A variable used several times is
+/// listed once, with the first default given for it.
+pub fn template_vars(template: &str) -> Vec<TemplateVar> {
+    let mut vars: Vec<TemplateVar> = Vec::new();
+    for (token, _) in tokenize(template) {
+        let (name, default) = match token {
+            Token::Text(_)
+            | Token::Env(_)
+            | Token::DateTime(_)
+            | Token::File(_)
+            | Token::Alias(_) => continue,
+            Token::Var(name, None) if is_builtin_time(name) || name == STDIN_VAR => continue,
+            Token::Var(name, default) => (name.to_string(), default.map(str::to_string)),
+            Token::Position(n) => (format!("${}", n), None),
+            Token::AllArgs => ("$@".to_string(), None),
+        };
+        match vars.iter_mut().find(|var| var.name == name) {
+            Some(var) if var.default.is_none() => var.default = default,
+            Some(_) => {}
+            None => vars.push(TemplateVar { name, default }),
+        }
+    }
+    vars
+}
+
+/// Whether `template` contains `$1`..`$9` or `$@`, so it accepts positional arguments.
+pub fn uses_positional_args(template: &str) -> bool {
+    tokenize(template)
+        .iter()
+        .any(|(token, _)| matches!(token, Token::Position(_) | Token::AllArgs))
+}
+
+fn is_builtin_time(name: &str) -> bool {
+    BUILTIN_TIME_FORMATS
+        .iter()
+        .any(|(builtin, _)| *builtin == name)
+}
+
+/// Formats `time` with a strftime string, or `None` if the string is invalid.
+fn format_time(time: &chrono::DateTime<chrono::Local>, format: &str) -> Option<String> {
+    let items: Vec<_> = chrono::format::StrftimeItems::new(format).collect();
+    if items.contains(&chrono::format::Item::Error) {
+        return None;
+    }
+    Some(time.format_with_items(items.into_iter()).to_string())
+}
+
+/// Expands a leading `~` to the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Reads a file for `{{file:path}}`, relative to the current directory.
+fn read_template_file(path: &str, max_bytes: u64) -> Result<String, String> {
+    let path = expand_tilde(path);
+    let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+    if size > max_bytes {
+        return Err(format!(
+            "{} bytes is more than the limit of {} bytes (max_file_bytes in config.toml)",
+            size, max_bytes
+        ));
+    }
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|_| "not valid UTF-8 text".to_string())
+}
+
+fn push_unique(names: &mut Vec<String>, name: String) {
+    if !names.contains(&name) {
+        names.push(name);
+    }
+}
+
+/// What a render collects across included aliases.
+struct RenderState {
+    now: chrono::DateTime<chrono::Local>,
+    missing_vars: Vec<String>,
+    missing_env: Vec<String>,
+    uses_stdin: bool,
+}
+
+/// Renders `template` into `rendered`. `stack` holds the aliases being expanded, outermost
+/// first, to detect cycles in `{{alias:name}}` references.
+fn render_into(
+    template: &str,
+    context: &TemplateContext,
+    stack: &mut Vec<String>,
+    state: &mut RenderState,
+    rendered: &mut String,
+) -> Result<(), RenderError> {
+    for (token, source) in tokenize(template) {
+        let value = match token {
+            Token::Text(text) => Some(text.to_string()),
+            Token::Alias(name) => {
+                if let Some(start) = stack.iter().position(|alias| alias == name) {
+                    let mut cycle = stack[start..].to_vec();
+                    cycle.push(name.to_string());
+                    return Err(RenderError::AliasCycle(cycle));
+                }
+                if stack.len() > MAX_ALIAS_DEPTH {
+                    let mut chain = stack.clone();
+                    chain.push(name.to_string());
+                    return Err(RenderError::AliasTooDeep(chain));
+                }
+                let included = context
+                    .aliases
+                    .get(name)
+                    .ok_or_else(|| RenderError::UnknownAlias(name.to_string()))?;
+
+                stack.push(name.to_string());
+                render_into(included, context, stack, state, rendered)?;
+                stack.pop();
+                continue;
+            }
+            _ if context.aliases_only => Some(source.to_string()),
+            Token::Var(name, default) => context
+                .vars
+                .get(name)
+                .cloned()
+                .or(if name == STDIN_VAR {
+                    context.stdin.clone()
+                } else {
+                    None
+                })
+                .or(default.map(str::to_string))
+                .or_else(|| {
+                    let (_, format) = BUILTIN_TIME_FORMATS.iter().find(|(n, _)| *n == name)?;
+                    format_time(&state.now, format)
+                }),
+            Token::DateTime(format) => match format_time(&state.now, format) {
+                Some(value) => Some(value),
+                None => return Err(RenderError::InvalidDateFormat(source.to_string())),
+            },
+            Token::File(path) => match read_template_file(path, context.max_file_bytes) {
+                Ok(contents) => Some(contents),
+                Err(reason) => {
+                    return Err(RenderError::File {
+                        placeholder: source.to_string(),
+                        reason,
+                    });
+                }
+            },
+            Token::Env(name) => match lookup_env(context, name) {
+                Some(value) => Some(value),
+                None if context.allow_missing_env => Some(String::new()),
+                None => {
+                    push_unique(&mut state.missing_env, name.to_string());
+                    continue;
+                }
+            },
+            Token::Position(n) => context.positional.get(n - 1).cloned(),
+            Token::AllArgs if context.positional.is_empty() => None,
+            Token::AllArgs => Some(context.positional.join(" ")),
+        };
+
+        state.uses_stdin |= matches!(token, Token::Var(name, _) if name == STDIN_VAR);
+        match (value, token) {
+            (Some(value), _) => rendered.push_str(&value),
+            (None, Token::Var(name, _)) => push_unique(&mut state.missing_vars, name.to_string()),
+            (None, Token::Position(n)) => push_unique(&mut state.missing_vars, format!("${}", n)),
+            (None, _) => push_unique(&mut state.missing_vars, "$@".to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn render_with_stack(
+    template: &str,
+    context: &TemplateContext,
+    mut stack: Vec<String>,
+) -> Result<String, RenderError> {
+    let mut state = RenderState {
+        now: context.now.unwrap_or_else(chrono::Local::now),
+        missing_vars: Vec::new(),
+        missing_env: Vec::new(),
+        uses_stdin: false,
+    };
+    let mut rendered = String::with_capacity(template.len());
+    render_into(template, context, &mut stack, &mut state, &mut rendered)?;
+
+    if !state.missing_vars.is_empty() {
+        return Err(RenderError::MissingVars(state.missing_vars));
+    }
+    if !state.missing_env.is_empty() {
+        return Err(RenderError::MissingEnv(state.missing_env));
+    }
+    if let Some(extra) = &context.extra
+        && !context.aliases_only
+    {
+        rendered.push_str("\n\n");
+        rendered.push_str(extra);
+    }
+    if let Some(stdin) = &context.stdin
+        && !state.uses_stdin
+        && !context.aliases_only
+    {
+        rendered.push_str("\n\n");
+        rendered.push_str(stdin);
+    }
+    Ok(rendered)
+}
+
+/// Renders a prompt template when a shortcut runs; nothing is substituted when saving.
+///
+/// - `{{name}}` comes from `context.vars`, falling back to the text after the first `:` in
+///   `{{name:default}}`.
+/// - `{{date}}` (YYYY-MM-DD) and `{{time}}` (HH:MM) are the local time unless given as
+///   variables, and `{{datetime:FORMAT}}` takes a strftime format.
+/// - `{{env:NAME}}` comes from the process environment.
+/// - `{{file:path}}` is the contents of a file, up to `context.max_file_bytes`.
+/// - `{{alias:name}}` is another alias' prompt, rendered the same way.
+/// - `{{stdin}}` is `context.stdin`; without that placeholder, the input is appended after
+///   a blank line.
+/// - `$1`..`$9` and `$@` (all arguments separated by spaces) come from
+///   `context.positional`.
+/// - `context.extra` is appended after a blank line, before any piped input that's
+///   appended too.
+///
+/// Substituted values are never expanded again. Values the template doesn't use are
+/// ignored.
+pub fn render_prompt(template: &str, context: &TemplateContext) -> Result<String, RenderError> {
+    render_with_stack(template, context, Vec::new())
+}
+
+/// Renders the prompt of alias `name` from `context.aliases` like `render_prompt`, so a
+/// cycle back to `name` itself is reported from the start.
+pub fn render_alias(name: &str, context: &TemplateContext) -> Result<String, RenderError> {
+    let template = context
+        .aliases
+        .get(name)
+        .ok_or_else(|| RenderError::UnknownAlias(name.to_string()))?;
+    render_with_stack(template, context, vec![name.to_string()])
+}
+
+/// Explains why a prompt of `size` bytes is refused, and what to do instead.
+fn prompt_too_large(size: u64, max_bytes: usize) -> String {
+    format!(
+        "The prompt is {}, over the limit of {} (max_prompt_bytes in config.toml). Keep large text in a file and have the prompt inline it with {{{{file:path}}}} when it runs, or raise the limit.",
+        format_size(size),
+        format_size(max_bytes as u64)
+    )
+}
+
+/// Refuses prompts over `max_bytes`, which would slow down every command that loads the
+/// shortcuts, completion included.
+pub fn check_prompt_size(prompt: &str, max_bytes: usize) -> Result<(), String> {
+    if prompt.len() > max_bytes {
+        return Err(prompt_too_large(prompt.len() as u64, max_bytes));
+    }
+    Ok(())
+}
+
+/// Reads a prompt from a file, trimmed the same way as a prompt from stdin. A file over
+/// `max_bytes` is refused before it's read.
+pub fn read_prompt_from_file(path: &Path, max_bytes: usize) -> Result<String, String> {
+    let read_error = |e: io::Error| format!("Could not read '{}': {}", path.display(), e);
+    let size = fs::metadata(path).map_err(read_error)?.len();
+    if size > max_bytes as u64 {
+        return Err(prompt_too_large(size, max_bytes));
+    }
+    let bytes = fs::read(path).map_err(read_error)?;
+    let text = String::from_utf8(bytes)
+        .map_err(|_| format!("'{}' is not valid UTF-8 text", path.display()))?;
+    Ok(text.trim().to_string())
+}
+
+/// Reads a text prompt from the system clipboard.
+#[cfg(feature = "clipboard")]
+pub fn read_prompt_from_clipboard() -> Result<String, String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| {
+        format!(
+            "No clipboard available ({}). Pipe the prompt via stdin instead.",
+            e
+        )
+    })?;
+
+    let text = match clipboard.get_text() {
+        Ok(text) => text,
+        Err(arboard::Error::ContentNotAvailable) => {
+            return Err("The clipboard does not contain text".to_string());
+        }
+        Err(e) => return Err(format!("Could not read the clipboard: {}", e)),
+    };
+
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        return Err("The clipboard is empty".to_string());
+    }
+    Ok(text)
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn read_prompt_from_clipboard() -> Result<String, String> {
+    Err("This build of qwk has no clipboard support (enable the 'clipboard' feature)".to_string())
+}
+
+/// Parses the multi-entry format used by `--set --batch`.
+///
+/// An entry starts with a line containing only `@name`; every following line up to
+/// the next entry is its prompt. Lines starting with `@@` are prompt text with the
+/// leading `@` removed. Errors reference 1-based line numbers.
+pub fn parse_batch_prompts(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries: Vec<(String, usize, Vec<&str>)> = Vec::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+
+        // A line starting with '@@' is prompt text beginning with a single '@'
+        let text = if line.starts_with("@@") {
+            &line[1..]
+        } else if let Some(name) = line.strip_prefix('@') {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(format!(
+                    "line {}: missing alias name after '@'",
+                    line_number
+                ));
+            }
+            if name.starts_with('-') || name.chars().any(char::is_whitespace) {
+                return Err(format!(
+                    "line {}: invalid alias name '{}'",
+                    line_number, name
+                ));
+            }
+            if let Some((_, first_line, _)) = entries.iter().find(|(n, _, _)| n == name) {
+                return Err(format!(
+                    "line {}: duplicate alias '{}' (first defined on line {})",
+                    line_number, name, first_line
+                ));
+            }
+            entries.push((name.to_string(), line_number, Vec::new()));
+            continue;
+        } else {
+            line
+        };
+
+        match entries.last_mut() {
+            Some((_, _, lines)) => lines.push(text),
+            None if text.trim().is_empty() => {}
+            None => {
+                return Err(format!(
+                    "line {}: prompt text before the first '@name' entry",
+                    line_number
+                ));
+            }
+        }
+    }
+
+    if entries.is_empty() {
+        return Err("no '@name' entries found".to_string());
+    }
+
+    entries
+        .into_iter()
+        .map(|(name, line_number, lines)| {
+            let prompt = lines.join("\n").trim().to_string();
+            if prompt.is_empty() {
+                Err(format!(
+                    "line {}: empty prompt for alias '{}'",
+                    line_number, name
+                ))
+            } else {
+                Ok((name, prompt))
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -80,6 +1275,50 @@ pub fn parse_agent_args(args: &[String]) -> Result<Vec<String>, String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_closest_matches() {
+        let aliases = ["review", "preview", "summ", "sum", "explain"];
+        assert_eq!(closest_matches("reivew", aliases), ["review"]);
+        assert_eq!(closest_matches("revew", aliases), ["review"]);
+        // Equally close ones are all given
+        assert_eq!(closest_matches("sun", aliases), ["sum"]);
+        assert_eq!(closest_matches("summm", aliases), ["summ"]);
+        assert_eq!(closest_matches("sux", aliases), ["sum"]);
+        assert_eq!(closest_matches("reviews", aliases), ["review"]);
+        assert_eq!(closest_matches("eview", aliases), ["review"]);
+        assert_eq!(closest_matches("xreview", aliases), ["preview", "review"]);
+        assert_eq!(closest_matches("b", ["c", "a", "bcd"]), ["a", "c"]);
+        // Too far off to be a typo
+        assert!(closest_matches("deploy", aliases).is_empty());
+    }
+
+    #[test]
+    fn test_confirm_with() {
+        let answer = |input: &str| confirm_reset(&mut io::Cursor::new(input), true).unwrap();
+        assert!(answer("y\n"));
+        assert!(answer(" YES \n"));
+        assert!(!answer("n\n"));
+        assert!(!answer("\n"));
+        // End of input is a no
+        assert!(!answer(""));
+
+        // Piped input is left unread
+        let mut input = io::Cursor::new("y\n");
+        assert!(matches!(
+            confirm_with("Run?", &mut input, false),
+            Err(ConfirmError::NotATerminal)
+        ));
+        assert_eq!(input.position(), 0);
+
+        let answer = |input: &str| {
+            confirm_with_default("Set up?", true, &mut io::Cursor::new(input), true).unwrap()
+        };
+        assert!(answer("\n"));
+        assert!(answer("y\n"));
+        assert!(!answer("no\n"));
+        assert!(!answer(""));
+    }
+
     #[test]
     fn test_get_current_datetime_format() {
         let datetime = get_current_datetime();
@@ -97,6 +1336,50 @@ mod tests {
         assert!(digits_only.chars().all(|c| c.is_ascii_digit()));
     }
 
+    #[test]
+    fn test_parse_datetime() {
+        let datetime = get_current_datetime();
+        let parsed = parse_datetime(&datetime).unwrap();
+        assert_eq!(parsed.format(DATETIME_FORMAT).to_string(), datetime);
+
+        assert_eq!(
+            parse_datetime("20240501_100200").unwrap().to_rfc3339(),
+            "2024-05-01T10:02:00+00:00"
+        );
+        assert!(parse_datetime("20241301_100200").is_none());
+        assert!(parse_datetime("2024-05-01").is_none());
+    }
+
+    #[test]
+    fn test_format_age() {
+        let now = parse_datetime("20240510_120000").unwrap();
+        let age = |datetime| format_age(parse_datetime(datetime).unwrap(), now);
+        assert_eq!(age("20240510_120000"), "just now");
+        assert_eq!(age("20240510_130000"), "just now");
+        assert_eq!(age("20240510_115900"), "1 minute ago");
+        assert_eq!(age("20240510_111500"), "45 minutes ago");
+        assert_eq!(age("20240510_090000"), "3 hours ago");
+        assert_eq!(age("20240509_120000"), "1 day ago");
+        assert_eq!(age("20240501_100200"), "9 days ago");
+    }
+
+    #[test]
+    fn test_format_duration_ms() {
+        assert_eq!(format_duration_ms(850), "850ms");
+        assert_eq!(format_duration_ms(12_340), "12.3s");
+        assert_eq!(format_duration_ms(125_000), "2m 05s");
+        assert_eq!(format_duration_ms(3_720_000), "1h 02m");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GiB");
+    }
+
     #[test]
     fn test_truncate_prompt() {
         // Test short prompt (no truncation)
@@ -113,6 +1396,8 @@ mod tests {
             truncate_prompt("This is a very long prompt that should be truncated", 20),
             "This is a very lo..."
         );
+        // Cut between characters, not inside one
+        assert_eq!(truncate_prompt("Résumé résumé résumé", 8), "Résu...");
 
         // Test prompt with newlines (should be cleaned)
         assert_eq!(
@@ -133,6 +1418,140 @@ mod tests {
         assert_eq!(truncate_prompt("Hello world", 5), "He...");
     }
 
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Summarize PR"), "summarize-pr");
+        assert_eq!(slugify("  code_review v2!  "), "code-review-v2");
+        assert_eq!(slugify("already-slugged"), "already-slugged");
+        assert_eq!(slugify("***"), "");
+    }
+
+    #[test]
+    fn test_mask_secret() {
+        assert_eq!(mask_secret("sk-ant-api03-abcdef"), "sk-a****");
+        assert_eq!(mask_secret("http://gpu-box:11434"), "http****");
+        assert_eq!(mask_secret("12345678"), "****");
+        assert_eq!(mask_secret(""), "****");
+        assert_eq!(mask_secret("ééééééééé"), "éééé****");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("claud", "claude"), 1);
+        assert_eq!(edit_distance("codxe", "codex"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_in_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+        for (name, mode) in [("claude", 0o755), ("clause", 0o644), ("codex", 0o755)] {
+            let path = bin.join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let path_var = env::join_paths([dir.path().join("missing"), bin.clone()]).unwrap();
+        let path_var = Some(path_var.as_os_str());
+
+        assert_eq!(find_in_path("claude", path_var), Some(bin.join("claude")));
+        // Not executable
+        assert_eq!(find_in_path("clause", path_var), None);
+        assert_eq!(find_in_path("claude", None), None);
+        let full_path = bin.join("codex").to_string_lossy().to_string();
+        assert_eq!(find_in_path(&full_path, None), Some(bin.join("codex")));
+
+        assert_eq!(
+            suggest_executable("claud", path_var).as_deref(),
+            Some("claude")
+        );
+        assert_eq!(suggest_executable("aider", path_var), None);
+        assert_eq!(suggest_executable("./claud", path_var), None);
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_agent_call_args() {
+        let prompt = "Say \"hi\" to {user}";
+        let (_, default_args) = parse_agent_command("claude --model opus");
+        assert_eq!(
+            agent_call_args(&default_args, &strings(&["-y"]), prompt),
+            strings(&["--model", "opus", "-y", prompt])
+        );
+
+        let (_, default_args) = parse_agent_command("llm -s \"{prompt}\" --system={prompt}");
+        assert_eq!(
+            agent_call_args(&default_args, &strings(&["-m", "gpt-4o"]), prompt),
+            vec![
+                "-s".to_string(),
+                prompt.to_string(),
+                format!("--system={}", prompt),
+                "-m".to_string(),
+                "gpt-4o".to_string(),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_agent_call() {
+        let prompt = "x\"; rm -rf ~ #";
+        let (program, args, env) = shell_agent_call("llm | glow -", &[], Some(prompt));
+        assert_eq!(program, "sh");
+        assert_eq!(args, strings(&["-c", "llm | glow - \"$0\"", prompt]));
+        assert!(env.is_empty());
+
+        let (_, args, _) =
+            shell_agent_call("llm {prompt} | glow -", &strings(&["-v"]), Some(prompt));
+        assert_eq!(
+            args,
+            strings(&["-c", "llm \"$0\" | glow - \"$@\"", prompt, "-v"])
+        );
+
+        let (_, args, _) = shell_agent_call("llm | glow -", &[], None);
+        assert_eq!(args, strings(&["-c", "llm | glow -", "qwk"]));
+    }
+
+    #[test]
+    fn test_join_agent_command() {
+        let args = strings(&[
+            "--append-system-prompt",
+            "be brief, don't ramble",
+            "{prompt}",
+            "$HOME",
+            "",
+        ]);
+        let joined = join_agent_command("my agent", &args).unwrap();
+        assert_eq!(parse_agent_command(&joined), ("my agent".to_string(), args));
+        assert_eq!(
+            join_agent_command("claude", &strings(&["--model", "opus"])).unwrap(),
+            "claude --model opus"
+        );
+        assert!(join_agent_command("claude", &strings(&["a\0b"])).is_err());
+    }
+
+    #[test]
+    fn test_remove_agent_args() {
+        let mut args = strings(&["--model", "opus", "--verbose", "--model", "opus"]);
+        assert!(remove_agent_args(&mut args, &strings(&["--model", "opus"])));
+        assert_eq!(args, ["--verbose", "--model", "opus"]);
+        assert!(!remove_agent_args(
+            &mut args,
+            &strings(&["--model", "sonnet"])
+        ));
+        assert!(!remove_agent_args(&mut args, &[]));
+        assert!(remove_agent_args(&mut args, &strings(&["--verbose"])));
+        assert_eq!(args, ["--model", "opus"]);
+    }
+
     #[test]
     fn test_parse_agent_command() {
         let test_cases = vec![
@@ -220,4 +1639,612 @@ mod tests {
             }
         }
     }
+
+    /// Yields `chunks` one at a time with a delay, like a slow pipe, and flips `cancel`
+    /// once `cancel_after` chunks have been read.
+    struct SlowPipe<'a> {
+        chunks: Vec<&'static [u8]>,
+        cancel_after: Option<usize>,
+        cancel: &'a AtomicBool,
+        reads: usize,
+    }
+
+    impl Read for SlowPipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(5));
+            if Some(self.reads) == self.cancel_after {
+                self.cancel.store(true, Ordering::SeqCst);
+            }
+            let Some(chunk) = self.chunks.get(self.reads) else {
+                return Ok(0);
+            };
+            self.reads += 1;
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_read_prompt_from_slow_pipe() {
+        let cancel = AtomicBool::new(false);
+        let pipe = SlowPipe {
+            chunks: vec![b"  Review ", b"this code\n"],
+            cancel_after: None,
+            cancel: &cancel,
+            reads: 0,
+        };
+        assert_eq!(
+            read_prompt_from(pipe, 1024, &cancel).unwrap(),
+            "Review this code"
+        );
+    }
+
+    #[test]
+    fn test_read_prompt_caps_size() {
+        let cancel = AtomicBool::new(false);
+        let pipe = SlowPipe {
+            chunks: vec![b"0123456789", b"0123456789"],
+            cancel_after: None,
+            cancel: &cancel,
+            reads: 0,
+        };
+        assert!(matches!(
+            read_prompt_from(pipe, 15, &cancel),
+            Err(PromptReadError::TooLarge { limit: 15 })
+        ));
+
+        // Exactly at the limit is fine
+        assert_eq!(
+            read_prompt_from(&b"0123456789"[..], 10, &cancel).unwrap(),
+            "0123456789"
+        );
+    }
+
+    #[test]
+    fn test_read_prompt_cancelled_mid_read() {
+        let cancel = AtomicBool::new(false);
+        let pipe = SlowPipe {
+            chunks: vec![b"first ", b"second ", b"third"],
+            cancel_after: Some(1),
+            cancel: &cancel,
+            reads: 0,
+        };
+        assert!(matches!(
+            read_prompt_from(pipe, 1024, &cancel),
+            Err(PromptReadError::Cancelled)
+        ));
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> TemplateContext {
+        TemplateContext {
+            vars: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn positional(args: &[&str]) -> TemplateContext {
+        TemplateContext {
+            positional: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        let template = "Summarize the following {{language}} code focusing on {{ aspect }}";
+        assert_eq!(
+            render_prompt(
+                template,
+                &vars(&[("language", "rust"), ("aspect", "error-handling")])
+            )
+            .unwrap(),
+            "Summarize the following rust code focusing on error-handling"
+        );
+
+        // Prompts without placeholders pass through untouched
+        assert_eq!(
+            render_prompt("plain { text }", &TemplateContext::default()).unwrap(),
+            "plain { text }"
+        );
+
+        // Unused values are ignored, missing ones are reported once each in order
+        assert_eq!(
+            render_prompt("{{a}} {{b}} {{a}}", &vars(&[("unused", "x")])),
+            Err(RenderError::MissingVars(vec![
+                "a".to_string(),
+                "b".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_render_prompt_braces() {
+        let values = vars(&[("name", "v")]);
+
+        assert_eq!(render_prompt("{{{name}}}", &values).unwrap(), "{v}");
+        assert_eq!(render_prompt("{{name}}}", &values).unwrap(), "v}");
+        assert_eq!(render_prompt(r"\{{name}}", &values).unwrap(), "{{name}}");
+        assert_eq!(
+            render_prompt(r"\{{name}} is {{name}}", &values).unwrap(),
+            "{{name}} is v"
+        );
+        // Not valid names, so left alone
+        assert_eq!(
+            render_prompt("{{}} {{two words}} {{unclosed", &values).unwrap(),
+            "{{}} {{two words}} {{unclosed"
+        );
+        // Values are inserted literally, never re-rendered
+        assert_eq!(
+            render_prompt("{{name}}", &vars(&[("name", "{{other}}")])).unwrap(),
+            "{{other}}"
+        );
+    }
+
+    #[test]
+    fn test_render_prompt_defaults() {
+        let template = "Review {{lang:rust}} code{{suffix:}} for {{focus: bugs: all kinds}}";
+        assert_eq!(
+            render_prompt(template, &TemplateContext::default()).unwrap(),
+            "Review rust code for  bugs: all kinds"
+        );
+        assert_eq!(
+            render_prompt(template, &vars(&[("lang", "go"), ("suffix", "!")])).unwrap(),
+            "Review go code! for  bugs: all kinds"
+        );
+        assert_eq!(
+            render_prompt("{{a:x}} {{b}}", &TemplateContext::default()),
+            Err(RenderError::MissingVars(vec!["b".to_string()]))
+        );
+        // An invalid name before the colon isn't a placeholder
+        assert_eq!(
+            render_prompt("{{not valid:x}}", &TemplateContext::default()).unwrap(),
+            "{{not valid:x}}"
+        );
+    }
+
+    #[test]
+    fn test_render_prompt_env() {
+        let env = |value: &str| {
+            Some(HashMap::from([(
+                "QWK_TEST_RENDER_FOCUS".to_string(),
+                value.to_string(),
+            )]))
+        };
+        let template = "Focus on {{env:QWK_TEST_RENDER_FOCUS}}{{ env: QWK_TEST_RENDER_UNSET }}";
+        let context = TemplateContext {
+            env: env("error handling"),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_prompt(template, &context),
+            Err(RenderError::MissingEnv(vec![
+                "QWK_TEST_RENDER_UNSET".to_string()
+            ]))
+        );
+
+        let mut context = TemplateContext {
+            allow_missing_env: true,
+            ..context
+        };
+        assert_eq!(
+            render_prompt(template, &context).unwrap(),
+            "Focus on error handling"
+        );
+
+        // Values from the environment are not expanded again, `{{env:}}` isn't a placeholder
+        context.env = env("{{x}} $1");
+        assert_eq!(
+            render_prompt("{{env:QWK_TEST_RENDER_FOCUS}} {{env:}}", &context).unwrap(),
+            "{{x}} $1 {{env:}}"
+        );
+    }
+
+    #[test]
+    fn test_render_prompt_dates() {
+        use chrono::TimeZone;
+
+        let context = TemplateContext {
+            now: Some(chrono::Local.with_ymd_and_hms(2024, 3, 7, 9, 5, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_prompt(
+                "Standup for {{date}} at {{time}} ({{datetime:%A %d/%m}})",
+                &context
+            )
+            .unwrap(),
+            "Standup for 2024-03-07 at 09:05 (Thursday 07/03)"
+        );
+
+        // Explicit values and defaults win over the built-in time
+        let mut context = context;
+        context
+            .vars
+            .insert("date".to_string(), "yesterday".to_string());
+        assert_eq!(
+            render_prompt("{{date}} {{time:soon}}", &context).unwrap(),
+            "yesterday soon"
+        );
+
+        assert_eq!(
+            render_prompt("{{datetime:%Q}}", &context),
+            Err(RenderError::InvalidDateFormat(
+                "{{datetime:%Q}}".to_string()
+            ))
+        );
+        assert_eq!(
+            render_prompt("{{datetime:}}", &context).unwrap(),
+            "{{datetime:}}"
+        );
+        assert!(template_vars("{{date}} {{time}} {{datetime:%Y}}").is_empty());
+    }
+
+    #[test]
+    fn test_render_prompt_stdin() {
+        let context = TemplateContext {
+            stdin: Some("diff --git a/x b/x".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_prompt("Review this change:", &context).unwrap(),
+            "Review this change:\n\ndiff --git a/x b/x"
+        );
+        assert_eq!(
+            render_prompt("Review\n{{stdin}}\ncarefully", &context).unwrap(),
+            "Review\ndiff --git a/x b/x\ncarefully"
+        );
+
+        // Text given after the shortcut name comes before it
+        let context = TemplateContext {
+            extra: Some("in the parser".to_string()),
+            ..context
+        };
+        assert_eq!(
+            render_prompt("Review this change:", &context).unwrap(),
+            "Review this change:\n\nin the parser\n\ndiff --git a/x b/x"
+        );
+
+        // Without piped input the placeholder needs a default or a value
+        let context = TemplateContext::default();
+        assert_eq!(render_prompt("Review", &context).unwrap(), "Review");
+        assert_eq!(
+            render_prompt("Review {{stdin}}", &context),
+            Err(RenderError::MissingVars(vec!["stdin".to_string()]))
+        );
+        assert_eq!(
+            render_prompt("Review {{stdin:the staged diff}}", &context).unwrap(),
+            "Review the staged diff"
+        );
+    }
+
+    #[test]
+    fn test_render_prompt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = dir.path().join("openapi.yaml");
+        fs::write(&spec, "openapi: 3.0.0\n").unwrap();
+        let binary = dir.path().join("blob.bin");
+        fs::write(&binary, [0xff, 0xfe]).unwrap();
+
+        let template = format!("Review this API spec:\n{{{{file:{}}}}}", spec.display());
+        assert_eq!(
+            render_prompt(&template, &TemplateContext::default()).unwrap(),
+            "Review this API spec:\nopenapi: 3.0.0\n"
+        );
+
+        let small = TemplateContext {
+            max_file_bytes: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            render_prompt(&template, &small),
+            Err(RenderError::File { reason, .. }) if reason.contains("limit of 4 bytes")
+        ));
+
+        let missing = format!("{{{{file:{}}}}}", dir.path().join("missing").display());
+        assert!(matches!(
+            render_prompt(&missing, &TemplateContext::default()),
+            Err(RenderError::File { placeholder, .. }) if placeholder == missing
+        ));
+
+        let binary = format!("{{{{file:{}}}}}", binary.display());
+        assert!(matches!(
+            render_prompt(&binary, &TemplateContext::default()),
+            Err(RenderError::File { reason, .. }) if reason == "not valid UTF-8 text"
+        ));
+
+        // Escaped placeholders are never read
+        assert_eq!(
+            render_prompt(r"\{{file:missing}}", &TemplateContext::default()).unwrap(),
+            "{{file:missing}}"
+        );
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_tilde("~/specs/a.yaml"), home.join("specs/a.yaml"));
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~other/a"), PathBuf::from("~other/a"));
+        assert_eq!(expand_tilde("specs/a.yaml"), PathBuf::from("specs/a.yaml"));
+    }
+
+    fn with_aliases(aliases: &[(&str, &str)]) -> TemplateContext {
+        TemplateContext {
+            aliases: aliases
+                .iter()
+                .map(|(name, prompt)| (name.to_string(), prompt.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_alias() {
+        let mut context = with_aliases(&[
+            ("persona", "You are a careful {{role:reviewer}}."),
+            ("review", "{{alias:persona}} Review {{alias: work/focus }}"),
+            ("work/focus", "$1"),
+        ]);
+        context.positional = vec!["main.rs".to_string()];
+        assert_eq!(
+            render_alias("review", &context).unwrap(),
+            "You are a careful reviewer. Review main.rs"
+        );
+        assert_eq!(
+            render_prompt("{{alias:persona}}", &context).unwrap(),
+            "You are a careful reviewer."
+        );
+
+        // Only alias references are expanded for --show --resolved
+        context.aliases_only = true;
+        context.positional.clear();
+        assert_eq!(
+            render_alias("review", &context).unwrap(),
+            "You are a careful {{role:reviewer}}. Review $1"
+        );
+
+        assert_eq!(
+            render_alias("x", &with_aliases(&[("x", "{{alias:missing}}")])),
+            Err(RenderError::UnknownAlias("missing".to_string()))
+        );
+        assert_eq!(
+            render_prompt(r"\{{alias:missing}}", &TemplateContext::default()).unwrap(),
+            "{{alias:missing}}"
+        );
+    }
+
+    #[test]
+    fn test_render_alias_cycles() {
+        let context = with_aliases(&[
+            ("a", "{{alias:b}}"),
+            ("b", "{{alias:c}}"),
+            ("c", "{{alias:a}}"),
+            ("self", "{{alias:self}}"),
+        ]);
+        assert_eq!(
+            render_alias("a", &context),
+            Err(RenderError::AliasCycle(
+                ["a", "b", "c", "a"].map(String::from).to_vec()
+            ))
+        );
+        assert_eq!(
+            render_alias("self", &context),
+            Err(RenderError::AliasCycle(
+                ["self", "self"].map(String::from).to_vec()
+            ))
+        );
+
+        // A chain without a cycle still stops at the depth limit
+        let chain: Vec<(String, String)> = (0..=MAX_ALIAS_DEPTH + 1)
+            .map(|i| (format!("n{}", i), format!("{{{{alias:n{}}}}}", i + 1)))
+            .collect();
+        let mut context = TemplateContext {
+            aliases: chain.into_iter().collect(),
+            ..Default::default()
+        };
+        context
+            .aliases
+            .insert(format!("n{}", MAX_ALIAS_DEPTH + 2), "end".to_string());
+        assert!(matches!(
+            render_alias("n0", &context),
+            Err(RenderError::AliasTooDeep(chain)) if chain.len() == MAX_ALIAS_DEPTH + 2
+        ));
+        assert_eq!(render_alias(&format!("n{}", 2), &context).unwrap(), "end");
+    }
+
+    #[test]
+    fn test_template_vars() {
+        assert_eq!(
+            template_vars("{{a}} $2 {{b:}} {{a:later}} $@ {{b:ignored}}"),
+            vec![
+                TemplateVar {
+                    name: "a".to_string(),
+                    default: Some("later".to_string())
+                },
+                TemplateVar {
+                    name: "$2".to_string(),
+                    default: None
+                },
+                TemplateVar {
+                    name: "b".to_string(),
+                    default: Some(String::new())
+                },
+                TemplateVar {
+                    name: "$@".to_string(),
+                    default: None
+                },
+            ]
+        );
+        assert!(template_vars(r"plain \{{a}}").is_empty());
+    }
+
+    #[test]
+    fn test_render_prompt_positional() {
+        assert_eq!(
+            render_prompt(
+                "Explain this error: $1",
+                &positional(&["cannot borrow `x` as \"mutable\""])
+            )
+            .unwrap(),
+            "Explain this error: cannot borrow `x` as \"mutable\""
+        );
+        assert_eq!(
+            render_prompt("$2 then $1, all: $@", &positional(&["a", "b c"])).unwrap(),
+            "b c then a, all: a b c"
+        );
+        // Arguments containing `$` or braces are inserted literally
+        assert_eq!(
+            render_prompt("cost $1", &positional(&["$2 {{x}}"])).unwrap(),
+            "cost $2 {{x}}"
+        );
+        // `$10` is `$1` followed by `0`; `$0`, `$x` and escaped forms aren't placeholders
+        assert_eq!(
+            render_prompt(r"$10 $0 $x \$1 \$@", &positional(&["a"])).unwrap(),
+            r"a0 $0 $x $1 $@"
+        );
+
+        assert_eq!(
+            render_prompt("$1 $3 $@", &positional(&["a"])),
+            Err(RenderError::MissingVars(vec!["$3".to_string()]))
+        );
+        assert_eq!(
+            render_prompt("$@", &TemplateContext::default()),
+            Err(RenderError::MissingVars(vec!["$@".to_string()]))
+        );
+
+        assert!(uses_positional_args("Explain $1"));
+        assert!(uses_positional_args("All of $@"));
+        assert!(!uses_positional_args(r"Costs $0 or \$1 and {{var}}"));
+    }
+
+    #[test]
+    fn test_read_prompt_interactive() {
+        let input = "Review this code.\n.\nignored after the terminator\n";
+
+        let mut output = Vec::new();
+        let prompt = read_prompt_interactive(input.as_bytes(), &mut output, true).unwrap();
+        assert_eq!(prompt, "Review this code.");
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Enter your prompt"));
+        assert!(output.contains("> "));
+
+        // Without a TTY, '.' lines are prompt content and nothing is echoed
+        let mut output = Vec::new();
+        let prompt = read_prompt_interactive(input.as_bytes(), &mut output, false).unwrap();
+        assert_eq!(prompt, "Review this code.\n.\nignored after the terminator");
+        assert!(output.is_empty());
+
+        // EOF also ends interactive entry
+        let prompt = read_prompt_interactive(&b"line one\nline two"[..], io::sink(), true).unwrap();
+        assert_eq!(prompt, "line one\nline two");
+    }
+
+    #[test]
+    fn test_read_prompt_from_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let prompt_file = temp_dir.path().join("prompt.md");
+        fs::write(&prompt_file, "# Review\n\nReview this code.\n\n").unwrap();
+        assert_eq!(
+            read_prompt_from_file(&prompt_file, 1024).unwrap(),
+            "# Review\n\nReview this code."
+        );
+
+        let missing = temp_dir.path().join("missing.md");
+        assert!(
+            read_prompt_from_file(&missing, 1024)
+                .unwrap_err()
+                .starts_with("Could not read")
+        );
+
+        let binary = temp_dir.path().join("binary.bin");
+        fs::write(&binary, [0xff, 0xfe, 0x00]).unwrap();
+        assert!(
+            read_prompt_from_file(&binary, 1024)
+                .unwrap_err()
+                .ends_with("is not valid UTF-8 text")
+        );
+
+        let limit = 2048;
+        let big = temp_dir.path().join("big.log");
+        fs::write(&big, "x".repeat(limit + 1)).unwrap();
+        let error = read_prompt_from_file(&big, limit).unwrap_err();
+        assert!(error.contains("2.0 KiB"), "{}", error);
+        assert!(error.contains("max_prompt_bytes"), "{}", error);
+    }
+
+    #[test]
+    fn test_check_prompt_size() {
+        let limit = 512 * 1024;
+        assert!(check_prompt_size(&"x".repeat(limit), limit).is_ok());
+        let error = check_prompt_size(&"x".repeat(limit + 1), limit).unwrap_err();
+        assert!(
+            error.starts_with("The prompt is 512.0 KiB, over the limit of 512.0 KiB"),
+            "{}",
+            error
+        );
+        assert!(error.contains("{{file:path}}"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_batch_prompts() {
+        let input =
+            "@review\nReview this code.\nBe thorough.\n\n@docs\n@@mention the team\nWrite docs.\n";
+        let entries = parse_batch_prompts(input).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "review".to_string(),
+                    "Review this code.\nBe thorough.".to_string()
+                ),
+                (
+                    "docs".to_string(),
+                    "@mention the team\nWrite docs.".to_string()
+                ),
+            ]
+        );
+
+        // Leading blank lines are ignored
+        assert_eq!(parse_batch_prompts("\n\n@a\nprompt").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_batch_prompts_errors() {
+        let test_cases = vec![
+            (
+                "@a\none\n@a\ntwo",
+                "line 3: duplicate alias 'a' (first defined on line 1)",
+            ),
+            ("@a\n\n@b\ntwo", "line 1: empty prompt for alias 'a'"),
+            (
+                "stray text\n@a\nprompt",
+                "line 1: prompt text before the first '@name' entry",
+            ),
+            (
+                "@@escaped\n@a\nprompt",
+                "line 1: prompt text before the first '@name' entry",
+            ),
+            (
+                "@a\nprompt\n@\nmore",
+                "line 3: missing alias name after '@'",
+            ),
+            (
+                "@two words\nprompt",
+                "line 1: invalid alias name 'two words'",
+            ),
+            ("", "no '@name' entries found"),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(
+                parse_batch_prompts(input),
+                Err(expected.to_string()),
+                "Unexpected result for input: {:?}",
+                input
+            );
+        }
+    }
 }
@@ -0,0 +1,471 @@
+//! How shortcuts start the agent: its command, arguments, directory, environment and
+//! output, and what qwk does when it fails, hangs or is missing.
+#![cfg(unix)]
+
+use crate::sandbox::Sandbox;
+use std::fs;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+// Runs shortcuts with `pwd` as the agent to see the directory `--cwd` starts it in.
+
+const PRINT_DIR: &str = "sh -c pwd";
+
+#[test]
+fn test_cwd() {
+    let sandbox = Sandbox::new();
+    let project = sandbox.dir.path().join("project");
+    fs::create_dir_all(&project).unwrap();
+    let project = project.canonicalize().unwrap();
+    let project_str = project.to_str().unwrap();
+    sandbox.qwk(&["--agent", PRINT_DIR]);
+
+    sandbox.qwk(&["--set", "review", "Review", "--cwd", project_str]);
+    assert_eq!(sandbox.qwk(&["review"]).trim(), project_str);
+
+    // A directory given for the run wins over the alias's own
+    let other = sandbox.config_dir.canonicalize().unwrap();
+    let other_str = other.to_str().unwrap();
+    assert_eq!(
+        sandbox.qwk(&["review", "--cwd", other_str]).trim(),
+        other_str
+    );
+
+    let output = sandbox.run(&["review", "--cwd", "/no/such/dir"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("/no/such/dir does not exist"), "{}", stderr);
+
+    // An empty path removes it
+    sandbox.qwk(&["--set", "review", "Review", "--cwd", ""]);
+    assert!(!sandbox.qwk(&["--list", "--long"]).contains("runs in"));
+}
+
+// Runs shortcuts with a shell as the agent to see the variables set with `--agent-env`.
+
+const PRINT_HOST: &str = r#"sh -c 'printf %s "$QWK_TEST_HOST"'"#;
+
+#[test]
+fn test_agent_env() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", PRINT_HOST]);
+    assert_eq!(sandbox.qwk(&["summ"]), "");
+
+    let output = sandbox.qwk(&["--agent-env", "set", "QWK_TEST_HOST=http://gpu-box:11434"]);
+    assert!(!output.contains("gpu-box"), "{}", output);
+    assert_eq!(sandbox.qwk(&["summ"]), "http://gpu-box:11434");
+
+    // Values are only printed masked
+    for args in [&["--agent-env", "list"][..], &["--agent"]] {
+        let output = sandbox.qwk(args);
+        assert!(output.contains("QWK_TEST_HOST=http****"), "{}", output);
+        assert!(!output.contains("gpu-box"), "{}", output);
+    }
+
+    sandbox.qwk(&["--agent-env", "unset", "QWK_TEST_HOST"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "");
+    assert!(
+        !sandbox
+            .run(&["--agent-env", "unset", "QWK_TEST_HOST"])
+            .status
+            .success()
+    );
+    assert!(
+        !sandbox
+            .run(&["--agent-env", "set", "NOVALUE"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_named_agent_env() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", PRINT_HOST]);
+    sandbox.qwk(&["--agent-add", "work", PRINT_HOST]);
+    sandbox.qwk(&["--agent-env", "set", "QWK_TEST_HOST=work", "--for", "work"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "");
+    assert_eq!(sandbox.qwk(&["summ", "--with", "work"]), "work");
+
+    // Once in use, the named agent is the one changed
+    sandbox.qwk(&["--agent-use", "work"]);
+    sandbox.qwk(&["--agent-env", "set", "QWK_TEST_HOST=other"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "other");
+    // Replacing its command keeps them
+    sandbox.qwk(&["--agent-add", "work", PRINT_HOST]);
+    assert_eq!(sandbox.qwk(&["summ"]), "other");
+
+    assert!(
+        !sandbox
+            .run(&["--agent-env", "list", "--for", "missing"])
+            .status
+            .success()
+    );
+}
+
+// With `exec_agent`, qwk becomes the agent instead of waiting on it, unless something has
+// to happen after the agent exits.
+
+/// The pid the agent reports ahead of its prompt, and that of the qwk process started.
+fn pids(sandbox: &Sandbox, args: &[&str]) -> (String, String) {
+    let child = sandbox
+        .command(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let qwk_pid = child.id().to_string();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let agent_pid = stdout.split_whitespace().next().unwrap_or_default();
+    (agent_pid.to_string(), qwk_pid)
+}
+
+#[test]
+fn test_exec_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "pid", "ignored"]);
+    sandbox.qwk(&["--agent", "--shell", "echo $$"]);
+
+    // Off by default
+    let (agent, qwk) = pids(&sandbox, &["pid"]);
+    assert_ne!(agent, qwk);
+
+    // The run log needs qwk to outlive the agent
+    sandbox.add_settings("exec_agent = true");
+    let (agent, qwk) = pids(&sandbox, &["pid"]);
+    assert_ne!(agent, qwk);
+
+    sandbox.add_settings("track_runs = false");
+    let (agent, qwk) = pids(&sandbox, &["pid"]);
+    assert_eq!(agent, qwk);
+
+    // As do a timeout and a chain
+    let (agent, qwk) = pids(&sandbox, &["pid", "--timeout", "60"]);
+    assert_ne!(agent, qwk);
+    let output = sandbox.qwk(&["--chain", "pid", "pid"]);
+    assert_eq!(output.lines().count(), 2, "{}", output);
+}
+
+// Runs shortcuts with agents that aren't installed to see `--agent --fallback` take over.
+
+const MISSING: &str = "qwk-test-missing-agent";
+
+#[test]
+fn test_fallback_when_not_found() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", MISSING, "--force"]);
+    sandbox.qwk(&[
+        "--agent",
+        "--fallback",
+        "qwk-test-missing-too",
+        "--fallback",
+        "echo",
+    ]);
+    assert!(
+        sandbox
+            .qwk(&["--agent"])
+            .contains("Fallbacks: qwk-test-missing-too, echo")
+    );
+
+    let output = sandbox.run(&["summ"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Summarize\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "'{}' not found in PATH; falling back to 'qwk-test-missing-too'",
+            MISSING
+        )),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("falling back to 'echo'"), "{}", stderr);
+
+    // With all of them missing, the last one's error is the one that counts
+    sandbox.qwk(&[
+        "--agent",
+        "--clear-fallbacks",
+        "--fallback",
+        "qwk-test-missing-too",
+    ]);
+    let output = sandbox.run(&["summ"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Error: agent 'qwk-test-missing-too'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_no_fallback_after_failure() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "sh -c 'exit 3'"]);
+    sandbox.qwk(&["--agent", "--fallback", "echo"]);
+
+    // The agent ran and failed, which is for it to report
+    let output = sandbox.run(&["summ"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+// Saves the agent's stdout to a file with `--output`, `--append` and `--tee`.
+
+#[test]
+fn test_output() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "sh -c 'echo \"$0\"; echo working >&2'"]);
+    let file = sandbox.dir.path().join("answer.md");
+    let path = file.to_str().unwrap();
+
+    let output = sandbox.run(&["summ", "--output", path]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    // Only stdout goes to the file
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "working\n");
+    assert_eq!(fs::read_to_string(&file).unwrap(), "Summarize\n");
+
+    sandbox.qwk(&["summ", "--output", path, "--append"]);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "Summarize\nSummarize\n");
+
+    assert_eq!(
+        sandbox.qwk(&["summ", "--output", path, "--tee"]),
+        "Summarize\n"
+    );
+    assert_eq!(fs::read_to_string(&file).unwrap(), "Summarize\n");
+}
+
+#[test]
+fn test_output_errors_before_running() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    let marker = sandbox.dir.path().join("ran");
+    let agent = format!("touch {}", marker.display());
+    sandbox.qwk(&["--agent", &agent]);
+
+    let missing = sandbox.dir.path().join("missing").join("answer.md");
+    let output = sandbox.run(&["summ", "--output", missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: could not open "));
+    assert!(!marker.exists());
+
+    let output = sandbox.run(&["summ", "--tee"]);
+    assert!(!output.status.success());
+    assert!(!marker.exists());
+}
+
+// Runs shortcuts with agents set with `--shell`, whose command is a shell script that gets
+// the prompt as a parameter.
+
+const HOSTILE_PROMPT: &str = "x\"; rm -rf ~; echo \"$(id)` `'";
+
+#[test]
+fn test_shell_agent_prompt_is_one_argument() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "hostile", HOSTILE_PROMPT]);
+    // The prompt reaches printf as one argument, whatever it holds
+    sandbox.qwk(&["--agent", "--shell", "printf '%s|' {prompt} | tr '|' '\\n'"]);
+    assert_eq!(sandbox.qwk(&["hostile"]), format!("{}\n", HOSTILE_PROMPT));
+
+    // Without a placeholder the prompt comes last, after the arguments given after --
+    sandbox.qwk(&["--agent", "--shell", "printf '<%s>'"]);
+    assert_eq!(
+        sandbox.qwk(&["hostile", "--", "a b", "c"]),
+        format!("<a b><c><{}>", HOSTILE_PROMPT)
+    );
+}
+
+#[test]
+fn test_shell_agent_pipeline() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize this"]);
+    sandbox.qwk(&["--agent", "--shell", "--stdin-prompt", "cat | tr a-z A-Z"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "SUMMARIZE THIS");
+
+    let shown = sandbox.qwk(&["--agent"]);
+    assert!(shown.contains("Runs as: a shell script"), "{}", shown);
+    // Its arguments aren't words to edit
+    assert!(
+        !sandbox
+            .run(&["--agent", "--add-arg", "-v"])
+            .status
+            .success()
+    );
+
+    // Without --shell, the same command is a program and its arguments
+    sandbox.qwk(&["--agent", "echo {prompt} | tr a-z A-Z", "--force"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "Summarize this | tr a-z A-Z\n");
+}
+
+#[test]
+fn test_named_shell_agent() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+    sandbox.qwk(&[
+        "--agent-add",
+        "loud",
+        "echo {prompt} | tr a-z A-Z",
+        "--shell",
+    ]);
+    assert_eq!(sandbox.qwk(&["summ", "--with", "loud"]), "SUMMARIZE\n");
+    assert!(sandbox.qwk(&["--agents"]).contains("argument (shell)"));
+}
+
+// An agent ended by a signal makes qwk exit with 128 plus the signal's number, as shells
+// report it, rather than with success.
+
+#[test]
+fn test_agent_killed_by_signal() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+
+    for (signal, code) in [("TERM", 143), ("INT", 130), ("KILL", 137)] {
+        let agent = format!("sh -c 'kill -{} $$'", signal);
+        sandbox.qwk(&["--agent", &agent]);
+        let output = sandbox.run(&["summ"]);
+        assert_eq!(output.status.code(), Some(code), "SIG{}", signal);
+        // Ending the agent is the user's doing, not an error of qwk's
+        assert!(output.stderr.is_empty(), "SIG{}", signal);
+    }
+}
+
+// Runs shortcuts with `sleep` as the agent to see `--timeout` stop it.
+
+#[test]
+fn test_timeout() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "sh -c 'exec sleep 10'"]);
+
+    let started = Instant::now();
+    let output = sandbox.run(&["summ", "--timeout", "1"]);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(124));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("stopped after running for 1s"),
+        "{}",
+        stderr
+    );
+
+    // From config.toml, unless the run turns it off
+    fs::write(
+        sandbox.config_dir.join("config.toml"),
+        "agent_timeout_secs = 1\n",
+    )
+    .unwrap();
+    assert_eq!(sandbox.run(&["summ"]).status.code(), Some(124));
+    sandbox.qwk(&["--agent", "sh -c 'exit 3'"]);
+    assert_eq!(
+        sandbox.run(&["summ", "--timeout", "0"]).status.code(),
+        Some(3)
+    );
+    assert_eq!(sandbox.run(&["summ"]).status.code(), Some(3));
+}
+
+// Runs shortcuts with `cat` as the agent to see the prompt arrive on its stdin, with
+// nothing added to its arguments.
+
+#[test]
+fn test_agent_stdin_prompt() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize\nthis"]);
+
+    sandbox.qwk(&["--agent", "cat", "--stdin-prompt"]);
+    assert_eq!(sandbox.qwk(&["summ"]), "Summarize\nthis");
+    // Per-call arguments still go to the agent
+    assert_eq!(
+        sandbox.qwk(&["summ", "--", "-n"]),
+        "     1\tSummarize\n     2\tthis"
+    );
+
+    // Without the option, cat is given the prompt as a file name to read
+    sandbox.qwk(&["--agent", "cat"]);
+    let output = sandbox.run(&["summ"]);
+    assert!(!output.status.success());
+    assert_eq!(sandbox.qwk(&["summ", "--stdin-prompt"]), "Summarize\nthis");
+}
+
+#[test]
+fn test_named_agent_stdin_prompt() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+    sandbox.qwk(&["--agent-add", "pipe", "cat", "--stdin-prompt"]);
+    assert_eq!(sandbox.qwk(&["summ", "--with", "pipe"]), "Summarize");
+    assert_eq!(sandbox.qwk(&["summ"]), "Summarize\n");
+
+    // The prompt can't go both to stdin and into a {prompt} placeholder
+    sandbox.qwk(&["--agent-add", "both", "echo {prompt}", "--stdin-prompt"]);
+    let output = sandbox.run(&["summ", "--with", "both"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("{prompt}"));
+}
+
+// Dangerous agent arguments are warned about, and with `forbid_dangerous_args` refused
+// when stored, but never when given for one run after `--`.
+
+#[test]
+fn test_dangerous_args() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    let output = sandbox.run(&["--agent", "echo --yolo"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'--yolo' matches dangerous pattern"),
+        "{}",
+        stderr
+    );
+
+    // Per-call args are warned about even when nothing is refused
+    sandbox.qwk(&["--agent", "echo"]);
+    let output = sandbox.run(&["summ", "--", "--yolo"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "--yolo Summarize\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'--yolo' matches dangerous pattern"),
+        "{}",
+        stderr
+    );
+
+    sandbox.add_settings("forbid_dangerous_args = true");
+
+    // Strict mode refuses a stored argument, naming the pattern
+    sandbox.qwk(&["--agent", "echo --yolo"]);
+    let output = sandbox.run(&["summ"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Refusing to run"), "{}", stderr);
+    assert!(stderr.contains("^--yolo$"), "{}", stderr);
+
+    // But the same argument after `--` is the escape hatch
+    sandbox.qwk(&["--agent", "echo"]);
+    let output = sandbox.run(&["summ", "--", "--yolo"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "--yolo Summarize\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: agent argument '--yolo'"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,93 @@
+//! Runs the `qwk` binary against an aliases file moved out of the config directory with
+//! `QWK_ALIASES_FILE`.
+
+use crate::sandbox::Sandbox;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Where the sandbox's aliases are kept instead of the config directory.
+fn aliases_file(sandbox: &Sandbox) -> PathBuf {
+    sandbox.dir.path().join("scripts").join("shortcuts.json")
+}
+
+/// Runs qwk with its aliases in `aliases_file`, which must succeed, and returns its stdout.
+fn qwk(sandbox: &Sandbox, args: &[&str]) -> String {
+    let output = sandbox
+        .command(args)
+        .env("QWK_ALIASES_FILE", aliases_file(sandbox))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "qwk {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn backups(sandbox: &Sandbox) -> Vec<PathBuf> {
+    let file = aliases_file(sandbox);
+    let mut backups: Vec<_> = fs::read_dir(file.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| file_name(path).starts_with("aliases_backup_"))
+        .collect();
+    backups.sort();
+    backups
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().to_string()
+}
+
+#[test]
+fn test_set_and_run_use_the_aliases_file() {
+    let sandbox = Sandbox::new();
+    qwk(&sandbox, &["--set", "summ", "Summarize this"]);
+    qwk(&sandbox, &["--agent", "echo"]);
+
+    assert!(aliases_file(&sandbox).exists());
+    assert!(!sandbox.config_dir.join("aliases.json").exists());
+    // The agent stays in the config directory
+    assert!(sandbox.config_dir.join("config.toml").exists());
+
+    assert!(qwk(&sandbox, &["--list"]).contains("summ - Summarize this"));
+    assert_eq!(qwk(&sandbox, &["summ"]), "Summarize this\n");
+}
+
+#[test]
+fn test_reset_backs_up_next_to_the_aliases_file() {
+    let sandbox = Sandbox::new();
+    qwk(&sandbox, &["--set", "summ", "Summarize this"]);
+    qwk(&sandbox, &["--reset", "--yes"]);
+
+    assert!(!aliases_file(&sandbox).exists());
+    let backups = backups(&sandbox);
+    assert_eq!(backups.len(), 1);
+    assert!(
+        fs::read_to_string(&backups[0])
+            .unwrap()
+            .contains("Summarize this")
+    );
+    assert!(qwk(&sandbox, &["--backups"]).contains(&file_name(&backups[0])));
+
+    qwk(&sandbox, &["--restore", "--yes"]);
+    assert!(qwk(&sandbox, &["--list"]).contains("summ"));
+}
+
+#[test]
+fn test_import_backs_up_next_to_the_aliases_file() {
+    let sandbox = Sandbox::new();
+    qwk(&sandbox, &["--set", "summ", "Summarize this"]);
+
+    let import = aliases_file(&sandbox).with_file_name("theirs.json");
+    fs::write(&import, r#"{"review": "Review this"}"#).unwrap();
+    qwk(&sandbox, &["--import", import.to_str().unwrap()]);
+
+    assert_eq!(backups(&sandbox).len(), 1);
+    let list = qwk(&sandbox, &["--list"]);
+    assert!(list.contains("summ") && list.contains("review"), "{}", list);
+}
@@ -0,0 +1,325 @@
+//! Shell completion: what `qwk --complete` offers where the cursor is, and setting it up
+//! and removing it again.
+
+use crate::sandbox::Sandbox;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_setup_completion_print() {
+    let sandbox = Sandbox::new();
+    let script = sandbox.qwk(&["--setup-completion", "--shell", "fish", "--print"]);
+    assert!(script.starts_with("function __qwk_complete"), "{}", script);
+
+    let output = sandbox.run(&["--setup-completion", "--shell", "tcsh", "--print"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("bash, zsh, fish, powershell"), "{}", stderr);
+}
+
+#[test]
+fn test_complete_many_aliases() {
+    let sandbox = Sandbox::new();
+    let mut aliases = String::from("{");
+    for i in 0..1000 {
+        let separator = if i == 0 { "" } else { "," };
+        write!(
+            aliases,
+            r#"{}"alias{:04}": {{"prompt": "Prompt {}"}}"#,
+            separator, i, i
+        )
+        .unwrap();
+    }
+    aliases.push('}');
+    let import = sandbox.dir.path().join("aliases.json");
+    fs::write(&import, aliases).unwrap();
+    sandbox.qwk(&["--import", import.to_str().unwrap()]);
+
+    // Completion runs on every TAB, so it has to stay quick however many aliases there are;
+    // the bound is loose enough for a debug build on a slow machine
+    let started = Instant::now();
+    let completions = sandbox.qwk(&["--complete", "--", "qwk", "--show", "alias"]);
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(completions.lines().count(), 1000);
+    assert_eq!(completions.lines().next(), Some("alias0000"));
+}
+
+#[test]
+fn test_special_characters() {
+    let sandbox = Sandbox::new();
+    let import = sandbox.dir.path().join("aliases.json");
+    fs::write(
+        &import,
+        r#"{"my alias": {"prompt": "A"}, "it's": {"prompt": "B"}, "glob*": {"prompt": "C"},
+            "-dash": {"prompt": "D"}, "$HOME": {"prompt": "E"}}"#,
+    )
+    .unwrap();
+    sandbox.qwk(&["--import", import.to_str().unwrap()]);
+
+    // Bash puts candidates on the command line as they are, so qwk quotes them, and reads
+    // the word being completed as typed
+    let complete =
+        |word: &str| sandbox.qwk(&["--complete", "--shell", "bash", "--", "qwk", "--show", word]);
+    assert_eq!(complete("'my"), "'my alias'\n");
+    assert_eq!(complete("my\\ a"), "'my alias'\n");
+    assert_eq!(complete("\"it"), "\"it's\"\n");
+    assert_eq!(complete("gl"), "'glob*'\n");
+    assert_eq!(complete("-"), "-dash\n");
+    assert_eq!(complete("'$"), "'$HOME'\n");
+    // The other shells quote candidates themselves
+    assert_eq!(
+        sandbox.qwk(&["--complete", "--", "qwk", "--show", "my"]),
+        "my alias\n"
+    );
+}
+
+// Runs the bash script itself, as bash would on TAB
+#[cfg(unix)]
+#[test]
+fn test_bash_script() {
+    let sandbox = Sandbox::new();
+    let import = sandbox.dir.path().join("aliases.json");
+    fs::write(
+        &import,
+        r#"{"my alias": {"prompt": "A"}, "my*": {"prompt": "B"}, "summ": {"prompt": "C"}}"#,
+    )
+    .unwrap();
+    sandbox.qwk(&["--import", import.to_str().unwrap()]);
+    let script = sandbox.dir.path().join("qwk.bash");
+    fs::write(
+        &script,
+        sandbox.qwk(&["--setup-completion", "--shell", "bash", "--print"]),
+    )
+    .unwrap();
+
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_qwk")).parent().unwrap();
+    let path = format!(
+        "{}:{}",
+        bin_dir.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    // A file named like a candidate, which an unquoted glob would expand to
+    fs::write(sandbox.dir.path().join("my-file"), "").unwrap();
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(r#"source "$1"; COMP_WORDS=(qwk --show my); COMP_CWORD=2; _qwk_complete; printf '%s\n' "${COMPREPLY[@]}""#)
+        .arg("bash")
+        .arg(&script)
+        .current_dir(sandbox.dir.path())
+        .env("PATH", path)
+        .env("QWK_CONFIG_DIR", &sandbox.config_dir)
+        .env("QWK_NO_LOCAL", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "'my alias'\n'my*'\n"
+    );
+}
+
+#[test]
+fn test_static_bash_script() {
+    let sandbox = Sandbox::new();
+    let script = sandbox.dir.path().join("qwk.bash");
+    fs::write(&script, sandbox.qwk(&["--completions", "bash"])).unwrap();
+    assert!(!sandbox.config_dir.join("completions").exists());
+
+    let complete = |words: &str| {
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                r#"source "$1"; COMP_WORDS=({}); COMP_CWORD=$((${{#COMP_WORDS[@]}} - 1)); _qwk qwk "${{COMP_WORDS[COMP_CWORD]}}" "${{COMP_WORDS[COMP_CWORD-1]}}"; printf '%s\n' "${{COMPREPLY[@]}}""#,
+                words
+            ))
+            .arg("bash")
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(complete("qwk --se"), "--set\n--setup-completion\n");
+    assert_eq!(
+        complete("qwk --set summ --fr"),
+        "--from-file\n--from-clipboard\n"
+    );
+    assert!(complete("qwk --import --format ''").starts_with("json\n"));
+}
+
+#[test]
+fn test_complete_by_position() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--set", "review", "Review the diff"]);
+
+    // The word being completed comes last, even when it's `--` itself
+    let first = sandbox.qwk(&["--complete", "--", "qwk", "--"]);
+    assert!(first.lines().any(|line| line == "--list"), "{}", first);
+    assert!(!first.lines().any(|line| line == "summ"), "{}", first);
+    let first = sandbox.qwk(&["--complete", "--", "qwk", ""]);
+    assert!(first.lines().any(|line| line == "summ"), "{}", first);
+    assert!(first.lines().any(|line| line == "--remove"), "{}", first);
+
+    assert_eq!(
+        sandbox.qwk(&["--complete", "--", "qwk", "--remove", ""]),
+        "review\nsumm\n"
+    );
+    assert_eq!(
+        sandbox.qwk(&["--complete", "--", "qwk", "--agent", "--preset", "cla"]),
+        "claude\n"
+    );
+    assert_eq!(sandbox.qwk(&["--complete", "--", "qwk", "summ", ""]), "");
+
+    // Fish shows the prompt next to each shortcut
+    assert_eq!(
+        sandbox.qwk(&["--complete", "--shell", "fish", "--", "qwk", "--show", "r"]),
+        "review\tReview the diff\n"
+    );
+    assert_eq!(
+        sandbox.qwk(&["--complete", "--shell", "zsh", "--", "qwk", "--show", "r"]),
+        "review:Review the diff\n"
+    );
+
+    // As passed by the completion scripts of older versions
+    assert_eq!(
+        sandbox.qwk(&["--complete", "s", "--previous", "--show"]),
+        "summ\n"
+    );
+}
+
+// Where the rc file is found comes from $HOME only on unix
+#[cfg(unix)]
+#[test]
+fn test_remove_completion() {
+    let sandbox = Sandbox::new();
+    let home = sandbox.dir.path().join("home");
+    fs::create_dir_all(&home).unwrap();
+    let bashrc = home.join(".bashrc");
+    fs::write(&bashrc, "export EDITOR=vim\n").unwrap();
+    let completion = |args: &[&str]| {
+        let output = sandbox
+            .command(args)
+            .env("HOME", &home)
+            .env("SHELL", "/bin/bash")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    completion(&["--setup-completion"]);
+    // Only a line sourcing the script goes into the rc file
+    let script = sandbox.config_dir.join("completions").join("qwk.bash");
+    let block = format!(
+        "# >>> qwk completion >>>\n# qwk-completion-version: 3\nsource {}\n# <<< qwk completion <<<\n",
+        script.display()
+    );
+    let installed = fs::read_to_string(&bashrc).unwrap();
+    assert_eq!(installed, format!("export EDITOR=vim\n\n{}", block));
+    assert!(
+        fs::read_to_string(&script)
+            .unwrap()
+            .contains("_qwk_complete")
+    );
+
+    let removed = completion(&["--remove-completion"]);
+    assert!(removed.contains("Removed autocompletion"), "{}", removed);
+    assert_eq!(fs::read_to_string(&bashrc).unwrap(), "export EDITOR=vim\n");
+    assert!(!script.exists());
+    assert_eq!(
+        fs::read_to_string(home.join(".bashrc.qwk.bak")).unwrap(),
+        installed
+    );
+
+    let again = completion(&["--remove-completion"]);
+    assert!(again.contains("Nothing to remove"), "{}", again);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_upgrade_completion() {
+    let sandbox = Sandbox::new();
+    let home = sandbox.dir.path().join("home");
+    fs::create_dir_all(&home).unwrap();
+    let bashrc = home.join(".bashrc");
+    let completion = |args: &[&str]| {
+        let output = sandbox
+            .command(args)
+            .env("HOME", &home)
+            .env("SHELL", "/bin/bash")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    // A whole script from an earlier qwk is replaced where it is
+    let legacy = "export EDITOR=vim\n# qwk autocompletion setup\n\n_qwk_complete() {\n    COMPREPLY=($(qwk --complete \"$cur\" --previous \"$prev\"))\n}\ncomplete -F _qwk_complete qwk\nexport PAGER=less\n";
+    fs::write(&bashrc, legacy).unwrap();
+    let upgraded = completion(&["--setup-completion"]);
+    assert!(upgraded.contains("Updated autocompletion"), "{}", upgraded);
+    let script = sandbox.config_dir.join("completions").join("qwk.bash");
+    assert_eq!(
+        fs::read_to_string(&bashrc).unwrap(),
+        format!(
+            "export EDITOR=vim\n# >>> qwk completion >>>\n# qwk-completion-version: 3\nsource {}\n# <<< qwk completion <<<\nexport PAGER=less\n",
+            script.display()
+        )
+    );
+    assert!(script.exists());
+
+    let again = completion(&["--setup-completion"]);
+    assert!(again.contains("already set up"), "{}", again);
+    // A script that has fallen behind is rewritten
+    fs::write(&script, "# old\n").unwrap();
+    let again = completion(&["--setup-completion"]);
+    assert!(
+        again.contains("Updated the autocompletion script"),
+        "{}",
+        again
+    );
+    assert!(
+        fs::read_to_string(&script)
+            .unwrap()
+            .contains("_qwk_complete")
+    );
+}
+
+// Agent programs are looked for on PATH, which only holds a fake `llm` here
+#[cfg(unix)]
+#[test]
+fn test_complete_agent_programs() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let sandbox = Sandbox::new();
+    let bin = sandbox.dir.path().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let llm = bin.join("llm");
+    fs::write(&llm, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&llm, fs::Permissions::from_mode(0o755)).unwrap();
+    sandbox.qwk(&["--agent-add", "fast", "llm -m fast"]);
+
+    let complete = |words: &[&str]| {
+        let output = sandbox
+            .command(&[&["--complete", "--", "qwk"], words].concat())
+            .env("PATH", &bin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let agent = complete(&["--agent", ""]);
+    assert!(agent.starts_with("llm\n--add-arg\n"), "{}", agent);
+    assert!(!agent.contains("claude"), "{}", agent);
+    assert_eq!(complete(&["--agent", "l"]), "llm\n");
+    assert_eq!(complete(&["--with", ""]), "fast\n");
+    assert_eq!(complete(&["--agent-use", "f"]), "fast\n");
+}
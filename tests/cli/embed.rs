@@ -0,0 +1,114 @@
+//! Running qwk in-process through `run_with_args`, and the exit codes and messages the
+//! binary gets from it.
+
+use std::fs;
+use std::process::ExitCode;
+
+use crate::sandbox::Sandbox;
+use qwak::{QwkError, run_with_args};
+
+fn args(args: &[&str]) -> Vec<String> {
+    ["qwk"]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+#[test]
+fn test_run_with_args_in_process() {
+    let sandbox = Sandbox::new();
+    // The only test in this binary that touches the environment
+    unsafe {
+        std::env::set_var("QWK_CONFIG_DIR", &sandbox.config_dir);
+        std::env::set_var("QWK_NO_LOCAL", "1");
+        std::env::remove_var("QWK_PROFILE");
+        std::env::remove_var("QWK_ALIASES_FILE");
+    }
+
+    let code = run_with_args(args(&["-q", "--set", "summ", "Summarize"])).unwrap();
+    assert_eq!(code, ExitCode::SUCCESS);
+    // `-q` was for that run only
+    assert!(!qwak::output::is_quiet());
+    let aliases = fs::read_to_string(sandbox.config_dir.join("aliases.json")).unwrap();
+    assert!(aliases.contains("Summarize"), "{}", aliases);
+
+    let error = run_with_args(args(&["--show", "summs"])).unwrap_err();
+    assert!(
+        matches!(&error, QwkError::AliasNotFound { name, suggestions } if name == "summs" && suggestions == &["summ"]),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.code(), 66);
+    assert_eq!(
+        error.to_string(),
+        "Shortcut 'summs' not found; did you mean 'summ'?"
+    );
+
+    run_with_args(args(&["-q", "--archive", "summ"])).unwrap();
+    run_with_args(args(&["-q", "--set", "summ", "Summarize again"])).unwrap();
+    let error = run_with_args(args(&["--unarchive", "summ"])).unwrap_err();
+    assert!(
+        matches!(&error, QwkError::AliasExists(name) if name == "summ"),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.code(), 73);
+
+    let error = run_with_args(args(&["--agent-use", "fast"])).unwrap_err();
+    assert!(
+        matches!(&error, QwkError::AgentNotFound(name) if name == "fast"),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.code(), 69);
+
+    let error = run_with_args(args(&["--no-such-flag"])).unwrap_err();
+    assert!(matches!(error, QwkError::Usage(_)), "{:?}", error);
+    assert_eq!(error.code(), 2);
+
+    // The agent runs as a child even where the binary would replace itself with it, or
+    // this test would end here
+    #[cfg(unix)]
+    {
+        sandbox.add_settings("exec_agent = true\ntrack_runs = false");
+        run_with_args(args(&["-q", "--agent", "sh -c 'exit 3'"])).unwrap();
+        let code = run_with_args(args(&["summ", "--no-stdin"])).unwrap();
+        assert_eq!(code, ExitCode::from(3));
+    }
+
+    // A file that can't be parsed is left alone, not taken for no shortcuts
+    let aliases_file = sandbox.config_dir.join("aliases.json");
+    fs::write(&aliases_file, "{\"summ\": ").unwrap();
+    let error = run_with_args(args(&["--show", "summ"])).unwrap_err();
+    assert!(
+        matches!(&error, QwkError::ConfigCorrupt { path, .. } if *path == aliases_file),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.code(), 78);
+    assert_eq!(fs::read_to_string(&aliases_file).unwrap(), "{\"summ\": ");
+}
+
+#[test]
+fn test_errors_from_the_binary() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.run(&["--show", "missing"]);
+    assert_eq!(output.status.code(), Some(66));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Shortcut 'missing' not found\n"
+    );
+
+    let output = sandbox.run(&["--chain", "missing"]);
+    assert_eq!(output.status.code(), Some(66));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Shortcut not found: missing; nothing was run\n"
+    );
+
+    let output = sandbox.run(&["--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: unexpected argument"));
+}
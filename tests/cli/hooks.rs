@@ -0,0 +1,73 @@
+//! Pre- and post-hooks run around the agent, from the alias or from config.toml.
+#![cfg(unix)]
+
+use crate::sandbox::Sandbox;
+use std::fs;
+
+#[test]
+fn test_hooks() {
+    let sandbox = Sandbox::new();
+    let dir = sandbox.dir.path();
+    sandbox.qwk(&["--agent", "echo"]);
+    let pre_hook = format!("sh -c 'echo \"$1\" > {}/pre' sh {{prompt}}", dir.display());
+    let post_hook = format!(
+        "sh -c 'echo \"$QWK_ALIAS $QWK_EXIT_CODE\" > {}/post; echo hook output'",
+        dir.display()
+    );
+    sandbox.qwk(&[
+        "--set",
+        "summ",
+        "Summarize",
+        "--pre-hook",
+        &pre_hook,
+        "--post-hook",
+        &post_hook,
+    ]);
+
+    let dry_run = sandbox.qwk(&["summ", "--dry-run"]);
+    assert!(
+        dry_run.contains(&format!("Pre-hook: {}\n", pre_hook)),
+        "{}",
+        dry_run
+    );
+    assert!(!dir.join("pre").exists());
+
+    // The hooks' output stays off stdout, and the prompt only goes where {prompt} is
+    let output = sandbox.run(&["summ"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Summarize\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "hook output\n");
+    assert_eq!(fs::read_to_string(dir.join("pre")).unwrap(), "Summarize\n");
+    assert_eq!(fs::read_to_string(dir.join("post")).unwrap(), "summ 0\n");
+
+    // A failing pre-hook stops the run, unless told otherwise
+    sandbox.qwk(&["--set", "summ", "Summarize", "--pre-hook", "sh -c 'exit 3'"]);
+    let output = sandbox.run(&["summ"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("so 'summ' was not run"));
+    sandbox.add_settings("ignore_hook_failure = true");
+    assert_eq!(sandbox.qwk(&["summ"]), "Summarize\n");
+
+    // Hooks in config.toml apply to shortcuts without their own
+    sandbox.add_settings(&format!("pre_hook = \"touch {}/global\"", dir.display()));
+    sandbox.qwk(&["--set", "review", "Review"]);
+    sandbox.qwk(&["review"]);
+    assert!(dir.join("global").exists());
+}
+
+#[test]
+fn test_refused_agent_runs_no_hook() {
+    let sandbox = Sandbox::new();
+    let dir = sandbox.dir.path();
+    sandbox.qwk(&["--agent", "echo --yolo"]);
+    let pre_hook = format!("touch {}/pre", dir.display());
+    sandbox.qwk(&["--set", "summ", "Summarize", "--pre-hook", &pre_hook]);
+    sandbox.add_settings("forbid_dangerous_args = true");
+
+    let output = sandbox.run(&["summ"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to run"));
+    assert!(!dir.join("pre").exists());
+    assert!(!sandbox.config_dir.join("usage.json").exists());
+}
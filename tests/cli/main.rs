@@ -0,0 +1,12 @@
+//! Runs the `qwk` binary, and qwk in-process, each test with a config directory of its own.
+
+mod sandbox;
+
+mod agents;
+mod aliases_file;
+mod completion;
+mod embed;
+mod hooks;
+mod notify;
+mod setup;
+mod shortcuts;
@@ -0,0 +1,61 @@
+//! `--notify` sends a desktop notification once a long enough run ends, through
+//! `notify-send`, here a stand-in that records what it was given.
+#![cfg(all(unix, not(target_os = "macos")))]
+
+use crate::sandbox::Sandbox;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+fn run_with_path(sandbox: &Sandbox, bin: &Path, args: &[&str]) -> Output {
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    sandbox
+        .command(args)
+        .env("PATH", path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_notify() {
+    let sandbox = Sandbox::new();
+    let bin = sandbox.dir.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let sent = sandbox.dir.path().join("sent");
+    let notify_send = bin.join("notify-send");
+    fs::write(
+        &notify_send,
+        format!("#!/bin/sh\necho \"$@\" >> {}\n", sent.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&notify_send, fs::Permissions::from_mode(0o755)).unwrap();
+    sandbox.qwk(&["--agent", "echo"]);
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+
+    // Quicker than notify_after_secs, and without --notify
+    assert!(
+        run_with_path(&sandbox, &bin, &["summ", "--notify"])
+            .status
+            .success()
+    );
+    sandbox.add_settings("notify_after_secs = 0");
+    assert!(run_with_path(&sandbox, &bin, &["summ"]).status.success());
+    assert!(!sent.exists());
+
+    let output = run_with_path(&sandbox, &bin, &["summ", "--notify"]);
+    assert_eq!(output.stdout, b"Summarize\n");
+    let sent_args = fs::read_to_string(&sent).unwrap();
+    assert!(
+        sent_args.starts_with("--app-name=qwk -- qwk: summ finished 'summ' ran for "),
+        "{}",
+        sent_args
+    );
+
+    // A notifier that fails only gets a warning
+    fs::write(&notify_send, "#!/bin/sh\nexit 1\n").unwrap();
+    let output = run_with_path(&sandbox, &bin, &["summ", "--notify"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not send a notification"));
+}
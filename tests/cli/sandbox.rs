@@ -0,0 +1,79 @@
+//! A config directory of its own for running the `qwk` binary in tests.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+pub struct Sandbox {
+    pub dir: TempDir,
+    pub config_dir: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let config_dir = dir.path().join("config");
+        fs::create_dir_all(&config_dir).unwrap();
+        // Skip the first-run completion setup, which edits shell rc files
+        fs::write(config_dir.join(".first_run_complete"), "").unwrap();
+        Sandbox { dir, config_dir }
+    }
+
+    /// Adds `settings`, lines of TOML, to the end of config.toml.
+    pub fn add_settings(&self, settings: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.config_dir.join("config.toml"))
+            .unwrap();
+        writeln!(file, "{}", settings).unwrap();
+    }
+
+    /// A qwk command using the sandbox's config directory, to be run by the caller.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_qwk"));
+        command
+            .args(args)
+            .env("QWK_CONFIG_DIR", &self.config_dir)
+            .env("QWK_NO_LOCAL", "1")
+            .env_remove("QWK_PROFILE")
+            .env_remove("QWK_PASSPHRASE")
+            .env_remove("QWK_ALIASES_FILE")
+            .env_remove("QWK_VERBOSE")
+            .env_remove("QWK_AUTO_SETUP");
+        command
+    }
+
+    /// Runs qwk with nothing on its stdin, whether or not it succeeds.
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).stdin(Stdio::null()).output().unwrap()
+    }
+
+    /// Runs qwk with `input` piped to its stdin, whether or not it succeeds.
+    pub fn run_with_stdin(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // qwk may exit without reading it all, which isn't for this to fail on
+        let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs qwk, which must succeed, and returns its stdout.
+    pub fn qwk(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "qwk {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
@@ -0,0 +1,110 @@
+//! Setting qwk up and starting over: the first run, `--set --batch` and `--reset`.
+#![cfg(unix)]
+
+use crate::sandbox::Sandbox;
+use std::fs;
+use std::process::Stdio;
+
+// The first-run welcome and completion setup only happen at a terminal, so scripts, CI and
+// cron jobs neither see them nor get their rc files edited, unless `QWK_AUTO_SETUP` asks
+// for it.
+
+#[test]
+fn test_no_first_run_without_a_terminal() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+    let marker = sandbox.config_dir.join(".first_run_complete");
+    fs::remove_file(&marker).unwrap();
+
+    // A home of the sandbox's own, in case the rc file were edited after all
+    let output = sandbox
+        .command(&["summ"])
+        .env("HOME", sandbox.dir.path())
+        .env("SHELL", "/bin/bash")
+        .env_remove("CI")
+        .env_remove("QWK_NO_FIRST_RUN")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Summarize\n");
+    assert!(output.stderr.is_empty(), "{:?}", output);
+    assert!(!sandbox.dir.path().join(".bashrc").exists());
+    // Left for a run at a terminal
+    assert!(!marker.exists());
+}
+
+#[test]
+fn test_auto_setup() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+    let marker = sandbox.config_dir.join(".first_run_complete");
+    fs::remove_file(&marker).unwrap();
+
+    let output = sandbox
+        .command(&["summ"])
+        .env("HOME", sandbox.dir.path())
+        .env("SHELL", "/bin/bash")
+        .env("QWK_AUTO_SETUP", "1")
+        .env("CI", "true")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"Summarize\n");
+    let bashrc = fs::read_to_string(sandbox.dir.path().join(".bash_profile")).unwrap();
+    assert!(bashrc.contains("# >>> qwk completion >>>"), "{}", bashrc);
+    assert!(marker.exists());
+}
+
+// `--set --batch` reads its entries from stdin as they are.
+
+#[test]
+fn test_batch_line_numbers() {
+    let sandbox = Sandbox::new();
+
+    // Leading blank lines count, so the line numbers are those of the input
+    let output = sandbox.run_with_stdin(
+        &["--set", "--batch"],
+        "\n\n@summ\nSummarize\n@summ\nAgain\n",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 5: duplicate alias 'summ' (first defined on line 3)"),
+        "{}",
+        stderr
+    );
+
+    let output = sandbox.run_with_stdin(
+        &["--set", "--batch"],
+        "\n\n@summ\nSummarize\n\n@review\nReview\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(sandbox.qwk(&["--show", "summ"]), "Summarize\n");
+    assert_eq!(sandbox.qwk(&["--show", "review"]), "Review\n");
+}
+
+// `--reset` only takes its answer from a terminal; scripts pass `--yes`.
+
+#[test]
+fn test_reset_needs_a_terminal_or_yes() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+
+    // Neither nothing nor a piped "y" counts as an answer
+    for output in [
+        sandbox.run(&["--reset"]),
+        sandbox.run_with_stdin(&["--reset"], "y\n"),
+    ] {
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Pass --yes to reset anyway"), "{}", stderr);
+        assert_eq!(sandbox.qwk(&["--count"]), "1\n");
+    }
+
+    sandbox.qwk(&["--reset", "--yes"]);
+    assert_eq!(sandbox.qwk(&["--count"]), "0\n");
+}
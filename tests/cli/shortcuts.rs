@@ -0,0 +1,560 @@
+//! Running shortcuts: their prompts, the ways of picking and previewing them, and what qwk
+//! reports about a run.
+#![cfg(unix)]
+
+use crate::sandbox::Sandbox;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Runs several shortcuts in turn with `--chain`.
+
+/// Prints its last argument, the prompt, and fails when that's "fail".
+const CHAIN_AGENT: &str = r#"sh -c 'for last; do :; done; echo "$*"; test "$last" != fail' sh"#;
+
+fn chain_sandbox() -> Sandbox {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "changelog", "changelog"]);
+    sandbox.qwk(&["--set", "review", "fail"]);
+    sandbox.qwk(&["--set", "announce", "announce"]);
+    sandbox.qwk(&["--agent", CHAIN_AGENT]);
+    sandbox
+}
+
+#[test]
+fn test_chain() {
+    let sandbox = chain_sandbox();
+    let output = sandbox.run(&["--chain", "changelog", "announce", "--", "-v"]);
+    assert!(output.status.success());
+    // Every shortcut gets the arguments after --
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "-v changelog\n-v announce\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("[1/2] changelog\n[2/2] announce\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.ends_with("changelog  0\nannounce   0\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_chain_stops_at_failure() {
+    let sandbox = chain_sandbox();
+    let output = sandbox.run(&["--chain", "changelog", "review", "announce"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "changelog\nfail\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.ends_with("review     1\nannounce   skipped\n"),
+        "{}",
+        stderr
+    );
+
+    let output = sandbox.run(&["--chain", "changelog", "review", "announce", "--keep-going"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .ends_with("announce\n")
+    );
+}
+
+#[test]
+fn test_chain_goes_on_after_a_shortcut_that_cannot_run() {
+    let sandbox = chain_sandbox();
+    sandbox.qwk(&["--set", "needs", "Needs {{x}}"]);
+    let output = sandbox.run(&["--chain", "needs", "changelog", "--keep-going"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "changelog\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("missing template variables: x"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.ends_with("needs      1\nchangelog  0\n"),
+        "{}",
+        stderr
+    );
+
+    // Without --keep-going it stops there, but still shows what ran
+    let output = sandbox.run(&["--chain", "needs", "changelog"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.ends_with("needs      1\nchangelog  skipped\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_chain_checks_names_first() {
+    let sandbox = chain_sandbox();
+    let output = sandbox.run(&["--chain", "changelog", "review", "anounce"]);
+    assert_eq!(output.status.code(), Some(66));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Shortcut not found: anounce; nothing was run\n"
+    );
+    assert!(sandbox.qwk(&["--runs"]).starts_with("No runs recorded."));
+}
+
+// Shortcuts set with `--confirm` ask before running, and refuse to without a terminal to
+// ask on unless given `--yes`.
+
+#[test]
+fn test_confirm_run() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "deploy", "Deploy it", "--confirm"]);
+    sandbox.qwk(&["--agent", "echo"]);
+    assert!(
+        sandbox
+            .qwk(&["--list", "--long"])
+            .contains("asks before running")
+    );
+
+    // The tests' stdin isn't a terminal
+    let output = sandbox.run(&["deploy"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Pass --yes to run it anyway"));
+
+    assert_eq!(sandbox.qwk(&["deploy", "--yes"]), "Deploy it\n");
+    // A dry run runs nothing, so it doesn't ask
+    sandbox.qwk(&["deploy", "--dry-run"]);
+
+    // Setting the prompt again keeps the mark, until it's taken off
+    sandbox.qwk(&["--set", "deploy", "Deploy it now"]);
+    assert!(!sandbox.run(&["deploy"]).status.success());
+    sandbox.qwk(&["--set", "deploy", "Deploy it now", "--confirm=false"]);
+    assert_eq!(sandbox.qwk(&["deploy"]), "Deploy it now\n");
+}
+
+// A name that's a likely typo of a shortcut gets the shortcut suggested.
+
+fn stderr(sandbox: &Sandbox, args: &[&str]) -> String {
+    String::from_utf8(sandbox.run(args).stderr).unwrap()
+}
+
+#[test]
+fn test_did_you_mean() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--agent", "echo"]);
+    for alias in ["review", "preview", "summ"] {
+        sandbox.qwk(&["--set", alias, "Prompt"]);
+    }
+
+    // Without a terminal there's no offer to run it instead
+    assert_eq!(sandbox.run(&["reivew"]).status.code(), Some(66));
+    assert_eq!(
+        stderr(&sandbox, &["reivew"]),
+        "Shortcut 'reivew' not found; did you mean 'review'?\n"
+    );
+    assert_eq!(
+        stderr(&sandbox, &["xreview"]),
+        "Shortcut 'xreview' not found; did you mean 'preview' or 'review'?\n"
+    );
+    assert_eq!(
+        stderr(&sandbox, &["deploy"]),
+        "Shortcut 'deploy' not found\n"
+    );
+
+    assert!(stderr(&sandbox, &["--show", "sum"]).ends_with("did you mean 'summ'?\n"));
+    assert!(stderr(&sandbox, &["--remove", "sum"]).ends_with("did you mean 'summ'?\n"));
+    assert_eq!(sandbox.qwk(&["--count"]), "3\n");
+}
+
+// Prints the agent command of shortcuts with `--dry-run`, without running anything.
+
+#[test]
+fn test_dry_run() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize\nthis"]);
+    sandbox.qwk(&[
+        "--agent",
+        "qwk-test-missing-agent --model 'opus 4'",
+        "--force",
+    ]);
+    sandbox.qwk(&["--agent-env", "set", "TOKEN=sk-abcdefghijk"]);
+
+    let output = sandbox.qwk(&["summ", "--dry-run", "--cwd", "/", "--", "-v"]);
+    let expected = [
+        "Program: \"qwk-test-missing-agent\"",
+        "Arguments:",
+        "  \"--model\"",
+        "  \"opus 4\"",
+        "  \"-v\"",
+        "  \"Summarize\\nthis\"",
+        "Command: qwk-test-missing-agent --model 'opus 4' -v 'Summarize\nthis'",
+        "Directory: /",
+        "Environment: TOKEN=sk-a****",
+    ];
+    assert!(
+        output.ends_with(&(expected.join("\n") + "\n")),
+        "{}",
+        output
+    );
+    assert!(output.starts_with("Not found in PATH: qwk-test-missing-agent\n"));
+
+    // Nothing ran, so nothing was counted
+    assert!(sandbox.qwk(&["--stats"]).contains("summ   0     never"));
+}
+
+// Arguments after the name of a shortcut without `$1`..`$9` or `$@` extend its prompt.
+
+#[test]
+fn test_extra_text() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "explain", "Explain this Rust error."]);
+    sandbox.qwk(&["--set", "fix", "Fix $1"]);
+    sandbox.qwk(&["--agent", "printf '<%s>'"]);
+
+    assert_eq!(
+        sandbox.qwk(&[
+            "explain",
+            "why does rustc say",
+            "E0502 here",
+            "--",
+            "-m",
+            "opus"
+        ]),
+        "<-m><opus><Explain this Rust error.\n\nwhy does rustc say E0502 here>"
+    );
+    // Placeholders in the added text are left alone
+    assert_eq!(
+        sandbox.qwk(&["explain", "{{date}}"]),
+        "<Explain this Rust error.\n\n{{date}}>"
+    );
+    // Shortcuts with positional placeholders still take them as arguments
+    assert_eq!(sandbox.qwk(&["fix", "the parser"]), "<Fix the parser>");
+
+    // Options are still refused, in case they were meant for the agent
+    let output = sandbox.run(&["explain", "why", "--model", "opus"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Use 'qwk explain -- --model'"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+// Runs the last shortcut again with `--last`.
+
+#[test]
+fn test_last() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--set", "review", "Review"]);
+    sandbox.qwk(&["--agent", "echo"]);
+
+    let output = sandbox.run(&["--last"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No runs recorded yet"));
+
+    sandbox.qwk(&["review"]);
+    assert_eq!(sandbox.qwk(&["summ", "--", "-n", "a b"]), "a b Summarize");
+
+    let output = sandbox.run(&["--last"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "a b Summarize");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Running: qwk summ -- -n 'a b'\n"
+    );
+
+    let dry_run = sandbox.qwk(&["--last", "--dry-run"]);
+    assert!(
+        dry_run.contains("Command: echo -n 'a b' Summarize"),
+        "{}",
+        dry_run
+    );
+    // Running it again made it the last run, and the dry run didn't
+    assert_eq!(sandbox.qwk(&["--runs"]).lines().count(), 4);
+}
+
+// Piped input is added to a shortcut's prompt, however long it takes to come, unless
+// `--no-stdin` leaves the pipe alone.
+
+#[test]
+fn test_piped_stdin() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--set", "wrap", "Summarize {{stdin}} please"]);
+    sandbox.qwk(&["--agent", "echo"]);
+
+    let output = sandbox.run_with_stdin(&["summ"], "notes\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Summarize\n\nnotes\n"
+    );
+    let output = sandbox.run_with_stdin(&["wrap"], "notes");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Summarize notes please\n"
+    );
+
+    // /dev/null is neither a file nor a pipe
+    assert_eq!(sandbox.qwk(&["summ"]), "Summarize\n");
+}
+
+#[test]
+fn test_slow_pipe_is_waited_for() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+
+    let mut child = sandbox
+        .command(&["summ"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // Like a `cargo test` or `curl` that takes a while before its first line
+    thread::sleep(Duration::from_millis(500));
+    stdin.write_all(b"slow notes\n").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Summarize\n\nslow notes\n"
+    );
+}
+
+#[test]
+fn test_no_stdin_leaves_an_idle_pipe_alone() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "echo"]);
+
+    let mut child = sandbox
+        .command(&["summ", "--no-stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Held open until the end of the test without anything written to it
+    let _stdin = child.stdin.take().unwrap();
+
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("qwk waited on a pipe despite --no-stdin");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    assert_eq!(stdout, "Summarize\n");
+}
+
+// `--preview` prints the rendered prompt and the agent command; without a terminal it
+// stops there, so the agent never runs.
+
+#[test]
+fn test_preview_renders_only() {
+    let sandbox = Sandbox::new();
+    let marker = sandbox.dir.path().join("ran");
+    sandbox.qwk(&["--agent", "--shell", &format!("touch {}", marker.display())]);
+    sandbox.qwk(&["--set", "greet", "Hello {{name}}", "--confirm"]);
+
+    let output = sandbox.qwk(&["greet", "--preview", "--var", "name=world"]);
+    assert!(
+        output.starts_with("Hello world\n---\nCommand: "),
+        "{}",
+        output
+    );
+    assert!(!marker.exists());
+
+    // Piped input is part of the prompt, and a shortcut set with --confirm doesn't refuse
+    let output = sandbox.run_with_stdin(&["greet", "--preview", "--var", "name=you"], "a diff");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Hello you\n\na diff\n---\n"));
+    assert!(!marker.exists());
+}
+
+// Status lines go to stderr, so stdout only has what a command was asked for, and
+// `-q/--quiet` drops them.
+
+fn streams(output: std::process::Output) -> (String, String) {
+    assert!(output.status.success(), "{:?}", output);
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_status_goes_to_stderr() {
+    let sandbox = Sandbox::new();
+    let (stdout, stderr) = streams(sandbox.run(&["--set", "summ", "Summarize"]));
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "Alias 'summ' set successfully\n");
+
+    // Before the command, as on the fast path, or after it
+    for args in [
+        &["-q", "--set", "summ", "Summarize this"][..],
+        &["--set", "summ", "Summarize this", "--quiet"],
+        &["--agent", "echo", "-q"],
+    ] {
+        assert_eq!(streams(sandbox.run(args)), (String::new(), String::new()));
+    }
+
+    // Data still goes to stdout
+    let (stdout, _) = streams(sandbox.run(&["-q", "--show", "summ"]));
+    assert_eq!(stdout, "Summarize this\n");
+    assert_eq!(sandbox.qwk(&["-q", "summ"]), "Summarize this\n");
+}
+
+// `qwk run <shortcut>` runs a shortcut whatever its name, like the bare `qwk <shortcut>`.
+
+#[test]
+fn test_run_command() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--agent", "echo"]);
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    // Named like the command itself, so only the spelled-out form reaches it
+    sandbox.qwk(&["--set", "run", "Run it"]);
+
+    assert_eq!(sandbox.qwk(&["run", "summ"]), "Summarize\n");
+    assert_eq!(sandbox.qwk(&["run", "run"]), "Run it\n");
+    assert_eq!(sandbox.qwk(&["--run", "summ", "--", "-n"]), "Summarize");
+    assert_eq!(
+        sandbox.qwk(&["-q", "run", "summ", "this", "--", "-n"]),
+        "Summarize\n\nthis"
+    );
+    assert!(!sandbox.run(&["run"]).status.success());
+
+    // Completion offers shortcuts after it, but not commands
+    let completions = sandbox.qwk(&["--complete", "", "--previous", "run"]);
+    assert!(
+        completions.lines().any(|line| line == "summ"),
+        "{}",
+        completions
+    );
+    assert!(!completions.contains("--set"), "{}", completions);
+}
+
+// Logs shortcut runs to `runs.jsonl` and shows them with `--runs`.
+
+#[test]
+fn test_runs() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--set", "review", "Review"]);
+    sandbox.qwk(&["--agent", "sh -c 'exit 3'"]);
+    assert_eq!(sandbox.qwk(&["--runs"]), "No runs recorded.\n");
+
+    sandbox.run(&["summ", "--", "-m", "gpt 4"]);
+    sandbox.run(&["review"]);
+    // A dry run runs nothing
+    sandbox.qwk(&["summ", "--dry-run"]);
+
+    let lines: Vec<String> = sandbox.qwk(&["--runs"]).lines().map(String::from).collect();
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].starts_with("DATE "));
+    assert!(lines[1].contains(" review "), "{}", lines[1]);
+    assert!(lines[2].contains(" summ "), "{}", lines[2]);
+    assert!(lines[2].contains(" 3 "), "{}", lines[2]);
+    assert!(lines[2].ends_with("-m 'gpt 4'"), "{}", lines[2]);
+
+    assert_eq!(sandbox.qwk(&["--runs", "--limit", "1"]).lines().count(), 2);
+    let summ = sandbox.qwk(&["--runs", "--alias", "summ"]);
+    assert!(!summ.contains("review"), "{}", summ);
+}
+
+#[test]
+fn test_track_runs_off() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "true"]);
+    sandbox.add_settings("track_runs = false");
+
+    sandbox.qwk(&["summ"]);
+    assert!(!sandbox.config_dir.join("runs.jsonl").exists());
+    assert!(sandbox.qwk(&["--runs"]).contains("track_runs is false"));
+}
+
+// Reports how a shortcut runs with `-v`/`--verbose` or `QWK_VERBOSE`, on stderr only.
+
+#[test]
+fn test_verbose() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "sh -c 'echo \"$0\"; exit 3'", "--force"]);
+
+    let output = sandbox.run(&["-v", "summ"]);
+    assert_eq!(output.status.code(), Some(3));
+    // The agent's output is untouched
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Summarize\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let aliases_file = sandbox.config_dir.join("aliases.json");
+    let config_file = sandbox.config_dir.join("config.toml");
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(
+        lines[..3],
+        [
+            format!("qwk: alias 'summ' from {}", aliases_file.display()),
+            format!(
+                "qwk: agent 'sh -c 'echo \"$0\"; exit 3'' (set with --agent in {})",
+                config_file.display()
+            ),
+            "qwk: running sh -c 'echo \"$0\"; exit 3' Summarize".to_string(),
+        ]
+    );
+    assert!(
+        lines[3].starts_with("qwk: exited with code 3 after "),
+        "{}",
+        stderr
+    );
+    assert_eq!(lines.len(), 4, "{}", stderr);
+}
+
+#[test]
+fn test_verbose_from_env() {
+    let sandbox = Sandbox::new();
+    sandbox.qwk(&["--set", "summ", "Summarize"]);
+    sandbox.qwk(&["--agent", "true", "--force"]);
+
+    let run = |value: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_qwk"))
+            .arg("summ")
+            .env("QWK_CONFIG_DIR", &sandbox.config_dir)
+            .env("QWK_NO_LOCAL", "1")
+            .env("QWK_VERBOSE", value)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert!(run("1").contains("qwk: running true Summarize\n"));
+    assert_eq!(run("0"), "");
+
+    // Without it, nothing is reported
+    let output = sandbox.run(&["summ"]);
+    assert!(output.stderr.is_empty());
+}